@@ -87,16 +87,19 @@ mod test {
         let r1 = BoxedResponse {
             id: Some(RequestId::Int(7)),
             body: ResponseBody::Update(Box::new(Empty {})),
+            last_update: false,
         };
         let r2 = BoxedResponse {
             id: Some(RequestId::Int(8)),
             body: ResponseBody::Error(Box::new(rpc::RpcError::from(
                 crate::connection::RequestCancelled,
             ))),
+            last_update: false,
         };
         let r3 = BoxedResponse {
             id: Some(RequestId::Int(9)),
             body: ResponseBody::Success(Box::new(Empty {})),
+            last_update: false,
         };
 
         // These should get serialized as follows.
@@ -6,6 +6,7 @@ use std::{
     collections::HashMap,
     pin::Pin,
     sync::{Arc, Mutex, RwLock, Weak},
+    task::{ready, Poll},
 };
 
 use asynchronous_codec::JsonCodecError;
@@ -17,7 +18,6 @@ use futures::{
 };
 use rpc::dispatch::BoxedUpdateSink;
 use serde_json::error::Category as JsonErrorCategory;
-use tor_async_utils::SinkExt as _;
 
 use crate::{
     cancel::{Cancel, CancelHandle},
@@ -350,18 +350,13 @@ impl Connection {
             method,
         } = request;
 
+        let pending_update = PendingUpdate::default();
         let update_sender: BoxedUpdateSink = if meta.updates {
-            let id_clone = id.clone();
-            let sink =
-                tx_response
-                    .clone()
-                    .with_fn(move |obj: Box<dyn erased_serde::Serialize + Send>| {
-                        Result::<BoxedResponse, _>::Ok(BoxedResponse {
-                            id: Some(id_clone.clone()),
-                            body: ResponseBody::Update(obj),
-                        })
-                    });
-            Box::pin(sink)
+            Box::pin(UpdateResponseSink::new(
+                id.clone(),
+                tx_response.clone(),
+                Arc::clone(&pending_update),
+            ))
         } else {
             let sink = futures::sink::drain().sink_err_into();
             Box::pin(sink)
@@ -388,6 +383,20 @@ impl Connection {
             Err(_cancelled) => ResponseBody::Error(Box::new(rpc::RpcError::from(RequestCancelled))),
         };
 
+        // If the method left an update undelivered (because it returned, or
+        // was cancelled, without flushing its sink), send that update now.
+        // No more updates can follow it, so it is the last one.
+        let pending_update = pending_update.lock().expect("Lock poisoned").take();
+        if let Some(update) = pending_update {
+            let _ignore_err = tx_response
+                .send(BoxedResponse {
+                    id: Some(id.clone()),
+                    body: ResponseBody::Update(update),
+                    last_update: true,
+                })
+                .await;
+        }
+
         // Send the response.
         //
         // (It's okay to ignore the error here, since it can only mean that the
@@ -396,6 +405,7 @@ impl Connection {
             .send(BoxedResponse {
                 id: Some(id.clone()),
                 body,
+                last_update: false,
             })
             .await;
 
@@ -435,6 +445,109 @@ impl Connection {
     }
 }
 
+/// An update that an [`UpdateResponseSink`] has accepted, but not yet delivered.
+///
+/// This is shared with [`Connection::run_method_and_deliver_response`], so
+/// that an update which is still pending when the method returns is not lost.
+type PendingUpdate = Arc<Mutex<Option<rpc::dispatch::RpcValue>>>;
+
+/// A sink that delivers the updates for a single request as [`BoxedResponse`]s.
+///
+/// We hold each update back until the sink is next readied, flushed, or
+/// closed.  If it is closed while an update is pending, that update was the
+/// last one, and we mark it as such.  (If the method finishes without doing
+/// any of those, [`Connection::run_method_and_deliver_response`] delivers the
+/// pending update, and marks it as the last one.)
+struct UpdateResponseSink {
+    /// The ID of the request whose updates we're sending.
+    id: RequestId,
+    /// The sink to which we deliver responses.
+    tx_response: mpsc::Sender<BoxedResponse>,
+    /// An update that we have accepted, but not yet delivered.
+    pending: PendingUpdate,
+}
+
+impl UpdateResponseSink {
+    /// Create a new UpdateResponseSink to send the updates for `id` on `tx_response`,
+    /// holding any undelivered update in `pending`.
+    fn new(
+        id: RequestId,
+        tx_response: mpsc::Sender<BoxedResponse>,
+        pending: PendingUpdate,
+    ) -> Self {
+        Self {
+            id,
+            tx_response,
+            pending,
+        }
+    }
+
+    /// Return true if we have an update that we haven't yet delivered.
+    fn has_pending(&self) -> bool {
+        self.pending.lock().expect("Lock poisoned").is_some()
+    }
+
+    /// Try to deliver our pending update, if any,
+    /// marking it as the last update if `last_update` is true.
+    fn poll_deliver_pending(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+        last_update: bool,
+    ) -> Poll<Result<(), rpc::SendUpdateError>> {
+        if !self.has_pending() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.tx_response.poll_ready_unpin(cx))?;
+        let update = self.pending.lock().expect("Lock poisoned").take();
+        if let Some(update) = update {
+            self.tx_response.start_send_unpin(BoxedResponse {
+                id: Some(self.id.clone()),
+                body: ResponseBody::Update(update),
+                last_update,
+            })?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<rpc::dispatch::RpcValue> for UpdateResponseSink {
+    type Error = rpc::SendUpdateError;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_deliver_pending(cx, false)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: rpc::dispatch::RpcValue) -> Result<(), Self::Error> {
+        let mut pending = self.pending.lock().expect("Lock poisoned");
+        debug_assert!(pending.is_none());
+        *pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_deliver_pending(cx, false))?;
+        this.tx_response.poll_flush_unpin(cx).map_err(Into::into)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_deliver_pending(cx, true))?;
+        // We don't close `tx_response` itself:
+        // the connection still needs it to send our final response.
+        this.tx_response.poll_flush_unpin(cx).map_err(Into::into)
+    }
+}
+
 /// A failure that results in closing a [`Connection`].
 #[derive(Clone, Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -540,3 +653,119 @@ impl tor_error::HasKind for RequestCancelled {
         tor_error::ErrorKind::Other
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use futures_await_test::async_test;
+
+    #[async_test]
+    async fn final_update_is_marked() {
+        let (tx, rx) = mpsc::channel(4);
+        let mut sink: rpc::UpdateSink<u32> = Box::pin(
+            UpdateResponseSink::new(RequestId::Int(5), tx, PendingUpdate::default()).with(
+                |n: u32| {
+                    futures::future::ok::<_, rpc::SendUpdateError>(
+                        Box::new(n) as rpc::dispatch::RpcValue
+                    )
+                },
+            ),
+        );
+        sink.send(1).await.unwrap();
+        sink.send(2).await.unwrap();
+        rpc::send_final_update(&mut sink, 3).await.unwrap();
+        drop(sink);
+
+        let responses: Vec<String> = rx
+            .map(|r| serde_json::to_string(&r).unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            responses,
+            vec![
+                r#"{"id":5,"update":1}"#,
+                r#"{"id":5,"update":2}"#,
+                r#"{"id":5,"update":3,"last_update":true}"#,
+            ]
+        );
+    }
+
+    /// An object to invoke test methods on.
+    #[derive(Deftly)]
+    #[derive_deftly(Object)]
+    struct Dummy;
+
+    /// A method that feeds an update to its sink, but returns without flushing it.
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(method_name = "x-test:feed_then_return"))]
+    struct FeedThenReturn {}
+
+    impl rpc::RpcMethod for FeedThenReturn {
+        type Output = rpc::Nil;
+        type Update = u32;
+    }
+
+    async fn feed_then_return(
+        _obj: Arc<Dummy>,
+        _method: Box<FeedThenReturn>,
+        _ctx: Arc<dyn rpc::Context>,
+        mut updates: rpc::UpdateSink<u32>,
+    ) -> Result<rpc::Nil, rpc::RpcError> {
+        updates.feed(1).await.unwrap();
+        Ok(rpc::Nil::default())
+    }
+
+    rpc::static_rpc_invoke_fn! {
+        feed_then_return;
+    }
+
+    #[async_test]
+    async fn fed_update_is_delivered() {
+        let conn = Connection::new(
+            ConnectionId::from([0; 16]),
+            Arc::new(RwLock::new(rpc::DispatchTable::from_inventory())),
+            MacKey::new(&mut rand::thread_rng()),
+            Weak::new(),
+        );
+        let obj = rpc::Context::register_owned(conn.as_ref(), Arc::new(Dummy));
+        let request: Request = serde_json::from_value(serde_json::json!({
+            "id": 7,
+            "obj": obj.as_ref(),
+            "meta": { "updates": true },
+            "method": "x-test:feed_then_return",
+            "params": {},
+        }))
+        .unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        conn.run_method_and_deliver_response(tx, request).await;
+
+        // The update arrives before the final response, marked as the last one.
+        let responses: Vec<String> = rx
+            .map(|r| serde_json::to_string(&r).unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            responses,
+            vec![
+                r#"{"id":7,"update":1,"last_update":true}"#,
+                r#"{"id":7,"result":{}}"#,
+            ]
+        );
+    }
+}
@@ -55,10 +55,11 @@ pub(crate) struct Request {
     /// The method to actually execute.
     ///
     /// Using "flatten" here will make it expand to "method" and "params".
+    /// We use `deserialize_method` to make sure that "params" is an object.
     ///
     /// TODO RPC: Note that our spec says that "params" can be omitted, but I
     /// don't think we support that right now.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "rpc::deserialize_method")]
     pub(crate) method: Box<dyn rpc::DeserMethod>,
 }
 
@@ -88,6 +89,12 @@ pub(crate) struct BoxedResponse {
     /// The body  that we're sending.
     #[serde(flatten)]
     pub(crate) body: ResponseBody,
+    /// True if this is an update, and no further updates will be sent
+    /// in reply to the same request.
+    ///
+    /// (A final response will still follow.)
+    #[serde(rename = "last_update", skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) last_update: bool,
 }
 
 impl BoxedResponse {
@@ -99,7 +106,11 @@ impl BoxedResponse {
     {
         let error: rpc::RpcError = error.into();
         let body = ResponseBody::Error(Box::new(error));
-        Self { id, body }
+        Self {
+            id,
+            body,
+            last_update: false,
+        }
     }
 }
 
@@ -287,6 +298,10 @@ mod test {
             RPE::ParamType,
             r#"{ "id": 3, "obj": "hello", "method": "x-test:dummy", "params": 7 }"#
         );
+        expect_err!(
+            RPE::ParamType,
+            r#"{ "id": 3, "obj": "hello", "method": "x-test:dummy", "params": [7] }"#
+        );
     }
 
     #[test]
@@ -297,6 +312,7 @@ mod test {
                 hello: 99,
                 world: "foo".into(),
             })),
+            last_update: false,
         };
         let s = serde_json::to_string(&resp).unwrap();
         // NOTE: This is a bit fragile for a test, since nothing in serde or
@@ -309,6 +325,7 @@ mod test {
             body: ResponseBody::Error(Box::new(rpc::RpcError::from(
                 crate::err::RequestParseError::IdMissing,
             ))),
+            last_update: false,
         };
         let s = serde_json::to_string(&resp).unwrap();
         // NOTE: as above.
@@ -316,5 +333,32 @@ mod test {
             s,
             r#"{"error":{"message":"error: Request did not have any `id` field.","code":-32600,"kinds":["arti:RpcInvalidRequest"]}}"#
         );
+
+        let resp = BoxedResponse {
+            id: Some(RequestId::Int(8)),
+            body: ResponseBody::Update(Box::new(DummyResponse {
+                hello: 3,
+                world: "bar".into(),
+            })),
+            last_update: false,
+        };
+        let s = serde_json::to_string(&resp).unwrap();
+        // NOTE: as above.
+        assert_eq!(s, r#"{"id":8,"update":{"hello":3,"world":"bar"}}"#);
+
+        let resp = BoxedResponse {
+            id: Some(RequestId::Int(8)),
+            body: ResponseBody::Update(Box::new(DummyResponse {
+                hello: 4,
+                world: "baz".into(),
+            })),
+            last_update: true,
+        };
+        let s = serde_json::to_string(&resp).unwrap();
+        // NOTE: as above.
+        assert_eq!(
+            s,
+            r#"{"id":8,"update":{"hello":4,"world":"baz"},"last_update":true}"#
+        );
     }
 }
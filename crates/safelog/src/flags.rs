@@ -2,7 +2,8 @@
 //!
 //! By default, safelogging is on.  There are two ways to turn it off: Globally
 //! (with [`disable_safe_logging`]) and locally (with
-//! [`with_safe_logging_suppressed`]).
+//! [`with_safe_logging_suppressed`]).  With the `tracing` feature, it can also
+//! be turned off locally for the events of a single `tracing` target.
 
 use crate::{Error, Result};
 use fluid_let::fluid_let;
@@ -24,6 +25,23 @@ fluid_let!(
 pub(crate) fn unsafe_logging_enabled() -> bool {
     LOGGING_STATE.load(Ordering::Relaxed) < 0
         || SAFE_LOGGING_SUPPRESSED_IN_THREAD.get(|v| v == Some(&true))
+        || suppressed_for_current_target()
+}
+
+/// Returns true if safe logging is suppressed for the target of the event
+/// that we are currently formatting.
+#[cfg(feature = "tracing")]
+fn suppressed_for_current_target() -> bool {
+    crate::target::suppressed_for_current_target()
+}
+
+/// Returns true if safe logging is suppressed for the target of the event
+/// that we are currently formatting.
+///
+/// (Without the `tracing` feature, we never know the target.)
+#[cfg(not(feature = "tracing"))]
+fn suppressed_for_current_target() -> bool {
+    false
 }
 
 /// Run a given function with the regular `safelog` functionality suppressed.
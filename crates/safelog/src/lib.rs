@@ -50,9 +50,15 @@ use serde::{Deserialize, Serialize};
 mod err;
 mod flags;
 mod impls;
+#[cfg(feature = "tracing")]
+mod target;
+mod time;
 
 pub use err::Error;
 pub use flags::{disable_safe_logging, enforce_safe_logging, with_safe_logging_suppressed, Guard};
+#[cfg(feature = "tracing")]
+pub use target::{with_safe_logging_suppressed_for_target, TargetAwareFormat};
+pub use time::{CoarseDuration, CoarseTime};
 
 use std::ops::Deref;
 
@@ -106,11 +112,55 @@ impl<T> Sensitive<T> {
     }
 
     /// Return a reference to the inner value
+    ///
+    /// The returned [`SensitiveRef`] dereferences to `&T`,
+    /// but is still scrubbed when formatted,
+    /// so that we can't leak the value by accident
+    /// by formatting the result of this function.
     //
     // This isn't `AsRef` or `as_ref` because we don't want to offer "de-sensitivisation"
     // via what is usually a semantically-neutral interface.
-    pub fn as_inner(&self) -> &T {
-        &self.0
+    pub fn as_inner(&self) -> SensitiveRef<'_, T> {
+        SensitiveRef(&self.0)
+    }
+}
+
+/// A reference to the value inside a [`Sensitive<T>`].
+///
+/// Returned by [`Sensitive::as_inner`].
+///
+/// Like `Sensitive<T>`, this type dereferences to `T`,
+/// and attempts to turn it into a string all produce the string `[scrubbed]`
+/// (unless safe logging is suppressed or disabled).
+/// To get a plain `&T` that lives as long as the `Sensitive<T>`,
+/// use [`SensitiveRef::into_inner`].
+//
+// We implement Clone and Copy by hand, since deriving them would require `T: Copy`.
+pub struct SensitiveRef<'a, T>(&'a T);
+
+impl<'a, T> SensitiveRef<'a, T> {
+    /// Extract the inner reference from a `SensitiveRef`.
+    ///
+    /// (This is an associated function, rather than a method,
+    /// so that it doesn't hide any `into_inner` method on `T`.)
+    pub fn into_inner(r: SensitiveRef<'a, T>) -> &'a T {
+        r.0
+    }
+}
+
+impl<'a, T> Clone for SensitiveRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for SensitiveRef<'a, T> {}
+
+impl<'a, T> Deref for SensitiveRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0
     }
 }
 
@@ -143,6 +193,16 @@ macro_rules! impl_display_traits {
             }
         }
 
+        impl<'a, T: std::fmt::$trait> std::fmt::$trait for SensitiveRef<'a, T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                if flags::unsafe_logging_enabled() {
+                    std::fmt::$trait::fmt(self.0, f)
+                } else {
+                    write!(f, "[scrubbed]")
+                }
+            }
+        }
+
         impl<T: std::fmt::$trait> std::fmt::$trait for BoxSensitive<T> {
             #[inline]
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -195,6 +255,21 @@ impl_display_traits! {
     Display, Debug, Binary, Octal, LowerHex, UpperHex, LowerExp, UpperExp, Pointer
 }
 
+/// Wrap `value` for use as a structured field in a `tracing` event or span.
+///
+/// The field is recorded via the `Display` implementation of
+/// [`Sensitive<T>`], so it is scrubbed unless safe logging is disabled,
+/// no matter how the subscriber chooses to format it.
+///
+/// ```
+/// # let name = "Alice";
+/// tracing::info!(user = safelog::field(name), "User logged in");
+/// ```
+#[cfg(feature = "tracing")]
+pub fn field<T: std::fmt::Display>(value: T) -> tracing::field::DisplayValue<Sensitive<T>> {
+    tracing::field::display(Sensitive::new(value))
+}
+
 /// A `redactable` object is one where we know a way to display _part_ of it
 /// when we are running with safe logging enabled.
 ///
@@ -322,6 +397,43 @@ impl<T: Redactable> std::fmt::Debug for MaybeRedacted<T> {
     }
 }
 
+/// A wrapper around a slice of `Redactable` values that displays each of them
+/// in redacted format.
+///
+/// The elements are shown in a bracketed, comma-separated list, like
+/// `[127.x.x.x, 10.x.x.x]`.  As with [`Redacted`], they are shown in full if
+/// safe logging is disabled or suppressed.
+#[derive(Educe)]
+#[educe(Clone, Copy)]
+#[derive(derive_more::From)]
+pub struct RedactedSlice<'a, T: Redactable>(&'a [T]);
+
+impl<'a, T: Redactable> RedactedSlice<'a, T> {
+    /// Create a new `RedactedSlice`.
+    pub fn new(values: &'a [T]) -> Self {
+        Self(values)
+    }
+}
+
+impl<'a, T: Redactable> std::fmt::Display for RedactedSlice<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, value) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            std::fmt::Display::fmt(&Redacted(value), f)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<'a, T: Redactable> std::fmt::Debug for RedactedSlice<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.0.iter().map(Redacted)).finish()
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -369,7 +481,12 @@ mod test {
         assert!(!flags::unsafe_logging_enabled());
         assert_eq!(format!("{:?}", &sv), "[scrubbed]");
         assert_eq!(format!("{:?}", sv.as_ref()), "[scrubbed]");
-        assert_eq!(format!("{:?}", sv.as_inner()), "[104, 49]");
+        assert_eq!(format!("{:?}", sv.as_inner()), "[scrubbed]");
+        assert_eq!(
+            format!("{:?}", SensitiveRef::into_inner(sv.as_inner())),
+            "[104, 49]"
+        );
+        assert_eq!(sv.as_inner().len(), 2);
         let normal = with_safe_logging_suppressed(|| format!("{:?}", &sv));
         assert_eq!(normal, "[104, 49]");
 
@@ -381,6 +498,32 @@ mod test {
         assert_eq!(*sv, vec![104, 49]);
     }
 
+    #[test]
+    #[serial]
+    fn sensitive_ref() {
+        let val = Sensitive::<u32>::new(0x0ed19a);
+        let r = val.as_inner();
+
+        // We can still use the reference as a `&u32`...
+        assert_eq!(*r + 1, 0x0ed19b);
+        assert_eq!(SensitiveRef::into_inner(r), &0x0ed19a);
+
+        // ...but formatting it doesn't leak the value.
+        let closure = || format!("{:?}, {}, {:x}", r, r, r);
+        assert_eq!(closure(), "[scrubbed], [scrubbed], [scrubbed]");
+        assert_eq!(
+            with_safe_logging_suppressed(closure),
+            "971162, 971162, ed19a"
+        );
+
+        let ptr: *const u8 = std::ptr::null();
+        let val = Sensitive::new(ptr);
+        let expect = format!("{:p}", ptr);
+        let closure = || format!("{:p}", val.as_inner());
+        assert_eq!(closure(), "[scrubbed]");
+        assert_eq!(with_safe_logging_suppressed(closure), expect);
+    }
+
     #[test]
     #[serial]
     #[allow(deprecated)]
@@ -470,4 +613,42 @@ mod test {
 
         assert_eq!(Redacted::new(localhost).unwrap(), localhost);
     }
+
+    #[test]
+    #[serial]
+    fn redacted_slice() {
+        let addrs = [
+            std::net::Ipv4Addr::LOCALHOST,
+            std::net::Ipv4Addr::new(10, 0, 0, 1),
+        ];
+        let closure = || {
+            let slice = RedactedSlice::new(&addrs);
+            format!("{} {:?}", slice, slice)
+        };
+
+        assert_eq!(closure(), "[127.x.x.x, 10.x.x.x] [127.x.x.x, 10.x.x.x]");
+        assert_eq!(
+            with_safe_logging_suppressed(closure),
+            "[127.0.0.1, 10.0.0.1] [127.0.0.1, 10.0.0.1]"
+        );
+        assert_eq!(
+            RedactedSlice::<std::net::Ipv4Addr>::new(&[]).to_string(),
+            "[]"
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    #[serial]
+    #[tracing_test::traced_test]
+    fn tracing_field() {
+        tracing::info!(user = field("alice"), "safe logging enabled");
+        assert!(logs_contain("user=[scrubbed]"));
+        assert!(!logs_contain("alice"));
+
+        with_safe_logging_suppressed(|| {
+            tracing::info!(user = field("bob"), "safe logging suppressed");
+        });
+        assert!(logs_contain("user=bob"));
+    }
 }
@@ -0,0 +1,191 @@
+//! Code for suppressing safelogging for the events from a single `tracing`
+//! target.
+//!
+//! Unlike [`with_safe_logging_suppressed`](crate::with_safe_logging_suppressed),
+//! this needs to know the target of the event that is currently being
+//! formatted.  We learn that from [`TargetAwareFormat`], which must wrap the
+//! event formatter of the subscriber that writes the logs.
+
+use fluid_let::fluid_let;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+fluid_let!(
+    /// A dynamic variable naming the target for which safe-logging is
+    /// temporarily disabled.
+    static SAFE_LOGGING_SUPPRESSED_FOR_TARGET: String
+);
+
+fluid_let!(
+    /// A dynamic variable holding the target of the event that
+    /// [`TargetAwareFormat`] is currently formatting.
+    static CURRENT_EVENT_TARGET: &'static str
+);
+
+/// Return true if `event_target` is `target`, or a module inside it.
+fn target_matches(event_target: &str, target: &str) -> bool {
+    match event_target.strip_prefix(target) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Returns true if safe logging is suppressed for the event that we are
+/// currently formatting.
+pub(crate) fn suppressed_for_current_target() -> bool {
+    SAFE_LOGGING_SUPPRESSED_FOR_TARGET.get(|target| {
+        let Some(target) = target else {
+            return false;
+        };
+        CURRENT_EVENT_TARGET.get(|current| current.is_some_and(|c| target_matches(c, target)))
+    })
+}
+
+/// Run a given function with the regular `safelog` functionality suppressed,
+/// but only for `tracing` events whose target is `target` (or a module
+/// inside it).
+///
+/// Events from any other target, and values that are formatted outside of
+/// any event, are still scrubbed as usual.  As with
+/// [`with_safe_logging_suppressed`](crate::with_safe_logging_suppressed),
+/// this only affects the current thread.  If calls to this function are
+/// nested, only the innermost `target` is exempted.
+///
+/// This only works for events formatted by a subscriber whose event
+/// formatter is wrapped in [`TargetAwareFormat`]; with any other subscriber,
+/// nothing is exempted.  Note also that span fields are formatted when the
+/// span is created, not as part of an event, so they remain scrubbed.
+///
+/// # Examples
+///
+/// ```
+/// use safelog::{Sensitive, TargetAwareFormat, with_safe_logging_suppressed_for_target};
+///
+/// let subscriber = tracing_subscriber::fmt()
+///     .event_format(TargetAwareFormat::new(tracing_subscriber::fmt::format()))
+///     .finish();
+///
+/// tracing::subscriber::with_default(subscriber, || {
+///     with_safe_logging_suppressed_for_target("my_crate", || {
+///         // This value is displayed...
+///         tracing::info!(target: "my_crate::conn", "User: {}", Sensitive::new("alice"));
+///         // ...but this one is scrubbed.
+///         tracing::info!(target: "other_crate", "User: {}", Sensitive::new("bob"));
+///     });
+/// });
+/// ```
+pub fn with_safe_logging_suppressed_for_target<F, V>(target: &str, func: F) -> V
+where
+    F: FnOnce() -> V,
+{
+    SAFE_LOGGING_SUPPRESSED_FOR_TARGET.set(target.to_owned(), func)
+}
+
+/// A wrapper for a `tracing_subscriber` event formatter that lets
+/// [`with_safe_logging_suppressed_for_target`] know the target of each event
+/// that it formats.
+#[derive(Clone, Debug, Default)]
+pub struct TargetAwareFormat<E> {
+    /// The formatter that does the actual work.
+    inner: E,
+}
+
+impl<E> TargetAwareFormat<E> {
+    /// Wrap the event formatter `inner`.
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, N, E> FormatEvent<S, N> for TargetAwareFormat<E>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    E: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        CURRENT_EVENT_TARGET.set(event.metadata().target(), || {
+            self.inner.format_event(ctx, writer, event)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::Sensitive;
+    use serial_test::serial;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer that appends everything to a shared buffer.
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn matching() {
+        assert!(target_matches("foo", "foo"));
+        assert!(target_matches("foo::bar", "foo"));
+        assert!(!target_matches("foobar", "foo"));
+        assert!(!target_matches("bar::foo", "foo"));
+    }
+
+    #[test]
+    #[serial]
+    fn suppress_for_target() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = {
+            let buf = Arc::clone(&buf);
+            tracing_subscriber::fmt()
+                .event_format(TargetAwareFormat::new(tracing_subscriber::fmt::format()))
+                .with_writer(move || SharedBuf(Arc::clone(&buf)))
+                .finish()
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            with_safe_logging_suppressed_for_target("noisy", || {
+                tracing::info!(target: "noisy::inner", "value={}", Sensitive::new("alpha"));
+                tracing::info!(target: "quiet", "value={}", Sensitive::new("beta"));
+                tracing::info!(target: "noisy_neighbor", "value={}", Sensitive::new("gamma"));
+
+                // Outside of an event, values are still scrubbed.
+                assert_eq!(Sensitive::new("delta").to_string(), "[scrubbed]");
+            });
+            tracing::info!(target: "noisy", "value={}", Sensitive::new("epsilon"));
+        });
+
+        let logs = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("value=alpha"));
+        assert!(!logs.contains("beta"));
+        assert!(!logs.contains("gamma"));
+        assert!(!logs.contains("epsilon"));
+        assert_eq!(logs.matches("value=[scrubbed]").count(), 3);
+    }
+}
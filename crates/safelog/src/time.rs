@@ -0,0 +1,227 @@
+//! Redactable wrappers for times and durations.
+//!
+//! Precise timestamps and durations can help to fingerprint a user: an exact
+//! connection time or uptime is often enough to link two log messages, or a
+//! log message to some externally observed event.  The types here log their
+//! values in full when safe logging is disabled, and rounded to a coarse
+//! granularity otherwise.
+
+use super::Redactable;
+use std::fmt::{self, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default granularity for [`CoarseTime`]: one hour.
+const DEFAULT_TIME_GRANULARITY: Duration = Duration::from_secs(60 * 60);
+
+/// Default granularity for [`CoarseDuration`]: one minute.
+const DEFAULT_DURATION_GRANULARITY: Duration = Duration::from_secs(60);
+
+/// A [`SystemTime`] that is rounded to a coarse granularity when redacted.
+///
+/// When displayed normally, or when safe logging is disabled, this is shown
+/// as a full-precision RFC 3339 timestamp.  When redacted, it is rounded to
+/// the nearest multiple of its granularity (one hour by default), counting
+/// from the Unix epoch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct CoarseTime {
+    /// The time itself.
+    time: SystemTime,
+    /// The granularity to round to when redacting.
+    granularity: Duration,
+}
+
+/// A [`Duration`] that is rounded to a coarse granularity when redacted.
+///
+/// When displayed normally, or when safe logging is disabled, this is shown
+/// in full precision.  When redacted, it is rounded to the nearest multiple of
+/// its granularity (one minute by default).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct CoarseDuration {
+    /// The duration itself.
+    duration: Duration,
+    /// The granularity to round to when redacting.
+    granularity: Duration,
+}
+
+impl CoarseTime {
+    /// Wrap `time`, to be rounded to the nearest hour when redacted.
+    pub fn new(time: SystemTime) -> Self {
+        CoarseTime {
+            time,
+            granularity: DEFAULT_TIME_GRANULARITY,
+        }
+    }
+
+    /// Round to the nearest multiple of `granularity` when redacted, instead
+    /// of to the nearest hour.
+    ///
+    /// A granularity of zero disables rounding.
+    #[must_use]
+    pub fn with_granularity(self, granularity: Duration) -> Self {
+        CoarseTime {
+            granularity,
+            ..self
+        }
+    }
+
+    /// Return the wrapped time.
+    pub fn into_inner(self) -> SystemTime {
+        self.time
+    }
+}
+
+impl CoarseDuration {
+    /// Wrap `duration`, to be rounded to the nearest minute when redacted.
+    pub fn new(duration: Duration) -> Self {
+        CoarseDuration {
+            duration,
+            granularity: DEFAULT_DURATION_GRANULARITY,
+        }
+    }
+
+    /// Round to the nearest multiple of `granularity` when redacted, instead
+    /// of to the nearest minute.
+    ///
+    /// A granularity of zero disables rounding.
+    #[must_use]
+    pub fn with_granularity(self, granularity: Duration) -> Self {
+        CoarseDuration {
+            granularity,
+            ..self
+        }
+    }
+
+    /// Return the wrapped duration.
+    pub fn into_inner(self) -> Duration {
+        self.duration
+    }
+}
+
+impl From<SystemTime> for CoarseTime {
+    fn from(time: SystemTime) -> Self {
+        CoarseTime::new(time)
+    }
+}
+
+impl From<Duration> for CoarseDuration {
+    fn from(duration: Duration) -> Self {
+        CoarseDuration::new(duration)
+    }
+}
+
+/// Round `d` to the nearest multiple of `granularity`.
+///
+/// (Halfway cases round up.  If `granularity` is zero, return `d` unchanged.)
+fn round_duration(d: Duration, granularity: Duration) -> Duration {
+    let g = granularity.as_nanos();
+    if g == 0 {
+        return d;
+    }
+    let rounded = (d.as_nanos() + g / 2) / g * g;
+    // This saturates after about 584 years, which is plenty.
+    Duration::from_nanos(u64::try_from(rounded).unwrap_or(u64::MAX))
+}
+
+/// Write `time` as an RFC 3339 timestamp, with as much precision as it has.
+fn write_time(f: &mut Formatter<'_>, time: SystemTime, whole_seconds: bool) -> fmt::Result {
+    // humantime can't format times before the epoch; we shouldn't see those
+    // anyway.
+    if time < UNIX_EPOCH {
+        return write!(f, "(before 1970)");
+    }
+    if whole_seconds {
+        write!(f, "{}", humantime::format_rfc3339_seconds(time))
+    } else {
+        write!(f, "{}", humantime::format_rfc3339(time))
+    }
+}
+
+impl fmt::Display for CoarseTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_time(f, self.time, false)
+    }
+}
+
+impl Redactable for CoarseTime {
+    fn display_redacted(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => {
+                let rounded = UNIX_EPOCH + round_duration(since_epoch, self.granularity);
+                write_time(f, rounded, self.granularity >= Duration::from_secs(1))
+            }
+            Err(_) => write_time(f, self.time, true),
+        }
+    }
+}
+
+impl fmt::Display for CoarseDuration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.duration))
+    }
+}
+
+impl Redactable for CoarseDuration {
+    fn display_redacted(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let rounded = round_duration(self.duration, self.granularity);
+        write!(f, "{}", humantime::format_duration(rounded))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use crate::with_safe_logging_suppressed;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn coarse_time() {
+        let t = humantime::parse_rfc3339("2023-06-01T13:31:15.25Z").unwrap();
+        let ct = CoarseTime::new(t);
+
+        assert_eq!(ct.to_string(), "2023-06-01T13:31:15.250000000Z");
+        assert_eq!(ct.redacted().to_string(), "2023-06-01T14:00:00Z");
+        assert_eq!(
+            with_safe_logging_suppressed(|| ct.redacted().to_string()),
+            "2023-06-01T13:31:15.250000000Z"
+        );
+
+        let ct = ct.with_granularity(Duration::from_secs(60));
+        assert_eq!(ct.redacted().to_string(), "2023-06-01T13:31:00Z");
+        let ct = ct.with_granularity(Duration::ZERO);
+        assert_eq!(ct.redacted().to_string(), "2023-06-01T13:31:15.250000000Z");
+    }
+
+    #[test]
+    #[serial]
+    fn coarse_duration() {
+        let d = Duration::from_millis((3 * 3600 + 29 * 60 + 31) * 1000 + 500);
+        let cd = CoarseDuration::new(d);
+
+        assert_eq!(cd.to_string(), "3h 29m 31s 500ms");
+        assert_eq!(cd.redacted().to_string(), "3h 30m");
+        assert_eq!(
+            with_safe_logging_suppressed(|| cd.redacted().to_string()),
+            "3h 29m 31s 500ms"
+        );
+
+        let cd = cd.with_granularity(Duration::from_secs(3600));
+        assert_eq!(cd.redacted().to_string(), "3h");
+        let cd = CoarseDuration::new(Duration::from_secs(20));
+        assert_eq!(cd.redacted().to_string(), "0s");
+    }
+}
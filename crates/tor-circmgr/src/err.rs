@@ -4,6 +4,7 @@ use std::{sync::Arc, time::Instant};
 
 use futures::task::SpawnError;
 use retry_error::RetryError;
+use safelog::SensitiveRef;
 use thiserror::Error;
 
 use tor_async_utils::oneshot;
@@ -362,10 +363,10 @@ impl Error {
     pub fn peers(&self) -> Vec<&OwnedChanTarget> {
         match self {
             Error::RequestFailed(errors) => errors.sources().flat_map(|e| e.peers()).collect(),
-            Error::Channel { peer, .. } => vec![peer.as_inner()],
+            Error::Channel { peer, .. } => vec![SensitiveRef::into_inner(peer.as_inner())],
             Error::Protocol {
                 peer: Some(peer), ..
-            } => vec![peer.as_inner()],
+            } => vec![SensitiveRef::into_inner(peer.as_inner())],
             _ => vec![],
         }
     }
@@ -5,13 +5,21 @@
 
 use std::borrow::Cow;
 use std::fmt::{Debug, Display};
-use std::iter;
 use std::net;
 use std::num::NonZeroU16;
+use std::str::FromStr;
+
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+use std::{
+    collections::BTreeSet,
+    io,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    sync::{Arc, Mutex},
+};
 
 use either::Either;
-use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use itertools::{chain, Itertools};
+use serde::{Deserialize, Serialize, Serializer};
 use strum::{Display, EnumString, IntoStaticStr};
 
 /// Boolean, but with additional `"auto"` option
@@ -47,6 +55,25 @@ impl BoolOrAuto {
             BoolOrAuto::Explicit(v) => Some(v),
         }
     }
+
+    /// Returns the explicitly set boolean value, or the result of `default_fn`
+    ///
+    /// `default_fn` is only called if the value is `Auto`.
+    ///
+    /// ```
+    /// use tor_config::BoolOrAuto;
+    ///
+    /// fn calculate_default() -> bool { //...
+    /// # false }
+    /// let bool_or_auto: BoolOrAuto = // ...
+    /// # Default::default();
+    /// let _: bool = bool_or_auto.resolve(calculate_default);
+    /// # assert_eq!(BoolOrAuto::Auto.resolve(|| true), true);
+    /// # assert_eq!(BoolOrAuto::Explicit(false).resolve(|| true), false);
+    /// ```
+    pub fn resolve(self, default_fn: impl FnOnce() -> bool) -> bool {
+        self.as_bool().unwrap_or_else(default_fn)
+    }
 }
 
 /// How we (de) serialize a [`BoolOrAuto`]
@@ -249,6 +276,114 @@ impl<T: NotAutoValue> ExplicitOrAuto<T> {
     }
 }
 
+/// A serializable value, or auto, where the value may itself be `"auto"`.
+///
+/// This is like [`ExplicitOrAuto`], but it can hold any type,
+/// including strings (or other types) that could legitimately be `"auto"`.
+/// To tell the two apart, an explicit value is always wrapped in a table
+/// with a single `value` key, whereas `Auto` is the bare string `"auto"`.
+///
+/// The tradeoff is that explicit values are more verbose to write,
+/// so prefer [`ExplicitOrAuto`] for types that implement [`NotAutoValue`].
+///
+/// ## Example
+///
+/// In the following serialized TOML config
+///
+/// ```toml
+///  nickname = "auto"
+///  other_nickname = { value = "auto" }
+/// ```
+///
+/// `nickname` is set to [`Auto`](QuotedExplicitOrAuto::Auto),
+/// whereas `other_nickname` is explicitly set to the string `"auto"`.
+#[derive(Clone, Copy, Hash, Debug, Default, Ord, PartialOrd, Eq, PartialEq)]
+#[allow(clippy::exhaustive_enums)] // we will add variants very rarely if ever
+#[derive(Serialize, Deserialize)]
+#[serde(
+    from = "QuotedExplicitOrAutoSerde<T>",
+    into = "QuotedExplicitOrAutoSerde<T>",
+    bound(
+        serialize = "T: Serialize + Clone",
+        deserialize = "T: Deserialize<'de>"
+    )
+)]
+pub enum QuotedExplicitOrAuto<T> {
+    /// Automatic
+    #[default]
+    Auto,
+    /// Explicitly specified
+    Explicit(T),
+}
+
+impl<T> QuotedExplicitOrAuto<T> {
+    /// Returns the explicitly set value, or `None`.
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            QuotedExplicitOrAuto::Auto => None,
+            QuotedExplicitOrAuto::Explicit(v) => Some(v),
+        }
+    }
+
+    /// Returns a reference to the explicitly set value, or `None`.
+    ///
+    /// Like [`QuotedExplicitOrAuto::into_value`], except it returns a reference to the inner type.
+    pub fn as_value(&self) -> Option<&T> {
+        match self {
+            QuotedExplicitOrAuto::Auto => None,
+            QuotedExplicitOrAuto::Explicit(v) => Some(v),
+        }
+    }
+}
+
+/// How we (de) serialize a [`QuotedExplicitOrAuto`]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum QuotedExplicitOrAutoSerde<T> {
+    /// The string `"auto"`
+    Auto(AutoKeyword),
+    /// A table containing the explicit value
+    Explicit(QuotedValue<T>),
+}
+
+/// The keyword `"auto"`, and nothing else
+#[derive(Serialize, Deserialize)]
+enum AutoKeyword {
+    /// `"auto"`
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+/// An explicit value, in the form `{ value = ... }`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuotedValue<T> {
+    /// The value
+    value: T,
+}
+
+impl<T> From<QuotedExplicitOrAuto<T>> for QuotedExplicitOrAutoSerde<T> {
+    fn from(eoa: QuotedExplicitOrAuto<T>) -> QuotedExplicitOrAutoSerde<T> {
+        match eoa {
+            QuotedExplicitOrAuto::Auto => QuotedExplicitOrAutoSerde::Auto(AutoKeyword::Auto),
+            QuotedExplicitOrAuto::Explicit(value) => {
+                QuotedExplicitOrAutoSerde::Explicit(QuotedValue { value })
+            }
+        }
+    }
+}
+
+impl<T> From<QuotedExplicitOrAutoSerde<T>> for QuotedExplicitOrAuto<T> {
+    fn from(s: QuotedExplicitOrAutoSerde<T>) -> QuotedExplicitOrAuto<T> {
+        match s {
+            QuotedExplicitOrAutoSerde::Auto(AutoKeyword::Auto) => QuotedExplicitOrAuto::Auto,
+            QuotedExplicitOrAutoSerde::Explicit(QuotedValue { value }) => {
+                QuotedExplicitOrAuto::Explicit(value)
+            }
+        }
+    }
+}
+
 /// A marker trait for types that do not serialize to the same value as [`ExplicitOrAuto::Auto`].
 ///
 /// **Important**: you should not implement this trait manually.
@@ -346,6 +481,8 @@ impl TryFrom<PaddingLevelSerde> for PaddingLevel {
 ///  * Listen on the following port on localhost (IPv6 and IPv4)
 ///  * Listen on precisely the following address and port
 ///  * Listen on several addresses/ports
+///  * Listen on a socket passed to us by systemd (`"systemd:NAME"`;
+///    Linux only, and only with the `systemd` cargo feature)
 ///
 /// Currently only IP (v6 and v4) is supported.
 #[derive(Clone, Hash, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
@@ -354,6 +491,11 @@ impl TryFrom<PaddingLevelSerde> for PaddingLevel {
 pub struct Listen(Vec<ListenItem>);
 
 impl Listen {
+    /// A `Listen` specifying no addresses (no listening)
+    ///
+    /// This is the same as [`Listen::new_none()`], but usable in `const` contexts.
+    pub const DISABLED: Listen = Listen(Vec::new());
+
     /// Create a new `Listen` specifying no addresses (no listening)
     pub fn new_none() -> Listen {
         Listen(vec![])
@@ -384,6 +526,31 @@ impl Listen {
         self.0.is_empty()
     }
 
+    /// Return true if this `Listen` specifies no listening, so that whatever
+    /// it configures is turned off
+    ///
+    /// This is the same as [`is_empty`](Listen::is_empty),
+    /// but makes the intent clearer at call sites that check whether a service is disabled.
+    pub fn is_disabled(&self) -> bool {
+        self.is_empty()
+    }
+
+    /// Combine this `Listen` with `other`, a `Listen` from a later configuration layer
+    ///
+    /// With [`MergeMode::Replace`], the result is just `other`.
+    /// With [`MergeMode::Append`], the result is the items of `self`,
+    /// followed by those items of `other` that do not already appear in `self`.
+    ///
+    /// Arti's own configuration loading uses [`MergeMode::Replace`]:
+    /// a `Listen` from a later source (eg, the command line)
+    /// completely replaces one from an earlier source (eg, a configuration file).
+    pub fn merged_with(&self, other: &Listen, mode: MergeMode) -> Listen {
+        match mode {
+            MergeMode::Replace => other.clone(),
+            MergeMode::Append => Listen(chain!(&self.0, &other.0).unique().cloned().collect_vec()),
+        }
+    }
+
     /// List the network socket addresses to listen on
     ///
     /// Each returned item is a list of `SocketAddr`,
@@ -394,17 +561,133 @@ impl Listen {
     /// for particular address families, eg IPv6 vs IPv4 localhost.
     /// Other errors (eg, `EADDRINUSE`) should always be treated as serious problems.
     ///
-    /// Fails if the listen spec involves listening on things other than IP addresses.
-    /// (Currently that is not possible.)
+    /// Fails if the listen spec involves listening on things other than IP addresses,
+    /// such as sockets passed to us by systemd.
     pub fn ip_addrs(
         &self,
     ) -> Result<
         impl Iterator<Item = impl Iterator<Item = net::SocketAddr> + '_> + '_,
         ListenUnsupported,
     > {
+        if !self.0.iter().all(ListenItem::is_ip) {
+            return Err(ListenUnsupported {});
+        }
         Ok(self.0.iter().map(|i| i.iter()))
     }
 
+    /// List every network socket address that we might listen on, in one flat list
+    ///
+    /// This loses the grouping that [`ip_addrs`](Listen::ip_addrs) provides:
+    /// it does not say which addresses are alternatives,
+    /// of which only *at least one* needs to be successfully bound.
+    /// So it is suitable for reporting, or for checking for conflicts,
+    /// but code that actually binds sockets should use `ip_addrs` instead.
+    ///
+    /// Items that don't specify IP addresses,
+    /// such as sockets passed to us by systemd, are skipped.
+    pub fn all_addrs(&self) -> impl Iterator<Item = net::SocketAddr> + '_ {
+        self.0.iter().flat_map(ListenItem::iter)
+    }
+
+    /// List the privileged ports (those below 1024) that we are configured to listen on
+    ///
+    /// On many operating systems (including most Unix-like ones),
+    /// binding to these ports fails unless we have special privileges.
+    /// So the application may want to warn the user about them,
+    /// before we fail to bind with a more confusing error.
+    ///
+    /// Each port appears once, in the order in which it first appears in the configuration.
+    /// Items that don't specify IP addresses, such as sockets passed to us by systemd,
+    /// are skipped (the socket has already been bound for us).
+    pub fn warn_privileged_ports(&self) -> Vec<u16> {
+        self.all_addrs()
+            .map(|addr| addr.port())
+            .filter(|port| (1..1024).contains(port))
+            .unique()
+            .collect()
+    }
+
+    /// List the addresses we would listen on that conflict with those of `other`
+    ///
+    /// Two addresses conflict if they have the same port,
+    /// and the same IP address, or the same address family where either IP address
+    /// is unspecified (`0.0.0.0` or `[::]`).
+    /// A localhost port means both `127.0.0.1` and `[::1]`,
+    /// so it conflicts with a port on either of those addresses.
+    /// Port 0 (meaning "any free port") never conflicts.
+    ///
+    /// This is useful for reporting, at configuration time,
+    /// that two services would try to bind the same address.
+    ///
+    /// Returns addresses from `self`, each once, in the order in which they first appear.
+    /// Items that don't specify IP addresses, such as sockets passed to us by systemd,
+    /// are skipped.
+    pub fn conflicts_with(&self, other: &Listen) -> Vec<net::SocketAddr> {
+        /// Return true if binding both `a` and `b` would fail.
+        fn conflict(a: &net::SocketAddr, b: &net::SocketAddr) -> bool {
+            a.port() != 0
+                && a.port() == b.port()
+                && a.is_ipv4() == b.is_ipv4()
+                && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+        }
+
+        self.all_addrs()
+            .filter(|a| other.all_addrs().any(|b| conflict(a, &b)))
+            .unique()
+            .collect()
+    }
+
+    /// Take ownership of the sockets systemd passed us, for each `systemd:NAME` item
+    ///
+    /// Returns one file descriptor for each systemd-activated item, in order;
+    /// other items are ignored.
+    ///
+    /// This only works when we were started by systemd using socket activation:
+    /// the sockets are found using the `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`
+    /// environment variables (see `sd_listen_fds(3)`),
+    /// and `NAME` must match the `FileDescriptorName=` of one of the sockets.
+    ///
+    /// Each socket passed by systemd can be taken only once per process;
+    /// after that, attempts to take it again will fail.
+    /// The sockets we return are close-on-exec, so child processes don't inherit them.
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    pub fn systemd_sockets(&self) -> Result<Vec<OwnedFd>, SystemdListenError> {
+        self.0
+            .iter()
+            .filter_map(|i| match i {
+                ListenItem::SystemdActivated(name) => Some(take_systemd_socket(name)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Describe this `Listen` in a form suitable for a log message
+    ///
+    /// Unlike the `Display` implementation, this notes the address families involved,
+    /// and marks addresses that are reachable from other hosts as `public`.
+    /// For example, `localhost:9050 (IPv4+IPv6), [::]:9150 (IPv6, public)`.
+    ///
+    /// Returns `not listening` if no listening is configured.
+    pub fn describe(&self) -> String {
+        if self.0.is_empty() {
+            return "not listening".into();
+        }
+        self.0.iter().map(ListenItem::describe).join(", ")
+    }
+
+    /// Serialize this `Listen`, always using the list form
+    ///
+    /// The usual `Serialize` implementation is compact:
+    /// it writes `false` for "do not listen", and a bare value for a single item.
+    /// This function instead always writes a list, even if it has zero or one items,
+    /// which can be preferable for tooling that generates configuration files.
+    ///
+    /// Suitable for use with `#[serde(serialize_with = "Listen::serialize_as_list")]`.
+    /// Either form is accepted when deserializing.
+    pub fn serialize_as_list<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ListenSerde::List(self.0.iter().cloned().map(Into::into).collect()).serialize(serializer)
+    }
+
     /// Get the localhost port to listen on
     ///
     /// Returns `None` if listening is configured to be disabled.
@@ -431,12 +714,174 @@ impl Display for Listen {
         Ok(())
     }
 }
+
+/// Parse a single listen specification, as might be given on the command line
+///
+/// Accepts a port number (meaning that port on localhost, or no listening if it is `0`),
+/// a single `addr:port`, `systemd:NAME` (if supported),
+/// or `"none"` or `""` (meaning no listening).
+///
+/// Listening on several addresses, or using the other forms accepted in the
+/// configuration file (such as `false`), can only be specified via serde.
+impl FromStr for Listen {
+    type Err = InvalidListen;
+
+    fn from_str(s: &str) -> Result<Listen, Self::Err> {
+        use ListenItemSerde as LIS;
+        let item = match s.parse::<u16>() {
+            Ok(port) => LIS::Port(port),
+            Err(_) if s == "none" => LIS::String(String::new()),
+            Err(_) => LIS::String(s.to_owned()),
+        };
+        ListenSerde::One(item).try_into()
+    }
+}
+
+impl TryFrom<&str> for Listen {
+    type Error = InvalidListen;
+
+    fn try_from(s: &str) -> Result<Listen, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How to combine two [`Listen`]s from different configuration layers
+///
+/// See [`Listen::merged_with`].
+#[derive(Clone, Copy, Hash, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum MergeMode {
+    /// The later layer replaces the earlier one entirely
+    Replace,
+    /// The later layer's items are added after the earlier one's, skipping duplicates
+    Append,
+}
+
 /// [`Listen`] configuration specified something not supported by application code
 #[derive(thiserror::Error, Debug, Clone)]
 #[non_exhaustive]
 #[error("Unsupported listening configuration")]
 pub struct ListenUnsupported {}
 
+/// Prefix for a listen item naming a socket passed to us by systemd
+const SYSTEMD_PREFIX: &str = "systemd:";
+
+/// Problem taking ownership of a socket passed to us by systemd
+///
+/// See [`Listen::systemd_sockets`].
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum SystemdListenError {
+    /// We weren't started using systemd socket activation
+    #[error("Not started by systemd socket activation (LISTEN_PID and LISTEN_FDS not set for us)")]
+    NotActivated,
+
+    /// systemd didn't pass us any socket with this name
+    #[error("systemd did not pass us a socket named {0:?}")]
+    NoSuchSocket(String),
+
+    /// The socket(s) with this name have already been taken
+    #[error("systemd socket {0:?} has already been taken")]
+    AlreadyTaken(String),
+
+    /// We couldn't stop the socket from being inherited by child processes
+    #[error("Could not set close-on-exec on systemd socket {name:?}")]
+    CloseOnExec {
+        /// The name of the socket
+        name: String,
+        /// What went wrong
+        #[source]
+        error: Arc<io::Error>,
+    },
+}
+
+/// File descriptors passed by systemd that we have already taken ownership of
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+static SYSTEMD_TAKEN: Mutex<BTreeSet<RawFd>> = Mutex::new(BTreeSet::new());
+
+/// The first file descriptor passed by systemd (`SD_LISTEN_FDS_START`)
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Take ownership of the (first not-yet-taken) socket called `name` passed by systemd
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn take_systemd_socket(name: &str) -> Result<OwnedFd, SystemdListenError> {
+    use std::env::var;
+    use SystemdListenError as SLE;
+
+    let pid: u32 = var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .ok_or(SLE::NotActivated)?;
+    if pid != std::process::id() {
+        return Err(SLE::NotActivated);
+    }
+    let n_fds: usize = var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or(SLE::NotActivated)?;
+    let names = var("LISTEN_FDNAMES").ok();
+
+    let mut taken = SYSTEMD_TAKEN.lock().expect("poisoned");
+    let mut candidates = systemd_fds_with_names(n_fds, names.as_deref())
+        .filter(|&(_, n)| n == name)
+        .map(|(fd, _)| fd)
+        .peekable();
+    if candidates.peek().is_none() {
+        return Err(SLE::NoSuchSocket(name.to_owned()));
+    }
+    let fd = candidates
+        .find(|fd| !taken.contains(fd))
+        .ok_or_else(|| SLE::AlreadyTaken(name.to_owned()))?;
+
+    // systemd doesn't set close-on-exec on the sockets it passes us,
+    // so unless we do, they would leak into every child process we spawn.
+    set_cloexec(fd).map_err(|error| SLE::CloseOnExec {
+        name: name.to_owned(),
+        error: Arc::new(error),
+    })?;
+    taken.insert(fd);
+
+    // SAFETY: LISTEN_PID names us, so systemd passed this fd, open, to this process,
+    // for us to own.  We have recorded in SYSTEMD_TAKEN that it is now owned,
+    // so we will never make another `OwnedFd` for it.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Pair each of the `n_fds` file descriptors passed by systemd with its name
+///
+/// `names` is the value of `LISTEN_FDNAMES`, if it was set.
+/// As in `sd_listen_fds_with_names(3)`, any file descriptor without a name there
+/// is called `unknown`.
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn systemd_fds_with_names(
+    n_fds: usize,
+    names: Option<&str>,
+) -> impl Iterator<Item = (RawFd, &str)> {
+    let names = names
+        .into_iter()
+        .flat_map(|names| names.split(':'))
+        .chain(std::iter::repeat("unknown"));
+    (LISTEN_FDS_START..).zip(names).take(n_fds)
+}
+
+/// Set `FD_CLOEXEC` on `fd`, as `sd_listen_fds(3)` does
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: F_GETFD and F_SETFD only read and change the flags of `fd`;
+    // if `fd` is not open, they fail with EBADF.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: As above.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 /// One item in the `Listen`
 ///
 /// We distinguish `Localhost`,
@@ -451,6 +896,12 @@ enum ListenItem {
 
     /// Any other single socket address
     General(net::SocketAddr),
+
+    /// A socket passed to us by systemd, identified by its `FileDescriptorName=`
+    ///
+    /// Only meaningful when we were started using systemd socket activation.
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    SystemdActivated(String),
 }
 
 impl ListenItem {
@@ -466,7 +917,37 @@ impl ListenItem {
                     .into_iter()
                     .map(move |ip| net::SocketAddr::new(ip, port))
             }),
-            LI::General(addr) => Either::Right(iter::once(addr).cloned()),
+            LI::General(addr) => Either::Right(Some(*addr).into_iter()),
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            LI::SystemdActivated(_) => Either::Right(None.into_iter()),
+        }
+    }
+
+    /// Describe this item; see [`Listen::describe`]
+    fn describe(&self) -> String {
+        use ListenItem as LI;
+        match self {
+            LI::Localhost(port) => format!("localhost:{port} (IPv4+IPv6)"),
+            LI::General(addr) => {
+                let family = if addr.is_ipv4() { "IPv4" } else { "IPv6" };
+                if addr.ip().is_loopback() {
+                    format!("{addr} ({family})")
+                } else {
+                    format!("{addr} ({family}, public)")
+                }
+            }
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            LI::SystemdActivated(name) => format!("systemd socket {name}"),
+        }
+    }
+
+    /// Is this item an IP address (or addresses), as opposed to some other kind of socket?
+    fn is_ip(&self) -> bool {
+        use ListenItem as LI;
+        match self {
+            LI::Localhost(_) | LI::General(_) => true,
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            LI::SystemdActivated(_) => false,
         }
     }
 }
@@ -476,6 +957,8 @@ impl Display for ListenItem {
         match self {
             ListenItem::Localhost(port) => write!(f, "localhost port {}", port)?,
             ListenItem::General(addr) => write!(f, "{}", addr)?,
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            ListenItem::SystemdActivated(name) => write!(f, "systemd socket {}", name)?,
         }
         Ok(())
     }
@@ -503,7 +986,7 @@ enum ListenItemSerde {
     /// When appearing "loose" (in ListenSerde::One), `0` is parsed as none.
     Port(u16),
 
-    /// An string which will be parsed as an address and port
+    /// An string which will be parsed as an address and port, or `systemd:NAME`
     ///
     /// When appearing "loose" (in ListenSerde::One), `""` is parsed as none.
     String(String),
@@ -529,6 +1012,8 @@ impl From<ListenItem> for ListenItemSerde {
         match i {
             LI::Localhost(port) => LIS::Port(port.into()),
             LI::General(addr) => LIS::String(addr.to_string()),
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            LI::SystemdActivated(name) => LIS::String(format!("{SYSTEMD_PREFIX}{name}")),
         }
     }
 }
@@ -548,6 +1033,25 @@ pub enum InvalidListen {
     /// Specified listen was a list containing a zero integer
     #[error("Invalid listen specification: zero (for no port) not permitted in list")]
     ZeroPortInList,
+
+    /// Specified a systemd socket, but that isn't supported in this build or on this platform
+    #[error("Invalid listen specification: systemd sockets not supported (needs Linux, and the `systemd` cargo feature)")]
+    SystemdUnsupported,
+
+    /// Specified a systemd socket with an empty or invalid name
+    #[error("Invalid listen specification: invalid systemd socket name {0:?}")]
+    InvalidSystemdName(String),
+
+    /// An element of a list was invalid
+    #[error("Invalid listen specification: element at index {index} ({value}): {error}")]
+    InvalidListElement {
+        /// The (zero-based) index of the bad element within the list
+        index: usize,
+        /// The bad element, as it appeared in the configuration
+        value: String,
+        /// What was wrong with it
+        error: Box<InvalidListen>,
+    },
 }
 impl TryFrom<ListenSerde> for Listen {
     type Error = InvalidListen;
@@ -559,7 +1063,19 @@ impl TryFrom<ListenSerde> for Listen {
             LS::Bool(true) => return Err(InvalidListen::InvalidBool),
             LS::One(i) if i.means_none() => vec![],
             LS::One(i) => vec![i.try_into()?],
-            LS::List(l) => l.into_iter().map(|i| i.try_into()).try_collect()?,
+            LS::List(l) => l
+                .into_iter()
+                .enumerate()
+                .map(|(index, i)| {
+                    let value = i.describe();
+                    i.try_into()
+                        .map_err(|error| InvalidListen::InvalidListElement {
+                            index,
+                            value,
+                            error: Box::new(error),
+                        })
+                })
+                .try_collect()?,
         }))
     }
 }
@@ -574,6 +1090,15 @@ impl ListenItemSerde {
             LIS::String(s) => s.is_empty(),
         }
     }
+
+    /// Describe this item as it would appear in a config file, for error messages
+    fn describe(&self) -> String {
+        use ListenItemSerde as LIS;
+        match self {
+            LIS::Port(port) => port.to_string(),
+            LIS::String(s) => format!("{s:?}"),
+        }
+    }
 }
 impl TryFrom<ListenItemSerde> for ListenItem {
     type Error = InvalidListen;
@@ -582,12 +1107,31 @@ impl TryFrom<ListenItemSerde> for ListenItem {
         use ListenItem as LI;
         use ListenItemSerde as LIS;
         Ok(match i {
-            LIS::String(s) => LI::General(s.parse()?),
+            LIS::String(s) => match s.strip_prefix(SYSTEMD_PREFIX) {
+                Some(name) => systemd_listen_item(name)?,
+                None => LI::General(s.parse()?),
+            },
             LIS::Port(p) => LI::Localhost(p.try_into().map_err(|_| InvalidListen::ZeroPortInList)?),
         })
     }
 }
 
+/// Make a [`ListenItem`] for the systemd socket called `name`
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn systemd_listen_item(name: &str) -> Result<ListenItem, InvalidListen> {
+    // sd_listen_fds(3) uses `:` to separate the names, so it can't appear in one
+    if name.is_empty() || name.contains(':') {
+        return Err(InvalidListen::InvalidSystemdName(name.to_owned()));
+    }
+    Ok(ListenItem::SystemdActivated(name.to_owned()))
+}
+
+/// Make a [`ListenItem`] for the systemd socket called `name` (unsupported here)
+#[cfg(not(all(feature = "systemd", target_os = "linux")))]
+fn systemd_listen_item(_name: &str) -> Result<ListenItem, InvalidListen> {
+    Err(InvalidListen::SystemdUnsupported)
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -621,6 +1165,9 @@ mod test {
 
         #[serde(default)]
         auto_or_bool: ExplicitOrAuto<bool>,
+
+        #[serde(default)]
+        auto_or_string: QuotedExplicitOrAuto<String>,
     }
 
     #[test]
@@ -770,6 +1317,179 @@ mod test {
         chk_err("did not match any variant", r#"listen = [ [] ]"#);
     }
 
+    #[test]
+    fn listen_list_error() {
+        let s = r#"listen = [ 23, "bad", 77 ]"#;
+        let got: Result<TestConfigFile, _> = toml::from_str(s);
+        let got = got.expect_err(s).to_string();
+        assert!(got.contains(r#"index 1 ("bad")"#), "{got}");
+
+        let l = ListenSerde::List(vec![
+            ListenItemSerde::Port(23),
+            ListenItemSerde::Port(77),
+            ListenItemSerde::Port(0),
+        ]);
+        let e = Listen::try_from(l).unwrap_err();
+        assert!(
+            matches!(
+                &e,
+                InvalidListen::InvalidListElement { index: 2, value, error }
+                    if value == "0" && matches!(**error, InvalidListen::ZeroPortInList)
+            ),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn listen_serialize_as_list() {
+        #[derive(Serialize)]
+        struct ListConfig<'l> {
+            #[serde(serialize_with = "Listen::serialize_as_list")]
+            listen: &'l Listen,
+        }
+
+        let chk = |l: Listen, compact: &str, list: &str| {
+            let tc = TestConfigFile {
+                listen: Some(l.clone()),
+                ..TestConfigFile::default()
+            };
+            let got = toml::to_string(&tc).unwrap();
+            assert!(got.contains(compact), "{got}");
+
+            let got = toml::to_string(&ListConfig { listen: &l }).unwrap();
+            assert_eq!(got.trim_end(), list);
+
+            // The list form reads back as the same `Listen`.
+            let tc: TestConfigFile = toml::from_str(&got).unwrap();
+            assert_eq!(tc.listen.unwrap(), l);
+        };
+
+        chk(Listen::new_none(), "listen = false", "listen = []");
+        chk(
+            Listen::new_localhost(9050),
+            "listen = 9050",
+            "listen = [9050]",
+        );
+        chk(
+            Listen(vec![
+                ListenItem::Localhost(9050.try_into().unwrap()),
+                ListenItem::General("127.0.0.1:9051".parse().unwrap()),
+            ]),
+            r#"listen = [9050, "127.0.0.1:9051"]"#,
+            r#"listen = [9050, "127.0.0.1:9051"]"#,
+        );
+    }
+
+    #[test]
+    fn listen_from_str() {
+        use net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+        let chk = |exp: Listen, s: &str| {
+            assert_eq!(s.parse::<Listen>().expect(s), exp, "s={:?}", s);
+            assert_eq!(Listen::try_from(s).expect(s), exp, "s={:?}", s);
+        };
+
+        chk(Listen::new_localhost(9050), "9050");
+        chk(
+            Listen(vec![ListenItem::General(SocketAddr::new(
+                Ipv4Addr::LOCALHOST.into(),
+                9051,
+            ))]),
+            "127.0.0.1:9051",
+        );
+        chk(
+            Listen(vec![ListenItem::General(SocketAddr::new(
+                Ipv6Addr::UNSPECIFIED.into(),
+                56,
+            ))]),
+            "[::]:56",
+        );
+        chk(Listen::new_none(), "none");
+        chk(Listen::new_none(), "");
+        chk(Listen::new_none(), "0");
+
+        for s in ["false", "localhost", "127.0.0.1", "70000", "9050, 9051"] {
+            let _: InvalidListen = s.parse::<Listen>().expect_err(s);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    fn listen_systemd() {
+        let socks = ListenItem::SystemdActivated("socks".into());
+
+        let l: Listen = "systemd:socks".parse().unwrap();
+        assert_eq!(l, Listen(vec![socks.clone()]));
+        assert_eq!(l.to_string(), "systemd socket socks");
+        assert!(l.ip_addrs().is_err());
+        assert!(l.localhost_port_legacy().is_err());
+
+        let tc: TestConfigFile = toml::from_str(r#"listen = [ 9050, "systemd:socks" ]"#).unwrap();
+        let l = tc.listen.clone().unwrap();
+        assert_eq!(
+            l,
+            Listen(vec![ListenItem::Localhost(9050.try_into().unwrap()), socks])
+        );
+        assert!(l.ip_addrs().is_err());
+        let tc2: TestConfigFile = toml::from_str(&toml::to_string(&tc).unwrap()).unwrap();
+        assert_eq!(tc2.listen.unwrap(), l);
+
+        for s in ["systemd:", "systemd:a:b"] {
+            let e = s.parse::<Listen>().expect_err(s);
+            assert!(
+                matches!(e, InvalidListen::InvalidSystemdName(_)),
+                "{s}: {e}"
+            );
+        }
+
+        // We were not started by systemd, so there is nothing to take.
+        assert!(matches!(
+            l.systemd_sockets(),
+            Err(SystemdListenError::NotActivated)
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    fn systemd_fd_names() {
+        let pairs = |n_fds, names| systemd_fds_with_names(n_fds, names).collect_vec();
+
+        assert_eq!(pairs(2, Some("socks:dns")), vec![(3, "socks"), (4, "dns")]);
+        // LISTEN_FDNAMES unset: every socket is "unknown".
+        assert_eq!(
+            pairs(3, None),
+            vec![(3, "unknown"), (4, "unknown"), (5, "unknown")]
+        );
+        // Too few names: the rest are "unknown".
+        assert_eq!(pairs(2, Some("socks")), vec![(3, "socks"), (4, "unknown")]);
+        // Too many names: the extra ones are ignored.
+        assert_eq!(pairs(1, Some("socks:dns")), vec![(3, "socks")]);
+        assert_eq!(pairs(0, Some("socks")), vec![]);
+    }
+
+    #[test]
+    #[cfg(not(all(feature = "systemd", target_os = "linux")))]
+    fn listen_systemd_unsupported() {
+        let e = "systemd:socks".parse::<Listen>().unwrap_err();
+        assert!(matches!(e, InvalidListen::SystemdUnsupported), "{e}");
+    }
+
+    #[test]
+    fn listen_privileged_ports() {
+        let chk = |s: &str, exp: &[u16]| {
+            let tc: TestConfigFile = toml::from_str(s).expect(s);
+            assert_eq!(tc.listen.unwrap().warn_privileged_ports(), exp, "{s}");
+        };
+
+        chk("listen = false", &[]);
+        chk("listen = 9050", &[]);
+        chk("listen = 80", &[80]);
+        chk(
+            r#"listen = [ 80, 9050, "127.0.0.1:443", "[::1]:8080", "0.0.0.0:80", "[::]:0" ]"#,
+            &[80, 443],
+        );
+    }
+
     #[test]
     fn display_listen() {
         let empty = Listen::new_none();
@@ -794,6 +1514,136 @@ mod test {
         assert_eq!(multi_addr.to_string(), "localhost port 1234, 1.2.3.4:5678");
     }
 
+    #[test]
+    fn describe_listen() {
+        assert_eq!(Listen::new_none().describe(), "not listening");
+
+        assert_eq!(
+            Listen::new_localhost(9050).describe(),
+            "localhost:9050 (IPv4+IPv6)"
+        );
+
+        let mixed = Listen(vec![
+            ListenItem::Localhost(9050.try_into().unwrap()),
+            ListenItem::General("127.0.0.1:9051".parse().unwrap()),
+            ListenItem::General("[::1]:9052".parse().unwrap()),
+            ListenItem::General("0.0.0.0:9150".parse().unwrap()),
+            ListenItem::General("[2001:db8::1]:9151".parse().unwrap()),
+        ]);
+        assert_eq!(
+            mixed.describe(),
+            "localhost:9050 (IPv4+IPv6), 127.0.0.1:9051 (IPv4), [::1]:9052 (IPv6), \
+             0.0.0.0:9150 (IPv4, public), [2001:db8::1]:9151 (IPv6, public)"
+        );
+    }
+
+    #[test]
+    fn listen_merge() {
+        let p = |port: u16| ListenItem::Localhost(port.try_into().unwrap());
+        let a = |s: &str| ListenItem::General(s.parse().unwrap());
+
+        let earlier = Listen(vec![p(9050), a("1.2.3.4:5678")]);
+        let later = Listen(vec![a("1.2.3.4:5678"), p(9150), p(9150)]);
+
+        assert_eq!(earlier.merged_with(&later, MergeMode::Replace), later);
+        assert_eq!(
+            earlier.merged_with(&Listen::new_none(), MergeMode::Replace),
+            Listen::new_none()
+        );
+
+        assert_eq!(
+            earlier.merged_with(&later, MergeMode::Append),
+            Listen(vec![p(9050), a("1.2.3.4:5678"), p(9150)])
+        );
+        assert_eq!(
+            earlier.merged_with(&Listen::new_none(), MergeMode::Append),
+            earlier
+        );
+        assert_eq!(
+            Listen::new_none().merged_with(&earlier, MergeMode::Append),
+            earlier
+        );
+    }
+
+    #[test]
+    fn listen_all_addrs() {
+        use net::SocketAddr;
+
+        let p = |port: u16| ListenItem::Localhost(port.try_into().unwrap());
+        let a = |s: &str| ListenItem::General(s.parse().unwrap());
+        let sa = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+        let l = Listen(vec![p(9050), a("[::]:9150"), p(9051)]);
+        assert_eq!(
+            l.all_addrs().collect_vec(),
+            vec![
+                sa("[::1]:9050"),
+                sa("127.0.0.1:9050"),
+                sa("[::]:9150"),
+                sa("[::1]:9051"),
+                sa("127.0.0.1:9051"),
+            ]
+        );
+        assert_eq!(
+            l.all_addrs().collect_vec(),
+            l.ip_addrs().unwrap().flatten().collect_vec()
+        );
+
+        assert_eq!(Listen::new_none().all_addrs().count(), 0);
+    }
+
+    #[test]
+    fn listen_disabled() {
+        let tc: TestConfigFile = toml::from_str("listen = []").unwrap();
+        for l in [
+            Listen::new_none(),
+            Listen::new_localhost(0),
+            Listen::new_localhost_optional(None),
+            tc.listen.unwrap(),
+            Listen::DISABLED,
+        ] {
+            assert!(l.is_disabled(), "{:?}", l);
+            assert_eq!(l, Listen::DISABLED);
+        }
+        assert!(!Listen::new_localhost(9050).is_disabled());
+    }
+
+    #[test]
+    fn listen_conflicts() {
+        use net::SocketAddr;
+
+        let p = |port: u16| ListenItem::Localhost(port.try_into().unwrap());
+        let a = |s: &str| ListenItem::General(s.parse().unwrap());
+        let sa = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+        let socks = Listen(vec![p(9050)]);
+        let rpc = Listen(vec![a("127.0.0.1:9050"), a("127.0.0.1:9180")]);
+        assert_eq!(socks.conflicts_with(&rpc), vec![sa("127.0.0.1:9050")]);
+        assert_eq!(rpc.conflicts_with(&socks), vec![sa("127.0.0.1:9050")]);
+
+        // Both address families of a localhost port.
+        assert_eq!(
+            socks.conflicts_with(&Listen(vec![p(9051), p(9050)])),
+            vec![sa("[::1]:9050"), sa("127.0.0.1:9050")]
+        );
+
+        // Unspecified addresses conflict within their own address family only.
+        let public = Listen(vec![a("0.0.0.0:9050")]);
+        assert_eq!(socks.conflicts_with(&public), vec![sa("127.0.0.1:9050")]);
+        assert_eq!(public.conflicts_with(&socks), vec![sa("0.0.0.0:9050")]);
+        assert_eq!(Listen(vec![a("[::]:9050")]).conflicts_with(&public), vec![]);
+
+        // No overlap.
+        assert_eq!(socks.conflicts_with(&Listen::new_localhost(9150)), vec![]);
+        assert_eq!(
+            socks.conflicts_with(&Listen(vec![a("1.2.3.4:9050")])),
+            vec![]
+        );
+        assert_eq!(socks.conflicts_with(&Listen::new_none()), vec![]);
+        let any_port = Listen(vec![a("127.0.0.1:0")]);
+        assert_eq!(any_port.conflicts_with(&any_port), vec![]);
+    }
+
     #[test]
     fn explicit_or_auto() {
         use ExplicitOrAuto as EOA;
@@ -844,6 +1694,7 @@ mod test {
 padding = "normal"
 auto_or_usize = "auto"
 auto_or_bool = "auto"
+auto_or_string = "auto"
 "#
         );
 
@@ -855,7 +1706,52 @@ auto_or_bool = "auto"
 padding = "normal"
 auto_or_usize = "auto"
 auto_or_bool = true
+auto_or_string = "auto"
 "#
         );
     }
+
+    #[test]
+    fn quoted_explicit_or_auto() {
+        use QuotedExplicitOrAuto as QEOA;
+
+        let chk = |eoa: QEOA<String>, s| {
+            let tc: TestConfigFile = toml::from_str(s).expect(s);
+            assert_eq!(eoa, tc.auto_or_string, "{:?}", s);
+        };
+
+        chk(QEOA::Auto, r#"auto_or_string = "auto""#);
+        chk(
+            QEOA::Explicit("auto".into()),
+            r#"auto_or_string = { value = "auto" }"#,
+        );
+        chk(
+            QEOA::Explicit("other".into()),
+            r#"auto_or_string = { value = "other" }"#,
+        );
+
+        let chk_e = |s| {
+            let tc: Result<TestConfigFile, _> = toml::from_str(s);
+            let _ = tc.expect_err(s);
+        };
+
+        chk_e(r#"auto_or_string = "other""#);
+        chk_e(r#"auto_or_string = {}"#);
+        chk_e(r#"auto_or_string = { value = "auto", extra = 1 }"#);
+        chk_e(r#"auto_or_string = { value = 1 }"#);
+
+        // A literal "auto" string survives a round trip, and stays distinct from Auto.
+        for eoa in [QEOA::Explicit("auto".to_string()), QEOA::Auto] {
+            let config = TestConfigFile {
+                auto_or_string: eoa.clone(),
+                ..TestConfigFile::default()
+            };
+            let toml = toml::to_string(&config).unwrap();
+            let tc: TestConfigFile = toml::from_str(&toml).unwrap();
+            assert_eq!(tc.auto_or_string, eoa, "{toml}");
+        }
+
+        assert_eq!(QEOA::Explicit(7).into_value(), Some(7));
+        assert_eq!(QEOA::<u8>::Auto.as_value(), None);
+    }
 }
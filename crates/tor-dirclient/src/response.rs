@@ -172,7 +172,7 @@ impl SourceInfo {
 
     /// Return information about the peer from which we received this info.
     pub fn cache_id(&self) -> &OwnedChanTarget {
-        self.cache_id.as_inner()
+        &self.cache_id
     }
 }
 
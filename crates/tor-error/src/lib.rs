@@ -720,6 +720,12 @@ pub enum ErrorKind {
     #[cfg(feature = "rpc")]
     RpcMethodNotImpl,
 
+    /// An RPC operation failed because a method type requires some
+    /// capability that is not enabled in this build or configuration.
+    #[display(fmt = "RPC method not available in this build or configuration")]
+    #[cfg(feature = "rpc")]
+    RpcMethodUnavailable,
+
     /// An RPC operation failed because the method type's parameters were not
     /// correct for it.
     #[display(fmt = "RPC invalid parameters")]
@@ -46,6 +46,20 @@ pub(crate) async fn report_status_events(
                 if let Some(inner) = inner.upgrade() {
                     let mut inner = inner.lock().expect("Poisoned lock");
                     inner.handle_msg(id, status, skew, &runtime);
+                    // Handle any other messages that are already queued while
+                    // we hold the lock, so that a burst of reports doesn't make
+                    // us take the lock (and wait to be scheduled) once per report.
+                    while let Ok(Some(msg)) = events.try_next() {
+                        match msg {
+                            Msg::Status(id, status, skew) => {
+                                inner.handle_msg(id, status, skew, &runtime);
+                            }
+                            #[cfg(test)]
+                            Msg::Ping(sender) => {
+                                let _ignore = sender.send(());
+                            }
+                        }
+                    }
                 } else {
                     // The guard manager has gone away.
                     return;
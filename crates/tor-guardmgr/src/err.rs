@@ -52,6 +52,11 @@ pub enum PickGuardError {
     #[error("Tried to pick from an empty list")]
     NoCandidatesAvailable,
 
+    /// Our filter requires an IPv6 guard, but none of the guards in our
+    /// sample has an IPv6 address.
+    #[error("No guards with an IPv6 address are available")]
+    NoIpv6Guards,
+
     /// An internal programming error occurred.
     #[error("Internal error")]
     Internal(#[from] Bug),
@@ -63,7 +68,7 @@ impl tor_error::HasKind for PickGuardError {
         use PickGuardError as E;
         match self {
             E::AllFallbacksDown { .. } | E::AllGuardsDown { .. } => EK::TorAccessFailed,
-            E::NoCandidatesAvailable => EK::NoPath,
+            E::NoCandidatesAvailable | E::NoIpv6Guards => EK::NoPath,
             E::Internal(_) => EK::Internal,
         }
     }
@@ -88,6 +93,10 @@ impl tor_error::HasRetryTime for PickGuardError {
             // though, then we should suggest a random delay.
             E::AllGuardsDown { .. } | E::AllFallbacksDown { .. } => RT::AfterWaiting,
 
+            // IPv6 guards might show up in a later directory, or once our
+            // sample grows.
+            E::NoIpv6Guards => RT::AfterWaiting,
+
             // We were asked to choose some kind of guard that doesn't exist in
             // our current universe; that's not going to be come viable down the
             // line.
@@ -143,6 +152,31 @@ impl GuardMgrError {
     }
 }
 
+/// A problem found by [`GuardMgr::verify_persistence_consistency`](crate::GuardMgr::verify_persistence_consistency).
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Inconsistency {
+    /// We couldn't load our guard state from storage.
+    #[error("Unable to load stored guard state")]
+    Storage(#[source] tor_persist::Error),
+
+    /// There is no guard state in storage.
+    #[error("No guard state has been stored")]
+    NothingStored,
+
+    /// The stored guard state doesn't match the guard state in memory.
+    #[error("Stored guard state differs from the state in memory, in: {}", differing.join(", "))]
+    Mismatch {
+        /// The names of the top-level parts of the guard state (such as
+        /// guard samples) that differ.
+        differing: Vec<String>,
+    },
+
+    /// An internal programming error occurred.
+    #[error("Internal error")]
+    Internal(#[from] Bug),
+}
+
 /// An error encountered while configuring or reconfiguring a guard manager
 ///
 /// When this occurs during initial configuration, it will be returned wrapped
@@ -1,6 +1,6 @@
 //! Implement GuardFilter and related types.
 
-use tor_linkspec::ChanTarget;
+use tor_linkspec::{ChanTarget, HasChanMethod};
 // TODO(nickm): Conceivably, this type should be exposed from a lower-level crate than
 // tor-netdoc.
 use tor_netdoc::types::policy::AddrPortPattern;
@@ -22,6 +22,35 @@ pub struct GuardFilter {
     /// This list of filters has "and" semantics: a relay is permitted by this
     /// filter if ALL patterns in this list permit that first hop.
     filters: Vec<SingleFilter>,
+
+    /// Whether, and how strongly, we favor guards that have an IPv6 address.
+    ipv6: Ipv6Preference,
+}
+
+/// How a [`GuardFilter`] treats guards that have no IPv6 address.
+///
+/// Guards are considered to be reachable over IPv6 if they list an IPv6
+/// address in the directory.  Guards that we reach through a pluggable
+/// transport are never preferred over other guards, but since we can't tell
+/// which address family they use, they are not rejected by
+/// [`Ipv6Preference::Require`] either.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Ipv6Preference {
+    /// Don't take IPv6 reachability into account.
+    #[default]
+    NoPreference,
+    /// Among our primary guards, use those that have an IPv6 address when we
+    /// can, but fall back to the others if none of them are usable.
+    ///
+    /// This does not change which guards are primary, and we never use a
+    /// non-primary guard just because it has an IPv6 address.
+    Prefer,
+    /// Only use guards that have an IPv6 address, and only contact them over
+    /// IPv6.
+    ///
+    /// If there is no such guard, guard selection fails.
+    Require,
 }
 
 /// A single restriction places upon usable guards.
@@ -48,9 +77,31 @@ impl GuardFilter {
             .push(SingleFilter::ReachableAddrs(addrs.into_iter().collect()));
     }
 
+    /// Set whether this filter should favor, or only permit, guards
+    /// that have an IPv6 address.
+    pub fn set_ipv6_preference(&mut self, preference: Ipv6Preference) {
+        self.ipv6 = preference;
+    }
+
+    /// Return the [`Ipv6Preference`] of this filter.
+    pub fn ipv6_preference(&self) -> Ipv6Preference {
+        self.ipv6
+    }
+
     /// Return true if this filter permits the provided `target`.
     pub(crate) fn permits<C: ChanTarget>(&self, target: &C) -> bool {
         self.filters.iter().all(|filt| filt.permits(target))
+            && (self.ipv6 != Ipv6Preference::Require || !lacks_ipv6(target))
+    }
+
+    /// Return true if `target` is among the guards that this filter prefers.
+    ///
+    /// (If this filter has no preference, every guard is preferred.)
+    pub(crate) fn prefers<C: ChanTarget>(&self, target: &C) -> bool {
+        match self.ipv6 {
+            Ipv6Preference::NoPreference | Ipv6Preference::Require => true,
+            Ipv6Preference::Prefer => has_ipv6(target),
+        }
     }
 
     /// Modify `first_hop` so that it contains no elements not permitted by this
@@ -67,12 +118,32 @@ impl GuardFilter {
         for filt in &self.filters {
             first_hop = filt.modify_hop(first_hop)?;
         }
+        // Only direct connections have addresses that we can restrict.
+        if self.ipv6 == Ipv6Preference::Require && first_hop.chan_method().socket_addrs().is_some()
+        {
+            let r = first_hop
+                .chan_target_mut()
+                .chan_method_mut()
+                .retain_addrs(|addr| addr.is_ipv6());
+            if r.is_err() {
+                // See the comment in SingleFilter::modify_hop.
+                return Err(tor_error::internal!(
+                    "Tried to apply an IPv6 filter to an unsupported guard"
+                )
+                .into());
+            }
+        }
         Ok(first_hop)
     }
 
     /// Return true if this filter excludes no guards at all.
     pub(crate) fn is_unfiltered(&self) -> bool {
-        self.filters.is_empty()
+        self.filters.is_empty() && self.ipv6 != Ipv6Preference::Require
+    }
+
+    /// Return true if this filter only permits guards that have an IPv6 address.
+    pub(crate) fn requires_ipv6(&self) -> bool {
+        self.ipv6 == Ipv6Preference::Require
     }
 
     /// Return a fraction between 0.0 and 1.0 describing what fraction of the
@@ -120,9 +191,34 @@ impl GuardFilter {
                 }
             });
         }
+        if self.ipv6 == Ipv6Preference::Require {
+            let any_ipv6 = "[::]/0:*"
+                .parse()
+                .expect("Couldn't parse a pattern matching all IPv6 addresses?");
+            selector.push_restriction(RelayRestriction::require_address(vec![any_ipv6]));
+        }
     }
 }
 
+/// Return true if `target` is contacted directly, and lists an IPv6 address.
+fn has_ipv6<C: ChanTarget + ?Sized>(target: &C) -> bool {
+    target
+        .chan_method()
+        .socket_addrs()
+        .is_some_and(|addrs| addrs.iter().any(|addr| addr.is_ipv6()))
+}
+
+/// Return true if `target` is contacted directly, and lists no IPv6 address.
+///
+/// (Unlike `!has_ipv6(target)`, this is false for targets that we don't
+/// contact via an address, such as those using a pluggable transport.)
+pub(crate) fn lacks_ipv6<C: ChanTarget + ?Sized>(target: &C) -> bool {
+    target
+        .chan_method()
+        .socket_addrs()
+        .is_some_and(|addrs| !addrs.iter().any(|addr| addr.is_ipv6()))
+}
+
 impl SingleFilter {
     /// Return true if this filter permits the provided target.
     fn permits<C: ChanTarget>(&self, target: &C) -> bool {
@@ -13,7 +13,7 @@ use crate::sample::Candidate;
 use crate::skew::SkewObservation;
 use crate::util::randomize_time;
 use crate::{ids::GuardId, GuardParams, GuardRestriction, GuardUsage};
-use crate::{sample, ExternalActivity, GuardSetSelector, GuardUsageKind};
+use crate::{sample, DropReason, ExternalActivity, GuardSetSelector, GuardUsageKind};
 
 #[cfg(feature = "bridge-client")]
 use safelog::Redactable as _;
@@ -165,6 +165,20 @@ pub(crate) struct Guard {
     #[serde(with = "humantime_serde")]
     unlisted_since: Option<SystemTime>,
 
+    /// How many times have we given out this guard in response to a request,
+    /// over its whole lifetime?
+    ///
+    /// Once this exceeds `GuardParams::max_lifetime_attempts` (if that is
+    /// nonzero), we retire the guard.
+    #[serde(default)]
+    lifetime_attempts: u32,
+
+    /// A note that the user has attached to this guard, if any.
+    ///
+    /// This is purely informational: we never use it when selecting guards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+
     /// True if this guard is listed in the latest consensus, but we don't
     /// have a microdescriptor for it.
     #[serde(skip)]
@@ -182,6 +196,11 @@ pub(crate) struct Guard {
     #[serde(skip)]
     retry_at: Option<Instant>, // derived from retry_schedule.
 
+    /// If this guard is currently Unreachable, the delay we chose from
+    /// `retry_schedule` when we set `retry_at`.
+    #[serde(skip)]
+    retry_delay: Option<Duration>,
+
     /// Schedule use to determine when we can next attempt to connect to this
     /// guard.
     #[serde(skip)]
@@ -224,6 +243,27 @@ pub(crate) struct Guard {
     #[serde(skip)]
     suspicious_behavior_warned: bool,
 
+    /// How many attempts to use this guard have failed in a row?
+    ///
+    /// Once this reaches `GuardParams::primary_demote_threshold`, the guard
+    /// is no longer eligible to be primary until it has been reachable for
+    /// `GuardParams::primary_promote_delay`.
+    #[serde(skip)]
+    failure_streak: u32,
+
+    /// If this guard is currently reachable, when did it most recently become
+    /// reachable?
+    #[serde(skip)]
+    reachable_since: Option<SystemTime>,
+
+    /// When did we most recently use this guard successfully?
+    #[serde(skip)]
+    last_success: Option<SystemTime>,
+
+    /// When did this guard most recently serve us valid directory information?
+    #[serde(skip)]
+    last_dir_success: Option<SystemTime>,
+
     /// Latest clock skew (if any) we have observed from this guard.
     #[serde(skip)]
     clock_skew: Option<SkewObservation>,
@@ -322,16 +362,23 @@ impl Guard {
             disabled: None,
             confirmed_at: None,
             unlisted_since: None,
+            lifetime_attempts: 0,
+            note: None,
             dir_info_missing: false,
             last_tried_to_connect_at: None,
             reachable: Reachable::Untried,
             retry_at: None,
+            retry_delay: None,
             dir_status: guard_dirstatus(),
             retry_schedule: None,
             is_dir_cache: true,
             exploratory_circ_pending: false,
             circ_history: CircHistory::default(),
             suspicious_behavior_warned: false,
+            failure_streak: 0,
+            reachable_since: None,
+            last_success: None,
+            last_dir_success: None,
             clock_skew: None,
             unknown_fields: Default::default(),
             sensitivity: DisplayRule::Sensitive,
@@ -343,11 +390,46 @@ impl Guard {
         &self.id
     }
 
+    /// Return the note that the user has attached to this guard, if any.
+    pub(crate) fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    /// Replace the note attached to this guard.
+    pub(crate) fn set_note(&mut self, note: Option<String>) {
+        self.note = note;
+    }
+
+    /// Return the time at which this guard most recently served us valid
+    /// directory information, if it has done so.
+    pub(crate) fn last_dir_success(&self) -> Option<SystemTime> {
+        self.last_dir_success
+    }
+
+    /// Record that this guard served us valid directory information at `now`.
+    pub(crate) fn note_dir_success(&mut self, now: SystemTime) {
+        self.last_dir_success = Some(self.last_dir_success.map_or(now, |t| t.max(now)));
+    }
+
     /// Return the reachability status for this guard.
     pub(crate) fn reachable(&self) -> Reachable {
         self.reachable
     }
 
+    /// If we are retrying this guard after it was unreachable, return the last
+    /// time (if any) at which we used it successfully.
+    ///
+    /// When several guards become retriable at once (for example, after
+    /// [`mark_retriable`](Guard::mark_retriable) is called on all of them),
+    /// we try the ones that succeeded most recently first, since they are the
+    /// most likely to still be working.
+    pub(crate) fn retry_priority(&self) -> Option<SystemTime> {
+        match self.reachable {
+            Reachable::Retriable => self.last_success,
+            _ => None,
+        }
+    }
+
     /// Return the next time at which this guard will be retriable for a given
     /// usage.
     ///
@@ -416,11 +498,14 @@ impl Guard {
             disabled: self.disabled,
             confirmed_at: self.confirmed_at,
             unlisted_since: self.unlisted_since,
+            lifetime_attempts: self.lifetime_attempts,
+            note: self.note,
             unknown_fields: self.unknown_fields,
 
             // All non-persistent fields get taken from `other`.
             last_tried_to_connect_at: other.last_tried_to_connect_at,
             retry_at: other.retry_at,
+            retry_delay: other.retry_delay,
             retry_schedule: other.retry_schedule,
             reachable: other.reachable,
             is_dir_cache: other.is_dir_cache,
@@ -428,6 +513,10 @@ impl Guard {
             dir_info_missing: other.dir_info_missing,
             circ_history: other.circ_history,
             suspicious_behavior_warned: other.suspicious_behavior_warned,
+            failure_streak: other.failure_streak,
+            reachable_since: other.reachable_since,
+            last_success: other.last_success,
+            last_dir_success: other.last_dir_success,
             dir_status: other.dir_status,
             clock_skew: other.clock_skew,
             sensitivity: other.sensitivity,
@@ -477,6 +566,16 @@ impl Guard {
         self.exploratory_circ_pending = pending;
     }
 
+    /// Return information about when we last tried this guard, and when we
+    /// will next retry it.
+    pub(crate) fn retry_info(&self) -> RetryInfo {
+        RetryInfo {
+            last_tried: self.last_tried_to_connect_at,
+            retry_delay: self.retry_delay,
+            next_retry: self.retry_at,
+        }
+    }
+
     /// Possibly mark this guard as retriable, if it has been down for
     /// long enough.
     ///
@@ -498,10 +597,27 @@ impl Guard {
         if self.reachable == Reachable::Unreachable {
             self.set_reachable(Reachable::Retriable);
             self.retry_at = None;
+            self.retry_delay = None;
             self.retry_schedule = None;
         }
     }
 
+    /// Forget everything transient we have learned about whether this guard is
+    /// reachable.
+    ///
+    /// Unlike [`Guard::mark_retriable`], this also clears our directory-cache
+    /// retry timer and our streak of failures, so the guard is immediately
+    /// usable for any purpose and eligible to be primary again.  Nothing that
+    /// is persisted (sampling, confirmation) is changed.
+    pub(crate) fn clear_reachability_state(&mut self) {
+        self.mark_retriable();
+        self.retry_at = None;
+        self.retry_delay = None;
+        self.retry_schedule = None;
+        self.dir_status = guard_dirstatus();
+        self.failure_streak = 0;
+    }
+
     /// Return true if this guard obeys all of the given restrictions.
     fn obeys_restrictions(&self, restrictions: &[GuardRestriction]) -> bool {
         restrictions.iter().all(|r| self.obeys_restriction(r))
@@ -514,6 +630,9 @@ impl Guard {
             GuardRestriction::AvoidAllIds(avoid_ids) => {
                 self.id.0.identities().all(|id| !avoid_ids.contains(id))
             }
+            GuardRestriction::RequireAnyId(ids) => {
+                self.id.0.identities().any(|id| ids.contains(id))
+            }
         }
     }
 
@@ -623,12 +742,22 @@ impl Guard {
 
     /// Return true if we should remove this guard from the current guard
     /// sample.
+    pub(crate) fn is_expired(&self, params: &GuardParams, now: SystemTime) -> bool {
+        self.expiry_reason(params, now).is_some()
+    }
+
+    /// If we should remove this guard from the current guard sample, return
+    /// the reason why.
     ///
     /// Guards may be ready for removal because they have been
     /// confirmed too long ago, if they have been sampled too long ago
     /// (if they are not confirmed), or if they have been unlisted for
     /// too long.
-    pub(crate) fn is_expired(&self, params: &GuardParams, now: SystemTime) -> bool {
+    pub(crate) fn expiry_reason(
+        &self,
+        params: &GuardParams,
+        now: SystemTime,
+    ) -> Option<DropReason> {
         /// Helper: Return true if `t2` is after `t1` by at least `d`.
         fn expired_by(t1: SystemTime, d: Duration, t2: SystemTime) -> bool {
             if let Ok(elapsed) = t2.duration_since(t1) {
@@ -640,23 +769,23 @@ impl Guard {
         if self.disabled.is_some() {
             // We never forget a guard that we've disabled: we've disabled
             // it for a reason.
-            return false;
+            return None;
         }
         if let Some(confirmed_at) = self.confirmed_at {
             if expired_by(confirmed_at, params.lifetime_confirmed, now) {
-                return true;
+                return Some(DropReason::ExpiredConfirmed);
             }
         } else if expired_by(self.added_at, params.lifetime_unconfirmed, now) {
-            return true;
+            return Some(DropReason::ExpiredUnconfirmed);
         }
 
         if let Some(unlisted_since) = self.unlisted_since {
             if expired_by(unlisted_since, params.lifetime_unlisted, now) {
-                return true;
+                return Some(DropReason::UnlistedTooLong);
             }
         }
 
-        false
+        None
     }
 
     /// Record that a failure has happened for this guard.
@@ -674,8 +803,11 @@ impl Guard {
 
         // TODO-SPEC: Document this behavior in guard-spec.
         self.retry_at = Some(now + retry_interval);
+        self.retry_delay = Some(retry_interval);
 
         self.circ_history.n_failures += 1;
+        self.failure_streak = self.failure_streak.saturating_add(1);
+        self.reachable_since = None;
     }
 
     /// Note that we have launch an attempted use of this guard.
@@ -687,15 +819,14 @@ impl Guard {
             .last_tried_to_connect_at
             .map(|last| last.max(connect_attempt))
             .or(Some(connect_attempt));
+        self.lifetime_attempts = self.lifetime_attempts.saturating_add(1);
     }
 
-    /// Return true if this guard has an exploratory circuit pending and
-    /// if the most recent attempt to connect to it is after `when`.
-    ///
-    /// See [`Self::exploratory_circ_pending`].
-    pub(crate) fn exploratory_attempt_after(&self, when: Instant) -> bool {
-        self.exploratory_circ_pending
-            && self.last_tried_to_connect_at.map(|t| t > when) == Some(true)
+    /// Return true if we have given out this guard more than
+    /// `params.max_lifetime_attempts` times, and should therefore retire it.
+    pub(crate) fn is_overused(&self, params: &GuardParams) -> bool {
+        let limit = params.max_lifetime_attempts;
+        limit != 0 && self.lifetime_attempts > limit
     }
 
     /// Note that a guard has been used successfully.
@@ -712,10 +843,21 @@ impl Guard {
         params: &GuardParams,
     ) -> NewlyConfirmed {
         self.retry_at = None;
+        self.retry_delay = None;
         self.retry_schedule = None;
         self.set_reachable(Reachable::Reachable);
         self.exploratory_circ_pending = false;
         self.circ_history.n_successes += 1;
+        self.last_success = Some(now);
+
+        let reachable_since = *self.reachable_since.get_or_insert(now);
+        if self.failure_streak < params.primary_demote_threshold
+            || reachable_for_at_least(reachable_since, params.primary_promote_delay, now)
+        {
+            // Either we never failed often enough to get demoted, or we have
+            // now been reachable for long enough to be trusted again.
+            self.failure_streak = 0;
+        }
 
         if self.confirmed_at.is_none() {
             self.confirmed_at = Some(
@@ -735,6 +877,23 @@ impl Guard {
         }
     }
 
+    /// Return true if this guard may currently be selected as a primary guard,
+    /// given its recent history of failures.
+    ///
+    /// A guard that has failed `primary_demote_threshold` times in a row is
+    /// demoted, and doesn't become eligible again until it has been
+    /// continuously reachable for `primary_promote_delay`.  This keeps a
+    /// flapping guard from repeatedly reshuffling our primary guards.
+    pub(crate) fn eligible_for_primary(&self, params: &GuardParams, now: SystemTime) -> bool {
+        if self.failure_streak < params.primary_demote_threshold {
+            return true;
+        }
+        match self.reachable_since {
+            Some(since) => reachable_for_at_least(since, params.primary_promote_delay, now),
+            None => false,
+        }
+    }
+
     /// Record that an external operation has succeeded on this guard.
     pub(crate) fn record_external_success(&mut self, how: ExternalActivity) {
         match how {
@@ -809,6 +968,17 @@ impl Guard {
     pub(crate) fn confirmed(&self) -> bool {
         self.confirmed_at.is_some()
     }
+
+    /// Return the time at which this guard was confirmed, if it is confirmed.
+    pub(crate) fn confirmed_at(&self) -> Option<SystemTime> {
+        self.confirmed_at
+    }
+
+    /// Return the `valid_after` time of the oldest consensus in our current
+    /// run of consensuses that haven't listed this guard, if it is unlisted.
+    pub(crate) fn unlisted_since(&self) -> Option<SystemTime> {
+        self.unlisted_since
+    }
 }
 
 impl tor_linkspec::HasAddrs for Guard {
@@ -865,6 +1035,36 @@ enum GuardDisabled {
     },
 }
 
+/// Return true if a guard that has been reachable since `since` has been
+/// reachable for at least `d` as of `now`.
+fn reachable_for_at_least(since: SystemTime, d: Duration, now: SystemTime) -> bool {
+    now.duration_since(since)
+        .map(|elapsed| elapsed >= d)
+        .unwrap_or(false)
+}
+
+/// Information about when a guard was last tried, and when it will be retried.
+///
+/// Returned by [`GuardMgr::retry_schedule`](crate::GuardMgr::retry_schedule).
+#[derive(Clone, Debug, Eq, PartialEq, amplify::Getters)]
+pub struct RetryInfo {
+    /// When we last gave out this guard in response to a request, if we have
+    /// done so since we started.
+    #[getter(as_copy)]
+    last_tried: Option<Instant>,
+    /// If the guard is believed to be unreachable, how long we decided to wait
+    /// after its latest failure before trying it again.
+    #[getter(as_copy)]
+    retry_delay: Option<Duration>,
+    /// If the guard is believed to be unreachable, when it will become
+    /// eligible to be retried.
+    ///
+    /// `None` means that the guard is not waiting for a retry: either it
+    /// hasn't failed, or it has already been marked as retriable.
+    #[getter(as_copy)]
+    next_retry: Option<Instant>,
+}
+
 /// Return a new RetryDelay tracker for a guard.
 ///
 /// `is_primary should be true if the guard is primary.
@@ -1152,6 +1352,10 @@ mod test {
         assert!(!g.is_expired(&params, now + 25 * DAY));
         assert!(!g.is_expired(&params, now + 70 * DAY));
         assert!(g.is_expired(&params, now + 200 * DAY)); // lifetime_unconfirmed.
+        assert_eq!(
+            g.expiry_reason(&params, now + 200 * DAY),
+            Some(DropReason::ExpiredUnconfirmed)
+        );
 
         let mut g = basic_guard();
         let _ = g.record_success(now, &params);
@@ -1159,12 +1363,29 @@ mod test {
         assert!(!g.is_expired(&params, now + 10 * DAY));
         assert!(!g.is_expired(&params, now + 25 * DAY));
         assert!(g.is_expired(&params, now + 70 * DAY)); // lifetime_confirmed.
+        assert_eq!(
+            g.expiry_reason(&params, now + 70 * DAY),
+            Some(DropReason::ExpiredConfirmed)
+        );
 
         let mut g = basic_guard();
         g.mark_unlisted(now);
         assert!(!g.is_expired(&params, now));
         assert!(!g.is_expired(&params, now + 10 * DAY));
         assert!(g.is_expired(&params, now + 25 * DAY)); // lifetime_unlisted
+        assert_eq!(
+            g.expiry_reason(&params, now + 25 * DAY),
+            Some(DropReason::UnlistedTooLong)
+        );
+
+        // Being relisted restarts the clock: only continuous absence counts.
+        let mut g = basic_guard();
+        g.mark_unlisted(now);
+        g.mark_listed();
+        g.mark_unlisted(now + 15 * DAY);
+        assert_eq!(g.unlisted_since(), Some(now + 15 * DAY));
+        assert!(!g.is_expired(&params, now + 25 * DAY));
+        assert!(g.is_expired(&params, now + 40 * DAY));
     }
 
     #[test]
@@ -1281,21 +1502,16 @@ mod test {
         let mut g = basic_guard();
         let t1 = Instant::now();
         let t2 = t1 + Duration::from_secs(100);
-        let t3 = t1 + Duration::from_secs(200);
 
-        assert!(!g.exploratory_attempt_after(t1));
         assert!(!g.exploratory_circ_pending());
 
         g.note_exploratory_circ(true);
         g.record_attempt(t2);
         assert!(g.exploratory_circ_pending());
-        assert!(g.exploratory_attempt_after(t1));
-        assert!(!g.exploratory_attempt_after(t3));
+        assert_eq!(g.last_tried_to_connect_at, Some(t2));
 
         g.note_exploratory_circ(false);
         assert!(!g.exploratory_circ_pending());
-        assert!(!g.exploratory_attempt_after(t1));
-        assert!(!g.exploratory_attempt_after(t3));
     }
 
     #[test]
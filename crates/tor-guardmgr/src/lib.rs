@@ -49,14 +49,18 @@
 
 use futures::channel::mpsc;
 use futures::task::SpawnExt;
+use safelog::Redacted;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, Instant, SystemTime};
-#[cfg(feature = "bridge-client")]
 use tor_error::internal;
-use tor_linkspec::{OwnedChanTarget, OwnedCircTarget, RelayId, RelayIdSet};
+use tor_linkspec::{
+    HasRelayIds as _, OwnedChanTarget, OwnedCircTarget, RelayId, RelayIdSet, RelayIds,
+};
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
 use tor_netdir::NetDirProvider;
 use tor_proto::ClockSkew;
 use tor_units::BoundedInt32;
@@ -66,7 +70,7 @@ use tor_config::{define_list_builder_accessors, define_list_builder_helper};
 use tor_config::{impl_not_auto_value, ReconfigureError};
 use tor_config::{impl_standard_builder, ExplicitOrAuto};
 use tor_netdir::{params::NetParameters, NetDir, Relay};
-use tor_persist::{DynStorageHandle, StateMgr};
+use tor_persist::{DynStorageHandle, LockStatus, StateMgr};
 use tor_rtcompat::Runtime;
 
 #[cfg(feature = "bridge-client")]
@@ -80,9 +84,12 @@ pub mod fallback;
 mod filter;
 mod guard;
 mod ids;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod pending;
 mod sample;
 mod skew;
+mod usability;
 mod util;
 #[cfg(feature = "vanguards")]
 pub mod vanguards;
@@ -98,11 +105,15 @@ pub use config::testing::TestConfig;
 use tor_async_utils::oneshot;
 
 pub use config::GuardMgrConfig;
-pub use err::{GuardMgrConfigError, GuardMgrError, PickGuardError};
+pub use err::{GuardMgrConfigError, GuardMgrError, Inconsistency, PickGuardError};
 pub use events::ClockSkewEvents;
-pub use filter::GuardFilter;
+pub use filter::{GuardFilter, Ipv6Preference};
+pub use guard::RetryInfo;
 pub use ids::FirstHopId;
+#[cfg(feature = "metrics")]
+pub use metrics::{GuardMetrics, SelectionTiming};
 pub use pending::{GuardMonitor, GuardStatus, GuardUsable};
+pub use sample::{ListKind, SampleFingerprint, SampleHealth};
 pub use skew::SkewEstimate;
 
 #[cfg(feature = "vanguards")]
@@ -111,6 +122,7 @@ pub use vanguards::VanguardMgrError;
 
 use pending::{PendingRequest, RequestId};
 use sample::{GuardSet, Universe, UniverseRef};
+use usability::decide_usability;
 
 use crate::ids::{FirstHopIdInner, GuardId};
 
@@ -161,6 +173,97 @@ struct GuardMgrInner {
     /// This is updated whenever the consensus parameters change.
     params: GuardParams,
 
+    /// A temporary replacement for [`GuardParams::dir_parallelism`], if one
+    /// has been set with [`GuardMgr::set_dir_parallelism_override`].
+    ///
+    /// Unlike `params`, this is not replaced when the consensus parameters
+    /// change.  It is cleared when we get our first usable NetDir.
+    dir_parallelism_override: Option<usize>,
+
+    /// How many more guards than [`GuardParams::data_parallelism`] we are
+    /// currently willing to try in parallel, because of recent failures.
+    ///
+    /// This only has an effect if [`GuardParams::max_adaptive_parallelism`]
+    /// is set: see [`GuardMgr::set_max_adaptive_parallelism`].
+    extra_data_parallelism: usize,
+
+    /// A callback that can veto relays as guards, if one has been set with
+    /// [`GuardMgr::set_guard_acceptor`].
+    guard_acceptor: Option<GuardAcceptor>,
+
+    /// A callback to tell about failures to save our state, if one has been
+    /// set with [`GuardMgr::on_save_error`].
+    save_error_hook: Option<SaveErrorHook>,
+
+    /// When we last invoked `save_error_hook`, if we have done so since our
+    /// last successful save.
+    last_save_error_report: Option<Instant>,
+
+    /// When we last warned about a guard that also appears later in an
+    /// intended circuit path; see [`GuardMgr::note_intended_path`].
+    last_path_overlap_warning: Option<Instant>,
+
+    /// The smallest fraction of the guards in our last accepted NetDir that a
+    /// new NetDir may list before we treat it as suspicious.
+    ///
+    /// See [`GuardMgr::set_guard_shrink_threshold`].
+    guard_shrink_threshold: f64,
+
+    /// What we know about how many guards our recent NetDirs have listed.
+    guard_count: GuardCountCheck,
+
+    /// Information about the last NetDir we used to update our parameters
+    /// and guards, if any.
+    last_netdir_info: Option<NetDirInfo>,
+
+    /// The guards that we have recently removed from our samples.
+    ///
+    /// See [`GuardMgr::recent_dropped`].
+    drop_log: DropLog,
+
+    /// How hard we should try to save power; see [`GuardMgr::set_power_mode`].
+    power_mode: PowerMode,
+
+    /// Information about the last time the host was suspended, if it has
+    /// been; see [`GuardMgr::suspend`].
+    suspension: Option<Suspension>,
+
+    /// If true, we don't add any new guards to our sample; see
+    /// [`GuardMgr::freeze_sample`].
+    sample_frozen: bool,
+
+    /// The identities of the guards to which each live
+    /// [`GuardRestrictionScope`] restricts our selections, indexed by the
+    /// scope's identifier.
+    restriction_scopes: HashMap<u64, RelayIdSet>,
+
+    /// The identifier to give the next [`GuardRestrictionScope`].
+    next_restriction_scope: u64,
+
+    /// How long `select_guard` has spent choosing guards, if we are measuring
+    /// that; see [`GuardMgr::set_selection_timing`].
+    #[cfg(feature = "metrics")]
+    selection_timing: Option<SelectionTiming>,
+
+    /// Our most recent successful guard selections, oldest first; see
+    /// [`GuardMgr::recent_selections`].
+    ///
+    /// Never holds more than `selection_log_size` entries.
+    recent_selections: VecDeque<SelectionRecord>,
+
+    /// The largest number of entries to keep in `recent_selections`; see
+    /// [`GuardMgr::set_selection_log_size`].
+    selection_log_size: usize,
+
+    /// If set, we pretend that this is when we last received incoming traffic,
+    /// instead of asking `tor_proto`.
+    ///
+    /// `tor_proto` measures that time with its own clock, which a mock runtime
+    /// can't control; this lets tests drive the internet-down heuristic
+    /// deterministically.
+    #[cfg(test)]
+    test_last_incoming_traffic: Option<Instant>,
+
     /// A mpsc channel, used to tell the task running in
     /// [`daemon::report_status_events`] about a new event to monitor.
     ///
@@ -212,6 +315,13 @@ struct GuardMgrInner {
     /// time a GuardMgr is created, there is no NetDirProvider for it to use.
     netdir_provider: Option<Weak<dyn NetDirProvider>>,
 
+    /// The latest NetDir that our caller gave us with
+    /// [`GuardMgr::update_network`], if any.
+    ///
+    /// We only use this when our `netdir_provider` can't give us a timely
+    /// NetDir (or when we don't have one).
+    supplied_netdir: Option<Arc<NetDir>>,
+
     /// A netdir provider that we can use for discovering bridge descriptors.
     ///
     /// This has to be an Option so it can be initialized from None: at the time
@@ -225,6 +335,175 @@ struct GuardMgrInner {
     configured_bridges: Option<Arc<[bridge::BridgeConfig]>>,
 }
 
+/// A record of the last time the host was suspended.
+///
+/// See [`GuardMgr::suspend`] and [`GuardMgr::resume`].
+#[derive(Clone, Debug)]
+struct Suspension {
+    /// How long we had gone without incoming traffic when we were suspended.
+    offline_at_suspend: Duration,
+    /// When we were resumed, or `None` if we are still suspended.
+    resumed_at: Option<Instant>,
+    /// True if we are holding off on expiring guards until we have a
+    /// directory that is valid at the current time.
+    awaiting_live_netdir: bool,
+}
+
+/// A scope within which every guard that a [`GuardMgr`] selects must be one of
+/// a given set.
+///
+/// Returned by [`GuardMgr::with_guard_restriction_scope`].  The restriction
+/// is lifted when this object is dropped.
+#[must_use = "The guard restriction is lifted as soon as the scope is dropped"]
+pub struct GuardRestrictionScope {
+    /// The guard manager whose selections we're restricting.
+    inner: Weak<Mutex<GuardMgrInner>>,
+    /// Our identifier within [`GuardMgrInner::restriction_scopes`].
+    id: u64,
+}
+
+impl Drop for GuardRestrictionScope {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.upgrade() {
+            let mut inner = inner.lock().expect("Poisoned lock");
+            inner.restriction_scopes.remove(&self.id);
+        }
+    }
+}
+
+/// Default value for [`GuardMgrInner::guard_shrink_threshold`].
+const DEFAULT_GUARD_SHRINK_THRESHOLD: f64 = 0.5;
+
+/// Default value for [`GuardMgrInner::selection_log_size`].
+const DEFAULT_SELECTION_LOG_SIZE: usize = 32;
+
+/// Default value for [`DropLog::size`].
+const DEFAULT_DROP_LOG_SIZE: usize = 16;
+
+/// A bounded log of the guards that we have recently removed from our samples.
+#[derive(Debug)]
+struct DropLog {
+    /// The guards that we removed, oldest first.
+    entries: VecDeque<DroppedGuard>,
+    /// The largest number of entries that we keep.
+    size: usize,
+}
+
+impl Default for DropLog {
+    fn default() -> Self {
+        DropLog {
+            entries: VecDeque::new(),
+            size: DEFAULT_DROP_LOG_SIZE,
+        }
+    }
+}
+
+impl DropLog {
+    /// Record that we removed the guard `id` from a sample at `when`, for
+    /// `reason`.
+    fn record(&mut self, when: SystemTime, id: GuardId, reason: DropReason) {
+        if self.size == 0 {
+            return;
+        }
+        if self.entries.len() == self.size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(DroppedGuard {
+            guard: id.0,
+            reason,
+            when,
+        });
+    }
+
+    /// Change the largest number of entries that we keep to `size`,
+    /// forgetting the oldest entries if we already have too many.
+    fn set_size(&mut self, size: usize) {
+        self.size = size;
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+    }
+}
+
+/// Information used to notice a NetDir that lists drastically fewer guards
+/// than the ones before it.
+///
+/// A sudden drop in the number of guards could mean that somebody is
+/// manipulating our directory information, so we don't let such a NetDir
+/// change our guard sample until a later NetDir confirms it.
+#[derive(Debug, Default)]
+struct GuardCountCheck {
+    /// The number of guard-flagged relays in the last NetDir we accepted.
+    baseline: Option<usize>,
+    /// True if the last NetDir we examined listed too few guards, and no
+    /// later NetDir has confirmed it yet.
+    suspect: bool,
+}
+
+/// How often we run periodic events in [`PowerMode::Normal`].
+const NORMAL_PERIODIC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often we run periodic events in [`PowerMode::LowPower`].
+const LOW_POWER_PERIODIC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How hard a [`GuardMgr`] should try to avoid waking up to do maintenance.
+///
+/// See [`GuardMgr::set_power_mode`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum PowerMode {
+    /// Do periodic maintenance as often as we need to.
+    #[default]
+    Normal,
+    /// Wake up rarely, and put off maintenance that isn't urgent until we get
+    /// new directory information.
+    ///
+    /// This is meant for mobile devices running on battery.
+    LowPower,
+}
+
+/// A callback that decides whether a relay may be used as a guard.
+///
+/// See [`GuardMgr::set_guard_acceptor`].
+pub type GuardAcceptor = Arc<dyn Fn(&Relay<'_>) -> bool + Send + Sync>;
+
+/// A callback to tell about a failure to save the guard manager's state.
+///
+/// See [`GuardMgr::on_save_error`].
+pub type SaveErrorHook = Arc<dyn Fn(&tor_persist::Error) + Send + Sync>;
+
+/// The shortest interval between two invocations of a [`SaveErrorHook`].
+///
+/// State is saved often, so if saving is failing persistently we'd otherwise
+/// report the same problem over and over.
+const SAVE_ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The shortest interval between two warnings from
+/// [`GuardMgr::note_intended_path`].
+const PATH_OVERLAP_WARNING_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Return the Ed25519 identities of every relay in `netdir` that could be a
+/// guard, but that `acceptor` rejects.
+fn vetoed_guards(acceptor: &GuardAcceptor, netdir: &NetDir) -> HashSet<Ed25519Identity> {
+    use tor_linkspec::HasRelayIds as _;
+    netdir
+        .relays()
+        .filter(|r| r.low_level_details().is_suitable_as_guard() && !acceptor(r))
+        .filter_map(|r| r.ed_identity().copied())
+        .collect()
+}
+
+/// Return the outcome of an attempt to save our state, first telling `hook`
+/// about it if it failed.
+fn report_save_outcome(
+    hook: Option<SaveErrorHook>,
+    outcome: Result<(), tor_persist::Error>,
+) -> Result<(), GuardMgrError> {
+    if let (Some(hook), Err(e)) = (hook, &outcome) {
+        hook(e);
+    }
+    Ok(outcome?)
+}
+
 /// A selector that tells us which [`GuardSet`] of several is currently in use.
 #[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, strum::EnumIter)]
 enum GuardSetSelector {
@@ -237,6 +516,13 @@ enum GuardSetSelector {
     /// use when we have a filter that excludes a large fraction of the guards
     /// on the network.
     Restricted,
+    /// The "directory" guard set, from which we select guards for one-hop
+    /// directory requests when those are isolated from our data guards.
+    ///
+    /// This set is never the active set: it is only used alongside the
+    /// `Default` or `Restricted` set.  See
+    /// [`GuardMgr::set_directory_guard_isolation`].
+    Directory,
     /// The "bridges" guard set is currently in use: we are selecting our guards
     /// from among the universe of configured bridges.
     #[cfg(feature = "bridge-client")]
@@ -258,7 +544,9 @@ impl GuardSetSelector {
     /// its guards from.
     fn universe_type(&self) -> UniverseType {
         match self {
-            GuardSetSelector::Default | GuardSetSelector::Restricted => UniverseType::NetDir,
+            GuardSetSelector::Default
+            | GuardSetSelector::Restricted
+            | GuardSetSelector::Directory => UniverseType::NetDir,
             #[cfg(feature = "bridge-client")]
             GuardSetSelector::Bridges => UniverseType::BridgeSet,
         }
@@ -266,7 +554,13 @@ impl GuardSetSelector {
 }
 
 /// Persistent state for a guard manager, as serialized to disk.
+///
+/// On disk, this is wrapped in an envelope that records the version of its
+/// format; see [`GUARD_STATE_VERSION`].  The derived serde implementations
+/// here (generated with `remote = "Self"`) handle only the contents of that
+/// envelope.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 struct GuardSets {
     /// Which set of guards is currently in use?
     #[serde(skip)]
@@ -282,16 +576,101 @@ struct GuardSets {
     #[serde(default)]
     restricted: GuardSet,
 
+    /// A guard set to use for one-hop directory requests, when we are
+    /// keeping those separate from our other guards.
+    #[serde(default)]
+    directory: GuardSet,
+
     /// A guard set sampled from our configured bridges.
     #[serde(default)]
     #[cfg(feature = "bridge-client")]
     bridges: GuardSet,
 
+    /// Relays that we must never use as guards, in any set.
+    ///
+    /// See [`GuardMgr::set_guard_blocklist`].
+    #[serde(default)]
+    blocklist: HashSet<Ed25519Identity>,
+
     /// Unrecognized fields, including (possibly) other guard sets.
     #[serde(flatten)]
     remaining: HashMap<String, tor_persist::JsonValue>,
 }
 
+/// The current version of the format in which we store our [`GuardSets`].
+///
+/// Whenever we make an incompatible change to that format, we should increment
+/// this, and teach [`migrate_guard_state`] how to upgrade the old format.
+///
+/// (Version 0 is the format we used before we tagged our state with a version;
+/// it was identical to version 1, but without the envelope.)
+const GUARD_STATE_VERSION: u32 = 1;
+
+/// Upgrade serialized guard state from format `version` to the current
+/// [`GUARD_STATE_VERSION`].
+///
+/// Return an error if we don't know how to interpret `version`.
+fn migrate_guard_state(
+    version: u32,
+    data: tor_persist::JsonValue,
+) -> Result<tor_persist::JsonValue, String> {
+    match version {
+        // Nothing changed but the envelope.
+        0 | GUARD_STATE_VERSION => Ok(data),
+        _ => Err(format!(
+            "Unsupported guard state version {} (newest supported is {})",
+            version, GUARD_STATE_VERSION
+        )),
+    }
+}
+
+impl Serialize for GuardSets {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct as _;
+
+        /// Helper to serialize a `GuardSets` without its envelope.
+        struct Data<'a>(&'a GuardSets);
+        impl Serialize for Data<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                GuardSets::serialize(self.0, serializer)
+            }
+        }
+
+        let mut envelope = serializer.serialize_struct("GuardSets", 2)?;
+        envelope.serialize_field("version", &GUARD_STATE_VERSION)?;
+        envelope.serialize_field("data", &Data(self))?;
+        envelope.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for GuardSets {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+        use tor_persist::JsonValue;
+
+        /// The envelope in which we store a `GuardSets`.
+        #[derive(Deserialize)]
+        struct Envelope {
+            /// The version of the format of `data`.
+            version: u32,
+            /// The serialized `GuardSets` itself.
+            data: JsonValue,
+        }
+
+        let value = JsonValue::deserialize(deserializer)?;
+        let (version, data) = if value.get("version").is_some() {
+            let Envelope { version, data } =
+                Envelope::deserialize(value).map_err(D::Error::custom)?;
+            (version, data)
+        } else {
+            // Written before we had an envelope.
+            (0, value)
+        };
+        let data = migrate_guard_state(version, data).map_err(D::Error::custom)?;
+        GuardSets::deserialize(data).map_err(D::Error::custom)
+    }
+}
+
 /// The key (filename) we use for storing our persistent guard state in the
 /// `StateMgr`.
 ///
@@ -342,6 +721,27 @@ impl<R: Runtime> GuardMgr<R> {
             filter: GuardFilter::unfiltered(),
             last_primary_retry_time: runtime.now(),
             params: GuardParams::default(),
+            dir_parallelism_override: None,
+            extra_data_parallelism: 0,
+            guard_acceptor: None,
+            save_error_hook: None,
+            last_save_error_report: None,
+            last_path_overlap_warning: None,
+            guard_shrink_threshold: DEFAULT_GUARD_SHRINK_THRESHOLD,
+            guard_count: GuardCountCheck::default(),
+            last_netdir_info: None,
+            drop_log: DropLog::default(),
+            power_mode: PowerMode::default(),
+            suspension: None,
+            sample_frozen: false,
+            restriction_scopes: HashMap::new(),
+            next_restriction_scope: 0,
+            #[cfg(feature = "metrics")]
+            selection_timing: None,
+            recent_selections: VecDeque::new(),
+            selection_log_size: DEFAULT_SELECTION_LOG_SIZE,
+            #[cfg(test)]
+            test_last_incoming_traffic: None,
             ctrl,
             pending: HashMap::new(),
             waiting: Vec::new(),
@@ -350,6 +750,7 @@ impl<R: Runtime> GuardMgr<R> {
             send_skew,
             recv_skew,
             netdir_provider: None,
+            supplied_netdir: None,
             #[cfg(feature = "bridge-client")]
             bridge_desc_provider: None,
             #[cfg(feature = "bridge-client")]
@@ -419,6 +820,24 @@ impl<R: Runtime> GuardMgr<R> {
         Ok(())
     }
 
+    /// Give this guard manager a new [`NetDir`] to use.
+    ///
+    /// This is an alternative to [`install_netdir_provider`](Self::install_netdir_provider)
+    /// for callers that manage their own directory information: we keep
+    /// `netdir` and use it (for example, in [`select_guard`](Self::select_guard))
+    /// until we are given a newer one, so callers don't need to pass it in
+    /// on every call.
+    ///
+    /// If a [`NetDirProvider`] is installed and has a timely directory, that
+    /// directory takes precedence.
+    pub fn update_network(&self, netdir: Arc<NetDir>) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.supplied_netdir = Some(netdir);
+        inner.update(wallclock, now);
+    }
+
     /// Configure a new [`bridge::BridgeDescProvider`] for this [`GuardMgr`].
     ///
     /// It will be used to learn about changes in the set of available bridge
@@ -457,13 +876,140 @@ impl<R: Runtime> GuardMgr<R> {
 
     /// Flush our current guard state to the state manager, if there
     /// is any unsaved state.
+    ///
+    /// If this fails, we also tell the [save error hook](GuardMgr::on_save_error),
+    /// if there is one.
     pub fn store_persistent_state(&self) -> Result<(), GuardMgrError> {
-        let inner = self.inner.lock().expect("Poisoned lock");
+        let mut inner = self.inner.lock().expect("Poisoned lock");
         trace!("Flushing guard state to disk.");
-        inner.storage.store(&inner.guards)?;
+        let outcome = inner.storage.store(&inner.guards);
+        let hook = inner.save_error_hook_to_call(outcome.is_ok(), self.runtime.now());
+        drop(inner);
+        report_save_outcome(hook, outcome)
+    }
+
+    /// Flush our current guard state to the state manager, and make sure that
+    /// it has reached stable storage before returning.
+    ///
+    /// Unlike [`GuardMgr::store_persistent_state`], this waits for the storage
+    /// backend to sync the state to disk.  That can take a long time (often
+    /// tens of milliseconds, and far longer on a busy or slow disk), during
+    /// which nobody else can use this `GuardMgr`.  Only call this when losing
+    /// recent guard state in a crash would be a problem.
+    ///
+    /// Returns an error if the storage backend cannot guarantee durability.
+    /// As with `store_persistent_state`, failures are also reported to the
+    /// [save error hook](GuardMgr::on_save_error).
+    pub fn flush_durable(&self) -> Result<(), GuardMgrError> {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        trace!("Flushing guard state to stable storage.");
+        let outcome = inner.storage.store_durable(&inner.guards);
+        let hook = inner.save_error_hook_to_call(outcome.is_ok(), self.runtime.now());
+        drop(inner);
+        report_save_outcome(hook, outcome)
+    }
+
+    /// Move our persistent guard state to `state_mgr`, and keep using
+    /// `state_mgr` from now on, instead of the state manager we were using
+    /// before.
+    ///
+    /// This lets an application migrate our state to a different storage
+    /// backend without restarting.  We take the lock on `state_mgr` if we
+    /// don't already hold it, and save our current state there.  Only if
+    /// that succeeds do we switch to the new storage; otherwise, we release
+    /// any lock that we took, and keep using our old storage as if nothing
+    /// had happened.
+    ///
+    /// We don't remove anything from the old storage, or release any lock
+    /// on it: that is up to the caller.
+    pub fn rebind_storage<S>(&self, state_mgr: &S) -> Result<(), GuardMgrError>
+    where
+        S: StateMgr + Send + Sync + 'static,
+    {
+        let newly_locked = state_mgr.try_lock()? == LockStatus::NewlyAcquired;
+        let storage: DynStorageHandle<GuardSets> = state_mgr.clone().create_handle(STORAGE_KEY);
+
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if let Err(e) = storage.store(&inner.guards) {
+            drop(inner);
+            if newly_locked {
+                // We're already reporting an error; this one matters less.
+                if let Err(unlock_err) = state_mgr.unlock() {
+                    warn!(
+                        "Unable to release lock on new guard storage: {}",
+                        unlock_err
+                    );
+                }
+            }
+            return Err(e.into());
+        }
+        debug!("Moved guard state to new storage.");
+        inner.storage = storage;
         Ok(())
     }
 
+    /// Check whether our stored guard state matches our guard state in memory.
+    ///
+    /// We serialize our in-memory state, load our state back from storage,
+    /// and compare the two.  This is meant for tests and diagnostics: right
+    /// after a successful [`store_persistent_state`](Self::store_persistent_state),
+    /// a mismatch means that some part of our state doesn't survive being
+    /// saved and loaded.  Between saves, a mismatch is normal, since we
+    /// change our state as we use our guards.
+    pub fn verify_persistence_consistency(&self) -> Result<(), Inconsistency> {
+        /// Helper: serialize `guards` as JSON.
+        fn to_json(guards: &GuardSets) -> Result<tor_persist::JsonValue, Inconsistency> {
+            serde_json::to_value(guards)
+                .map_err(|e| internal!("Unable to serialize guard state: {}", e).into())
+        }
+
+        let inner = self.inner.lock().expect("Poisoned lock");
+        let stored = inner
+            .storage
+            .load()
+            .map_err(Inconsistency::Storage)?
+            .ok_or(Inconsistency::NothingStored)?;
+        let in_memory = to_json(&inner.guards)?;
+        drop(inner);
+        let stored = to_json(&stored)?;
+
+        let (in_memory, stored) = (&in_memory["data"], &stored["data"]);
+        let differing: Vec<String> = match (in_memory.as_object(), stored.as_object()) {
+            (Some(m), Some(s)) => m
+                .keys()
+                .chain(s.keys().filter(|k| !m.contains_key(*k)))
+                .filter(|k| m.get(*k) != s.get(*k))
+                .cloned()
+                .collect(),
+            _ if in_memory != stored => vec!["data".into()],
+            _ => vec![],
+        };
+        if differing.is_empty() {
+            Ok(())
+        } else {
+            Err(Inconsistency::Mismatch { differing })
+        }
+    }
+
+    /// Install a callback to be told whenever we fail to save our guard state,
+    /// replacing any previous one.
+    ///
+    /// This lets the application warn the user when saving keeps failing
+    /// (for example, because the disk is full), which would otherwise only
+    /// show up as errors from [`GuardMgr::store_persistent_state`].
+    ///
+    /// To avoid repeating the same warning over and over, `hook` is called at
+    /// most once every few minutes, unless a save succeeds in between.  It
+    /// is called without any locks held, so it may use this `GuardMgr`.
+    pub fn on_save_error<F>(&self, hook: F)
+    where
+        F: Fn(&tor_persist::Error) + Send + Sync + 'static,
+    {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.save_error_hook = Some(Arc::new(hook));
+        inner.last_save_error_report = None;
+    }
+
     /// Reload state from the state manager.
     ///
     /// We only call this method if we _don't_ have the lock on the state
@@ -510,6 +1056,71 @@ impl<R: Runtime> GuardMgr<R> {
         inner.guards.active_guards_mut().mark_all_guards_retriable();
     }
 
+    /// Tell this `GuardMgr` that the host is about to be suspended.
+    ///
+    /// Until [`resume`](GuardMgr::resume) is called, we stop running our
+    /// periodic events, and we don't expire any guards.
+    ///
+    /// Call this when the host is about to sleep (for example, when a laptop
+    /// lid is closed): otherwise, when it wakes up, the jump in the clocks can
+    /// make us expire guards based on a stale directory, or conclude that the
+    /// internet has been down for the whole time we were asleep.
+    pub fn suspend(&self) {
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        let offline_at_suspend = inner
+            .time_since_last_incoming_traffic(now)
+            .unwrap_or(Duration::ZERO);
+        debug!("Suspending guard manager.");
+        inner.suspension = Some(Suspension {
+            offline_at_suspend,
+            resumed_at: None,
+            awaiting_live_netdir: true,
+        });
+    }
+
+    /// Tell this `GuardMgr` that the host has resumed after a call to
+    /// [`suspend`](GuardMgr::suspend).
+    ///
+    /// Our periodic events start running again.  We still don't expire any
+    /// guards until we have a directory that is valid at the current time,
+    /// since our old one may say nothing about which guards are listed now.
+    ///
+    /// When deciding whether the internet has been down, we don't count the
+    /// time that we spent suspended.
+    ///
+    /// Does nothing if we are not suspended.
+    pub fn resume(&self) {
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if let Some(suspension) = &mut inner.suspension {
+            if suspension.resumed_at.is_none() {
+                debug!("Resuming guard manager.");
+                suspension.resumed_at = Some(now);
+            }
+        }
+    }
+
+    /// Forget which guards we believe to be down.
+    ///
+    /// This clears every guard's "unreachable" mark, retry timers, and count
+    /// of recent failures, so that all of them are immediately eligible for use
+    /// again.  It does not change which guards are sampled or confirmed.
+    ///
+    /// Call this when the local network changes (for example, when a mobile
+    /// device moves to a different network): failures we saw on the old
+    /// network tell us little about the new one.
+    pub fn clear_reachability_state(&self) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        let inner = &mut *inner;
+        inner.guards.clear_reachability_state();
+        // Guards that had been demoted for failing may be primary again.
+        inner
+            .guards
+            .active_guards_mut()
+            .select_primary_guards(&inner.params, self.runtime.wallclock());
+    }
+
     /// Configure this guardmgr to use a fixed [`NetDir`] instead of a provider.
     ///
     /// This function is for testing only, and is exclusive with
@@ -559,85 +1170,750 @@ impl<R: Runtime> GuardMgr<R> {
     }
 
     /// Replace the current [`GuardFilter`] used by this `GuardMgr`.
+    ///
+    /// Return true if the new filter is dangerously restrictive: that is, if
+    /// even after trying to extend our sample, it permits fewer guards than
+    /// the `guard-min-filtered-sample-size` network parameter asks for.  When
+    /// this happens, we also log a warning.  (We can only tell once we have a
+    /// network directory; before then, this always returns false.)
+    ///
+    /// See also [`SampleHealth::filter_too_restrictive`].
     // TODO should this be part of the config?
-    pub fn set_filter(&self, filter: GuardFilter) {
+    pub fn set_filter(&self, filter: GuardFilter) -> bool {
         let wallclock = self.runtime.wallclock();
         let now = self.runtime.now();
         let mut inner = self.inner.lock().expect("Poisoned lock");
-        inner.set_filter(filter, wallclock, now);
+        inner.set_filter(filter, wallclock, now)
     }
 
-    /// Select a guard for a given [`GuardUsage`].
-    ///
-    /// On success, we return a [`FirstHop`] object to identify which
-    /// guard we have picked, a [`GuardMonitor`] object that the
-    /// caller can use to report whether its attempt to use the guard
-    /// succeeded or failed, and a [`GuardUsable`] future that the
-    /// caller can use to decide whether a circuit built through the
-    /// guard is actually safe to use.
+    /// Replace the set of relays that we must never use as guards.
     ///
-    /// That last point is important: It's okay to build a circuit
-    /// through the guard returned by this function, but you can't
-    /// actually use it for traffic unless the [`GuardUsable`] future
-    /// yields "true".
-    pub fn select_guard(
-        &self,
-        usage: GuardUsage,
-    ) -> Result<(FirstHop, GuardMonitor, GuardUsable), PickGuardError> {
-        let now = self.runtime.now();
+    /// Unlike a [`GuardFilter`], this applies regardless of the request, and
+    /// is stored along with the rest of our persistent guard state.  Any
+    /// listed relay that is already in a guard sample is removed from it, and
+    /// will never be added to a sample or selected as a guard again.
+    pub fn set_guard_blocklist(&self, ids: HashSet<Ed25519Identity>) {
         let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.guards.blocklist = ids;
+        inner.update(wallclock, now);
+    }
 
+    /// Stop (or resume) adding new guards to our sample.
+    ///
+    /// While the sample is frozen, we keep updating the status of the guards
+    /// that are already in it, and we keep selecting guards from it as usual;
+    /// we just never extend it, even if it has too few usable guards.  This is
+    /// useful when the latest directory is believed to be bad, and we don't
+    /// want it to influence which guards we use.
+    ///
+    /// Unlike [`set_guard_blocklist`](GuardMgr::set_guard_blocklist), this is
+    /// not stored persistently.
+    pub fn freeze_sample(&self, frozen: bool) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
         let mut inner = self.inner.lock().expect("Poisoned lock");
+        if inner.sample_frozen != frozen {
+            debug!(
+                "{} guard sample.",
+                if frozen { "Freezing" } else { "Unfreezing" }
+            );
+            inner.sample_frozen = frozen;
+            inner.update(wallclock, now);
+        }
+    }
 
-        // (I am not 100% sure that we need to consider_all_retries here, but
-        // it should _probably_ not hurt.)
-        inner.guards.active_guards_mut().consider_all_retries(now);
+    /// Restrict every guard that we select to the guards in `ids`, until the
+    /// returned [`GuardRestrictionScope`] is dropped.
+    ///
+    /// This is meant for sensitive operations that should only use a
+    /// hand-picked subset of our guards (usually, some of our confirmed
+    /// guards) for as long as they last.  While the scope is held,
+    /// [`select_guard`](GuardMgr::select_guard) behaves as if every
+    /// [`GuardUsage`] had a [`GuardRestriction::RequireAnyId`] restriction
+    /// for the identities in `ids`: if none of those guards is usable, it
+    /// fails, except that directory requests may still use a fallback
+    /// directory.
+    ///
+    /// If several scopes are held at once, a guard must be permitted by all
+    /// of them.
+    pub fn with_guard_restriction_scope(&self, ids: &[RelayIds]) -> GuardRestrictionScope {
+        let ids: RelayIdSet = ids
+            .iter()
+            .flat_map(|ids| ids.identities().map(|id| id.to_owned()))
+            .collect();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        let id = inner.next_restriction_scope;
+        inner.next_restriction_scope += 1;
+        inner.restriction_scopes.insert(id, ids);
+        GuardRestrictionScope {
+            inner: Arc::downgrade(&self.inner),
+            id,
+        }
+    }
 
-        let (origin, guard) = inner.select_guard_with_expand(&usage, now, wallclock)?;
-        trace!(?guard, ?usage, "Guard selected");
+    /// Start (or stop) selecting guards for one-hop directory requests from a
+    /// separate sample than the one we use for data circuits.
+    ///
+    /// When this is enabled, the directory sample uses the same filter and
+    /// blocklist as our other guards, but it never includes any guard that
+    /// is in the data sample.  That way, an adversary who can observe our
+    /// directory fetches learns less about which guards we use for our
+    /// circuits.  This has no effect while we are using bridges.
+    ///
+    /// The default is false (a single shared sample).
+    pub fn set_directory_guard_isolation(&self, isolate: bool) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if inner.params.isolate_directory_guards != isolate {
+            inner.params.isolate_directory_guards = isolate;
+            inner.update(wallclock, now);
+        }
+    }
 
-        let (usable, usable_sender) = if origin.usable_immediately() {
-            (GuardUsable::new_usable_immediately(), None)
+    /// Turn test-network mode on or off.
+    ///
+    /// This mode is **only** for small private test networks (such as the
+    /// ones that `chutney` builds), where our usual guard parameters make
+    /// guard selection behave oddly.  While it is enabled, we use a much
+    /// smaller minimum sample size, drop the limit on how much of the
+    /// network's guard bandwidth we may sample, keep guards in the sample for
+    /// a much shorter time, and let a circuit through any guard be used as
+    /// soon as it succeeds, without waiting to see whether a guard we'd
+    /// rather use comes up.
+    ///
+    /// Never enable this on the real Tor network: it gives up the protection
+    /// that our guard parameters are there to provide.
+    ///
+    /// The default is false.
+    pub fn set_test_network_mode(&self, enabled: bool) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if inner.params.test_network == enabled {
+            return;
+        }
+        if enabled {
+            inner.params.test_network = true;
+            inner.params.apply_test_network_overrides();
         } else {
-            let (u, snd) = GuardUsable::new_uncertain();
-            (u, Some(snd))
-        };
-        let request_id = pending::RequestId::next();
-        let ctrl = inner.ctrl.clone();
-        let monitor = GuardMonitor::new(request_id, ctrl);
-
-        // Note that the network can be down even if all the primary guards
-        // are not yet marked as unreachable.  But according to guard-spec we
-        // don't want to acknowledge the net as down before that point, since
-        // we don't mark all the primary guards as retriable unless
-        // we've been forced to non-primary guards.
-        let net_has_been_down =
-            if let Some(duration) = tor_proto::time_since_last_incoming_traffic() {
-                inner
-                    .guards
-                    .active_guards_mut()
-                    .all_primary_guards_are_unreachable()
-                    && duration >= inner.params.internet_down_timeout
-            } else {
-                // TODO: Is this the correct behavior in this case?
-                false
+            // Go back to the defaults, until the next netdir tells us
+            // otherwise.
+            let p = &inner.params;
+            inner.params = GuardParams {
+                max_lifetime_attempts: p.max_lifetime_attempts,
+                max_adaptive_parallelism: p.max_adaptive_parallelism,
+                isolate_directory_guards: p.isolate_directory_guards,
+                max_guardset_age: p.max_guardset_age,
+                ..GuardParams::default()
             };
+        }
+        inner.update(wallclock, now);
+    }
 
-        let pending_request = pending::PendingRequest::new(
-            guard.first_hop_id(),
-            usage,
-            usable_sender,
-            net_has_been_down,
-        );
-        inner.pending.insert(request_id, pending_request);
+    /// Return the identities of the current primary guards, from most to least
+    /// preferred.
+    ///
+    /// The order is fully determined by our (persistent) guard state: first
+    /// come the confirmed guards, in the order in which we confirmed them;
+    /// then any guards that were already primary, in their previous order;
+    /// and then the other guards in the sample, in the order in which we
+    /// added them.  Guards that are unlisted, that our filter does not permit,
+    /// or that have recently failed too many times in a row are skipped.
+    ///
+    /// So two `GuardMgr`s with the same state and configuration will report
+    /// the same primary guards, in the same order.
+    pub fn primary_guards(&self) -> Vec<RelayIds> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .primary_guard_ids()
+            .iter()
+            .map(|id| id.0.clone())
+            .collect()
+    }
+
+    /// Attach a note to the guard in the active sample with every identity in
+    /// `id`, replacing any previous note.  An empty `note` removes it.
+    ///
+    /// Notes are stored along with the rest of our persistent guard state,
+    /// and reported by [`guard_notes`](GuardMgr::guard_notes).  They are
+    /// purely informational: we never use them when selecting guards.
+    ///
+    /// Return false if there is no such guard in the active sample.
+    pub fn set_guard_note(&self, id: &RelayIds, note: String) -> bool {
+        let note = (!note.is_empty()).then_some(note);
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards_mut()
+            .set_guard_note(&GuardId(id.clone()), note)
+    }
+
+    /// Return information about when we last tried the guard in the active
+    /// sample with every identity in `id`, and about when we will retry it.
+    ///
+    /// This is meant for debugging: it explains why we aren't using a guard
+    /// that has failed.  Guards are only retried once the time in
+    /// [`RetryInfo::next_retry`] has passed, unless something (such as
+    /// learning that the network was down) makes us retry them sooner.
+    ///
+    /// Return `None` if there is no such guard in the active sample.
+    pub fn retry_schedule(&self, id: &RelayIds) -> Option<RetryInfo> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .get(&GuardId(id.clone()))
+            .map(|guard| guard.retry_info())
+    }
+
+    /// Return the time since which the guard in the active sample with every
+    /// identity in `id` has been missing from the directory, if it is missing.
+    ///
+    /// This is meant for debugging.  A guard that stops being listed (because
+    /// it is absent from the consensus, unusable, or lacks the Guard flag) is
+    /// only treated as temporarily unlisted: we keep it in the sample, and
+    /// only remove it once it has been unlisted continuously for the
+    /// `guard-remove-unlisted-guards-after-days` consensus parameter.  If it
+    /// is listed again before then, this returns to `None` and the clock
+    /// starts over.
+    ///
+    /// The returned time is the `valid_after` time of the first consensus
+    /// that didn't list the guard.
+    ///
+    /// Return `None` if the guard is currently listed, or if there is no such
+    /// guard in the active sample.
+    pub fn unlisted_since(&self, id: &RelayIds) -> Option<SystemTime> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .get(&GuardId(id.clone()))
+            .and_then(|guard| guard.unlisted_since())
+    }
+
+    /// Note that the guard with every identity in `id` has served us valid
+    /// directory information.
+    ///
+    /// The directory manager should call this once it has checked a document
+    /// that it fetched through the guard; merely completing a circuit to the
+    /// guard isn't enough.
+    ///
+    /// Whenever we would choose at random among several primary guards for a
+    /// directory request (as we do when the
+    /// `guard-n-primary-dir-guards-to-use` consensus parameter is more than
+    /// one), we prefer the ones that have done this within the last day.  We
+    /// never prefer such a guard over one that ranks above it.  This
+    /// information is not persistent.
+    pub fn note_dir_success(&self, id: &RelayIds) {
+        let wallclock = self.runtime.wallclock();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .note_dir_success(&GuardId(id.clone()), wallclock);
+    }
+
+    /// Return the time at which the guard in the active sample with every
+    /// identity in `id` most recently served us valid directory information,
+    /// according to [`note_dir_success`](GuardMgr::note_dir_success).
+    ///
+    /// This is meant for debugging.
+    ///
+    /// Return `None` if it hasn't done so since we started, or if there is
+    /// no such guard in the active sample.
+    pub fn last_dir_success(&self, id: &RelayIds) -> Option<SystemTime> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .get(&GuardId(id.clone()))
+            .and_then(|guard| guard.last_dir_success())
+    }
+
+    /// Note that we currently have live circuits through the guard with every
+    /// identity in `id`.
+    ///
+    /// Whenever we would choose at random among several primary guards (as we
+    /// do when the `guard-n-primary-guards-to-use` or
+    /// `guard-n-primary-dir-guards-to-use` consensus parameter is more than
+    /// one), we prefer the ones that are in use.  We never prefer an in-use guard over a guard that
+    /// ranks above it, and we never choose a non-primary guard just because
+    /// it is in use.
+    ///
+    /// This tends to reduce the number of distinct guards that we expose our
+    /// traffic to over a session, and so the chance that one of them is
+    /// hostile.  The cost is that more of our circuits share a guard, which
+    /// makes them a little easier for that guard to link together, and makes
+    /// us a little more dependent on it staying up.
+    ///
+    /// The circuit manager should call [`note_guard_idle`](GuardMgr::note_guard_idle)
+    /// once its last circuit through the guard has closed.  This information
+    /// is not persistent.
+    pub fn note_guard_in_use(&self, id: &RelayIds) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.guards.set_in_use(&GuardId(id.clone()), true);
+    }
+
+    /// Note that we no longer have any live circuits through the guard with
+    /// every identity in `id`.
+    ///
+    /// See [`note_guard_in_use`](GuardMgr::note_guard_in_use).
+    pub fn note_guard_idle(&self, id: &RelayIds) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.guards.set_in_use(&GuardId(id.clone()), false);
+    }
+
+    /// Tell this `GuardMgr` about a circuit path that the caller intends to
+    /// build, starting with the guard that we selected for it.
+    ///
+    /// Returns true if the guard (that is, the first hop of `path`) shares
+    /// an identity with any later hop.  That should never happen: the caller
+    /// should have chosen the rest of the path to avoid the guard, or asked
+    /// us to avoid its other relays with [`GuardRestriction::AvoidId`].  If
+    /// it keeps happening, something is misconfigured, so we log a warning,
+    /// at most once every few minutes.
+    ///
+    /// This is purely advisory: it doesn't change how we choose guards.
+    pub fn note_intended_path(&self, path: &[RelayIds]) -> bool {
+        use tor_linkspec::HasRelayIds as _;
+
+        let Some((guard, rest)) = path.split_first() else {
+            return false;
+        };
+        let Some(idx) = rest.iter().position(|hop| hop.has_any_relay_id_from(guard)) else {
+            return false;
+        };
+
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if inner
+            .last_path_overlap_warning
+            .is_some_and(|last| now.saturating_duration_since(last) < PATH_OVERLAP_WARNING_INTERVAL)
+        {
+            return true;
+        }
+        inner.last_path_overlap_warning = Some(now);
+        drop(inner);
+
+        warn!(
+            "Guard {} is also hop {} of an intended circuit path. This is probably a bug or a misconfiguration.",
+            Redacted::new(guard),
+            idx + 2
+        );
+        true
+    }
+
+    /// Return the identity and note of every guard in the active sample that
+    /// has a note, in the order in which they were added to the sample.
+    ///
+    /// See [`set_guard_note`](GuardMgr::set_guard_note).
+    pub fn guard_notes(&self) -> Vec<(RelayIds, String)> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .guard_notes()
+            .map(|(id, note)| (id.0.clone(), note.to_owned()))
+            .collect()
+    }
+
+    /// Return the identities of the guards in the active sample that our
+    /// current [`GuardFilter`] doesn't permit, in the order in which they were
+    /// added to the sample.
+    ///
+    /// We keep such guards in the sample (so that we can go back to them if the
+    /// filter changes again), but we won't select them while the filter is in
+    /// place.  This can explain why a guard that we used before is no longer
+    /// being selected.
+    pub fn filtered_out_guards(&self) -> Vec<RelayIds> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .guards
+            .active_guards()
+            .filtered_out_guard_ids()
+            .map(|id| id.0.clone())
+            .collect()
+    }
+
+    /// Return a snapshot of how many guards in the active sample are available
+    /// for use.
+    ///
+    /// This can be used to warn the user before guard selection starts
+    /// failing because too few usable guards remain.
+    pub fn sample_health(&self) -> SampleHealth {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.guards.active_guards().sample_health(&inner.params)
+    }
+
+    /// Return a snapshot of metrics about our guards and pending requests.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> GuardMetrics {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        GuardMetrics::from_inner(&inner)
+    }
+
+    /// Start (or stop) measuring how long [`select_guard`](GuardMgr::select_guard)
+    /// spends choosing a guard while it holds our lock.
+    ///
+    /// This is off by default.  While it is on, the measurements are reported
+    /// in [`GuardMetrics::selection_timing`].  Turning it on again after
+    /// turning it off discards the old measurements.
+    #[cfg(feature = "metrics")]
+    pub fn set_selection_timing(&self, enabled: bool) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        match (enabled, &inner.selection_timing) {
+            (true, None) => inner.selection_timing = Some(SelectionTiming::default()),
+            (false, _) => inner.selection_timing = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// Return a snapshot of metrics about our guards and pending requests,
+    /// in the Prometheus text exposition format.
+    ///
+    /// See [`GuardMetrics::to_prometheus`].
+    #[cfg(feature = "metrics")]
+    pub fn metrics_prometheus(&self) -> String {
+        self.metrics().to_prometheus()
+    }
+
+    /// Return a [`SampleFingerprint`] identifying the set of confirmed guards
+    /// in the active sample.
+    ///
+    /// This is meant for logging: two log messages with the same fingerprint
+    /// were written while we had the same confirmed guards, even if we
+    /// restarted in between.
+    pub fn sample_fingerprint(&self) -> SampleFingerprint {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.guards.active_guards().fingerprint()
+    }
+
+    /// Return a snapshot of the parameters that this `GuardMgr` is currently
+    /// using, with any runtime overrides applied.
+    ///
+    /// This is meant for diagnostic purposes.
+    pub fn effective_params(&self) -> GuardParamsView {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        GuardParamsView::from(inner.effective_params().as_ref())
+    }
+
+    /// Return information about the last network directory that this
+    /// `GuardMgr` used to update its parameters and guards, or `None` if it
+    /// hasn't used one yet.
+    ///
+    /// This is meant for diagnostic purposes, such as noticing that we're
+    /// working from a stale consensus.
+    pub fn last_netdir_info(&self) -> Option<NetDirInfo> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.last_netdir_info.clone()
+    }
+
+    /// Return the state of every request that is currently waiting to learn
+    /// whether the circuit built for it may be used.
+    ///
+    /// A request waits when its circuit succeeded, but through a guard that
+    /// we don't yet know to be the best one available.  For each such request,
+    /// we report the answer we would give it now, if any.
+    ///
+    /// This is meant for debugging and diagnostic purposes.
+    pub fn waiting_request_states(&self) -> Vec<WaitingState> {
+        let now = self.runtime.now();
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner
+            .waiting
+            .iter()
+            .map(|pending| WaitingState {
+                guard: pending.guard_id().clone(),
+                waiting_for: pending
+                    .waiting_since()
+                    .and_then(|w| now.checked_duration_since(w))
+                    .unwrap_or_default(),
+                usable: inner.guard_usability_status(pending, now),
+            })
+            .collect()
+    }
+
+    /// Return the guards that we have most recently removed from our guard
+    /// samples, oldest first, along with when and why we removed them.
+    ///
+    /// We only remember a few guards: see [`GuardMgr::set_drop_log_size`].
+    ///
+    /// This is meant for debugging and diagnostic purposes.
+    pub fn recent_dropped(&self) -> Vec<DroppedGuard> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.drop_log.entries.iter().cloned().collect()
+    }
+
+    /// Change how many removed guards we remember for
+    /// [`GuardMgr::recent_dropped`], forgetting the oldest ones if we already
+    /// remember more than `n`.
+    ///
+    /// A size of 0 turns off the log.  The default is 16.
+    pub fn set_drop_log_size(&self, n: usize) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.drop_log.set_size(n);
+    }
+
+    /// Install a callback to decide whether each relay may be used as a guard,
+    /// replacing any previous one.
+    ///
+    /// Relays for which `acceptor` returns false are never added to a guard
+    /// sample, and never selected as guards.  Unlike the relays on the
+    /// [blocklist](GuardMgr::set_guard_blocklist), they are not removed from
+    /// our sample, so they can be used again if `acceptor` changes its mind.
+    ///
+    /// We consult `acceptor` about every relay that could be a guard whenever
+    /// we get a new directory, so it needs to be cheap.  Since it only sees
+    /// relays listed in the directory, it doesn't apply to bridges.
+    pub fn set_guard_acceptor(&self, acceptor: GuardAcceptor) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.guard_acceptor = Some(acceptor);
+        inner.update(wallclock, now);
+    }
+
+    /// Set the smallest fraction of guards that a new directory may list,
+    /// relative to the last directory we accepted, before we treat it as
+    /// suspicious.
+    ///
+    /// When we get a directory that lists fewer guard-flagged relays than
+    /// this, we log a warning, and we don't use it to mark guards as unlisted
+    /// or to add new guards to our sample, until a subsequent directory
+    /// confirms the drop.
+    ///
+    /// The default is 0.5.  A `fraction` of 0 disables this check.
+    pub fn set_guard_shrink_threshold(&self, fraction: f64) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.guard_shrink_threshold = fraction.clamp(0.0, 1.0);
+    }
+
+    /// Change how hard this `GuardMgr` tries to save power.
+    ///
+    /// In [`PowerMode::LowPower`], we run our periodic events far less often,
+    /// and during them we only do what's needed to answer pending guard
+    /// requests.  Other maintenance, such as expiring old guards and expanding
+    /// the guard sample, waits until we get new directory information, or
+    /// until a request can't be satisfied without it.  This means that we can
+    /// take longer to notice that a circuit is usable.
+    ///
+    /// [`PowerMode::Normal`] restores the default behavior.  A change of mode
+    /// takes effect once the current periodic interval has elapsed.
+    pub fn set_power_mode(&self, mode: PowerMode) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.power_mode = mode;
+    }
+
+    /// Retire each guard once we have given it out more than `n` times, or
+    /// remove that limit if `n` is 0.
+    ///
+    /// Every guard learns something about our activity each time we use it.
+    /// With this limit in place, once a guard has been given out more than `n`
+    /// times over its lifetime (counting across restarts), we remove it from
+    /// our sample, and sample a fresh guard to take its place.
+    ///
+    /// The default is 0 (no limit).
+    pub fn set_max_lifetime_attempts(&self, n: u32) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.params.max_lifetime_attempts = n;
+    }
+
+    /// Limit how long we keep the same set of confirmed guards, or remove the
+    /// limit if `age` is `None`.
+    ///
+    /// Every guard we use learns something about us, for as long as we use
+    /// it.  With this limit in place, once the guard that we confirmed
+    /// longest ago has been confirmed for more than `age`, we remove it from
+    /// our sample.  We do this gradually, removing at most one guard per day,
+    /// so that our whole set of guards doesn't change at once; this keeps an
+    /// observer from easily linking our old guards to our new ones.
+    ///
+    /// The default is `None` (no limit beyond the usual guard lifetimes).
+    pub fn set_max_guardset_age(&self, age: Option<Duration>) {
+        let wallclock = self.runtime.wallclock();
+        let now = self.runtime.now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.params.max_guardset_age = age;
+        inner.update(wallclock, now);
+    }
+
+    /// Let the number of primary guards that a data circuit may try in
+    /// parallel adapt to how reliable our guards have been, up to `max`; or
+    /// turn off this behavior if `max` is 0.
+    ///
+    /// When this is enabled, every failed attempt to use a guard lets us try
+    /// one more guard in parallel, and every successful attempt lets us try
+    /// one fewer.  We never try fewer guards than the consensus parameter
+    /// says, or more than `max`.
+    ///
+    /// The default is 0 (disabled).
+    pub fn set_max_adaptive_parallelism(&self, max: usize) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.params.max_adaptive_parallelism = max;
+    }
+
+    /// Override the number of primary guards that a one-hop directory request
+    /// may try in parallel, or clear a previous override if `n` is `None`.
+    ///
+    /// This is intended for use while bootstrapping, when we want to fetch
+    /// directory information faster than the consensus parameter would
+    /// otherwise allow.  The override is cleared automatically once we have
+    /// our first usable [`NetDir`], so that the consensus value is used again;
+    /// an override set after that lasts until it is cleared explicitly.
+    ///
+    /// An override of 0 is treated as 1.
+    pub fn set_dir_parallelism_override(&self, n: Option<usize>) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.dir_parallelism_override = n.map(|n| n.max(1));
+    }
+
+    /// Set the number of recent guard selections that we remember for
+    /// [`GuardMgr::recent_selections`], discarding the oldest ones if we
+    /// already remember more than `n`.
+    ///
+    /// A size of 0 turns off the log.  The default is 32.
+    pub fn set_selection_log_size(&self, n: usize) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.selection_log_size = n;
+        let excess = inner.recent_selections.len().saturating_sub(n);
+        inner.recent_selections.drain(..excess);
+    }
+
+    /// Return a record of our most recent successful calls to
+    /// [`select_guard`](GuardMgr::select_guard), oldest first.
+    ///
+    /// We only remember a bounded number of selections (see
+    /// [`GuardMgr::set_selection_log_size`]), and we only remember the
+    /// identities of the guards we chose in [`Redacted`] form.
+    ///
+    /// This is meant for debugging.
+    pub fn recent_selections(&self) -> Vec<SelectionRecord> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.recent_selections.iter().cloned().collect()
+    }
+
+    /// Return the identities of every guard that
+    /// [`select_guard`](GuardMgr::select_guard) could currently pick for
+    /// `usage`, in the order in which it would try them, along with the list
+    /// each guard came from.
+    ///
+    /// When `select_guard` has several equally good primary guards to choose
+    /// from, it picks one at random; in that case, it may pick a guard other
+    /// than the first one listed here.
+    ///
+    /// This function does not select a guard or change our state.  It does
+    /// not list fallback directories.
+    pub fn selection_order(&self, usage: &GuardUsage) -> Vec<(RelayIds, ListKind)> {
+        let now = self.runtime.now();
+        let inner = self.inner.lock().expect("Poisoned lock");
+        let usage = inner.apply_restriction_scopes(usage.clone());
+        let params = inner.effective_params();
+        let selector = inner.guard_set_for(&usage.kind);
+        inner
+            .guards
+            .guards(&selector)
+            .selection_order(&usage, &params, now)
+            .into_iter()
+            .map(|(src, id)| (id.0, src))
+            .collect()
+    }
+
+    /// Select a guard for a given [`GuardUsage`].
+    ///
+    /// On success, we return a [`FirstHop`] object to identify which
+    /// guard we have picked, a [`GuardMonitor`] object that the
+    /// caller can use to report whether its attempt to use the guard
+    /// succeeded or failed, and a [`GuardUsable`] future that the
+    /// caller can use to decide whether a circuit built through the
+    /// guard is actually safe to use.
+    ///
+    /// That last point is important: It's okay to build a circuit
+    /// through the guard returned by this function, but you can't
+    /// actually use it for traffic unless the [`GuardUsable`] future
+    /// yields "true".
+    pub fn select_guard(
+        &self,
+        usage: GuardUsage,
+    ) -> Result<(FirstHop, GuardMonitor, GuardUsable), PickGuardError> {
+        let now = self.runtime.now();
+        let wallclock = self.runtime.wallclock();
+
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        #[cfg(feature = "metrics")]
+        let locked_at = inner.selection_timing.is_some().then(|| self.runtime.now());
+        let usage = inner.apply_restriction_scopes(usage);
+
+        // (I am not 100% sure that we need to consider_all_retries here, but
+        // it should _probably_ not hurt.)
+        let selector = inner.guard_set_for(&usage.kind);
+        inner.guards.guards_mut(&selector).consider_all_retries(now);
+
+        let selected = inner.select_guard_with_expand(&usage, now, wallclock);
+        #[cfg(feature = "metrics")]
+        if let (Some(timing), Some(locked_at)) = (&mut inner.selection_timing, locked_at) {
+            timing.record(self.runtime.now().saturating_duration_since(locked_at));
+        }
+        let (origin, guard) = selected?;
+        trace!(?guard, ?usage, "Guard selected");
+        inner.note_selection(SelectionRecord {
+            when: wallclock,
+            usage: usage.kind.clone(),
+            guard: Redacted::new(RelayIds::from_relay_ids(&guard)),
+            origin,
+        });
+
+        let (usable, usable_sender) = if origin.usable_immediately() {
+            (GuardUsable::new_usable_immediately(), None)
+        } else {
+            let (u, snd) = GuardUsable::new_uncertain();
+            (u, Some(snd))
+        };
+        let request_id = pending::RequestId::next();
+        let ctrl = inner.ctrl.clone();
+        let monitor = GuardMonitor::new(request_id, ctrl);
+
+        // Note that the network can be down even if all the primary guards
+        // are not yet marked as unreachable.  But according to guard-spec we
+        // don't want to acknowledge the net as down before that point, since
+        // we don't mark all the primary guards as retriable unless
+        // we've been forced to non-primary guards.
+        let net_has_been_down = if let Some(duration) = inner.time_since_last_incoming_traffic(now)
+        {
+            inner
+                .guards
+                .active_guards_mut()
+                .all_primary_guards_are_unreachable()
+                && inner.time_offline(duration, now) >= inner.params.internet_down_timeout
+        } else {
+            // TODO: Is this the correct behavior in this case?
+            false
+        };
+
+        let pending_request = pending::PendingRequest::new(
+            guard.first_hop_id(),
+            usage,
+            usable_sender,
+            net_has_been_down,
+            now,
+        );
+        inner.pending.insert(request_id, pending_request);
 
         match &guard.sample {
             Some(sample) => {
                 let guard_id = GuardId::from_relay_ids(&guard);
-                inner
-                    .guards
-                    .guards_mut(sample)
-                    .record_attempt(&guard_id, now);
+                let inner = &mut *inner;
+                let retired =
+                    inner
+                        .guards
+                        .guards_mut(sample)
+                        .record_attempt(&guard_id, &inner.params, now);
+                if retired {
+                    inner.note_guard_retired(guard_id, wallclock, now);
+                }
             }
             None => {
                 // We don't record attempts for fallbacks; we only care when
@@ -648,6 +1924,37 @@ impl<R: Runtime> GuardMgr<R> {
         Ok((guard, monitor, usable))
     }
 
+    /// Pick a directory cache from `netdir` for fetching our initial directory
+    /// information, when we have never used a guard.
+    ///
+    /// Unlike [`select_guard`](GuardMgr::select_guard), this can return any
+    /// relay in `netdir` that is a directory cache (weighted by bandwidth), and
+    /// it does not change our guard state at all: the relay is not added to
+    /// our sample, and we don't track whether it succeeds.  We never return a
+    /// relay that is already in our sample, that our filter does not permit,
+    /// or that is on our guard blocklist.
+    ///
+    /// This is meant only for the initial consensus fetch; once we have a
+    /// usable directory, use `select_guard` instead.
+    pub fn select_bootstrap_dir_guard(&self, netdir: &NetDir) -> Result<RelayIds, PickGuardError> {
+        use tor_linkspec::HasRelayIds as _;
+        use tor_netdir::WeightRole;
+
+        let inner = self.inner.lock().expect("Poisoned lock");
+        let sample = inner.guards.active_guards();
+        let relay = netdir
+            .pick_relay(&mut rand::thread_rng(), WeightRole::BeginDir, |r| {
+                r.low_level_details().is_dir_cache()
+                    && inner.filter.permits(r)
+                    && !r
+                        .ed_identity()
+                        .is_some_and(|id| inner.guards.blocklist.contains(id))
+                    && sample.contains(&GuardId::from_relay_ids(r)) == Ok(false)
+            })
+            .ok_or(PickGuardError::NoCandidatesAvailable)?;
+        Ok(RelayIds::from_relay_ids(&relay))
+    }
+
     /// Record that _after_ we built a circuit with a guard, something described
     /// in `external_failure` went wrong with it.
     pub fn note_external_failure<T>(&self, identity: &T, external_failure: ExternalActivity)
@@ -682,7 +1989,12 @@ impl<R: Runtime> GuardMgr<R> {
     {
         let mut inner = self.inner.lock().expect("Poisoned lock");
 
-        inner.record_external_success(identity, external_activity, self.runtime.wallclock());
+        inner.record_external_success(
+            identity,
+            external_activity,
+            self.runtime.wallclock(),
+            self.runtime.now(),
+        );
     }
 
     /// Return a stream of events about our estimated clock skew; these events
@@ -739,6 +2051,7 @@ impl GuardSets {
         match selector {
             GuardSetSelector::Default => &self.default,
             GuardSetSelector::Restricted => &self.restricted,
+            GuardSetSelector::Directory => &self.directory,
             #[cfg(feature = "bridge-client")]
             GuardSetSelector::Bridges => &self.bridges,
         }
@@ -755,17 +2068,44 @@ impl GuardSets {
         match selector {
             GuardSetSelector::Default => &mut self.default,
             GuardSetSelector::Restricted => &mut self.restricted,
+            GuardSetSelector::Directory => &mut self.directory,
             #[cfg(feature = "bridge-client")]
             GuardSetSelector::Bridges => &mut self.bridges,
         }
     }
 
-    /// Update all non-persistent state for the guards in this object with the
-    /// state in `other`.
-    fn copy_status_from(&mut self, mut other: GuardSets) {
+    /// Clear the transient reachability state of the guards in every set.
+    fn clear_reachability_state(&mut self) {
         use strum::IntoEnumIterator;
         for sample in GuardSetSelector::iter() {
-            self.guards_mut(&sample)
+            self.guards_mut(&sample).clear_reachability_state();
+        }
+    }
+
+    /// Record, in every set, that the guard with every identity in `id` served
+    /// us valid directory information at `now`.
+    fn note_dir_success(&mut self, id: &GuardId, now: SystemTime) {
+        use strum::IntoEnumIterator;
+        for sample in GuardSetSelector::iter() {
+            self.guards_mut(&sample).note_dir_success(id, now);
+        }
+    }
+
+    /// Record, in every set, whether we currently have live circuits through
+    /// the guard with every identity in `id`.
+    fn set_in_use(&mut self, id: &GuardId, in_use: bool) {
+        use strum::IntoEnumIterator;
+        for sample in GuardSetSelector::iter() {
+            self.guards_mut(&sample).set_in_use(id, in_use);
+        }
+    }
+
+    /// Update all non-persistent state for the guards in this object with the
+    /// state in `other`.
+    fn copy_status_from(&mut self, mut other: GuardSets) {
+        use strum::IntoEnumIterator;
+        for sample in GuardSetSelector::iter() {
+            self.guards_mut(&sample)
                 .copy_ephemeral_status_into_newly_loaded_state(std::mem::take(
                     other.guards_mut(&sample),
                 ));
@@ -776,12 +2116,26 @@ impl GuardSets {
 
 impl GuardMgrInner {
     /// Look up the latest [`NetDir`] (if there is one) from our
-    /// [`NetDirProvider`] (if we have one).
+    /// [`NetDirProvider`] (if we have one), or else return the one given to
+    /// [`GuardMgr::update_network`] (if any).
     fn timely_netdir(&self) -> Option<Arc<NetDir>> {
         self.netdir_provider
             .as_ref()
             .and_then(Weak::upgrade)
             .and_then(|np| np.timely_netdir().ok())
+            .or_else(|| self.supplied_netdir.clone())
+    }
+
+    /// Remember that we chose a guard, as described in `record`, forgetting
+    /// our oldest selection if we would otherwise remember too many.
+    fn note_selection(&mut self, record: SelectionRecord) {
+        if self.selection_log_size == 0 {
+            return;
+        }
+        while self.recent_selections.len() >= self.selection_log_size {
+            self.recent_selections.pop_front();
+        }
+        self.recent_selections.push_back(record);
     }
 
     /// Look up the latest [`BridgeDescList`](bridge::BridgeDescList) (if there
@@ -857,6 +2211,14 @@ impl GuardMgrInner {
         }
     }
 
+    /// Note that we have retired the guard `id` from its sample, because we
+    /// had given it out too many times; then sample a replacement, and choose
+    /// new primary guards.
+    fn note_guard_retired(&mut self, id: GuardId, wallclock: SystemTime, now: Instant) {
+        self.drop_log.record(wallclock, id, DropReason::Retired);
+        self.update(wallclock, now);
+    }
+
     /// Update the status of all guards in the active set, based on the passage
     /// of time, our configuration, and the relevant Universe for our active
     /// set.
@@ -869,23 +2231,30 @@ impl GuardMgrInner {
             // This uses a NetDir unconditionally, since we always want to take
             // the network parameters our parameters from the consensus even if
             // the guards themselves are from a BridgeSet.
-            this.update_active_set_params_and_filter(netdir);
+            this.update_active_set_params_and_filter(netdir, wallclock);
         });
         self.with_opt_universe(|this, univ| {
             // Now we update the set of guards themselves based on the
             // Universe, which is either the latest NetDir, or the latest
             // BridgeSet—depending on what the GuardSet wants.
+            let expire = !this.guard_expiry_deferred(wallclock);
             Self::update_guardset_internal(
                 &this.params,
                 wallclock,
+                now,
                 this.guards.active_set.universe_type(),
+                this.guard_count.suspect,
+                this.sample_frozen,
+                expire,
                 this.guards.active_guards_mut(),
+                &mut this.drop_log,
                 univ,
             );
+            if this.directory_guards_isolated() {
+                this.update_directory_guards(wallclock, now, expire, univ);
+            }
             #[cfg(feature = "bridge-client")]
             this.update_desired_descriptors(now);
-            #[cfg(not(feature = "bridge-client"))]
-            let _ = now;
         });
     }
 
@@ -958,15 +2327,35 @@ impl GuardMgrInner {
     ///
     /// (This function is only invoked from `update`, which should be called
     /// under the above circumstances.)
-    fn update_active_set_params_and_filter(&mut self, netdir: Option<&NetDir>) {
+    fn update_active_set_params_and_filter(
+        &mut self,
+        netdir: Option<&NetDir>,
+        wallclock: SystemTime,
+    ) {
         // Set the parameters.  These always come from the NetDir, even if this
         // is a bridge set.
         if let Some(netdir) = netdir {
             match GuardParams::try_from(netdir.params()) {
-                Ok(params) => self.params = params,
+                Ok(params) => {
+                    self.params = GuardParams {
+                        // This doesn't come from the consensus, so we keep
+                        // whatever value we were configured with.
+                        max_lifetime_attempts: self.params.max_lifetime_attempts,
+                        max_adaptive_parallelism: self.params.max_adaptive_parallelism,
+                        isolate_directory_guards: self.params.isolate_directory_guards,
+                        max_guardset_age: self.params.max_guardset_age,
+                        test_network: self.params.test_network,
+                        ..params
+                    };
+                    if self.params.test_network {
+                        self.params.apply_test_network_overrides();
+                    }
+                }
                 Err(e) => warn!("Unusable guard parameters from consensus: {}", e),
             }
 
+            self.note_netdir(netdir);
+
             self.select_guard_set_based_on_filter(netdir);
         }
 
@@ -981,6 +2370,149 @@ impl GuardMgrInner {
                 .active_guards_mut()
                 .set_filter(self.filter.clone(), restrictive);
         }
+
+        // Likewise for the blocklist.
+        if self.guards.active_guards().blocklist() != &self.guards.blocklist {
+            let blocklist = self.guards.blocklist.clone();
+            let blocked = self.guards.active_guards_mut().set_blocklist(blocklist);
+            for id in blocked {
+                self.drop_log.record(wallclock, id, DropReason::Blocklisted);
+            }
+        }
+
+        // And for the relays that our acceptor rejects.  We can only ask the
+        // acceptor about relays in a NetDir: if we don't have one, we keep
+        // whatever we decided last time.
+        if let (Some(acceptor), Some(netdir)) = (&self.guard_acceptor, netdir) {
+            let vetoed = vetoed_guards(acceptor, netdir);
+            if self.guards.active_guards().vetoed() != &vetoed {
+                self.guards.active_guards_mut().set_vetoed(vetoed);
+            }
+        }
+    }
+
+    /// Record information about `netdir`, which we are about to use, and
+    /// check whether it lists drastically fewer guards than before.
+    ///
+    /// We only look at each NetDir once, so that calling this repeatedly with
+    /// the same one doesn't count as a confirmation.
+    fn note_netdir(&mut self, netdir: &NetDir) {
+        let lifetime = netdir.lifetime();
+        if self.last_netdir_info.as_ref().map(|info| info.valid_after)
+            == Some(lifetime.valid_after())
+        {
+            return;
+        }
+
+        if self.last_netdir_info.is_none() && self.dir_parallelism_override.take().is_some() {
+            // This is our first usable NetDir, so we're done bootstrapping.
+            debug!("Got a usable directory; clearing the dir_parallelism override.");
+        }
+
+        let n_guards = netdir
+            .relays()
+            .filter(|r| r.low_level_details().is_suitable_as_guard())
+            .count();
+        self.last_netdir_info = Some(NetDirInfo {
+            valid_after: lifetime.valid_after(),
+            fresh_until: lifetime.fresh_until(),
+            valid_until: lifetime.valid_until(),
+            n_guards,
+        });
+        self.check_guard_count(n_guards);
+    }
+
+    /// Check whether a new NetDir with `n_guards` guard-flagged relays lists
+    /// drastically fewer guards than the last NetDir we accepted, and update
+    /// `self.guard_count` accordingly.
+    fn check_guard_count(&mut self, n_guards: usize) {
+        let shrunk = match self.guard_count.baseline {
+            Some(baseline) => (n_guards as f64) < (baseline as f64) * self.guard_shrink_threshold,
+            None => false,
+        };
+
+        if shrunk && !self.guard_count.suspect {
+            warn!(
+                n_guards,
+                previously = self.guard_count.baseline,
+                "New directory lists far fewer guards than before. Not updating our guard sample until another directory confirms it."
+            );
+            self.guard_count.suspect = true;
+        } else {
+            if shrunk {
+                info!(
+                    n_guards,
+                    "Another directory confirms that there are far fewer guards than before."
+                );
+            }
+            self.guard_count.baseline = Some(n_guards);
+            self.guard_count.suspect = false;
+        }
+    }
+
+    /// Return true if we are selecting guards for one-hop directory requests
+    /// from the separate [`GuardSetSelector::Directory`] set.
+    fn directory_guards_isolated(&self) -> bool {
+        self.params.isolate_directory_guards
+            && self.guards.active_set.universe_type() == UniverseType::NetDir
+    }
+
+    /// Return the selector for the guard set that we should use for `kind`.
+    fn guard_set_for(&self, kind: &GuardUsageKind) -> GuardSetSelector {
+        if *kind == GuardUsageKind::OneHopDirectory && self.directory_guards_isolated() {
+            GuardSetSelector::Directory
+        } else {
+            self.guards.active_set.clone()
+        }
+    }
+
+    /// Bring the filter and blocklist of the directory guard set in line with
+    /// those of the active set, and then update its guards from `universe`.
+    ///
+    /// Every guard in the active set's sample is vetoed in the directory set,
+    /// so that the two never share a guard.
+    ///
+    /// Only call this when [`directory_guards_isolated`](Self::directory_guards_isolated)
+    /// is true.
+    fn update_directory_guards<U: Universe>(
+        &mut self,
+        wallclock: SystemTime,
+        now: Instant,
+        expire: bool,
+        universe: Option<&U>,
+    ) -> ExtendedStatus {
+        let active = self.guards.active_guards();
+        let filter = active.filter().clone();
+        let blocklist = active.blocklist().clone();
+        let mut vetoed = active.vetoed().clone();
+        vetoed.extend(active.sampled_ed_identities().copied());
+        let restrictive = self.guards.active_set == GuardSetSelector::Restricted;
+
+        let dir_guards = self.guards.guards_mut(&GuardSetSelector::Directory);
+        if dir_guards.filter() != &filter {
+            dir_guards.set_filter(filter, restrictive);
+        }
+        if dir_guards.blocklist() != &blocklist {
+            for id in dir_guards.set_blocklist(blocklist) {
+                self.drop_log.record(wallclock, id, DropReason::Blocklisted);
+            }
+        }
+        if dir_guards.vetoed() != &vetoed {
+            dir_guards.set_vetoed(vetoed);
+        }
+
+        Self::update_guardset_internal(
+            &self.params,
+            wallclock,
+            now,
+            UniverseType::NetDir,
+            self.guard_count.suspect,
+            self.sample_frozen,
+            expire,
+            dir_guards,
+            &mut self.drop_log,
+            universe,
+        )
     }
 
     /// Update the status of every guard in `active_guards`, and expand it as
@@ -993,16 +2525,35 @@ impl GuardMgrInner {
     /// We should call this whenever the contents of the universe have changed.
     ///
     /// We should also call this whenever a new GuardSet becomes active.
+    ///
+    /// If `netdir_suspect` is true, then our latest NetDir lists drastically
+    /// fewer guards than before, and so we don't use it to change the status
+    /// of our guards or to extend the sample.
+    ///
+    /// If `sample_frozen` is true, we never extend the sample.
+    ///
+    /// If `expire` is false, we don't expire any guards.  Otherwise, we record
+    /// every guard that we expire in `drop_log`.
+    #[allow(clippy::too_many_arguments)]
     fn update_guardset_internal<U: Universe>(
         params: &GuardParams,
-        now: SystemTime,
+        wallclock: SystemTime,
+        now: Instant,
         universe_type: UniverseType,
+        netdir_suspect: bool,
+        sample_frozen: bool,
+        expire: bool,
         active_guards: &mut GuardSet,
+        drop_log: &mut DropLog,
         universe: Option<&U>,
     ) -> ExtendedStatus {
         // Expire guards.  Do that early, in case doing so makes it clear that
         // we need to grab more guards or mark others as primary.
-        active_guards.expire_old_guards(params, now);
+        if expire {
+            for (id, reason) in active_guards.expire_old_guards(params, wallclock, now) {
+                drop_log.record(wallclock, id, reason);
+            }
+        }
 
         let extended = if let Some(universe) = universe {
             // TODO: This check here may be completely unnecessary. I inserted
@@ -1029,13 +2580,21 @@ impl GuardMgrInner {
                 // is missing, we just need to find a cache that has it.)
                 return ExtendedStatus::No;
             }
+            if netdir_suspect && universe_type == UniverseType::NetDir {
+                // Don't let a possibly manipulated NetDir mark our guards as
+                // unlisted, or fill our sample with the few guards it lists.
+                return ExtendedStatus::No;
+            }
             active_guards.update_status_from_dir(universe);
-            active_guards.extend_sample_as_needed(now, params, universe)
+            if sample_frozen {
+                return ExtendedStatus::No;
+            }
+            active_guards.extend_sample_as_needed(wallclock, now, params, universe)
         } else {
             ExtendedStatus::No
         };
 
-        active_guards.select_primary_guards(params);
+        active_guards.select_primary_guards(params, wallclock);
 
         extended
     }
@@ -1094,6 +2653,8 @@ impl GuardMgrInner {
             GuardSetSelector::Default => -0.05,
             GuardSetSelector::Restricted => 0.05,
             // If we're using bridges, then we don't switch between the other guard sets based on on the filter at all.
+            // The directory set is never active.
+            GuardSetSelector::Directory => return,
             #[cfg(feature = "bridge-client")]
             GuardSetSelector::Bridges => return,
         };
@@ -1143,10 +2704,88 @@ impl GuardMgrInner {
         }
     }
 
+    /// Return how long it has been (as of `now`) since we last received any
+    /// incoming traffic, or `None` if we never have.
+    fn time_since_last_incoming_traffic(&self, now: Instant) -> Option<Duration> {
+        #[cfg(test)]
+        if let Some(when) = self.test_last_incoming_traffic {
+            return Some(now.saturating_duration_since(when));
+        }
+        #[cfg(not(test))]
+        let _ = now;
+        tor_proto::time_since_last_incoming_traffic()
+    }
+
+    /// Return true if the host is suspended.
+    fn is_suspended(&self) -> bool {
+        self.suspension
+            .as_ref()
+            .is_some_and(|s| s.resumed_at.is_none())
+    }
+
+    /// Given that we have gone `since_traffic` without incoming traffic,
+    /// return how much of that time we spent awake.
+    ///
+    /// (The time we spent suspended doesn't tell us anything about whether
+    /// the internet is down.)
+    fn time_offline(&self, since_traffic: Duration, now: Instant) -> Duration {
+        match &self.suspension {
+            Some(s) => {
+                let awake = s
+                    .resumed_at
+                    .map_or(Duration::ZERO, |r| now.saturating_duration_since(r));
+                // If we've had traffic since we resumed, `since_traffic` is
+                // the smaller of the two.
+                since_traffic.min(s.offline_at_suspend + awake)
+            }
+            None => since_traffic,
+        }
+    }
+
+    /// Return true if we should not expire any guards at `wallclock`.
+    ///
+    /// We don't expire guards while we're suspended, or after we resume until
+    /// we have a directory that is valid at `wallclock`: until then, our idea
+    /// of which guards are listed may be arbitrarily out of date.
+    fn guard_expiry_deferred(&mut self, wallclock: SystemTime) -> bool {
+        let netdir_is_live = self
+            .last_netdir_info
+            .as_ref()
+            .is_some_and(|info| info.valid_after <= wallclock && wallclock <= info.valid_until);
+        match &mut self.suspension {
+            Some(s) if s.awaiting_live_netdir => {
+                if s.resumed_at.is_some() && netdir_is_live {
+                    s.awaiting_live_netdir = false;
+                }
+                s.awaiting_live_netdir
+            }
+            _ => false,
+        }
+    }
+
     /// Replace the current GuardFilter with `filter`.
-    fn set_filter(&mut self, filter: GuardFilter, wallclock: SystemTime, now: Instant) {
+    ///
+    /// Return true (and warn) if the new filter is dangerously restrictive.
+    fn set_filter(&mut self, filter: GuardFilter, wallclock: SystemTime, now: Instant) -> bool {
         self.filter = filter;
         self.update(wallclock, now);
+
+        // We can only judge the filter against the guards listed in a
+        // NetDir.  (With bridges, it's normal to have only a few guards.)
+        if self.guards.active_set.universe_type() != UniverseType::NetDir
+            || self.timely_netdir().is_none()
+        {
+            return false;
+        }
+        let health = self.guards.active_guards().sample_health(&self.params);
+        if health.filter_too_restrictive() {
+            warn!(
+                n_permitted = health.filtered_sample_size(),
+                min = health.min_filtered_sample_size(),
+                "Guard filter is too restrictive: it permits fewer guards than we want in our sample. This may harm anonymity."
+            );
+        }
+        health.filter_too_restrictive()
     }
 
     /// Called when the circuit manager reports (via [`GuardMonitor`]) that
@@ -1173,8 +2812,8 @@ impl GuardMgrInner {
                 let observation = skew::SkewObservation { skew, when: now };
 
                 match &guard_id.0 {
-                    FirstHopIdInner::Guard(_, id) => {
-                        self.guards.active_guards_mut().record_skew(id, observation);
+                    FirstHopIdInner::Guard(sample, id) => {
+                        self.guards.guards_mut(sample).record_skew(id, observation);
                     }
                     FirstHopIdInner::Fallback(id) => {
                         self.fallbacks.note_skew(id, observation);
@@ -1190,6 +2829,10 @@ impl GuardMgrInner {
                 self.update_skew(now);
             }
 
+            if let FirstHopIdInner::Guard(..) = &guard_id.0 {
+                self.adapt_data_parallelism(status);
+            }
+
             match (status, &guard_id.0) {
                 (GuardStatus::Failure, FirstHopIdInner::Fallback(id)) => {
                     // We used a fallback, and we weren't able to build a circuit through it.
@@ -1209,12 +2852,15 @@ impl GuardMgrInner {
                     }
 
                     // The guard succeeded.  Tell the GuardSet.
-                    self.guards.guards_mut(sample).record_success(
+                    let retired = self.guards.guards_mut(sample).record_success(
                         id,
                         &self.params,
                         None,
                         runtime.wallclock(),
                     );
+                    if retired {
+                        self.note_guard_retired(id.clone(), runtime.wallclock(), runtime.now());
+                    }
                     // Either tell the request whether the guard is
                     // usable, or schedule it as a "waiting" request.
                     if let Some(usable) = self.guard_usability_status(&pending, runtime.now()) {
@@ -1256,7 +2902,12 @@ impl GuardMgrInner {
         // status of guards above.
         self.guards
             .active_guards_mut()
-            .select_primary_guards(&self.params);
+            .select_primary_guards(&self.params, runtime.wallclock());
+        if self.directory_guards_isolated() {
+            self.guards
+                .guards_mut(&GuardSetSelector::Directory)
+                .select_primary_guards(&self.params, runtime.wallclock());
+        }
 
         // Some waiting request may just have become ready (usable or
         // not); we need to give them the information they're waiting
@@ -1264,6 +2915,41 @@ impl GuardMgrInner {
         self.expire_and_answer_pending_requests(runtime.now());
     }
 
+    /// Adjust how many guards we try in parallel for data circuits, now that
+    /// an attempt to use a guard has had the outcome `status`.
+    ///
+    /// See [`GuardMgr::set_max_adaptive_parallelism`].
+    fn adapt_data_parallelism(&mut self, status: GuardStatus) {
+        match status {
+            GuardStatus::Success => {
+                self.extra_data_parallelism = self.extra_data_parallelism.saturating_sub(1);
+            }
+            GuardStatus::Failure => {
+                // (We don't let this grow past the maximum, so that
+                // successes start bringing it down again at once.)
+                let max_extra = self
+                    .params
+                    .max_adaptive_parallelism
+                    .saturating_sub(self.params.data_parallelism);
+                self.extra_data_parallelism = (self.extra_data_parallelism + 1).min(max_extra);
+            }
+            GuardStatus::AttemptAbandoned | GuardStatus::Indeterminate => {}
+        }
+    }
+
+    /// Return the number of guards we're currently willing to try in parallel
+    /// for data circuits, taking adaptive parallelism into account.
+    fn data_parallelism(&self) -> usize {
+        let base = self.params.data_parallelism;
+        let max = self.params.max_adaptive_parallelism;
+        if max <= base {
+            // Adaptive parallelism is disabled, or can't make any difference.
+            base
+        } else {
+            (base + self.extra_data_parallelism).min(max)
+        }
+    }
+
     /// Helper to implement `GuardMgr::note_external_success()`.
     ///
     /// (This has to be a separate function so that we can borrow params while
@@ -1272,19 +2958,23 @@ impl GuardMgrInner {
         &mut self,
         identity: &T,
         external_activity: ExternalActivity,
-        now: SystemTime,
+        wallclock: SystemTime,
+        now: Instant,
     ) where
         T: tor_linkspec::HasRelayIds + ?Sized,
     {
         for id in self.lookup_ids(identity) {
             match &id.0 {
                 FirstHopIdInner::Guard(sample, id) => {
-                    self.guards.guards_mut(sample).record_success(
+                    let retired = self.guards.guards_mut(sample).record_success(
                         id,
                         &self.params,
                         Some(external_activity),
-                        now,
+                        wallclock,
                     );
+                    if retired {
+                        self.note_guard_retired(id.clone(), wallclock, now);
+                    }
                 }
                 FirstHopIdInner::Fallback(id) => {
                     if external_activity == ExternalActivity::DirCache {
@@ -1320,12 +3010,31 @@ impl GuardMgrInner {
     /// a circuit is usable.
     fn guard_usability_status(&self, pending: &PendingRequest, now: Instant) -> Option<bool> {
         match &pending.guard_id().0 {
-            FirstHopIdInner::Guard(sample, id) => self.guards.guards(sample).circ_usability_status(
-                id,
-                pending.usage(),
-                &self.params,
-                now,
-            ),
+            FirstHopIdInner::Guard(sample, id) => {
+                // Another circuit "blocks" this one if it goes through the
+                // guard in question, and if it's still being built, or has
+                // been built and is waiting to see whether it can be used.
+                //
+                // (circ_priority checks that the guard is one we'd rather
+                // use, and that it obeys our restrictions.)
+                let blocked_by = |blocker: &GuardId| {
+                    self.pending
+                        .values()
+                        .chain(self.waiting.iter())
+                        .any(|other| {
+                            let same_guard = matches!(
+                                &other.guard_id().0,
+                                FirstHopIdInner::Guard(s, g) if s == sample && g == blocker
+                            );
+                            same_guard && other.blocks(pending, self.params.np_connect_timeout, now)
+                        })
+                };
+                let priority =
+                    self.guards
+                        .guards(sample)
+                        .circ_priority(id, pending.usage(), blocked_by);
+                decide_usability(&priority, pending.waiting_since(), &self.params, now).into()
+            }
             // Fallback circuits are usable immediately, since we don't have to wait to
             // see whether any _other_ circuit succeeds or fails.
             FirstHopIdInner::Fallback(_) => Some(true),
@@ -1336,44 +3045,27 @@ impl GuardMgrInner {
     /// expire them and tell the circuit manager that their circuits
     /// are unusable.
     fn expire_and_answer_pending_requests(&mut self, now: Instant) {
-        // A bit ugly: we use a separate Vec here to avoid borrowing issues,
-        // and put it back when we're done.
-        let mut waiting = Vec::new();
-        std::mem::swap(&mut waiting, &mut self.waiting);
-
-        waiting.retain_mut(|pending| {
-            let expired = pending
-                .waiting_since()
-                .and_then(|w| now.checked_duration_since(w))
-                .map(|d| d >= self.params.np_idle_timeout)
-                == Some(true);
-            if expired {
-                trace!(?pending, "Pending request expired");
-                pending.reply(false);
-                return false;
-            }
-
-            // TODO-SPEC: guard_usability_status isn't what the spec says.  It
-            // says instead that we should look at _circuit_ status, saying:
-            //  "   Definition: In the algorithm above, C2 "blocks" C1 if:
-            // * C2 obeys all the restrictions that C1 had to obey, AND
-            // * C2 has higher priority than C1, AND
-            // * Either C2 is <complete>, or C2 is <waiting_for_better_guard>,
-            // or C2 has been <usable_if_no_better_guard> for no more than
-            // {NONPRIMARY_GUARD_CONNECT_TIMEOUT} seconds."
-            //
-            // See comments in sample::GuardSet::circ_usability_status.
-
-            if let Some(answer) = self.guard_usability_status(pending, now) {
-                trace!(?pending, answer, "Pending request now ready");
-                pending.reply(answer);
-                return false;
+        // We decide on all of our answers before we give any of them, since
+        // the waiting requests can block one another.
+        let answers: Vec<Option<bool>> = self
+            .waiting
+            .iter()
+            .map(|pending| self.guard_usability_status(pending, now))
+            .collect();
+
+        let mut answers = answers.into_iter();
+        self.waiting.retain_mut(|pending| {
+            // (`retain_mut` visits the requests in order, so each one gets
+            // its own answer.)
+            match answers.next().flatten() {
+                Some(answer) => {
+                    trace!(?pending, answer, "Pending request now ready");
+                    pending.reply(answer);
+                    false
+                }
+                None => true,
             }
-            true
         });
-
-        // Put the waiting list back.
-        std::mem::swap(&mut waiting, &mut self.waiting);
     }
 
     /// Return every currently extant FirstHopId for a guard or fallback
@@ -1419,12 +3111,61 @@ impl GuardMgrInner {
         vec
     }
 
+    /// Return `usage`, with a restriction added for every live
+    /// [`GuardRestrictionScope`].
+    fn apply_restriction_scopes(&self, mut usage: GuardUsage) -> GuardUsage {
+        usage.restrictions.extend(
+            self.restriction_scopes
+                .values()
+                .cloned()
+                .map(GuardRestriction::RequireAnyId),
+        );
+        usage
+    }
+
+    /// Note the outcome of an attempt at `now` to save our state, which
+    /// succeeded if `saved` is true.
+    ///
+    /// Return the [`SaveErrorHook`] to call, if we should report the failure.
+    fn save_error_hook_to_call(&mut self, saved: bool, now: Instant) -> Option<SaveErrorHook> {
+        if saved {
+            self.last_save_error_report = None;
+            return None;
+        }
+        let hook = self.save_error_hook.clone()?;
+        if self
+            .last_save_error_report
+            .is_some_and(|last| now.saturating_duration_since(last) < SAVE_ERROR_REPORT_INTERVAL)
+        {
+            return None;
+        }
+        self.last_save_error_report = Some(now);
+        Some(hook)
+    }
+
     /// Run any periodic events that update guard status, and return a
     /// duration after which periodic events should next be run.
     pub(crate) fn run_periodic_events(&mut self, wallclock: SystemTime, now: Instant) -> Duration {
-        self.update(wallclock, now);
-        self.expire_and_answer_pending_requests(now);
-        Duration::from_secs(1) // TODO: Too aggressive.
+        if self.is_suspended() {
+            // Our timers are frozen until we're resumed.
+            return match self.power_mode {
+                PowerMode::Normal => NORMAL_PERIODIC_INTERVAL,
+                PowerMode::LowPower => LOW_POWER_PERIODIC_INTERVAL,
+            };
+        }
+        match self.power_mode {
+            PowerMode::Normal => {
+                self.update(wallclock, now);
+                self.expire_and_answer_pending_requests(now);
+                NORMAL_PERIODIC_INTERVAL // TODO: Too aggressive.
+            }
+            PowerMode::LowPower => {
+                // We skip `update` here: it will get called anyway when we
+                // get a new NetDir.
+                self.expire_and_answer_pending_requests(now);
+                LOW_POWER_PERIODIC_INTERVAL
+            }
+        }
     }
 
     /// Try to select a guard, expanding the sample if the first attempt fails.
@@ -1454,13 +3195,23 @@ impl GuardMgrInner {
             // need to call update() or update_active_set_and_filter(). This
             // call is sufficient to  extend the sample and recompute primary
             // guards.
-            let extended = Self::update_guardset_internal(
-                &this.params,
-                wallclock,
-                this.guards.active_set.universe_type(),
-                this.guards.active_guards_mut(),
-                Some(univ),
-            );
+            let expire = !this.guard_expiry_deferred(wallclock);
+            let extended = if this.guard_set_for(&usage.kind) == GuardSetSelector::Directory {
+                this.update_directory_guards(wallclock, now, expire, Some(univ))
+            } else {
+                Self::update_guardset_internal(
+                    &this.params,
+                    wallclock,
+                    now,
+                    this.guards.active_set.universe_type(),
+                    this.guard_count.suspect,
+                    this.sample_frozen,
+                    expire,
+                    this.guards.active_guards_mut(),
+                    &mut this.drop_log,
+                    Some(univ),
+                )
+            };
             if extended == ExtendedStatus::Yes {
                 match this.select_guard_once(usage, now) {
                     Ok(res) => return Some(res),
@@ -1494,12 +3245,13 @@ impl GuardMgrInner {
         usage: &GuardUsage,
         now: Instant,
     ) -> Result<(sample::ListKind, FirstHop), PickGuardError> {
-        let active_set = &self.guards.active_set;
+        let params = self.effective_params();
+        let selector = self.guard_set_for(&usage.kind);
         #[cfg_attr(not(feature = "bridge-client"), allow(unused_mut))]
-        let (list_kind, mut first_hop) =
-            self.guards
-                .guards(active_set)
-                .pick_guard(active_set, usage, &self.params, now)?;
+        let (list_kind, mut first_hop) = self
+            .guards
+            .guards(&selector)
+            .pick_guard(&selector, usage, &params, now)?;
         #[cfg(feature = "bridge-client")]
         if self.guards.active_set.universe_type() == UniverseType::BridgeSet {
             // See if we can promote first_hop to a viable CircTarget.
@@ -1519,6 +3271,23 @@ impl GuardMgrInner {
         Ok((list_kind, first_hop))
     }
 
+    /// Return our current parameters, with any runtime overrides applied.
+    fn effective_params(&self) -> Cow<'_, GuardParams> {
+        let data_parallelism = self.data_parallelism();
+        if self.dir_parallelism_override.is_none()
+            && data_parallelism == self.params.data_parallelism
+        {
+            return Cow::Borrowed(&self.params);
+        }
+        Cow::Owned(GuardParams {
+            dir_parallelism: self
+                .dir_parallelism_override
+                .unwrap_or(self.params.dir_parallelism),
+            data_parallelism,
+            ..self.params.clone()
+        })
+    }
+
     /// Helper: Select a fallback directory.
     ///
     /// Called when we have no guard information to use. Return values are as
@@ -1591,8 +3360,97 @@ struct GuardParams {
     /// What fraction of the guards determine that our filter is "very
     /// restrictive"?
     extreme_threshold: f64,
+    /// How many times in a row must a guard fail before we stop considering
+    /// it as a primary guard?
+    primary_demote_threshold: u32,
+    /// Once a guard has been demoted from primary status, how long must it
+    /// remain reachable before we will consider it as a primary guard again?
+    primary_promote_delay: Duration,
+    /// How far backwards can the wallclock jump before we stop expiring
+    /// guards until it catches up again?
+    wallclock_backward_tolerance: Duration,
+    /// How many times may we give out a single guard before we retire it?
+    ///
+    /// If this is 0, there is no limit.
+    max_lifetime_attempts: u32,
+    /// The largest number of guards that we may try in parallel for data
+    /// circuits, if adaptive parallelism lets us exceed `data_parallelism`.
+    ///
+    /// If this is no more than `data_parallelism`, adaptive parallelism is
+    /// disabled.
+    max_adaptive_parallelism: usize,
+    /// Should we select guards for one-hop directory requests from a separate
+    /// sample?
+    isolate_directory_guards: bool,
+    /// If set, the largest age that our confirmed guards may reach before we
+    /// start rotating them out, one at a time.
+    max_guardset_age: Option<Duration>,
+    /// Are we running on a small, low-security test network?
+    ///
+    /// If so, the other parameters have been relaxed with
+    /// [`GuardParams::apply_test_network_overrides`].
+    test_network: bool,
 }
 
+/// Default value for [`GuardParams::primary_demote_threshold`].
+///
+/// (This is not a consensus parameter.)
+const DEFAULT_PRIMARY_DEMOTE_THRESHOLD: u32 = 3;
+
+/// Default value for [`GuardParams::primary_promote_delay`].
+///
+/// (This is not a consensus parameter.)
+const DEFAULT_PRIMARY_PROMOTE_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Default value for [`GuardParams::wallclock_backward_tolerance`].
+///
+/// (This is not a consensus parameter.)
+const DEFAULT_WALLCLOCK_BACKWARD_TOLERANCE: Duration = Duration::from_secs(60 * 60);
+
+/// Default value for [`GuardParams::max_lifetime_attempts`]: no limit.
+///
+/// (This is not a consensus parameter; see
+/// [`GuardMgr::set_max_lifetime_attempts`].)
+const DEFAULT_MAX_LIFETIME_ATTEMPTS: u32 = 0;
+
+/// Default value for [`GuardParams::max_adaptive_parallelism`]: disabled.
+///
+/// (This is not a consensus parameter; see
+/// [`GuardMgr::set_max_adaptive_parallelism`].)
+const DEFAULT_MAX_ADAPTIVE_PARALLELISM: usize = 0;
+
+/// Default value for [`GuardParams::isolate_directory_guards`]: one shared
+/// sample.
+///
+/// (This is not a consensus parameter; see
+/// [`GuardMgr::set_directory_guard_isolation`].)
+const DEFAULT_ISOLATE_DIRECTORY_GUARDS: bool = false;
+
+/// Default value for [`GuardParams::max_guardset_age`]: no limit.
+///
+/// (This is not a consensus parameter; see
+/// [`GuardMgr::set_max_guardset_age`].)
+const DEFAULT_MAX_GUARDSET_AGE: Option<Duration> = None;
+
+/// Default value for [`GuardParams::test_network`]: we're on a real network.
+///
+/// (This is not a consensus parameter; see
+/// [`GuardMgr::set_test_network_mode`].)
+const DEFAULT_TEST_NETWORK: bool = false;
+
+/// Value for [`GuardParams::min_filtered_sample_size`] on a test network.
+const TEST_NETWORK_MIN_FILTERED_SAMPLE_SIZE: usize = 3;
+
+/// Value for [`GuardParams::max_sample_bw_fraction`] on a test network.
+const TEST_NETWORK_MAX_SAMPLE_BW_FRACTION: f64 = 1.0;
+
+/// Value for [`GuardParams::lifetime_unconfirmed`] and
+/// [`GuardParams::lifetime_confirmed`] on a test network.
+const TEST_NETWORK_LIFETIME: Duration = Duration::from_secs(86400);
+
+/// Value for [`GuardParams::lifetime_unlisted`] on a test network.
+const TEST_NETWORK_LIFETIME_UNLISTED: Duration = Duration::from_secs(60 * 60);
+
 impl Default for GuardParams {
     fn default() -> Self {
         let one_day = Duration::from_secs(86400);
@@ -1611,10 +3469,36 @@ impl Default for GuardParams {
             internet_down_timeout: Duration::from_secs(600),
             filter_threshold: 0.2,
             extreme_threshold: 0.01,
+            primary_demote_threshold: DEFAULT_PRIMARY_DEMOTE_THRESHOLD,
+            primary_promote_delay: DEFAULT_PRIMARY_PROMOTE_DELAY,
+            wallclock_backward_tolerance: DEFAULT_WALLCLOCK_BACKWARD_TOLERANCE,
+            max_lifetime_attempts: DEFAULT_MAX_LIFETIME_ATTEMPTS,
+            max_adaptive_parallelism: DEFAULT_MAX_ADAPTIVE_PARALLELISM,
+            isolate_directory_guards: DEFAULT_ISOLATE_DIRECTORY_GUARDS,
+            max_guardset_age: DEFAULT_MAX_GUARDSET_AGE,
+            test_network: DEFAULT_TEST_NETWORK,
         }
     }
 }
 
+impl GuardParams {
+    /// Relax these parameters for use on a small test network.
+    ///
+    /// This is only for private networks (like the ones that `chutney`
+    /// builds), where there are too few relays for our usual sample sizes
+    /// and lifetimes to make sense.  It must never be used on the real Tor
+    /// network.
+    fn apply_test_network_overrides(&mut self) {
+        self.min_filtered_sample_size = self
+            .min_filtered_sample_size
+            .min(TEST_NETWORK_MIN_FILTERED_SAMPLE_SIZE);
+        self.max_sample_bw_fraction = TEST_NETWORK_MAX_SAMPLE_BW_FRACTION;
+        self.lifetime_unconfirmed = self.lifetime_unconfirmed.min(TEST_NETWORK_LIFETIME);
+        self.lifetime_confirmed = self.lifetime_confirmed.min(TEST_NETWORK_LIFETIME);
+        self.lifetime_unlisted = self.lifetime_unlisted.min(TEST_NETWORK_LIFETIME_UNLISTED);
+    }
+}
+
 impl TryFrom<&NetParameters> for GuardParams {
     type Error = tor_units::Error;
     fn try_from(p: &NetParameters) -> Result<GuardParams, Self::Error> {
@@ -1633,10 +3517,212 @@ impl TryFrom<&NetParameters> for GuardParams {
             internet_down_timeout: p.guard_internet_likely_down.try_into()?,
             filter_threshold: p.guard_meaningful_restriction.as_fraction(),
             extreme_threshold: p.guard_extreme_restriction.as_fraction(),
+            primary_demote_threshold: DEFAULT_PRIMARY_DEMOTE_THRESHOLD,
+            primary_promote_delay: DEFAULT_PRIMARY_PROMOTE_DELAY,
+            wallclock_backward_tolerance: DEFAULT_WALLCLOCK_BACKWARD_TOLERANCE,
+            max_lifetime_attempts: DEFAULT_MAX_LIFETIME_ATTEMPTS,
+            max_adaptive_parallelism: DEFAULT_MAX_ADAPTIVE_PARALLELISM,
+            isolate_directory_guards: DEFAULT_ISOLATE_DIRECTORY_GUARDS,
+            max_guardset_age: DEFAULT_MAX_GUARDSET_AGE,
+            test_network: DEFAULT_TEST_NETWORK,
         })
     }
 }
 
+/// A snapshot of the parameters that a [`GuardMgr`] is using.
+///
+/// Returned by [`GuardMgr::effective_params`].
+#[derive(Debug, Clone, amplify::Getters)]
+pub struct GuardParamsView {
+    /// How long should a sampled, un-confirmed guard be kept in the sample
+    /// before it expires?
+    #[getter(as_copy)]
+    lifetime_unconfirmed: Duration,
+    /// How long should a confirmed guard be kept in the sample before it
+    /// expires?
+    #[getter(as_copy)]
+    lifetime_confirmed: Duration,
+    /// How long may a guard be unlisted before we remove it from the sample?
+    #[getter(as_copy)]
+    lifetime_unlisted: Duration,
+    /// Largest number of guards we're willing to add to the sample.
+    #[getter(as_copy)]
+    max_sample_size: usize,
+    /// Largest fraction of the network's guard bandwidth that we're
+    /// willing to add to the sample.
+    #[getter(as_copy)]
+    max_sample_bw_fraction: f64,
+    /// Smallest number of guards that we're willing to have in the
+    /// sample, after applying a [`GuardFilter`].
+    #[getter(as_copy)]
+    min_filtered_sample_size: usize,
+    /// How many guards are considered "Primary"?
+    #[getter(as_copy)]
+    n_primary: usize,
+    /// When making a regular circuit, how many primary guards should we
+    /// be willing to try?
+    #[getter(as_copy)]
+    data_parallelism: usize,
+    /// When making a one-hop directory circuit, how many primary
+    /// guards should we be willing to try?
+    #[getter(as_copy)]
+    dir_parallelism: usize,
+    /// For how long does a pending attempt to connect to a guard
+    /// block an attempt to use a less-favored non-primary guard?
+    #[getter(as_copy)]
+    np_connect_timeout: Duration,
+    /// How long do we allow a circuit to a successful but unfavored
+    /// non-primary guard to sit around before deciding not to use it?
+    #[getter(as_copy)]
+    np_idle_timeout: Duration,
+    /// After how much time without successful activity does a
+    /// successful circuit indicate that we should retry our primary
+    /// guards?
+    #[getter(as_copy)]
+    internet_down_timeout: Duration,
+    /// How many times may we give out a single guard before we retire it?
+    /// (0 means "no limit".)
+    #[getter(as_copy)]
+    max_lifetime_attempts: u32,
+    /// The largest number of guards that adaptive parallelism lets us try in
+    /// parallel for data circuits.  (0 means "disabled".)
+    #[getter(as_copy)]
+    max_adaptive_parallelism: usize,
+    /// Do we select guards for one-hop directory requests from a separate
+    /// sample?
+    #[getter(as_copy)]
+    isolate_directory_guards: bool,
+    /// How old may our confirmed guards get before we start rotating them
+    /// out?  (`None` means "no limit".)
+    #[getter(as_copy)]
+    max_guardset_age: Option<Duration>,
+    /// Are we in test-network mode?  (If so, the other values here have
+    /// already been relaxed accordingly.)
+    #[getter(as_copy)]
+    test_network: bool,
+}
+
+impl From<&GuardParams> for GuardParamsView {
+    fn from(p: &GuardParams) -> Self {
+        GuardParamsView {
+            lifetime_unconfirmed: p.lifetime_unconfirmed,
+            lifetime_confirmed: p.lifetime_confirmed,
+            lifetime_unlisted: p.lifetime_unlisted,
+            max_sample_size: p.max_sample_size,
+            max_sample_bw_fraction: p.max_sample_bw_fraction,
+            min_filtered_sample_size: p.min_filtered_sample_size,
+            n_primary: p.n_primary,
+            data_parallelism: p.data_parallelism,
+            dir_parallelism: p.dir_parallelism,
+            np_connect_timeout: p.np_connect_timeout,
+            np_idle_timeout: p.np_idle_timeout,
+            internet_down_timeout: p.internet_down_timeout,
+            max_lifetime_attempts: p.max_lifetime_attempts,
+            max_adaptive_parallelism: p.max_adaptive_parallelism,
+            isolate_directory_guards: p.isolate_directory_guards,
+            max_guardset_age: p.max_guardset_age,
+            test_network: p.test_network,
+        }
+    }
+}
+
+/// Information about a network directory that a [`GuardMgr`] has used.
+///
+/// Returned by [`GuardMgr::last_netdir_info`].
+#[derive(Debug, Clone, amplify::Getters)]
+pub struct NetDirInfo {
+    /// The time when the directory's consensus became valid.
+    #[getter(as_copy)]
+    valid_after: SystemTime,
+    /// The time until which the directory's consensus is the newest we expect
+    /// to see.
+    #[getter(as_copy)]
+    fresh_until: SystemTime,
+    /// The time after which the directory's consensus is no longer valid.
+    #[getter(as_copy)]
+    valid_until: SystemTime,
+    /// The number of usable relays in the directory that are suitable for use
+    /// as guards.
+    #[getter(as_copy)]
+    n_guards: usize,
+}
+
+/// A record of a guard that [`GuardMgr::select_guard`] chose.
+///
+/// Returned by [`GuardMgr::recent_selections`].
+#[derive(Debug, Clone, amplify::Getters)]
+pub struct SelectionRecord {
+    /// The time at which we chose the guard.
+    #[getter(as_copy)]
+    when: SystemTime,
+    /// The kind of usage for which we chose the guard.
+    usage: GuardUsageKind,
+    /// The identities of the guard (or fallback) that we chose.
+    guard: Redacted<RelayIds>,
+    /// The list from which we chose the guard.
+    #[getter(as_copy)]
+    origin: ListKind,
+}
+
+/// The state of a request that is waiting to learn whether its circuit is usable.
+///
+/// Returned by [`GuardMgr::waiting_request_states`].
+#[derive(Debug, Clone, amplify::Getters)]
+pub struct WaitingState {
+    /// The guard (or fallback) that we gave out for this request.
+    guard: FirstHopId,
+    /// How long this request has been waiting.
+    #[getter(as_copy)]
+    waiting_for: Duration,
+    /// Whether the request's circuit is usable (`Some(true)`), unusable
+    /// (`Some(false)`), or whether we can't tell yet (`None`).
+    #[getter(as_copy)]
+    usable: Option<bool>,
+}
+
+/// A guard that we removed from one of our guard samples.
+///
+/// Returned by [`GuardMgr::recent_dropped`].
+#[derive(Debug, Clone, amplify::Getters)]
+pub struct DroppedGuard {
+    /// The identity of the guard that we removed.
+    guard: RelayIds,
+    /// Why we removed the guard.
+    #[getter(as_copy)]
+    reason: DropReason,
+    /// When we removed the guard.
+    #[getter(as_copy)]
+    when: SystemTime,
+}
+
+/// The reason why we removed a guard from a guard sample.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DropReason {
+    /// We never confirmed the guard, and it had been in the sample for too
+    /// long.
+    ExpiredUnconfirmed,
+    /// We confirmed the guard too long ago.
+    ExpiredConfirmed,
+    /// The guard had been missing from the directory for too long.
+    UnlistedTooLong,
+    /// Our confirmed guards were older than
+    /// [`max_guardset_age`](GuardParamsView::max_guardset_age), and this was
+    /// the oldest of them.
+    ///
+    /// See [`GuardMgr::set_max_guardset_age`].
+    RotatedOut,
+    /// The guard was on our blocklist.
+    ///
+    /// See [`GuardMgr::set_guard_blocklist`].
+    Blocklisted,
+    /// We had given out the guard more than
+    /// [`max_lifetime_attempts`](GuardParamsView::max_lifetime_attempts) times.
+    ///
+    /// See [`GuardMgr::set_max_lifetime_attempts`].
+    Retired,
+}
+
 /// Representation of a guard or fallback, as returned by [`GuardMgr::select_guard()`].
 #[derive(Debug, Clone)]
 pub struct FirstHop {
@@ -1800,6 +3886,20 @@ pub struct GuardUsage {
     /// The default is the empty list.
     #[builder(sub_builder, setter(custom))]
     restrictions: GuardRestrictionList,
+    /// If true, we should try to spread our circuits across our primary
+    /// guards, instead of concentrating them on the guards we're already
+    /// using.
+    ///
+    /// We then choose among all of our usable primary guards, regardless of
+    /// our parallelism parameters.  Among the equally good ones, we prefer
+    /// the ones that are _not_ in use (see [`GuardMgr::note_guard_in_use`]),
+    /// and among those, the one that we selected least recently.  This
+    /// replaces our usual preference for guards that are in use.  As with
+    /// that preference, we never prefer a guard over one that ranks above it.
+    ///
+    /// The default is false.
+    #[builder(default)]
+    spread_usage: bool,
 }
 
 impl_standard_builder! { GuardUsage: !Deserialize }
@@ -1843,6 +3943,8 @@ pub enum GuardRestriction {
     AvoidId(RelayId),
     /// Don't pick a guard with any of the provided Ed25519 identities.
     AvoidAllIds(RelayIdSet),
+    /// Only pick a guard that has at least one of the provided identities.
+    RequireAnyId(RelayIdSet),
 }
 
 /// The kind of vanguards to use.
@@ -1938,9 +4040,13 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use crate::guard::Reachable;
     use tor_linkspec::{HasAddrs, HasRelayIds};
     use tor_persist::TestingStateMgr;
     use tor_rtcompat::test_with_all_runtimes;
+    use tor_rtcompat::SleepProvider as _;
+    use tor_rtmock::MockRuntime;
+    use tracing_test::traced_test;
 
     #[test]
     fn guard_param_defaults() {
@@ -1979,6 +4085,38 @@ mod test {
         (guardmgr, statemgr, netdir)
     }
 
+    /// Install a [`NetDirProvider`] for `netdir` in `guardmgr`, and bring
+    /// `guardmgr` up to date with it.
+    ///
+    /// Unlike [`GuardMgr::install_test_netdir`], this returns the provider.
+    /// The `GuardMgr` keeps only a weak reference to it, so the caller must
+    /// hold on to it for as long as the `GuardMgr` should be able to use the
+    /// `NetDir`: for example, to refill its sample later on.
+    fn install_test_provider<R: Runtime>(
+        guardmgr: &GuardMgr<R>,
+        netdir: NetDir,
+        rt: &R,
+    ) -> Arc<dyn NetDirProvider> {
+        use tor_netdir::testprovider::TestNetDirProvider;
+        let provider: Arc<dyn NetDirProvider> = Arc::new(TestNetDirProvider::from(netdir));
+        guardmgr.install_netdir_provider(&provider).unwrap();
+        guardmgr
+            .inner
+            .lock()
+            .unwrap()
+            .update(rt.wallclock(), rt.now());
+        provider
+    }
+
+    /// Select a guard from `guardmgr` for `usage`, report that our circuit
+    /// through it succeeded, and wait for `guardmgr` to handle that report.
+    async fn select_and_succeed<R: Runtime>(guardmgr: &GuardMgr<R>, usage: GuardUsage) -> FirstHop {
+        let (guard, mon, _usable) = guardmgr.select_guard(usage).unwrap();
+        mon.succeeded();
+        guardmgr.flush_msg_queue().await;
+        guard
+    }
+
     #[test]
     #[allow(clippy::clone_on_copy)]
     fn simple_case() {
@@ -2012,6 +4150,194 @@ mod test {
         });
     }
 
+    #[test]
+    fn update_network() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let usage = GuardUsage::default();
+
+            // With no directory at all, we can only use fallbacks (and we
+            // have none).
+            assert!(guardmgr.select_guard(usage.clone()).is_err());
+
+            // Once we've been given a NetDir, we use it for every selection.
+            guardmgr.update_network(Arc::new(netdir));
+            for _ in 0..2 {
+                let (id, mon, _usable) = guardmgr.select_guard(usage.clone()).unwrap();
+                assert!(id.sample.is_some());
+                mon.succeeded();
+                guardmgr.flush_msg_queue().await;
+            }
+        });
+    }
+
+    #[test]
+    fn persistence_consistency() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            assert!(matches!(
+                guardmgr.verify_persistence_consistency(),
+                Err(Inconsistency::NothingStored)
+            ));
+
+            guardmgr.install_test_netdir(&netdir);
+            guardmgr.store_persistent_state().unwrap();
+            guardmgr.verify_persistence_consistency().unwrap();
+
+            // Confirming a guard changes our state, but we haven't saved it.
+            let (_id, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            match guardmgr.verify_persistence_consistency() {
+                Err(Inconsistency::Mismatch { differing }) => {
+                    assert_eq!(differing, vec!["default".to_string()]);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            // Once we save it, everything matches again.
+            guardmgr.store_persistent_state().unwrap();
+            guardmgr.verify_persistence_consistency().unwrap();
+        });
+    }
+
+    #[test]
+    fn rebind_storage() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt);
+            // (We install the netdir first, so that no background update
+            // changes our state between the time we save it and check it.)
+            guardmgr.install_test_netdir(&netdir);
+            guardmgr.store_persistent_state().unwrap();
+            let load = |mgr: &TestingStateMgr| -> Option<tor_persist::JsonValue> {
+                mgr.load(STORAGE_KEY).unwrap()
+            };
+
+            // If somebody else holds the lock on the new storage, we keep
+            // using the old one.
+            let locked = TestingStateMgr::new();
+            let holder = locked.new_manager();
+            assert!(holder.try_lock().unwrap().held());
+            assert!(guardmgr.rebind_storage(&locked).is_err());
+            assert!(load(&locked).is_none());
+            guardmgr.verify_persistence_consistency().unwrap();
+
+            // Otherwise, we save our state there...
+            let new = TestingStateMgr::new();
+            guardmgr.rebind_storage(&new).unwrap();
+            assert!(new.can_store());
+            assert_eq!(load(&new), load(&statemgr));
+
+            // ...and keep saving it there.
+            select_and_succeed(&guardmgr, GuardUsage::default()).await;
+            guardmgr.store_persistent_state().unwrap();
+            guardmgr.verify_persistence_consistency().unwrap();
+            assert_ne!(load(&new), load(&statemgr));
+        });
+    }
+
+    #[test]
+    fn unlisted_guard_survives_brief_absence() {
+        test_with_all_runtimes!(|rt| async move {
+            use tor_netdir::testnet;
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            guardmgr.update_network(Arc::new(netdir.clone()));
+
+            let (id, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            let ids = RelayIds::from_relay_ids(&id);
+            let guard_id = GuardId(ids.clone());
+            assert_eq!(guardmgr.unlisted_since(&ids), None);
+
+            // Build a consensus just like the first, but without our guard.
+            let missing_idx = usize::from(id.ed_identity().unwrap().as_bytes()[0]);
+            let netdir2 = testnet::construct_custom_netdir_with_params(
+                |idx, node| {
+                    if idx == missing_idx {
+                        node.omit_rs = true;
+                    }
+                },
+                [
+                    ("guard-min-filtered-sample-size", 5),
+                    ("guard-n-primary-guards", 2),
+                    ("guard-meaningful-restriction-percent", 75),
+                ],
+                None,
+            )
+            .unwrap()
+            .unwrap_if_sufficient()
+            .unwrap();
+            let valid_after = netdir2.lifetime().valid_after();
+
+            // While it's missing, the guard is only marked as unlisted.
+            guardmgr.update_network(Arc::new(netdir2));
+            assert_eq!(guardmgr.unlisted_since(&ids), Some(valid_after));
+            {
+                let inner = guardmgr.inner.lock().unwrap();
+                assert_eq!(inner.guards.active_guards().contains(&guard_id), Ok(true));
+            }
+
+            // Once it's back, it is still in the sample, and no longer unlisted.
+            guardmgr.update_network(Arc::new(netdir));
+            assert_eq!(guardmgr.unlisted_since(&ids), None);
+            let inner = guardmgr.inner.lock().unwrap();
+            assert_eq!(inner.guards.active_guards().contains(&guard_id), Ok(true));
+            let guard = inner.guards.active_guards().get(&guard_id).unwrap();
+            assert!(guard.confirmed());
+        });
+    }
+
+    #[test]
+    fn many_rapid_reports() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let usage = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            const N: usize = 500;
+            let monitors = (0..N)
+                .map(|_| guardmgr.select_guard(usage.clone()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(guardmgr.inner.lock().unwrap().pending.len(), N);
+
+            // Report every result at once, without yielding to the daemon.
+            for (i, (_id, mon, _usable)) in monitors.into_iter().enumerate() {
+                if i % 2 == 0 {
+                    mon.succeeded();
+                } else {
+                    mon.attempt_abandoned();
+                }
+            }
+
+            // Every report was received and handled.
+            guardmgr.flush_msg_queue().await;
+            let inner = guardmgr.inner.lock().unwrap();
+            assert!(inner.pending.is_empty());
+        });
+    }
+
+    #[test]
+    fn flush_durable() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+            let (id, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+
+            guardmgr.flush_durable().unwrap();
+            drop(guardmgr);
+
+            // The state we flushed is there when we reload.
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr2.install_test_netdir(&netdir);
+            let (id2, _mon, _usable) = guardmgr2.select_guard(GuardUsage::default()).unwrap();
+            assert!(id2.same_relay_ids(&id));
+        });
+    }
+
     #[test]
     fn simple_waiting() {
         // TODO(nickm): This test fails in rare cases; I suspect a
@@ -2058,6 +4384,65 @@ mod test {
         });
     }
 
+    #[test]
+    fn waiting_request_states() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+            assert!(guardmgr.waiting_request_states().is_empty());
+
+            // A circuit only waits for a better guard that we haven't yet
+            // found to be up or down.  So we confirm the primary guards and
+            // one other guard, "a", and then make the primary guards
+            // unreachable, and "a" retriable.
+            let id_a = {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                let params = inner.params.clone();
+                let (now, wallclock) = (rt.now(), rt.wallclock());
+                let guards = inner.guards.active_guards_mut();
+                let primary = guards.primary_guard_ids().to_vec();
+                let id_a = guards
+                    .sample_ids()
+                    .iter()
+                    .find(|id| !primary.contains(id))
+                    .unwrap()
+                    .clone();
+                for id in primary.iter().chain([&id_a]) {
+                    guards.record_success(id, &params, None, wallclock);
+                }
+                guards.record_failure(&id_a, None, now);
+                guards.consider_all_retries(now + Duration::from_secs(86400));
+                for id in &primary {
+                    guards.record_failure(id, None, now);
+                }
+                guards.select_primary_guards(&params, wallclock);
+                assert!(!guards.primary_guard_ids().contains(&id_a));
+                id_a
+            };
+
+            let (g_a, mon_a, _usable_a) = guardmgr.select_guard(u.clone()).unwrap();
+            assert_eq!(ids::GuardId::from_relay_ids(&g_a), id_a);
+            let (g_b, mon_b, usable_b) = guardmgr.select_guard(u.clone()).unwrap();
+
+            // The second guard succeeds, but it has to wait for the first,
+            // which we'd rather use, and which might still succeed.
+            mon_b.succeeded();
+            guardmgr.flush_msg_queue().await; // avoid race
+            let states = guardmgr.waiting_request_states();
+            assert_eq!(states.len(), 1);
+            assert_eq!(states[0].guard(), &g_b.first_hop_id());
+            assert_eq!(states[0].usable(), None);
+            assert!(states[0].waiting_for() < guardmgr.effective_params().np_idle_timeout());
+
+            // Once the first guard fails, the waiting request gets its answer.
+            mon_a.failed();
+            guardmgr.flush_msg_queue().await; // avoid race
+            assert!(usable_b.await.unwrap());
+            assert!(guardmgr.waiting_request_states().is_empty());
+        });
+    }
+
     #[test]
     fn filtering_basics() {
         test_with_all_runtimes!(|rt| async move {
@@ -2080,7 +4465,287 @@ mod test {
     }
 
     #[test]
-    fn external_status() {
+    #[traced_test]
+    fn restrictive_filter() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+
+            // Without a netdir, we can't tell.
+            let nothing = {
+                let mut f = GuardFilter::default();
+                // All the addresses in the test network are {0,1,2,3,4}.0.0.3:9001.
+                f.push_reachable_addresses(vec!["9.0.0.0/8:9001".parse().unwrap()]);
+                f
+            };
+            assert!(!guardmgr.set_filter(nothing.clone()));
+
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+            assert!(!guardmgr.set_filter(GuardFilter::default()));
+            assert!(!guardmgr.sample_health().filter_too_restrictive());
+
+            // A filter that permits no guards at all is certainly too restrictive.
+            assert!(guardmgr.set_filter(nothing));
+            let health = guardmgr.sample_health();
+            assert!(health.filter_too_restrictive());
+            assert_eq!(health.filtered_sample_size(), 0);
+            assert!(logs_contain("Guard filter is too restrictive"));
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn intended_path_overlap() {
+        MockRuntime::test_with_various(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+            let (guard, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            let guard = RelayIds::from_relay_ids(&guard);
+            let relay = |idx: u8| {
+                RelayIds::builder()
+                    .ed_identity([idx; 32].into())
+                    .build()
+                    .unwrap()
+            };
+            let other = (0..4)
+                .map(relay)
+                .find(|r| !r.has_any_relay_id_from(&guard))
+                .unwrap();
+            // (The logs are shared by every runtime we test with, so we
+            // count the warnings that are new since we started.)
+            let count_warnings = || {
+                let n = std::cell::Cell::new(0);
+                logs_assert(|lines: &[&str]| {
+                    n.set(
+                        lines
+                            .iter()
+                            .filter(|l| l.contains("of an intended circuit path"))
+                            .count(),
+                    );
+                    Ok(())
+                });
+                n.get()
+            };
+            let before = count_warnings();
+            let assert_warnings = |expected| assert_eq!(count_warnings() - before, expected);
+
+            // A path that doesn't reuse the guard is fine.
+            assert!(!guardmgr.note_intended_path(&[guard.clone(), other.clone(), relay(9)]));
+            assert!(!guardmgr.note_intended_path(&[]));
+            assert_warnings(0);
+
+            // One that does is reported, but we only warn about it now and then.
+            let overlapping = [guard.clone(), other, guard];
+            assert!(guardmgr.note_intended_path(&overlapping));
+            assert_warnings(1);
+            assert!(guardmgr.note_intended_path(&overlapping));
+            assert_warnings(1);
+
+            rt.advance_by(PATH_OVERLAP_WARNING_INTERVAL).await;
+            assert!(guardmgr.note_intended_path(&overlapping));
+            assert_warnings(2);
+        });
+    }
+
+    #[test]
+    fn filtered_out_guards() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            guardmgr.install_test_netdir(&netdir);
+            assert!(guardmgr.filtered_out_guards().is_empty());
+
+            // All the addresses in the test network are {0,1,2,3,4}.0.0.3:9001,
+            // and the relays with lower addresses have less bandwidth.  We pick
+            // the sampled guard with the lowest address, so that blocking that
+            // address doesn't make our filter "restrictive".
+            let guard_id = {
+                let inner = guardmgr.inner.lock().unwrap();
+                let sample = inner.guards.active_guards().sample_ids();
+                sample
+                    .iter()
+                    .min_by_key(|id| netdir.by_ids(&id.0).unwrap().addrs()[0])
+                    .unwrap()
+                    .clone()
+            };
+            let blocked = netdir.by_ids(&guard_id.0).unwrap().addrs()[0].ip();
+            // Permit every address except that one.
+            let filter = {
+                let mut f = GuardFilter::default();
+                f.push_reachable_addresses(
+                    (0..5)
+                        .map(|n| format!("{n}.0.0.3"))
+                        .filter(|addr| *addr != blocked.to_string())
+                        .map(|addr| format!("{addr}:9001").parse().unwrap()),
+                );
+                f
+            };
+            guardmgr.set_filter(filter);
+
+            let filtered_out = guardmgr.filtered_out_guards();
+            assert!(filtered_out.contains(&guard_id.0));
+            for ids in &filtered_out {
+                let g = netdir.by_ids(ids).unwrap();
+                assert_eq!(g.addrs()[0].ip(), blocked);
+            }
+            // The guard is still in the sample, but we won't pick it.
+            let inner = guardmgr.inner.lock().unwrap();
+            let guards = inner.guards.active_guards();
+            assert_eq!(inner.guards.active_set, GuardSetSelector::Default);
+            assert!(guards.get(&guard_id).is_some());
+            assert!(!guards.primary_guard_ids().contains(&guard_id));
+        });
+    }
+
+    #[test]
+    fn max_lifetime_attempts() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+            assert_eq!(guardmgr.effective_params().max_lifetime_attempts(), 0);
+            guardmgr.set_max_lifetime_attempts(3);
+            assert_eq!(guardmgr.effective_params().max_lifetime_attempts(), 3);
+
+            let first = select_and_succeed(&guardmgr, GuardUsage::default()).await;
+            let first_id = ids::GuardId::from_relay_ids(&first);
+            for _ in 0..2 {
+                let guard = select_and_succeed(&guardmgr, GuardUsage::default()).await;
+                assert!(guard.same_relay_ids(&first));
+            }
+            // We've given out the first guard three times, which is allowed.
+            assert!(guardmgr
+                .inner
+                .lock()
+                .unwrap()
+                .guards
+                .active_guards()
+                .primary_guard_ids()
+                .contains(&first_id));
+
+            // The fourth time is one too many, so the guard is retired, and
+            // another guard has been sampled to replace it.  (We might sample
+            // the same relay again, but if so, it's a new, unconfirmed guard.)
+            let (guard, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(guard.same_relay_ids(&first));
+            {
+                let inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards();
+                assert!(guards.get(&first_id).map_or(true, |g| !g.confirmed()));
+                assert!(!guards.primary_guard_ids().contains(&first_id));
+            }
+            let dropped = guardmgr.recent_dropped();
+            assert_eq!(dropped.len(), 1);
+            assert_eq!(dropped[0].guard(), &first_id.0);
+            assert_eq!(dropped[0].reason(), DropReason::Retired);
+            let health = guardmgr.sample_health();
+            assert!(health.n_usable() >= health.min_filtered_sample_size());
+
+            let (guard, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(!guard.same_relay_ids(&first));
+        });
+    }
+
+    #[test]
+    fn max_lifetime_attempts_many_retirements() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+            guardmgr.set_max_lifetime_attempts(1);
+            guardmgr.set_drop_log_size(usize::MAX);
+            let max_sample_size = guardmgr.effective_params().max_sample_size();
+
+            // Retire more guards than could ever fit in the sample at once: we
+            // should still be able to get a guard every time.
+            let n_retired = || {
+                guardmgr
+                    .recent_dropped()
+                    .iter()
+                    .filter(|d| d.reason() == DropReason::Retired)
+                    .count()
+            };
+            for _ in 0..max_sample_size * 4 {
+                select_and_succeed(&guardmgr, GuardUsage::default()).await;
+            }
+            assert!(n_retired() > max_sample_size);
+
+            let (guard, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            let guard_id = ids::GuardId::from_relay_ids(&guard);
+            let inner = guardmgr.inner.lock().unwrap();
+            assert!(inner.guards.active_guards().get(&guard_id).is_some());
+        });
+    }
+
+    #[test]
+    fn adaptive_parallelism() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            guardmgr.install_test_netdir(&netdir);
+            let u = GuardUsage::default();
+            let parallelism = || guardmgr.effective_params().data_parallelism();
+            assert_eq!(parallelism(), 1);
+
+            // Without adaptive parallelism, failures don't change anything.
+            let (_id, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+            mon.failed();
+            guardmgr.flush_msg_queue().await;
+            assert_eq!(parallelism(), 1);
+
+            guardmgr.set_max_adaptive_parallelism(3);
+            assert_eq!(guardmgr.effective_params().max_adaptive_parallelism(), 3);
+
+            // Failures make parallelism rise, up to the maximum.
+            for expected in [2, 3, 3] {
+                let (_id, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+                mon.failed();
+                guardmgr.flush_msg_queue().await;
+                assert_eq!(parallelism(), expected);
+            }
+
+            // Successes make it fall, down to the consensus value.
+            for expected in [2, 1, 1] {
+                let (_id, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+                mon.succeeded();
+                guardmgr.flush_msg_queue().await;
+                assert_eq!(parallelism(), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn guard_restriction_scope() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let usage = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+            let primary = guardmgr.primary_guards();
+
+            let (g, _mon, _usable) = guardmgr.select_guard(usage.clone()).unwrap();
+            assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+
+            {
+                // While the scope is held, we only get the guard it names.
+                let _scope = guardmgr.with_guard_restriction_scope(&primary[1..2]);
+                for _ in 0..5 {
+                    let (g, _mon, _usable) = guardmgr.select_guard(usage.clone()).unwrap();
+                    assert_eq!(RelayIds::from_relay_ids(&g), primary[1]);
+                }
+
+                // A nested scope that doesn't permit that guard leaves nothing to
+                // choose from.
+                let nested = guardmgr.with_guard_restriction_scope(&primary[..1]);
+                assert!(guardmgr.select_guard(usage.clone()).is_err());
+                drop(nested);
+                let (g, _mon, _usable) = guardmgr.select_guard(usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[1]);
+            }
+
+            // Once it's dropped, we're back to normal.
+            assert!(guardmgr.inner.lock().unwrap().restriction_scopes.is_empty());
+            let (g, _mon, _usable) = guardmgr.select_guard(usage).unwrap();
+            assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+        });
+    }
+
+    #[test]
+    fn prefer_guards_in_use() {
         test_with_all_runtimes!(|rt| async move {
             let (guardmgr, _statemgr, netdir) = init(rt);
             let data_usage = GuardUsage::default();
@@ -2089,37 +4754,1222 @@ mod test {
                 .build()
                 .unwrap();
             guardmgr.install_test_netdir(&netdir);
-            {
-                // Override this parameter, so that we can get deterministic results below.
-                let mut inner = guardmgr.inner.lock().unwrap();
-                inner.params.dir_parallelism = 1;
+            // Both primary guards are equally good for directory requests.
+            guardmgr.set_dir_parallelism_override(Some(2));
+            let primary = guardmgr.primary_guards();
+            assert_eq!(primary.len(), 2);
+
+            // We prefer the one that's in use...
+            guardmgr.note_guard_in_use(&primary[1]);
+            let mut monitors = Vec::new();
+            for _ in 0..20 {
+                let (g, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[1]);
+                monitors.push(mon);
             }
 
-            let (guard, mon, _usable) = guardmgr.select_guard(data_usage.clone()).unwrap();
-            mon.succeeded();
+            // ...but not over a guard that ranks above it.
+            let (g, _mon, _usable) = guardmgr.select_guard(data_usage).unwrap();
+            assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+
+            // Once it's idle, it's just another primary guard.
+            guardmgr.note_guard_idle(&primary[1]);
+            guardmgr.note_guard_in_use(&primary[0]);
+            for _ in 0..20 {
+                let (g, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+                monitors.push(mon);
+            }
+        });
+    }
 
-            // Record that this guard gave us a bad directory object.
-            guardmgr.note_external_failure(&guard, ExternalActivity::DirCache);
+    #[test]
+    fn spread_usage() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let spread_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .spread_usage(true)
+                .build()
+                .unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            // Both primary guards are equally good for directory requests.
+            guardmgr.set_dir_parallelism_override(Some(2));
+            let primary = guardmgr.primary_guards();
+            assert_eq!(primary.len(), 2);
+
+            // Successive selections cycle through the primary guards...
+            let mut monitors = Vec::new();
+            for i in 0..6 {
+                let (g, mon, _usable) = guardmgr.select_guard(spread_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[i % 2]);
+                monitors.push(mon);
+            }
 
-            // We ask for another guard, for data usage.  We should get the same
-            // one as last time, since the director failure doesn't mean this
-            // guard is useless as a primary guard.
-            let (g2, mon, _usable) = guardmgr.select_guard(data_usage).unwrap();
-            assert_eq!(g2.ed_identity(), guard.ed_identity());
-            mon.succeeded();
+            // ...avoiding the ones that are in use.
+            guardmgr.note_guard_in_use(&primary[0]);
+            for _ in 0..6 {
+                let (g, mon, _usable) = guardmgr.select_guard(spread_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[1]);
+                monitors.push(mon);
+            }
+        });
+    }
 
-            // But if we ask for a guard for directory usage, we should get a
-            // different one, since the last guard we gave out failed.
-            let (g3, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
-            assert_ne!(g3.ed_identity(), guard.ed_identity());
-            mon.succeeded();
+    #[test]
+    fn spread_usage_data() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let spread_usage = GuardUsageBuilder::new().spread_usage(true).build().unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            // With the default parameters, we'd only consider one primary
+            // guard for a data circuit...
+            assert_eq!(guardmgr.effective_params().data_parallelism(), 1);
+            let primary = guardmgr.primary_guards();
+            assert_eq!(primary.len(), 2);
+
+            // ...but when spreading our usage, we cycle through all of them.
+            let mut monitors = Vec::new();
+            for i in 0..6 {
+                let (g, mon, _usable) = guardmgr.select_guard(spread_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[i % 2]);
+                monitors.push(mon);
+            }
 
-            // Now record a success for for directory usage.
-            guardmgr.note_external_success(&guard, ExternalActivity::DirCache);
+            // Ordinary data circuits still use the first primary guard.
+            let (g, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+        });
+    }
 
-            // Now that the guard is working as a cache, asking for it should get us the same guard.
-            let (g4, _mon, _usable) = guardmgr.select_guard(dir_usage).unwrap();
-            assert_eq!(g4.ed_identity(), guard.ed_identity());
+    #[test]
+    fn prefer_dir_success() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let data_usage = GuardUsage::default();
+            let dir_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .build()
+                .unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            // Both primary guards are equally good for directory requests.
+            guardmgr.set_dir_parallelism_override(Some(2));
+            let primary = guardmgr.primary_guards();
+            assert_eq!(primary.len(), 2);
+            assert_eq!(guardmgr.last_dir_success(&primary[1]), None);
+
+            // We prefer the one that served us directory information...
+            guardmgr.note_dir_success(&primary[1]);
+            assert!(guardmgr.last_dir_success(&primary[1]).is_some());
+            assert_eq!(guardmgr.last_dir_success(&primary[0]), None);
+            let mut monitors = Vec::new();
+            for _ in 0..20 {
+                let (g, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
+                assert_eq!(RelayIds::from_relay_ids(&g), primary[1]);
+                monitors.push(mon);
+            }
+
+            // ...but only for directory requests.
+            let (g, _mon, _usable) = guardmgr.select_guard(data_usage).unwrap();
+            assert_eq!(RelayIds::from_relay_ids(&g), primary[0]);
+        });
+    }
+
+    #[test]
+    fn recent_selections() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let data_usage = GuardUsage::default();
+            let dir_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .build()
+                .unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            assert!(guardmgr.recent_selections().is_empty());
+
+            guardmgr.set_selection_log_size(3);
+            let kinds = [
+                GuardUsageKind::Data,
+                GuardUsageKind::OneHopDirectory,
+                GuardUsageKind::Data,
+                GuardUsageKind::OneHopDirectory,
+                GuardUsageKind::Data,
+            ];
+            let mut chosen = Vec::new();
+            for kind in &kinds {
+                let usage = match kind {
+                    GuardUsageKind::Data => data_usage.clone(),
+                    _ => dir_usage.clone(),
+                };
+                let (g, _mon, _usable) = guardmgr.select_guard(usage).unwrap();
+                chosen.push(RelayIds::from_relay_ids(&g));
+            }
+
+            // We only remember the last three selections, in order.
+            let records = guardmgr.recent_selections();
+            assert_eq!(records.len(), 3);
+            for (record, (kind, ids)) in records.iter().zip(kinds.iter().zip(&chosen).skip(2)) {
+                assert_eq!(record.usage(), kind);
+                assert_eq!(record.guard().as_inner(), ids);
+                assert_eq!(record.origin(), ListKind::Primary);
+            }
+            assert!(records.windows(2).all(|w| w[0].when() <= w[1].when()));
+            // The guard's identity is redacted when we display it.
+            assert_ne!(records[0].guard().to_string(), chosen[2].to_string());
+
+            // Shrinking the log forgets the oldest entries; a size of 0
+            // disables it.
+            guardmgr.set_selection_log_size(1);
+            let records = guardmgr.recent_selections();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].guard().as_inner(), &chosen[4]);
+            guardmgr.set_selection_log_size(0);
+            let _ = guardmgr.select_guard(data_usage).unwrap();
+            assert!(guardmgr.recent_selections().is_empty());
+        });
+    }
+
+    #[test]
+    fn selection_order() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let usage = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            let order = guardmgr.selection_order(&usage);
+            assert!(!order.is_empty());
+            // Primary guards come first, and no guard is listed twice.
+            assert_eq!(order[0].1, ListKind::Primary);
+            assert!(order
+                .windows(2)
+                .all(|w| !w[1].1.is_primary() || w[0].1.is_primary()));
+            let distinct: HashSet<_> = order.iter().map(|(id, _)| id).collect();
+            assert_eq!(distinct.len(), order.len());
+
+            // Listing the order doesn't change it, and select_guard picks
+            // the first guard listed.
+            for _ in 0..3 {
+                let order = guardmgr.selection_order(&usage);
+                let (g, _mon, _usable) = guardmgr.select_guard(usage.clone()).unwrap();
+                assert_eq!(order[0].0, RelayIds::from_relay_ids(&g));
+            }
+        });
+    }
+
+    #[test]
+    fn external_status() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let data_usage = GuardUsage::default();
+            let dir_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .build()
+                .unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            {
+                // Override this parameter, so that we can get deterministic results below.
+                let mut inner = guardmgr.inner.lock().unwrap();
+                inner.params.dir_parallelism = 1;
+            }
+
+            let (guard, mon, _usable) = guardmgr.select_guard(data_usage.clone()).unwrap();
+            mon.succeeded();
+
+            // Record that this guard gave us a bad directory object.
+            guardmgr.note_external_failure(&guard, ExternalActivity::DirCache);
+
+            // We ask for another guard, for data usage.  We should get the same
+            // one as last time, since the director failure doesn't mean this
+            // guard is useless as a primary guard.
+            let (g2, mon, _usable) = guardmgr.select_guard(data_usage).unwrap();
+            assert_eq!(g2.ed_identity(), guard.ed_identity());
+            mon.succeeded();
+
+            // But if we ask for a guard for directory usage, we should get a
+            // different one, since the last guard we gave out failed.
+            let (g3, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
+            assert_ne!(g3.ed_identity(), guard.ed_identity());
+            mon.succeeded();
+
+            // Now record a success for for directory usage.
+            guardmgr.note_external_success(&guard, ExternalActivity::DirCache);
+
+            // Now that the guard is working as a cache, asking for it should get us the same guard.
+            let (g4, _mon, _usable) = guardmgr.select_guard(dir_usage).unwrap();
+            assert_eq!(g4.ed_identity(), guard.ed_identity());
+        });
+    }
+
+    #[test]
+    fn isolated_directory_guards() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let data_usage = GuardUsage::default();
+            let dir_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .build()
+                .unwrap();
+            assert!(!guardmgr.effective_params().isolate_directory_guards());
+            guardmgr.set_directory_guard_isolation(true);
+            assert!(guardmgr.effective_params().isolate_directory_guards());
+            guardmgr.install_test_netdir(&netdir);
+
+            // Confirm a guard for data usage.
+            let (data_guard, mon, _usable) = guardmgr.select_guard(data_usage.clone()).unwrap();
+            assert_eq!(data_guard.sample, Some(GuardSetSelector::Default));
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+
+            let data_sample: HashSet<Ed25519Identity> = {
+                let inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards();
+                let id = GuardId::from_relay_ids(&data_guard);
+                assert!(guards.get(&id).unwrap().confirmed());
+                guards.sampled_ed_identities().copied().collect()
+            };
+            assert!(data_sample.contains(data_guard.ed_identity().unwrap()));
+
+            // Directory requests never get a guard from the data sample, even
+            // as their guards fail and we have to move on to others.  (We have
+            // no fallbacks here, so we stop once every directory guard is down.)
+            let mut n_selected = 0;
+            for _ in 0..3 {
+                let Ok((g, mon, _usable)) = guardmgr.select_guard(dir_usage.clone()) else {
+                    break;
+                };
+                n_selected += 1;
+                if g.sample.is_some() {
+                    assert_eq!(g.sample, Some(GuardSetSelector::Directory));
+                    assert!(!data_sample.contains(g.ed_identity().unwrap()));
+                }
+                mon.failed();
+                guardmgr.flush_msg_queue().await;
+            }
+            assert!(n_selected >= 2);
+
+            // Data requests still use the guard we confirmed.
+            let (g, _mon, _usable) = guardmgr.select_guard(data_usage).unwrap();
+            assert_eq!(g.ed_identity(), data_guard.ed_identity());
+        });
+    }
+
+    #[test]
+    fn bootstrap_dir_guard() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            guardmgr.install_test_netdir(&netdir);
+
+            // Confirm a guard, so that we have a non-trivial confirmed set.
+            let (_g, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+
+            let snapshot = || {
+                let inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards();
+                let sample = guards.sample_ids().to_vec();
+                let confirmed: Vec<_> = sample
+                    .iter()
+                    .filter(|id| guards.get(id).unwrap().confirmed())
+                    .cloned()
+                    .collect();
+                (sample, confirmed)
+            };
+            let (sample, confirmed) = snapshot();
+            assert_eq!(confirmed.len(), 1);
+
+            for _ in 0..20 {
+                let ids = guardmgr.select_bootstrap_dir_guard(&netdir).unwrap();
+                let relay = netdir.by_ids(&ids).unwrap();
+                assert!(relay.low_level_details().is_dir_cache());
+                assert!(!sample.iter().any(|id| id.0 == ids));
+            }
+            assert_eq!(snapshot(), (sample, confirmed));
+            assert!(guardmgr.inner.lock().unwrap().pending.is_empty());
+        });
+    }
+
+    #[test]
+    fn retry_schedule() {
+        MockRuntime::test_with_various(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+            let unknown = RelayIds::builder()
+                .ed_identity([0x42; 32].into())
+                .build()
+                .unwrap();
+            assert!(guardmgr.retry_schedule(&unknown).is_none());
+
+            let t0 = rt.now();
+            let (g, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            let id = RelayIds::from_relay_ids(&g);
+            let info = guardmgr.retry_schedule(&id).unwrap();
+            assert_eq!(info.last_tried(), Some(t0));
+            assert_eq!(info.next_retry(), None);
+
+            // After a failure, we have to wait before retrying.
+            mon.failed();
+            guardmgr.flush_msg_queue().await;
+            let info = guardmgr.retry_schedule(&id).unwrap();
+            let delay = info.retry_delay().unwrap();
+            let next_retry = info.next_retry().unwrap();
+            assert!(next_retry > rt.now());
+            assert_eq!(next_retry, t0 + delay);
+
+            // Once that time has come, the guard is retriable again.
+            {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards_mut();
+                let guard_id = GuardId(id.clone());
+                guards.consider_all_retries(next_retry - Duration::from_millis(1));
+                let reachable = guards.get(&guard_id).unwrap().reachable();
+                assert_eq!(reachable, Reachable::Unreachable);
+                guards.consider_all_retries(next_retry);
+                let reachable = guards.get(&guard_id).unwrap().reachable();
+                assert_eq!(reachable, Reachable::Retriable);
+            }
+            let info = guardmgr.retry_schedule(&id).unwrap();
+            assert_eq!(info.next_retry(), None);
+            assert_eq!(info.retry_delay(), None);
+            assert_eq!(info.last_tried(), Some(t0));
+        });
+    }
+
+    #[test]
+    fn save_error_hook() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        MockRuntime::test_with_various(|rt| async move {
+            // We don't hold the lock on this state manager, so saving fails.
+            let statemgr = TestingStateMgr::new();
+            let guardmgr =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            let reported = Arc::new(AtomicUsize::new(0));
+            {
+                let reported = Arc::clone(&reported);
+                guardmgr.on_save_error(move |_| {
+                    reported.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+
+            assert!(guardmgr.store_persistent_state().is_err());
+            assert_eq!(reported.load(Ordering::SeqCst), 1);
+
+            // Repeated failures are rate-limited...
+            assert!(guardmgr.store_persistent_state().is_err());
+            assert!(guardmgr.flush_durable().is_err());
+            assert_eq!(reported.load(Ordering::SeqCst), 1);
+
+            // ...until enough time has passed.
+            rt.advance_by(SAVE_ERROR_REPORT_INTERVAL).await;
+            assert!(guardmgr.store_persistent_state().is_err());
+            assert_eq!(reported.load(Ordering::SeqCst), 2);
+
+            // After a successful save, the next failure is reported at once.
+            assert!(statemgr.try_lock().unwrap().held());
+            guardmgr.store_persistent_state().unwrap();
+            statemgr.unlock().unwrap();
+            assert!(guardmgr.store_persistent_state().is_err());
+            assert_eq!(reported.load(Ordering::SeqCst), 3);
+        });
+    }
+
+    #[test]
+    fn internet_down_retries_primary_guards() {
+        MockRuntime::test_with_various(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+            let timeout = guardmgr.effective_params().internet_down_timeout();
+
+            // We last heard from the network just now.
+            guardmgr.inner.lock().unwrap().test_last_incoming_traffic = Some(rt.now());
+            rt.advance_by(timeout + Duration::from_secs(1)).await;
+
+            // Every primary guard has failed.
+            let primary = {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                let now = rt.now();
+                let guards = inner.guards.active_guards_mut();
+                let primary = guards.primary_guard_ids().to_vec();
+                for id in &primary {
+                    guards.record_failure(id, None, now);
+                }
+                assert!(guards.all_primary_guards_are_unreachable());
+                primary
+            };
+
+            // So we have to use some other guard.  Once that works, we decide
+            // that the internet was down, and not our primary guards.
+            let (g, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(!primary
+                .iter()
+                .any(|id| id.0 == RelayIds::from_relay_ids(&g)));
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+
+            let inner = guardmgr.inner.lock().unwrap();
+            let guards = inner.guards.active_guards();
+            for id in &primary {
+                assert_eq!(guards.get(id).unwrap().reachable(), Reachable::Retriable);
+            }
+            assert_eq!(inner.last_primary_retry_time, rt.now());
+        });
+    }
+
+    #[test]
+    fn primary_guards_stable() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            // Confirm a guard, so that the state has a non-trivial order.
+            let (_g, mon, _usable) = guardmgr.select_guard(u).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            guardmgr.store_persistent_state().unwrap();
+            let expected = guardmgr.primary_guards();
+            assert_eq!(expected.len(), 2);
+            drop(guardmgr);
+
+            // Load two managers from the same state.
+            let guardmgr1 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr1.install_test_netdir(&netdir);
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr2.install_test_netdir(&netdir);
+
+            assert_eq!(guardmgr1.primary_guards(), expected);
+            assert_eq!(guardmgr2.primary_guards(), expected);
+        });
+    }
+
+    #[test]
+    fn guard_blocklist() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            let (blocked, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            let blocked_id = *blocked.ed_identity().unwrap();
+            let blocked_guard_id = ids::GuardId::from_relay_ids(&blocked);
+            guardmgr.set_guard_blocklist([blocked_id].into_iter().collect());
+
+            {
+                // The guard is no longer in the sample.
+                let inner = guardmgr.inner.lock().unwrap();
+                assert_eq!(
+                    inner.guards.active_guards().contains(&blocked_guard_id),
+                    Ok(false)
+                );
+            }
+
+            // And we never select it, even once we've run out of other guards.
+            for _ in 0..20 {
+                let Ok((g, mon, _usable)) = guardmgr.select_guard(u.clone()) else {
+                    break;
+                };
+                assert_ne!(g.ed_identity(), Some(&blocked_id));
+                mon.failed();
+                guardmgr.flush_msg_queue().await;
+            }
+
+            // The blocklist is persistent.
+            guardmgr.store_persistent_state().unwrap();
+            drop(guardmgr);
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr2.install_test_netdir(&netdir);
+            let inner = guardmgr2.inner.lock().unwrap();
+            assert!(inner.guards.blocklist.contains(&blocked_id));
+            assert!(inner
+                .guards
+                .active_guards()
+                .blocklist()
+                .contains(&blocked_id));
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn selection_timing() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            // Off by default.
+            let _ = guardmgr.select_guard(u.clone()).unwrap();
+            assert!(guardmgr.metrics().selection_timing().is_none());
+
+            guardmgr.set_selection_timing(true);
+            for _ in 0..3 {
+                let _ = guardmgr.select_guard(u.clone()).unwrap();
+            }
+            let timing = guardmgr.metrics().selection_timing().clone().unwrap();
+            assert_eq!(timing.n_selections(), 3);
+            assert!(timing.max() <= timing.total());
+            assert!(guardmgr
+                .metrics_prometheus()
+                .contains("arti_guard_selections_timed_total 3"));
+
+            guardmgr.set_selection_timing(false);
+            assert!(guardmgr.metrics().selection_timing().is_none());
+        });
+    }
+
+    #[test]
+    fn guard_state_versioning() {
+        use tor_persist::JsonValue;
+
+        let mut guards = GuardSets::default();
+        guards.blocklist.insert(Ed25519Identity::from([7; 32]));
+
+        // We write the current version.
+        let json = serde_json::to_value(&guards).unwrap();
+        assert_eq!(json["version"], JsonValue::from(GUARD_STATE_VERSION));
+        assert!(json["data"]["blocklist"].is_array());
+
+        // ...and can read it back.
+        let loaded: GuardSets = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(loaded.blocklist, guards.blocklist);
+
+        // We can read state from before we had an envelope.
+        let loaded: GuardSets = serde_json::from_value(json["data"].clone()).unwrap();
+        assert_eq!(loaded.blocklist, guards.blocklist);
+
+        // But we reject a version we don't know.
+        let mut future = json;
+        future["version"] = JsonValue::from(GUARD_STATE_VERSION + 1);
+        let err = serde_json::from_value::<GuardSets>(future).unwrap_err();
+        assert!(
+            err.to_string().contains("Unsupported guard state version"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn freeze_sample() {
+        test_with_all_runtimes!(|rt| async move {
+            use tor_netdir::{
+                testnet, testprovider::TestNetDirProvider, MdReceiver, PartialNetDir,
+            };
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            let provider = Arc::new(TestNetDirProvider::new());
+            let dyn_provider: Arc<dyn NetDirProvider> = provider.clone();
+            guardmgr.install_netdir_provider(&dyn_provider).unwrap();
+            let install_netdir = |netdir: NetDir| {
+                provider.set_netdir(netdir);
+                let mut inner = guardmgr.inner.lock().unwrap();
+                inner.update(rt.wallclock(), rt.now());
+            };
+            install_netdir(netdir);
+            let sample_len = || {
+                let inner = guardmgr.inner.lock().unwrap();
+                inner.guards.active_guards().sample_ids().len()
+            };
+            let original = sample_len();
+            assert!(original >= 5);
+
+            // A netdir that asks for a larger sample.
+            let (con, mds) = testnet::construct_network().unwrap();
+            let override_p = "guard-min-filtered-sample-size=10 guard-n-primary-guards=2"
+                .parse()
+                .unwrap();
+            let mut bigger = PartialNetDir::new(con, Some(&override_p));
+            for md in mds {
+                bigger.add_microdesc(md);
+            }
+            let bigger = bigger.unwrap_if_sufficient().unwrap();
+
+            guardmgr.freeze_sample(true);
+            install_netdir(bigger);
+            assert_eq!(sample_len(), original);
+
+            // We can still select guards, and they come from the sample.
+            let (g, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            {
+                let inner = guardmgr.inner.lock().unwrap();
+                let id = ids::GuardId::from_relay_ids(&g);
+                assert_eq!(inner.guards.active_guards().contains(&id), Ok(true));
+            }
+
+            // Once we unfreeze, the sample grows.
+            guardmgr.freeze_sample(false);
+            assert!(sample_len() >= 10);
+        });
+    }
+
+    #[test]
+    fn guard_notes() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            let (g, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+            let id = ids::GuardId::from_relay_ids(&g).0;
+            assert!(guardmgr.guard_notes().is_empty());
+            assert!(guardmgr.set_guard_note(&id, "trusted friend's relay".into()));
+            assert_eq!(
+                guardmgr.guard_notes(),
+                vec![(id.clone(), "trusted friend's relay".to_owned())]
+            );
+
+            // We can't annotate a guard that isn't in the sample.
+            let stranger = RelayIds::builder()
+                .ed_identity([42; 32].into())
+                .build()
+                .unwrap();
+            assert!(!guardmgr.set_guard_note(&stranger, "fast".into()));
+
+            // Notes are persistent.
+            guardmgr.store_persistent_state().unwrap();
+            drop(guardmgr);
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr2.install_test_netdir(&netdir);
+            assert_eq!(
+                guardmgr2.guard_notes(),
+                vec![(id.clone(), "trusted friend's relay".to_owned())]
+            );
+
+            // An empty note removes it.
+            assert!(guardmgr2.set_guard_note(&id, String::new()));
+            assert!(guardmgr2.guard_notes().is_empty());
+        });
+    }
+
+    #[test]
+    fn effective_params() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            guardmgr.install_test_netdir(&netdir);
+
+            let expected = GuardParams::try_from(netdir.params()).unwrap();
+            let view = guardmgr.effective_params();
+            // These are set by init().
+            assert_eq!(view.min_filtered_sample_size(), 5);
+            assert_eq!(view.n_primary(), 2);
+            assert_eq!(view.lifetime_unconfirmed(), expected.lifetime_unconfirmed);
+            assert_eq!(view.lifetime_confirmed(), expected.lifetime_confirmed);
+            assert_eq!(view.lifetime_unlisted(), expected.lifetime_unlisted);
+            assert_eq!(view.max_sample_size(), expected.max_sample_size);
+            assert_eq!(view.data_parallelism(), expected.data_parallelism);
+            assert_eq!(view.dir_parallelism(), expected.dir_parallelism);
+            assert_eq!(view.np_connect_timeout(), expected.np_connect_timeout);
+            assert_eq!(view.np_idle_timeout(), expected.np_idle_timeout);
+            assert_eq!(view.internet_down_timeout(), expected.internet_down_timeout);
+
+            // Overrides are reflected.
+            guardmgr.set_dir_parallelism_override(Some(7));
+            assert_eq!(guardmgr.effective_params().dir_parallelism(), 7);
+        });
+    }
+
+    #[test]
+    fn test_network_mode() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            assert!(!guardmgr.effective_params().test_network());
+            guardmgr.set_test_network_mode(true);
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+
+            let view = guardmgr.effective_params();
+            assert!(view.test_network());
+            assert_eq!(
+                view.min_filtered_sample_size(),
+                TEST_NETWORK_MIN_FILTERED_SAMPLE_SIZE
+            );
+            assert_eq!(view.lifetime_unlisted(), TEST_NETWORK_LIFETIME_UNLISTED);
+
+            // Have the primary guards fail, so that we'll try non-primary ones.
+            for _ in 0..2 {
+                let (_id, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+                mon.failed();
+                guardmgr.flush_msg_queue().await;
+            }
+
+            // Ordinarily, a success with the second of these guards would have
+            // to wait until we heard about the first one.  Here, it's usable
+            // and confirmed right away.
+            let (_id3, _mon3, _usable3) = guardmgr.select_guard(u.clone()).unwrap();
+            let (id4, mon4, usable4) = guardmgr.select_guard(u.clone()).unwrap();
+            mon4.succeeded();
+            assert!(usable4.await.unwrap());
+            {
+                let inner = guardmgr.inner.lock().unwrap();
+                let id = GuardId::from_relay_ids(&id4);
+                assert!(inner.guards.active_guards().get(&id).unwrap().confirmed());
+            }
+
+            // Turning the mode off restores the usual parameters.
+            guardmgr.set_test_network_mode(false);
+            let view = guardmgr.effective_params();
+            assert!(!view.test_network());
+            assert_eq!(view.min_filtered_sample_size(), 5);
+        });
+    }
+
+    #[test]
+    fn guard_acceptor() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+
+            let vetoed = select_and_succeed(&guardmgr, u.clone()).await;
+            let vetoed_id = *vetoed.ed_identity().unwrap();
+            let vetoed_guard_id = ids::GuardId::from_relay_ids(&vetoed);
+            guardmgr.set_guard_acceptor(Arc::new(move |relay: &Relay<'_>| {
+                relay.ed_identity() != Some(&vetoed_id)
+            }));
+
+            {
+                // The guard stays in the sample, but it isn't primary anymore.
+                let inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards();
+                assert_eq!(guards.contains(&vetoed_guard_id), Ok(true));
+                assert!(guards.vetoed().contains(&vetoed_id));
+                assert!(!guards.primary_guard_ids().contains(&vetoed_guard_id));
+            }
+
+            // And we never select it, even once we've run out of other guards.
+            for _ in 0..20 {
+                let Ok((g, mon, _usable)) = guardmgr.select_guard(u.clone()) else {
+                    break;
+                };
+                assert_ne!(g.ed_identity(), Some(&vetoed_id));
+                mon.failed();
+                guardmgr.flush_msg_queue().await;
+            }
+
+            // Once the acceptor changes its mind, we can use the guard again.
+            guardmgr.set_guard_acceptor(Arc::new(|_: &Relay<'_>| true));
+            let inner = guardmgr.inner.lock().unwrap();
+            assert!(inner.guards.active_guards().vetoed().is_empty());
+        });
+    }
+
+    #[test]
+    fn shrunken_netdir() {
+        test_with_all_runtimes!(|rt| async move {
+            use tor_netdir::{testnet, testprovider::TestNetDirProvider};
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            let provider = Arc::new(TestNetDirProvider::new());
+            let dyn_provider: Arc<dyn NetDirProvider> = provider.clone();
+            guardmgr.install_netdir_provider(&dyn_provider).unwrap();
+            let install_netdir = |netdir: NetDir| {
+                provider.set_netdir(netdir);
+                let mut inner = guardmgr.inner.lock().unwrap();
+                inner.update(rt.wallclock(), rt.now());
+            };
+            install_netdir(netdir);
+            let (_guard, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+            mon.succeeded();
+            guardmgr.flush_msg_queue().await;
+
+            // Build a directory that lists only two guard-flagged relays, none
+            // of which are in our sample.
+            let sampled: Vec<ids::GuardId> = {
+                let inner = guardmgr.inner.lock().unwrap();
+                inner.guards.active_guards().sample_ids().to_vec()
+            };
+            let in_sample = |idx: usize| {
+                let id = ids::GuardId::new([idx as u8; 32].into(), [idx as u8; 20].into());
+                sampled.contains(&id)
+            };
+            let keep: Vec<usize> = (20..40).filter(|idx| !in_sample(*idx)).take(2).collect();
+            let shrunken = || {
+                testnet::construct_custom_netdir(|idx, node| {
+                    if idx >= 20 && !keep.contains(&idx) {
+                        node.omit_rs = true;
+                    }
+                })
+                .unwrap()
+                .unwrap_if_sufficient()
+                .unwrap()
+            };
+
+            // The first such directory doesn't change our sample.
+            install_netdir(shrunken());
+            {
+                let inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards();
+                assert!(inner.guard_count.suspect);
+                assert_eq!(guards.sample_ids(), &sampled[..]);
+                for id in &sampled {
+                    assert!(guards.get(id).unwrap().usable());
+                }
+            }
+
+            // But once another one confirms it, we believe it.
+            install_netdir(shrunken());
+            let inner = guardmgr.inner.lock().unwrap();
+            let guards = inner.guards.active_guards();
+            assert!(!inner.guard_count.suspect);
+            for id in &sampled {
+                assert!(!guards.get(id).unwrap().usable());
+            }
+        });
+    }
+
+    #[test]
+    fn last_netdir_info() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            assert!(guardmgr.last_netdir_info().is_none());
+
+            guardmgr.install_test_netdir(&netdir);
+            let info = guardmgr.last_netdir_info().unwrap();
+            let lifetime = netdir.lifetime();
+            assert_eq!(info.valid_after(), lifetime.valid_after());
+            assert_eq!(info.fresh_until(), lifetime.fresh_until());
+            assert_eq!(info.valid_until(), lifetime.valid_until());
+            // In the test network, relays 20 through 39 are guards.
+            assert_eq!(info.n_guards(), 20);
+        });
+    }
+
+    #[test]
+    fn clear_reachability_state() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+
+            let primary = {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards_mut();
+                let primary = guards.primary_guard_ids().to_vec();
+                assert!(!primary.is_empty());
+                for id in &primary {
+                    guards.record_failure(id, None, rt.now());
+                    guards.record_failure(id, Some(ExternalActivity::DirCache), rt.now());
+                }
+                assert!(guards.all_primary_guards_are_unreachable());
+                primary
+            };
+
+            // With all our primary guards down, we get some other guard.
+            let (id, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(!primary.iter().any(|p| id.same_relay_ids(&p.0)));
+
+            guardmgr.clear_reachability_state();
+
+            {
+                use crate::guard::Reachable;
+                let mut inner = guardmgr.inner.lock().unwrap();
+                let guards = inner.guards.active_guards_mut();
+                assert_eq!(guards.primary_guard_ids(), &primary[..]);
+                for id in &primary {
+                    let g = guards.get(id).unwrap();
+                    assert_ne!(g.reachable(), Reachable::Unreachable);
+                    for kind in [GuardUsageKind::Data, GuardUsageKind::OneHopDirectory] {
+                        let usage = GuardUsageBuilder::new().kind(kind).build().unwrap();
+                        assert!(g.ready_for_usage(&usage, rt.now()));
+                    }
+                }
+            }
+
+            // Now the first primary guard is back in use.
+            let (id, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(id.same_relay_ids(&primary[0].0));
+        });
+    }
+
+    #[test]
+    fn sample_fingerprint() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+            let fp0 = guardmgr.sample_fingerprint();
+            assert_eq!(fp0.to_string().len(), 16);
+
+            // Selecting a guard, without confirming it, doesn't change anything.
+            let (_id, mon, usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert_eq!(guardmgr.sample_fingerprint(), fp0);
+
+            // Once the guard is confirmed, the fingerprint changes.
+            mon.succeeded();
+            assert!(usable.await.unwrap());
+            guardmgr.flush_msg_queue().await;
+            let fp1 = guardmgr.sample_fingerprint();
+            assert_ne!(fp1, fp0);
+            assert_eq!(guardmgr.sample_fingerprint(), fp1);
+
+            // It stays the same across a restart.
+            guardmgr.store_persistent_state().unwrap();
+            drop(guardmgr);
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            guardmgr2.install_test_netdir(&netdir);
+            assert_eq!(guardmgr2.sample_fingerprint(), fp1);
+        });
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+
+            let (guard, mon, usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            assert!(usable.await.unwrap());
+            guardmgr.flush_msg_queue().await;
+            let guard_id = ids::GuardId::from_relay_ids(&guard);
+            let primary = guardmgr.primary_guards();
+
+            // We sleep for longer than any guard's lifetime.
+            let day = Duration::from_secs(86400);
+            let later = rt.wallclock() + day * 1000;
+            guardmgr.suspend();
+            {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                assert!(inner.is_suspended());
+                inner.run_periodic_events(later, rt.now());
+            }
+
+            // After we wake up, our directory is a thousand days out of date,
+            // so we still don't expire anything.
+            guardmgr.resume();
+            {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                assert!(!inner.is_suspended());
+                inner.run_periodic_events(later, rt.now());
+                inner.update(later, rt.now());
+
+                let g = inner.guards.active_guards().get(&guard_id).unwrap();
+                assert!(g.confirmed());
+
+                // The time we spent asleep doesn't count as time offline.
+                assert!(
+                    inner.time_offline(day * 1000, rt.now()) < inner.params.internet_down_timeout
+                );
+                // But the time since we resumed does.
+                let secs = Duration::from_secs(3);
+                assert_eq!(inner.time_offline(secs, rt.now() + secs * 2), secs);
+            }
+            assert_eq!(guardmgr.primary_guards(), primary);
+        });
+    }
+
+    #[test]
+    fn status_for_removed_guard() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let u = GuardUsage::default();
+            guardmgr.install_test_netdir(&netdir);
+
+            // Have all the primary guards fail, so that we get non-primary
+            // guards, whose circuits aren't usable immediately.
+            let n_primary = guardmgr.effective_params().n_primary();
+            for _ in 0..n_primary {
+                let (_id, mon, _usable) = guardmgr.select_guard(u.clone()).unwrap();
+                mon.failed();
+                guardmgr.flush_msg_queue().await; // avoid race
+            }
+
+            let (g1, mon1, usable1) = guardmgr.select_guard(u.clone()).unwrap();
+            let (g2, mon2, usable2) = guardmgr.select_guard(u.clone()).unwrap();
+            let removed: Vec<_> = [&g1, &g2]
+                .into_iter()
+                .map(ids::GuardId::from_relay_ids)
+                .collect();
+
+            // Both guards leave the sample before we hear how they did.
+            guardmgr.set_guard_blocklist(
+                [&g1, &g2]
+                    .into_iter()
+                    .map(|g| *g.ed_identity().unwrap())
+                    .collect(),
+            );
+            mon1.succeeded();
+            mon2.failed();
+            guardmgr.flush_msg_queue().await;
+
+            // Neither circuit is usable, since its guard is not listed.
+            assert!(!usable1.await.unwrap());
+            assert!(!usable2.await.unwrap());
+
+            // And the guards were not put back in the sample.
+            let inner = guardmgr.inner.lock().unwrap();
+            let guards = inner.guards.active_guards();
+            for id in &removed {
+                assert_eq!(guards.contains(id), Ok(false));
+                assert!(!guards.sample_ids().contains(id));
+            }
+        });
+    }
+
+    #[test]
+    fn abandon_cleans_up_pending() {
+        test_with_all_runtimes!(|rt| async move {
+            use crate::guard::Reachable;
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+
+            // (We pick a primary guard here, so the circuit's usability was
+            // known from the start: we only care about the pending request.)
+            let (g, mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert_eq!(guardmgr.inner.lock().unwrap().pending.len(), 1);
+
+            mon.attempt_abandoned();
+            guardmgr.flush_msg_queue().await;
+
+            // The request is gone at once...
+            assert!(guardmgr.inner.lock().unwrap().pending.is_empty());
+
+            // ...but nothing was held against the guard.
+            let inner = guardmgr.inner.lock().unwrap();
+            let guard = inner
+                .guards
+                .active_guards()
+                .get(&ids::GuardId::from_relay_ids(&g))
+                .unwrap();
+            assert_ne!(guard.reachable(), Reachable::Unreachable);
+        });
+    }
+
+    #[test]
+    fn low_power() {
+        test_with_all_runtimes!(|rt| async move {
+            use tor_netdir::testprovider::TestNetDirProvider;
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            guardmgr.set_power_mode(PowerMode::LowPower);
+            // Install the netdir without running an update.
+            let provider: Arc<dyn NetDirProvider> = Arc::new(TestNetDirProvider::from(netdir));
+            guardmgr.install_netdir_provider(&provider).unwrap();
+
+            let mut inner = guardmgr.inner.lock().unwrap();
+            let low_power_interval = inner.run_periodic_events(rt.wallclock(), rt.now());
+            // An idle tick doesn't expand the sample.
+            assert!(inner.guards.active_guards().sample_ids().is_empty());
+
+            inner.power_mode = PowerMode::Normal;
+            let normal_interval = inner.run_periodic_events(rt.wallclock(), rt.now());
+            assert!(low_power_interval >= normal_interval * 10);
+            assert!(!inner.guards.active_guards().sample_ids().is_empty());
+        });
+    }
+
+    #[test]
+    fn dir_parallelism_override() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+            let dir_usage = GuardUsageBuilder::new()
+                .kind(GuardUsageKind::OneHopDirectory)
+                .build()
+                .unwrap();
+            guardmgr.install_test_netdir(&netdir);
+            {
+                // Make the consensus value as small as possible.
+                let mut inner = guardmgr.inner.lock().unwrap();
+                inner.params.dir_parallelism = 1;
+            }
+
+            // Select many guards, and count how many distinct ones we are offered.
+            let n_distinct = |guardmgr: &GuardMgr<_>| {
+                let mut ids = Vec::new();
+                for _ in 0..50 {
+                    let (g, mon, _usable) = guardmgr.select_guard(dir_usage.clone()).unwrap();
+                    mon.attempt_abandoned();
+                    let id = g.ed_identity().unwrap().clone();
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+                ids.len()
+            };
+
+            // Only the first primary guard is ever offered.
+            assert_eq!(n_distinct(&guardmgr), 1);
+
+            // With the override, both primary guards are offered.
+            guardmgr.set_dir_parallelism_override(Some(3));
+            assert_eq!(n_distinct(&guardmgr), 2);
+
+            // Clearing the override restores the consensus value.
+            guardmgr.set_dir_parallelism_override(None);
+            assert_eq!(n_distinct(&guardmgr), 1);
+        });
+    }
+
+    #[test]
+    fn dir_parallelism_override_cleared_after_bootstrap() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt);
+
+            // An override set while we're bootstrapping lasts until we have a
+            // usable directory...
+            guardmgr.set_dir_parallelism_override(Some(7));
+            assert_eq!(guardmgr.effective_params().dir_parallelism(), 7);
+            guardmgr.install_test_netdir(&netdir);
+            let consensus_value = guardmgr.inner.lock().unwrap().params.dir_parallelism;
+            assert_ne!(consensus_value, 7);
+            assert_eq!(
+                guardmgr.effective_params().dir_parallelism(),
+                consensus_value
+            );
+
+            // ...but one set afterwards isn't cleared when we get a new one.
+            guardmgr.set_dir_parallelism_override(Some(7));
+            guardmgr.update_network(Arc::new(netdir));
+            assert_eq!(guardmgr.effective_params().dir_parallelism(), 7);
+        });
+    }
+
+    #[test]
+    fn recent_dropped() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let _provider = install_test_provider(&guardmgr, netdir, &rt);
+            assert!(guardmgr.recent_dropped().is_empty());
+
+            // Every guard in the sample is unconfirmed, so they all expire
+            // once they've been in the sample for long enough.
+            let later = rt.wallclock() + Duration::from_secs(86400 * 200);
+            {
+                let mut inner = guardmgr.inner.lock().unwrap();
+                inner.update(later, rt.now());
+            }
+            let dropped = guardmgr.recent_dropped();
+            assert!(!dropped.is_empty());
+            assert!(dropped.len() <= DEFAULT_DROP_LOG_SIZE);
+            for d in &dropped {
+                assert_eq!(d.reason(), DropReason::ExpiredUnconfirmed);
+                assert_eq!(d.when(), later);
+            }
+
+            // Shrinking the log forgets the oldest entries.
+            guardmgr.set_drop_log_size(1);
+            let last = guardmgr.recent_dropped();
+            assert_eq!(last.len(), 1);
+            assert_eq!(last[0].guard(), dropped.last().unwrap().guard());
+
+            // Blocklisting a guard in the sample drops it too.
+            let (g, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            let blocked_id = *g.ed_identity().unwrap();
+            guardmgr.set_guard_blocklist([blocked_id].into_iter().collect());
+            let dropped = guardmgr.recent_dropped();
+            assert_eq!(dropped.len(), 1);
+            assert_eq!(dropped[0].reason(), DropReason::Blocklisted);
+            assert_eq!(dropped[0].guard().ed_identity(), Some(&blocked_id));
+
+            // A size of 0 disables the log.
+            guardmgr.set_drop_log_size(0);
+            assert!(guardmgr.recent_dropped().is_empty());
         });
     }
 
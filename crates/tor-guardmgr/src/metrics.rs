@@ -0,0 +1,255 @@
+//! Metrics about a [`GuardMgr`](crate::GuardMgr), for export to monitoring
+//! systems.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::GuardMgrInner;
+
+/// A snapshot of metrics about a [`GuardMgr`](crate::GuardMgr).
+///
+/// Returned by [`GuardMgr::metrics`](crate::GuardMgr::metrics).
+///
+/// All of these describe the active guard sample, and are "gauges": they can
+/// go down as well as up.
+#[derive(Clone, Debug, amplify::Getters)]
+pub struct GuardMetrics {
+    /// The number of guards in the sample.
+    #[getter(as_copy)]
+    n_sampled: usize,
+    /// The number of guards in the sample that are confirmed.
+    #[getter(as_copy)]
+    n_confirmed: usize,
+    /// The number of primary guards.
+    #[getter(as_copy)]
+    n_primary: usize,
+    /// The number of guards that our filter permits, that are listed, and
+    /// that we have not found to be unreachable.
+    #[getter(as_copy)]
+    n_usable: usize,
+    /// The number of guards that our filter permits, that are listed, but
+    /// that we have found to be unreachable.
+    #[getter(as_copy)]
+    n_down: usize,
+    /// The number of guards we've given out whose status hasn't been reported.
+    #[getter(as_copy)]
+    n_pending_requests: usize,
+    /// The number of circuits that succeeded, but whose requests are waiting
+    /// to learn whether they may be used.
+    #[getter(as_copy)]
+    n_waiting_requests: usize,
+    /// How long guard selection has taken, if we are measuring that.
+    ///
+    /// See [`GuardMgr::set_selection_timing`](crate::GuardMgr::set_selection_timing).
+    selection_timing: Option<SelectionTiming>,
+}
+
+/// Measurements of how long [`GuardMgr::select_guard`](crate::GuardMgr::select_guard)
+/// has spent choosing guards, while holding the `GuardMgr`'s lock.
+#[derive(Clone, Debug, Default, amplify::Getters)]
+pub struct SelectionTiming {
+    /// The number of guard selections we have measured.
+    #[getter(as_copy)]
+    n_selections: u64,
+    /// The total time spent on all of those selections.
+    #[getter(as_copy)]
+    total: Duration,
+    /// The longest time spent on any one of those selections.
+    #[getter(as_copy)]
+    max: Duration,
+}
+
+impl SelectionTiming {
+    /// Record that a guard selection took `elapsed`.
+    pub(crate) fn record(&mut self, elapsed: Duration) {
+        self.n_selections = self.n_selections.saturating_add(1);
+        self.total = self.total.saturating_add(elapsed);
+        self.max = self.max.max(elapsed);
+    }
+}
+
+impl GuardMetrics {
+    /// Take a snapshot of the metrics for `inner`.
+    pub(crate) fn from_inner(inner: &GuardMgrInner) -> Self {
+        let guards = inner.guards.active_guards();
+        let health = guards.sample_health(&inner.params);
+        GuardMetrics {
+            n_sampled: guards.sample_ids().len(),
+            n_confirmed: guards.confirmed_guard_ids().len(),
+            n_primary: guards.primary_guard_ids().len(),
+            n_usable: health.n_usable(),
+            n_down: health.n_down(),
+            n_pending_requests: inner.pending.len(),
+            n_waiting_requests: inner.waiting.len(),
+            selection_timing: inner.selection_timing.clone(),
+        }
+    }
+
+    /// Render this snapshot in the Prometheus text exposition format.
+    ///
+    /// Every metric has a name beginning with `arti_guard_`,
+    /// and is preceded by `# HELP` and `# TYPE` lines.
+    /// The guard selection timing metrics are only included if we are
+    /// measuring guard selection.
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "arti_guard_sampled",
+                "Number of guards in the sample.",
+                self.n_sampled,
+            ),
+            (
+                "arti_guard_confirmed",
+                "Number of confirmed guards in the sample.",
+                self.n_confirmed,
+            ),
+            (
+                "arti_guard_primary",
+                "Number of primary guards.",
+                self.n_primary,
+            ),
+            (
+                "arti_guard_usable",
+                "Number of permitted guards not known to be unreachable.",
+                self.n_usable,
+            ),
+            (
+                "arti_guard_down",
+                "Number of permitted guards known to be unreachable.",
+                self.n_down,
+            ),
+            (
+                "arti_guard_pending_requests",
+                "Number of guard requests whose status has not been reported.",
+                self.n_pending_requests,
+            ),
+            (
+                "arti_guard_waiting_requests",
+                "Number of successful circuits waiting to learn whether they may be used.",
+                self.n_waiting_requests,
+            ),
+        ];
+
+        let mut out = String::new();
+        let mut write = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            writeln!(out, "# HELP {} {}", name, help).expect("Can't write to a String?");
+            writeln!(out, "# TYPE {} {}", name, kind).expect("Can't write to a String?");
+            writeln!(out, "{} {}", name, value).expect("Can't write to a String?");
+        };
+        for (name, help, value) in metrics {
+            write(name, "gauge", help, &value);
+        }
+        if let Some(timing) = &self.selection_timing {
+            write(
+                "arti_guard_selections_timed_total",
+                "counter",
+                "Number of guard selections whose duration was measured.",
+                &timing.n_selections,
+            );
+            write(
+                "arti_guard_selection_seconds_total",
+                "counter",
+                "Total time spent selecting guards.",
+                &timing.total.as_secs_f64(),
+            );
+            write(
+                "arti_guard_selection_seconds_max",
+                "gauge",
+                "Longest time spent on a single guard selection.",
+                &timing.max.as_secs_f64(),
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    /// Return true if `name` is a valid Prometheus metric name.
+    fn valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    /// Check that `text` is valid Prometheus text exposition format, as far
+    /// as we use it, and return the value of every metric in it.
+    fn parse_prometheus(text: &str) -> Vec<(String, f64)> {
+        let mut typed = Vec::new();
+        let mut samples = Vec::new();
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# HELP ") {
+                let (name, help) = rest.split_once(' ').unwrap();
+                assert!(valid_name(name), "{}", line);
+                assert!(!help.is_empty());
+            } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = rest.split_once(' ').unwrap();
+                assert!(valid_name(name), "{}", line);
+                assert!(["counter", "gauge"].contains(&kind), "{}", line);
+                typed.push(name.to_owned());
+            } else {
+                let (name, value) = line.split_once(' ').unwrap();
+                assert!(valid_name(name), "{}", line);
+                assert!(typed.iter().any(|t| t == name), "no TYPE for {}", name);
+                samples.push((name.to_owned(), value.parse().unwrap()));
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn prometheus() {
+        let metrics = GuardMetrics {
+            n_sampled: 20,
+            n_confirmed: 3,
+            n_primary: 2,
+            n_usable: 18,
+            n_down: 2,
+            n_pending_requests: 1,
+            n_waiting_requests: 0,
+            selection_timing: None,
+        };
+        let text = metrics.to_prometheus();
+        let samples = parse_prometheus(&text);
+        assert_eq!(samples.len(), 7);
+        let value = |name: &str| samples.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(value("arti_guard_sampled"), 20.0);
+        assert_eq!(value("arti_guard_confirmed"), 3.0);
+        assert_eq!(value("arti_guard_primary"), 2.0);
+        assert_eq!(value("arti_guard_usable"), 18.0);
+        assert_eq!(value("arti_guard_down"), 2.0);
+        assert_eq!(value("arti_guard_pending_requests"), 1.0);
+        assert_eq!(value("arti_guard_waiting_requests"), 0.0);
+
+        let mut timing = SelectionTiming::default();
+        timing.record(Duration::from_millis(3));
+        timing.record(Duration::from_millis(1));
+        let metrics = GuardMetrics {
+            selection_timing: Some(timing),
+            ..metrics
+        };
+        let samples = parse_prometheus(&metrics.to_prometheus());
+        assert_eq!(samples.len(), 10);
+        let value = |name: &str| samples.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(value("arti_guard_selections_timed_total"), 2.0);
+        assert_eq!(value("arti_guard_selection_seconds_total"), 0.004);
+        assert_eq!(value("arti_guard_selection_seconds_max"), 0.003);
+    }
+}
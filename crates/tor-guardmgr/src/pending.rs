@@ -16,7 +16,7 @@ use std::fmt::Debug;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tor_async_utils::oneshot;
 use tor_proto::ClockSkew;
 
@@ -110,6 +110,12 @@ pub enum GuardStatus {
 ///
 /// The `GuardMgr` needs to know about these statuses, so that it can tell
 /// whether the guard is running or not.
+///
+/// Reporting a status never blocks, and never takes the guard manager's lock:
+/// the report is added to an unbounded queue, which a background task
+/// drains.  (Since the queue is unbounded, reports are never dropped
+/// because it is full.)  So it is fine to report statuses from
+/// synchronous code, or at a high rate.
 #[must_use = "You need to report the status of any guard that you asked for"]
 #[derive(Educe)]
 #[educe(Debug)]
@@ -173,6 +179,12 @@ impl GuardMonitor {
     /// Report that we did not try to build a circuit using the guard,
     /// or that we can't tell whether the guard is working.
     ///
+    /// Call this when a circuit build is cancelled before we learn whether
+    /// it succeeded.  Unlike [`failed`](GuardMonitor::failed), this doesn't
+    /// count against the guard; and the guard manager stops tracking the
+    /// request as soon as it gets this report, rather than waiting for it to
+    /// time out.
+    ///
     /// Dropping a `GuardMonitor` is without calling `succeeded` or
     /// `failed` or `pending_status` is equivalent to calling this
     /// function.
@@ -287,6 +299,11 @@ pub(crate) struct PendingRequest {
     /// (This is an option so that we can safely make reply() once-only.
     /// Otherwise we run into lifetime issues elsewhere.)
     usable: Option<oneshot::Sender<bool>>,
+    /// The time at which we gave out this guard.
+    ///
+    /// We use this to tell how long the circuit built through this guard
+    /// has been pending.
+    launched_at: Instant,
     /// The time at which the circuit manager told us that this guard was
     /// successful.
     waiting_since: Option<Instant>,
@@ -305,11 +322,13 @@ impl PendingRequest {
         usage: crate::GuardUsage,
         usable: Option<oneshot::Sender<bool>>,
         net_has_been_down: bool,
+        now: Instant,
     ) -> Self {
         PendingRequest {
             guard_id,
             usage,
             usable,
+            launched_at: now,
             waiting_since: None,
             net_has_been_down,
         }
@@ -349,6 +368,42 @@ impl PendingRequest {
         }
     }
 
+    /// Return true if the circuit for this request "blocks" the circuit for
+    /// `other`, in the sense of the guard specification.
+    ///
+    /// The spec says that a circuit C2 blocks C1 if:
+    ///  * C2 obeys all the restrictions that C1 had to obey, AND
+    ///  * C2 has higher priority than C1, AND
+    ///  * Either C2 is `<complete>`, or C2 is `<waiting_for_better_guard>`,
+    ///    or C2 has been `<usable_if_no_better_guard>` for no more than
+    ///    `connect_timeout`.
+    ///
+    /// The caller is responsible for checking the second condition, since
+    /// priority depends on the order of guards in the sample.  The caller is
+    /// also responsible for checking that this request's guard obeys the
+    /// restrictions of `other`'s usage: here we only check that the two
+    /// requests are for the same kind of circuit.
+    ///
+    /// We never see `<complete>` circuits here, since we stop tracking a
+    /// request once we've told the circuit manager that it may be used.
+    pub(crate) fn blocks(
+        &self,
+        other: &PendingRequest,
+        connect_timeout: Duration,
+        now: Instant,
+    ) -> bool {
+        if self.usage.kind != other.usage.kind {
+            return false;
+        }
+        match self.waiting_since {
+            // This circuit has been built, and is waiting to see whether it
+            // may be used: it is `<waiting_for_better_guard>`.
+            Some(_) => true,
+            // This circuit is still being built.
+            None => now.saturating_duration_since(self.launched_at) <= connect_timeout,
+        }
+    }
+
     /// Mark this request as "waiting" since the time `now`.
     ///
     /// This function should only be called once per request.
@@ -357,3 +412,56 @@ impl PendingRequest {
         self.waiting_since = Some(now);
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::ids::{FirstHopIdInner, GuardId};
+    use crate::{GuardSetSelector, GuardUsageBuilder, GuardUsageKind};
+
+    /// Make a PendingRequest for a guard whose identities are all `n`,
+    /// launched at `now`.
+    fn request(n: u8, kind: GuardUsageKind, now: Instant) -> PendingRequest {
+        let id = GuardId::new([n; 32].into(), [n; 20].into());
+        let id = FirstHopId(FirstHopIdInner::Guard(GuardSetSelector::Default, id));
+        let usage = GuardUsageBuilder::default().kind(kind).build().unwrap();
+        PendingRequest::new(id, usage, None, false, now)
+    }
+
+    #[test]
+    fn blocking() {
+        let t1 = Instant::now();
+        let timeout = Duration::from_secs(15);
+        let c1 = request(1, GuardUsageKind::Data, t1);
+
+        // C2 is still being built, and hasn't been pending for too long: it
+        // blocks C1.
+        let mut c2 = request(2, GuardUsageKind::Data, t1);
+        assert!(c2.blocks(&c1, timeout, t1 + Duration::from_secs(10)));
+        // Once C2 has been pending for longer than the timeout, it no longer
+        // blocks C1.
+        assert!(!c2.blocks(&c1, timeout, t1 + Duration::from_secs(20)));
+        // But if C2 has been built, and is waiting for a better guard, it
+        // blocks C1 no matter how long ago it was launched.
+        c2.mark_waiting(t1 + Duration::from_secs(5));
+        assert!(c2.blocks(&c1, timeout, t1 + Duration::from_secs(20)));
+
+        // A circuit of a different kind never blocks C1, since it doesn't
+        // obey the same restrictions.
+        let c3 = request(3, GuardUsageKind::OneHopDirectory, t1);
+        assert!(!c3.blocks(&c1, timeout, t1));
+    }
+}
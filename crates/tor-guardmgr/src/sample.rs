@@ -3,23 +3,41 @@
 
 mod candidate;
 
-use crate::filter::GuardFilter;
+use crate::filter::{lacks_ipv6, GuardFilter};
 use crate::guard::{Guard, NewlyConfirmed, Reachable};
 use crate::skew::SkewObservation;
+use crate::usability::{BetterGuard, GuardPriority};
 use crate::{
-    ids::GuardId, ExternalActivity, GuardParams, GuardUsage, GuardUsageKind, PickGuardError,
+    ids::GuardId, DropReason, ExternalActivity, GuardParams, GuardUsage, GuardUsageKind,
+    PickGuardError,
 };
 use crate::{FirstHop, GuardSetSelector};
 use tor_basic_utils::iter::{FilterCount, IteratorExt as _};
 use tor_linkspec::{ByRelayIds, HasRelayIds};
+use tor_llcrypto::d::Sha3_256;
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
 
+use digest::Digest;
 use itertools::Itertools;
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::time::{Instant, SystemTime};
-use tracing::{debug, info};
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+/// How long to wait between removing guards from a sample whose confirmed
+/// guards are older than `max_guardset_age`.
+const AGED_ROTATION_INTERVAL: Duration = Duration::from_secs(86400);
+
+/// How recently must a guard have served us directory information for us to
+/// prefer it for directory requests?
+const RECENT_DIR_SUCCESS: Duration = Duration::from_secs(86400);
+
+/// How far must the wallclock move before we change the latest wallclock time
+/// that we persist?
+const WALLCLOCK_SAVE_GRANULARITY: Duration = Duration::from_secs(10 * 60);
 
 #[allow(unused_imports)]
 pub(crate) use candidate::{Candidate, CandidateStatus, Universe, UniverseRef, WeightThreshold};
@@ -132,18 +150,185 @@ pub(crate) struct GuardSet {
     /// If true, the active filter is "very restrictive".
     filter_is_restrictive: bool,
 
+    /// Relays that we must never use as guards.
+    ///
+    /// Unlike `active_filter`, this is enforced by removing any matching
+    /// guards from the sample entirely, and by never adding them again.
+    ///
+    /// (This is persisted by [`GuardMgr`](crate::GuardMgr), not by us.)
+    blocklist: HashSet<Ed25519Identity>,
+
+    /// Relays that the [`GuardAcceptor`](crate::GuardAcceptor) has rejected.
+    ///
+    /// Unlike `blocklist`, this doesn't remove anything from the sample, since
+    /// the acceptor may change its mind later on.  We just never add these
+    /// relays to the sample, and never select them as primary guards, or as
+    /// guards at all.
+    ///
+    /// (This is recomputed by [`GuardMgr`](crate::GuardMgr) whenever its
+    /// directory changes, and is never persisted.)
+    vetoed: HashSet<Ed25519Identity>,
+
+    /// Guards through which we currently have live circuits, according to
+    /// [`GuardMgr::note_guard_in_use`](crate::GuardMgr::note_guard_in_use).
+    ///
+    /// When several primary guards would be equally good choices for a
+    /// request, we prefer these.  (This is never persisted.)
+    in_use: HashSet<GuardId>,
+
+    /// For each guard that we have selected from this set, the value of
+    /// `n_selections` when we last did so.
+    ///
+    /// When a request asks us to spread its usage, we prefer the guards that
+    /// we selected least recently.  (This is never persisted.)
+    last_selected: HashMap<GuardId, u64>,
+
+    /// The number of times that we have selected a guard from this set.
+    /// (This is never persisted.)
+    n_selections: u64,
+
     /// Set to 'true' whenever something changes that would force us
     /// to call 'select_primary_guards()', and cleared whenever we call it.
     primary_guards_invalidated: bool,
 
+    /// The latest wallclock time that we have accepted when expiring or
+    /// extending this sample, and the monotonic time at which we saw it.
+    ///
+    /// We use this to notice when the wallclock has jumped backwards.
+    latest_wallclock: Option<WallclockAnchor>,
+
+    /// The latest wallclock time that we persist, so that we still notice a
+    /// backward jump that happens while we aren't running.
+    ///
+    /// This follows `latest_wallclock`, but only moves once the two differ by
+    /// [`WALLCLOCK_SAVE_GRANULARITY`], so that our state doesn't change every
+    /// time we look at the clock.
+    saved_wallclock: Option<SystemTime>,
+
+    /// The value of `latest_wallclock` just before the wallclock most
+    /// recently jumped forwards.
+    ///
+    /// If the wallclock later jumps back to where this anchor says it should
+    /// be, we treat that as a correction of the forward jump, rather than as
+    /// a backward jump.
+    wallclock_before_jump: Option<WallclockAnchor>,
+
+    /// True if we have noticed that the wallclock jumped backwards, and we
+    /// have warned about it.
+    wallclock_backwards_warned: bool,
+
+    /// The last time at which we removed a guard from this sample because the
+    /// confirmed guards had grown older than `max_guardset_age`.
+    ///
+    /// (This is never persisted.)
+    last_aged_rotation: Option<SystemTime>,
+
     /// Fields from the state file that was used to make this `GuardSet` that
     /// this version of Arti doesn't understand.
     unknown_fields: HashMap<String, JsonValue>,
 }
 
+/// A wallclock time that we have accepted, and the monotonic time at which
+/// we saw it.
+#[derive(Debug, Clone, Copy)]
+struct WallclockAnchor {
+    /// The wallclock time.
+    wallclock: SystemTime,
+    /// The monotonic time at which we saw `wallclock`.
+    ///
+    /// This is None if we loaded `wallclock` from our state file, since we
+    /// can't know how much time has passed since then.
+    at: Option<Instant>,
+}
+
+impl WallclockAnchor {
+    /// Return the wallclock time that we would expect at the monotonic time
+    /// `now`, if the wallclock has been running normally since this anchor.
+    fn expected_at(&self, now: Instant) -> SystemTime {
+        match self.at {
+            Some(at) => self.wallclock + now.saturating_duration_since(at),
+            None => self.wallclock,
+        }
+    }
+
+    /// Return how far `wallclock` is from the time we would expect at the
+    /// monotonic time `now`, in either direction.
+    fn distance_from(&self, wallclock: SystemTime, now: Instant) -> Duration {
+        abs_diff(self.expected_at(now), wallclock)
+    }
+}
+
+/// Return how far apart `a` and `b` are, in either direction.
+fn abs_diff(a: SystemTime, b: SystemTime) -> Duration {
+    match a.duration_since(b) {
+        Ok(d) => d,
+        Err(e) => e.duration(),
+    }
+}
+
+/// A snapshot of how many guards in a sample are available for use.
+///
+/// Returned by [`GuardMgr::sample_health`](crate::GuardMgr::sample_health).
+///
+/// Only guards that our current [`GuardFilter`] permits are counted.
+#[derive(Clone, Debug, amplify::Getters)]
+pub struct SampleHealth {
+    /// The number of guards in the sample that our filter permits.
+    #[getter(as_copy)]
+    filtered_sample_size: usize,
+    /// The number of filtered guards that we would try to keep in the sample,
+    /// if the network allows it.
+    #[getter(as_copy)]
+    min_filtered_sample_size: usize,
+    /// The number of filtered guards that are listed, and that we have not
+    /// found to be unreachable.
+    #[getter(as_copy)]
+    n_usable: usize,
+    /// The number of filtered guards that are listed, but that we have found
+    /// to be unreachable.
+    #[getter(as_copy)]
+    n_down: usize,
+}
+
+impl SampleHealth {
+    /// Return true if our filter permits fewer guards in the sample than we
+    /// would like to have.
+    ///
+    /// Once we have tried to extend the sample, this means that the filter
+    /// is so restrictive that our choice of guards, and therefore our
+    /// anonymity, is likely to suffer.
+    pub fn filter_too_restrictive(&self) -> bool {
+        self.filtered_sample_size < self.min_filtered_sample_size
+    }
+}
+
+/// A short identifier for the set of confirmed guards in a guard sample.
+///
+/// Returned by [`GuardMgr::sample_fingerprint`](crate::GuardMgr::sample_fingerprint).
+///
+/// The fingerprint depends only on the identities of the confirmed guards,
+/// not on their order or status, so it stays the same across restarts for as
+/// long as the set of confirmed guards does not change.
+///
+/// It is displayed as 16 hexadecimal digits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct SampleFingerprint([u8; 8]);
+
+impl fmt::Display for SampleFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
 /// Which of our lists did a given guard come from?
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub(crate) enum ListKind {
+///
+/// (The variants are ordered from most to least preferred.)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[non_exhaustive]
+pub enum ListKind {
     /// A guard that came from the primary guard list.
     Primary,
     /// A non-primary guard that came from the confirmed guard list.
@@ -226,6 +411,28 @@ impl GuardSet {
         self.guards.by_all_ids(id)
     }
 
+    /// Attach `note` to the guard with every identity in `id`, replacing any
+    /// previous note.
+    ///
+    /// Return false if there is no such guard in this sample.
+    pub(crate) fn set_guard_note(&mut self, id: &GuardId, note: Option<String>) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        self.guards
+            .modify_by_all_ids(id, |guard| guard.set_note(note));
+        true
+    }
+
+    /// Return the identity and note of every guard in this sample that has a
+    /// note, in the order in which they were added to the sample.
+    pub(crate) fn guard_notes(&self) -> impl Iterator<Item = (&GuardId, &str)> + '_ {
+        self.sample.iter().filter_map(|id| {
+            let note = self.get(id)?.note()?;
+            Some((id, note))
+        })
+    }
+
     /// Replace the filter used by this `GuardSet` with `filter`.
     ///
     /// Removes all primary guards that the filter doesn't permit.
@@ -254,6 +461,98 @@ impl GuardSet {
         &self.active_filter
     }
 
+    /// Return the identities of the guards in the sample that our current
+    /// filter doesn't permit, in sample order.
+    pub(crate) fn filtered_out_guard_ids(&self) -> impl Iterator<Item = &GuardId> + '_ {
+        self.sample.iter().filter(|id| {
+            self.guards
+                .by_all_ids(*id)
+                .is_some_and(|g| !self.active_filter.permits(g))
+        })
+    }
+
+    /// Replace the set of relays that this `GuardSet` must never use with
+    /// `blocklist`.
+    ///
+    /// Removes every guard on the blocklist from the sample, and returns the
+    /// identities of the guards we removed.
+    pub(crate) fn set_blocklist(&mut self, blocklist: HashSet<Ed25519Identity>) -> Vec<GuardId> {
+        self.blocklist = blocklist;
+
+        self.assert_consistency();
+        let n_pre = self.guards.len();
+        let blocklist = &self.blocklist; // avoid borrow issues
+        let blocked = self
+            .guards
+            .values()
+            .filter(|g| is_blocked(blocklist, *g))
+            .map(|g| g.guard_id().clone())
+            .collect();
+        self.guards.retain(|g| !is_blocked(blocklist, g));
+        let guards = &self.guards;
+        self.sample.retain(|id| guards.by_all_ids(id).is_some());
+        self.confirmed.retain(|id| guards.by_all_ids(id).is_some());
+        self.primary.retain(|id| guards.by_all_ids(id).is_some());
+        self.assert_consistency();
+
+        if self.guards.len() < n_pre {
+            let n_removed = n_pre - self.guards.len();
+            info!(n_removed, "Removed blocklisted guards from sample.");
+        }
+        self.primary_guards_invalidated = true;
+        blocked
+    }
+
+    /// Return the set of relays that this `GuardSet` must never use.
+    pub(crate) fn blocklist(&self) -> &HashSet<Ed25519Identity> {
+        &self.blocklist
+    }
+
+    /// Replace the set of relays that this `GuardSet` must currently avoid
+    /// with `vetoed`.
+    pub(crate) fn set_vetoed(&mut self, vetoed: HashSet<Ed25519Identity>) {
+        self.vetoed = vetoed;
+        self.primary_guards_invalidated = true;
+    }
+
+    /// Return the set of relays that this `GuardSet` must currently avoid.
+    pub(crate) fn vetoed(&self) -> &HashSet<Ed25519Identity> {
+        &self.vetoed
+    }
+
+    /// Record whether we currently have live circuits through the guard with
+    /// every identity in `id`.
+    pub(crate) fn set_in_use(&mut self, id: &GuardId, in_use: bool) {
+        if in_use {
+            self.in_use.insert(id.clone());
+        } else {
+            self.in_use.remove(id);
+        }
+    }
+
+    /// Record that the guard with every identity in `id` served us valid
+    /// directory information at `now`.
+    pub(crate) fn note_dir_success(&mut self, id: &GuardId, now: SystemTime) {
+        self.guards
+            .modify_by_all_ids(id, |guard| guard.note_dir_success(now));
+    }
+
+    /// Return true if `guard` has served us directory information recently
+    /// enough that we should prefer it for directory requests.
+    fn has_recent_dir_success(&self, guard: &Guard) -> bool {
+        let Some(when) = guard.last_dir_success() else {
+            return false;
+        };
+        match self.latest_wallclock {
+            // If the success is later than the latest time we've seen, it's recent.
+            Some(latest) => latest
+                .wallclock
+                .duration_since(when)
+                .map_or(true, |elapsed| elapsed <= RECENT_DIR_SUCCESS),
+            None => true,
+        }
+    }
+
     /// Copy non-persistent status from every guard shared with `other`.
     ///
     /// This is used as part of our reload process when we don't own our state
@@ -275,6 +574,16 @@ impl GuardSet {
                 }
             })
             .collect();
+        self.latest_wallclock = other.latest_wallclock;
+        self.saved_wallclock = other.saved_wallclock;
+        self.wallclock_before_jump = other.wallclock_before_jump;
+        self.wallclock_backwards_warned = other.wallclock_backwards_warned;
+        self.last_aged_rotation = other.last_aged_rotation;
+        self.blocklist = other.blocklist;
+        self.vetoed = other.vetoed;
+        self.in_use = other.in_use;
+        self.last_selected = other.last_selected;
+        self.n_selections = other.n_selections;
     }
 
     /// Return a serializable state object that can be stored to disk
@@ -289,6 +598,7 @@ impl GuardSet {
         GuardSample {
             guards,
             confirmed: Cow::Borrowed(&self.confirmed),
+            latest_wallclock: self.saved_wallclock,
             remaining: self.unknown_fields.clone(),
         }
     }
@@ -310,7 +620,20 @@ impl GuardSet {
             primary,
             active_filter: GuardFilter::default(),
             filter_is_restrictive: false,
+            blocklist: HashSet::new(),
+            vetoed: HashSet::new(),
+            in_use: HashSet::new(),
+            last_selected: HashMap::new(),
+            n_selections: 0,
             primary_guards_invalidated: true,
+            latest_wallclock: state.latest_wallclock.map(|wallclock| WallclockAnchor {
+                wallclock,
+                at: None,
+            }),
+            saved_wallclock: state.latest_wallclock,
+            wallclock_before_jump: None,
+            wallclock_backwards_warned: false,
+            last_aged_rotation: None,
             unknown_fields: state.remaining,
         };
 
@@ -333,6 +656,25 @@ impl GuardSet {
         guard_set
     }
 
+    /// Return the identities of the guards in this sample, in the order in
+    /// which they were added.
+    #[cfg(any(test, feature = "metrics"))]
+    pub(crate) fn sample_ids(&self) -> &[GuardId] {
+        &self.sample
+    }
+
+    /// Return the identities of the confirmed guards in this sample, in
+    /// confirmed order.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn confirmed_guard_ids(&self) -> &[GuardId] {
+        &self.confirmed
+    }
+
+    /// Return the Ed25519 identities of every guard in this sample that has one.
+    pub(crate) fn sampled_ed_identities(&self) -> impl Iterator<Item = &Ed25519Identity> + '_ {
+        self.guards.values().filter_map(|g| g.ed_identity())
+    }
+
     /// Return `Ok(true)` if `id` is definitely a member of this set, and
     /// `Ok(false)` if it is definitely not a member.  
     ///
@@ -364,10 +706,16 @@ impl GuardSet {
     /// Return true if any guards were added.
     pub(crate) fn extend_sample_as_needed<U: Universe>(
         &mut self,
-        now: SystemTime,
+        wallclock: SystemTime,
+        now: Instant,
         params: &GuardParams,
         dir: &U,
     ) -> crate::ExtendedStatus {
+        // If the clock has jumped backwards, don't stamp new guards with a
+        // time that would make them expire early once the clock recovers.
+        let now = self
+            .wallclock_went_backwards(params, wallclock, now)
+            .unwrap_or(wallclock);
         let mut any_added = crate::ExtendedStatus::No;
         while self.extend_sample_inner(now, params, dir) {
             any_added = crate::ExtendedStatus::Yes;
@@ -397,6 +745,7 @@ impl GuardSet {
             .filter(|g| {
                 g.usable()
                     && self.active_filter.permits(*g)
+                    && !is_blocked(&self.vetoed, *g)
                     && g.reachable() != Reachable::Unreachable
             })
             .count();
@@ -428,7 +777,9 @@ impl GuardSet {
                 (n_to_add * 3, &no_filter)
             };
 
-        let candidates = dir.sample(&self.guards, pre_filter, n_candidates);
+        // Vetoed guards aren't excluded until after we sample, so we ask for
+        // enough extra candidates that we can still add as many as we want.
+        let candidates = dir.sample(&self.guards, pre_filter, n_candidates + self.vetoed.len());
 
         // Add those candidates to the sample.
         let mut any_added = false;
@@ -449,6 +800,14 @@ impl GuardSet {
                 // We've reached our target; no need to add more.
                 break;
             }
+            if is_blocked(&self.blocklist, &candidate.owned_target) {
+                // We must never use this relay.
+                continue;
+            }
+            if is_blocked(&self.vetoed, &candidate.owned_target) {
+                // We mustn't use this relay for now.
+                continue;
+            }
             if self.active_filter.permits(&candidate.owned_target) {
                 n_filtered_usable += 1;
             }
@@ -475,6 +834,14 @@ impl GuardSet {
         self.primary_guards_invalidated = true;
     }
 
+    /// Return the identities of our primary guards, from most to least
+    /// preferred.
+    ///
+    /// See [`GuardSet::select_primary_guards`] for how they are ordered.
+    pub(crate) fn primary_guard_ids(&self) -> &[GuardId] {
+        &self.primary
+    }
+
     /// Return the number of our primary guards that are missing directory
     /// information in `universe`.
     ///
@@ -514,10 +881,24 @@ impl GuardSet {
     /// Primary guards are chosen according to preference order over all
     /// the guards in the set, restricted by the current filter.
     ///
+    /// The resulting order depends only on the contents of this set (and not
+    /// on, for example, hash table iteration order).  Guards are considered
+    /// in this order, and the first `params.n_primary` eligible ones are
+    /// taken:
+    ///
+    ///  1. Confirmed guards, in the order in which they were confirmed.
+    ///  2. Guards that were already primary, in their previous order.
+    ///  3. All other sampled guards, in the order in which they were added to
+    ///     the sample.
+    ///
     /// TODO: Enumerate all the times when this function needs to be called.
     ///
+    /// Guards that have been demoted for failing too often in a row (see
+    /// [`Guard::eligible_for_primary`]) are skipped until they have been
+    /// reachable again for long enough, as of `now`.
+    ///
     /// TODO: Make sure this is called enough.
-    pub(crate) fn select_primary_guards(&mut self, params: &GuardParams) {
+    pub(crate) fn select_primary_guards(&mut self, params: &GuardParams, now: SystemTime) {
         // TODO-SPEC: This is not 100% what the spec says, but it does match what
         // Tor does.  We pick first from the confirmed guards,
         // then from any previous primary guards, and then from maybe-reachable
@@ -543,7 +924,11 @@ impl GuardSet {
                     .guards
                     .by_all_ids(id)
                     .expect("Inconsistent guard state");
-                if g.usable() && self.active_filter.permits(g) {
+                if g.usable()
+                    && self.active_filter.permits(g)
+                    && !is_blocked(&self.vetoed, g)
+                    && g.eligible_for_primary(params, now)
+                {
                     Some(id.clone())
                 } else {
                     None
@@ -573,9 +958,35 @@ impl GuardSet {
 
     /// Remove all guards which should expire `now`, according to the settings
     /// in `params`.
-    pub(crate) fn expire_old_guards(&mut self, params: &GuardParams, now: SystemTime) {
+    ///
+    /// If the wallclock has jumped backwards by more than
+    /// `params.wallclock_backward_tolerance`, we don't expire anything until
+    /// it has caught up again.  (`mono_now` is the current monotonic time,
+    /// which we use to tell how far the wallclock should have advanced.)
+    ///
+    /// Returns the identity of every guard we removed, and the reason why.
+    pub(crate) fn expire_old_guards(
+        &mut self,
+        params: &GuardParams,
+        now: SystemTime,
+        mono_now: Instant,
+    ) -> Vec<(GuardId, DropReason)> {
+        if self
+            .wallclock_went_backwards(params, now, mono_now)
+            .is_some()
+        {
+            return Vec::new();
+        }
         self.assert_consistency();
         let n_pre = self.guards.len();
+        let mut expired: Vec<_> = self
+            .guards
+            .values()
+            .filter_map(|g| {
+                let reason = g.expiry_reason(params, now)?;
+                Some((g.guard_id().clone(), reason))
+            })
+            .collect();
         self.guards.retain(|g| !g.is_expired(params, now));
         let guards = &self.guards;
         self.sample.retain(|id| guards.by_all_ids(id).is_some());
@@ -588,6 +999,204 @@ impl GuardSet {
             debug!(n_expired, "Expired guards as too old.");
             self.primary_guards_invalidated = true;
         }
+
+        if let Some(id) = self.rotate_aged_guard(params, now) {
+            expired.push((id, DropReason::RotatedOut));
+        }
+        expired
+    }
+
+    /// If `params.max_guardset_age` is set, and the guard that we confirmed
+    /// longest ago was confirmed more than that long ago, remove it from the
+    /// sample.
+    ///
+    /// We remove at most one guard per [`AGED_ROTATION_INTERVAL`], so that an
+    /// aged-out set of guards is replaced gradually: if we replaced all of
+    /// them at once, an observer could easily link our old guards to our new
+    /// ones.
+    ///
+    /// Returns the identity of the guard we removed, if any.
+    fn rotate_aged_guard(&mut self, params: &GuardParams, now: SystemTime) -> Option<GuardId> {
+        let max_age = params.max_guardset_age?;
+        if let Some(last) = self.last_aged_rotation {
+            // (If the clock went backwards, we wait for it to catch up.)
+            if now
+                .duration_since(last)
+                .map_or(true, |d| d < AGED_ROTATION_INTERVAL)
+            {
+                return None;
+            }
+        }
+        let oldest = self
+            .confirmed
+            .iter()
+            .filter_map(|id| Some((self.guards.by_all_ids(id)?.confirmed_at()?, id)))
+            .min_by_key(|(confirmed_at, _)| *confirmed_at);
+        let (confirmed_at, id) = oldest?;
+        if now
+            .duration_since(confirmed_at)
+            .map_or(true, |age| age <= max_age)
+        {
+            return None;
+        }
+
+        let id = id.clone();
+        info!(guard_id = ?id, "Rotating out guard: our confirmed guards are too old.");
+        self.remove_guard(&id);
+        self.last_aged_rotation = Some(now);
+        Some(id)
+    }
+
+    /// Remove the guard with `id` from this sample entirely, so that a new
+    /// guard can take its place.
+    fn remove_guard(&mut self, id: &GuardId) {
+        self.assert_consistency();
+        self.guards.retain(|g| g.guard_id() != id);
+        self.sample.retain(|g| g != id);
+        self.confirmed.retain(|g| g != id);
+        self.primary.retain(|g| g != id);
+        self.assert_consistency();
+        self.primary_guards_invalidated = true;
+    }
+
+    /// If we have given out the guard with `guard_id` more than
+    /// `params.max_lifetime_attempts` times, remove it from this sample, and
+    /// return true.
+    fn retire_if_overused(&mut self, guard_id: &GuardId, params: &GuardParams) -> bool {
+        let overused = self
+            .get(guard_id)
+            .is_some_and(|guard| guard.is_overused(params));
+        if overused {
+            info!(
+                ?guard_id,
+                "Retiring guard: we have given it out too many times."
+            );
+            self.remove_guard(guard_id);
+        }
+        overused
+    }
+
+    /// Check whether the wallclock time `wallclock` is earlier than the
+    /// wallclock time we would expect at the monotonic time `now`, by more
+    /// than `params.wallclock_backward_tolerance`.
+    ///
+    /// We expect the wallclock to advance at the same rate as the monotonic
+    /// clock since the last wallclock time that we accepted.  If the wallclock
+    /// is behind that, return the time we expected.  Otherwise, accept
+    /// `wallclock`, and return None.
+    ///
+    /// As a special case, if the wallclock jumps back to where it would have
+    /// been without its most recent forward jump, we accept it: otherwise, a
+    /// clock that was briefly set too far forward would stop us from expiring
+    /// any guards until real time caught up with it.
+    fn wallclock_went_backwards(
+        &mut self,
+        params: &GuardParams,
+        wallclock: SystemTime,
+        now: Instant,
+    ) -> Option<SystemTime> {
+        let tolerance = params.wallclock_backward_tolerance;
+        if let Some(latest) = self.latest_wallclock {
+            let expected = latest.expected_at(now);
+            match expected.duration_since(wallclock) {
+                Ok(jump) if jump > tolerance => {
+                    let is_correction = self
+                        .wallclock_before_jump
+                        .is_some_and(|before| before.distance_from(wallclock, now) <= tolerance);
+                    if !is_correction {
+                        if !self.wallclock_backwards_warned {
+                            warn!(
+                                "Wallclock jumped backwards by {}; not expiring guards until it catches up.",
+                                humantime::format_duration(jump)
+                            );
+                            self.wallclock_backwards_warned = true;
+                        }
+                        return Some(expected);
+                    }
+                    info!(
+                        "Wallclock jumped backwards by {}, undoing an earlier jump forwards.",
+                        humantime::format_duration(jump)
+                    );
+                    self.wallclock_before_jump = None;
+                }
+                Ok(_) => {}
+                Err(e) if e.duration() > tolerance => {
+                    self.wallclock_before_jump = Some(latest);
+                }
+                Err(_) => {}
+            }
+        }
+        if self.wallclock_backwards_warned {
+            info!("Wallclock has caught up; resuming normal guard expiry.");
+            self.wallclock_backwards_warned = false;
+        }
+        self.latest_wallclock = Some(WallclockAnchor {
+            wallclock,
+            at: Some(now),
+        });
+        if self.saved_wallclock.map_or(true, |saved| {
+            abs_diff(saved, wallclock) >= WALLCLOCK_SAVE_GRANULARITY
+        }) {
+            self.saved_wallclock = Some(wallclock);
+        }
+        None
+    }
+
+    /// Return a [`SampleFingerprint`] for the confirmed guards in this sample.
+    pub(crate) fn fingerprint(&self) -> SampleFingerprint {
+        let mut ids: Vec<&GuardId> = self.confirmed.iter().collect();
+        ids.sort();
+
+        let mut d = Sha3_256::new();
+        d.update(b"tor-guardmgr sample fingerprint");
+        for id in ids {
+            // Each identity is preceded by a byte saying whether it is
+            // present, so that no two lists of IDs have the same encoding.
+            match id.0.ed_identity() {
+                Some(ed) => {
+                    d.update([1]);
+                    d.update(ed.as_bytes());
+                }
+                None => d.update([0]),
+            }
+            match id.0.rsa_identity() {
+                Some(rsa) => {
+                    d.update([1]);
+                    d.update(rsa.as_bytes());
+                }
+                None => d.update([0]),
+            }
+        }
+
+        let mut fp = [0_u8; 8];
+        fp.copy_from_slice(&d.finalize()[..8]);
+        SampleFingerprint(fp)
+    }
+
+    /// Return a [`SampleHealth`] describing how many guards in this sample are
+    /// available for use.
+    pub(crate) fn sample_health(&self, params: &GuardParams) -> SampleHealth {
+        let mut health = SampleHealth {
+            filtered_sample_size: 0,
+            min_filtered_sample_size: params.min_filtered_sample_size,
+            n_usable: 0,
+            n_down: 0,
+        };
+        for g in self.guards.values() {
+            if !self.active_filter.permits(g) {
+                continue;
+            }
+            health.filtered_sample_size += 1;
+            if !g.usable() {
+                continue;
+            }
+            if g.reachable() == Reachable::Unreachable {
+                health.n_down += 1;
+            } else {
+                health.n_usable += 1;
+            }
+        }
+        health
     }
 
     /// Return an iterator over the Id for every Guard in the sample that
@@ -686,10 +1295,37 @@ impl GuardSet {
             .collect();
     }
 
+    /// Clear the transient reachability state of every guard.
+    ///
+    /// See [`Guard::clear_reachability_state`].
+    pub(crate) fn clear_reachability_state(&mut self) {
+        let old_guards = std::mem::take(&mut self.guards);
+        self.guards = old_guards
+            .into_values()
+            .map(|mut guard| {
+                guard.clear_reachability_state();
+                guard
+            })
+            .collect();
+    }
+
     /// Record that an attempt has begun to use the guard with
     /// `guard_id`.
-    pub(crate) fn record_attempt(&mut self, guard_id: &GuardId, now: Instant) {
+    ///
+    /// If that guard has now been used more than
+    /// `params.max_lifetime_attempts` times, remove it from this sample, and
+    /// return true.  The caller should then update this `GuardSet`, so that a
+    /// new guard can take the retired one's place.
+    pub(crate) fn record_attempt(
+        &mut self,
+        guard_id: &GuardId,
+        params: &GuardParams,
+        now: Instant,
+    ) -> bool {
         let is_primary = self.guard_is_primary(guard_id);
+        self.n_selections += 1;
+        self.last_selected
+            .insert(guard_id.clone(), self.n_selections);
         self.guards.modify_by_all_ids(guard_id, |guard| {
             guard.record_attempt(now);
 
@@ -697,6 +1333,7 @@ impl GuardSet {
                 guard.note_exploratory_circ(true);
             }
         });
+        self.retire_if_overused(guard_id, params)
     }
 
     /// Record that an attempt to use the guard with `guard_id` has just
@@ -704,13 +1341,20 @@ impl GuardSet {
     ///
     /// If `how` is provided, it's an operation from outside the crate that the
     /// guard succeeded at doing.
+    ///
+    /// As with [`GuardSet::record_attempt`], if that guard has been used more
+    /// than `params.max_lifetime_attempts` times, remove it from this sample,
+    /// and return true.
     pub(crate) fn record_success(
         &mut self,
         guard_id: &GuardId,
         params: &GuardParams,
         how: Option<ExternalActivity>,
         now: SystemTime,
-    ) {
+    ) -> bool {
+        if !self.still_sampled(guard_id, "success") {
+            return false;
+        }
         self.assert_consistency();
         self.guards.modify_by_all_ids(guard_id, |guard| match how {
             Some(external) => guard.record_external_success(external),
@@ -724,6 +1368,25 @@ impl GuardSet {
             }
         });
         self.assert_consistency();
+        self.retire_if_overused(guard_id, params)
+    }
+
+    /// Return true if the guard with `guard_id` is still in this sample.
+    ///
+    /// If it isn't (for example, because it expired or was removed from the
+    /// sample while a circuit through it was pending), log that we are
+    /// ignoring the `what` we learned about it, and return false.  We must not
+    /// record anything about such a guard: doing so could put it back into
+    /// our confirmed list.
+    fn still_sampled(&self, guard_id: &GuardId, what: &str) -> bool {
+        let present = self.get(guard_id).is_some();
+        if !present {
+            debug!(
+                ?guard_id,
+                "Ignoring {} for a guard that is no longer in our sample.", what
+            );
+        }
+        present
     }
 
     /// Record that an attempt to use the guard with `guard_id` has just failed.
@@ -734,6 +1397,9 @@ impl GuardSet {
         how: Option<ExternalActivity>,
         now: Instant,
     ) {
+        if !self.still_sampled(guard_id, "failure") {
+            return;
+        }
         // TODO use instant uniformly for in-process, and systemtime for storage?
         let is_primary = self.guard_is_primary(guard_id);
         self.guards.modify_by_all_ids(guard_id, |guard| match how {
@@ -770,74 +1436,56 @@ impl GuardSet {
         self.guards.values().filter_map(|g| g.skew())
     }
 
-    /// Return whether the circuit manager can be allowed to use a
-    /// circuit with the `guard_id`.
+    /// Describe where the guard `guard_id` stands in our preference order, for
+    /// deciding whether a circuit built through it for `usage` may be used.
+    ///
+    /// The function `blocked_by` should return true if some other circuit,
+    /// through the guard with a given ID, "blocks" our circuit in the sense
+    /// of the guard specification.  (See
+    /// [`PendingRequest::blocks`](crate::pending::PendingRequest::blocks).)
+    /// We only call it for guards that we would rather use than `guard_id`,
+    /// and that obey the restrictions of `usage`.
     ///
-    /// Return `Some(bool)` if the circuit is usable, and `None` if we
-    /// cannot yet be sure.
-    pub(crate) fn circ_usability_status(
+    /// See [`decide_usability`](crate::usability::decide_usability).
+    pub(crate) fn circ_priority<F>(
         &self,
         guard_id: &GuardId,
         usage: &GuardUsage,
-        params: &GuardParams,
-        now: Instant,
-    ) -> Option<bool> {
-        // TODO-SPEC: This isn't what the spec says.  The spec is phrased
-        // in terms of circuits blocking circuits, whereas this algorithm is
-        // about guards blocking guards.
-        //
-        // Also notably, the spec also says:
-        //
-        // * Among guards that do not appear in {CONFIRMED_GUARDS},
-        // {is_pending}==true guards have higher priority.
-        // * Among those, the guard with earlier {last_tried_connect} time
-        // has higher priority.
-        // * Finally, among guards that do not appear in
-        // {CONFIRMED_GUARDS} with {is_pending==false}, all have equal
-        // priority.
-        //
-        // I believe this approach is fine too, but we ought to document it.
+        blocked_by: F,
+    ) -> GuardPriority
+    where
+        F: Fn(&GuardId) -> bool,
+    {
+        // TODO-SPEC: The spec says that among guards that do not appear in
+        // {CONFIRMED_GUARDS}, {is_pending}==true guards have higher priority,
+        // and that among those, the guard with earlier {last_tried_connect}
+        // time has higher priority.  We just use our usual preference order
+        // instead.
 
         if self.guard_is_primary(guard_id) {
-            // Circuits built to primary guards are always usable immediately.
-            //
-            // This has to be a special case, since earlier primary guards
-            // don't block later ones.
-            return Some(true);
+            return GuardPriority::Primary;
         }
 
-        // Assuming that the guard is _not_ primary, then the rule is
-        // fairly simple: we can use the guard if all the guards we'd
-        // _rather_ use are either down, or have had their circuit
-        // attempts pending for too long.
-
-        let cutoff = now
-            .checked_sub(params.np_connect_timeout)
-            .expect("Can't subtract connect timeout from now.");
-
+        let mut better = Vec::new();
         for (src, guard) in self.preference_order() {
             if guard.guard_id() == guard_id {
-                return Some(true);
+                return GuardPriority::NonPrimary(better);
             }
-            if guard.usable() && self.active_filter.permits(guard) && guard.conforms_to_usage(usage)
+            if guard.usable()
+                && self.active_filter.permits(guard)
+                && !is_blocked(&self.vetoed, guard)
+                && guard.conforms_to_usage(usage)
             {
-                match (src, guard.reachable()) {
-                    (_, Reachable::Reachable) => return Some(false),
-                    (_, Reachable::Unreachable) => (),
-                    (ListKind::Primary, Reachable::Untried | Reachable::Retriable) => {
-                        return Some(false)
-                    }
-                    (_, Reachable::Untried | Reachable::Retriable) => {
-                        if guard.exploratory_attempt_after(cutoff) {
-                            return None;
-                        }
-                    }
-                }
+                better.push(BetterGuard {
+                    is_primary: src.is_primary(),
+                    reachable: guard.reachable(),
+                    has_blocking_circuit: blocked_by(guard.guard_id()),
+                });
             }
         }
 
         // This guard is not even listed.
-        Some(false)
+        GuardPriority::Unlisted
     }
 
     /// Try to select a guard for a given `usage`.
@@ -867,56 +1515,122 @@ impl GuardSet {
         Ok((list_kind, first_hop))
     }
 
-    /// Try to select a guard for a given `usage`.
+    /// Return every guard in this set that we could currently use for
+    /// `usage`, in the order that `pick_guard_id` would consider them.
     ///
-    /// On success, returns the kind of guard that we got, and its identity.
-    fn pick_guard_id(
+    /// Increments `counts` to record how many guards each of our filters
+    /// rejected.
+    fn candidates(
         &self,
         usage: &GuardUsage,
-        params: &GuardParams,
         now: Instant,
-    ) -> Result<(ListKind, GuardId), PickGuardError> {
-        debug_assert!(!self.primary_guards_invalidated);
-        let n_options = match usage.kind {
-            GuardUsageKind::OneHopDirectory => params.dir_parallelism,
-            GuardUsageKind::Data => params.data_parallelism,
-        };
-
-        // Counts of how many elements were rejected by which of the filters
-        // below.
-        //
-        // Note that since we use `Iterator::take`, these counts won't cover the
-        // whole guard sample on the successful case: only in the failing case,
-        // when we fail to find any candidates.
-        let mut running = FilterCount::default();
-        let mut pending = FilterCount::default();
-        let mut suitable = FilterCount::default();
-        let mut filtered = FilterCount::default();
-
+        counts: &mut CandidateCounts,
+    ) -> Vec<(ListKind, &Guard)> {
         let mut options: Vec<_> = self
             .preference_order()
             // Discard the guards that are down or unusable, and see if any
             // are left.
-            .filter_cnt(&mut running, |(_, g)| {
+            .filter_cnt(&mut counts.running, |(_, g)| {
                 g.usable()
                     && g.reachable() != Reachable::Unreachable
                     && g.ready_for_usage(usage, now)
             })
             // Now remove those that are excluded because we're already trying
             // them on an exploratory basis.
-            .filter_cnt(&mut pending, |(_, g)| !g.exploratory_circ_pending())
+            .filter_cnt(&mut counts.pending, |(_, g)| !g.exploratory_circ_pending())
             // ...or because they don't support the operation we're
             // attempting...
-            .filter_cnt(&mut suitable, |(_, g)| g.conforms_to_usage(usage))
+            .filter_cnt(&mut counts.suitable, |(_, g)| g.conforms_to_usage(usage))
             // ... or because we specifically filtered them out.
-            .filter_cnt(&mut filtered, |(_, g)| self.active_filter.permits(*g))
-            // We only consider the first n_options such guards.
-            .take(n_options)
+            .filter_cnt(&mut counts.filtered, |(_, g)| {
+                self.active_filter.permits(*g)
+                    && !is_blocked(&self.blocklist, *g)
+                    && !is_blocked(&self.vetoed, *g)
+            })
             .collect();
 
+        // Among guards we're retrying, try those that worked most recently first.
+        order_retries(&mut options);
+
+        // Among the primary guards, try those that our filter prefers first.
+        // (We never reorder the other guards: we only use them when no primary
+        // guard is usable, and we don't want to expose ourselves to more guards
+        // than necessary.  `sort_by_key` is stable, and the primary guards come
+        // first, so every other guard keeps its place.)
+        let filter = &self.active_filter;
+        options
+            .sort_by_key(|(src, g)| (!src.is_primary(), src.is_primary() && !filter.prefers(*g)));
+
+        options
+    }
+
+    /// Return the identities of every guard that we could currently use for
+    /// `usage`, in the order that `pick_guard` would try them.
+    ///
+    /// Among the primary guards that `pick_guard` would choose between, the
+    /// ones it prefers come first; if several are equally preferred, it
+    /// picks one of them at random.
+    pub(crate) fn selection_order(
+        &self,
+        usage: &GuardUsage,
+        params: &GuardParams,
+        now: Instant,
+    ) -> Vec<(ListKind, GuardId)> {
+        let mut options = self.candidates(usage, now, &mut CandidateCounts::default());
+
+        // These are the guards that pick_guard_id would choose between.
+        let n_choices = options
+            .iter()
+            .take(n_options(usage, params))
+            .take_while(|(src, _)| src.is_primary())
+            .count();
+        // Rank them as pick_guard_id does.  (`sort_by_key` is stable, so
+        // equally ranked guards stay in preference order.)
+        options[..n_choices].sort_by_key(|(_, g)| self.pick_rank(usage, g));
+
+        options
+            .into_iter()
+            .map(|(src, g)| (src, g.guard_id().clone()))
+            .collect()
+    }
+
+    /// Try to select a guard for a given `usage`.
+    ///
+    /// On success, returns the kind of guard that we got, and its identity.
+    fn pick_guard_id(
+        &self,
+        usage: &GuardUsage,
+        params: &GuardParams,
+        now: Instant,
+    ) -> Result<(ListKind, GuardId), PickGuardError> {
+        debug_assert!(!self.primary_guards_invalidated);
+        let n_options = n_options(usage, params);
+
+        let mut counts = CandidateCounts::default();
+        let mut options = self.candidates(usage, now, &mut counts);
+        let CandidateCounts {
+            running,
+            pending,
+            suitable,
+            filtered,
+        } = counts;
+
+        // We only consider the first n_options such guards.
+        options.truncate(n_options);
+
         if options.iter().any(|(src, _)| src.is_primary()) {
-            // If there are any primary guards, we only consider those.
+            // If there are any primary guards, we only consider those, and
+            // among them, only those that rank best for this usage.
             options.retain(|(src, _)| src.is_primary());
+            if let Some(best) = options.iter().map(|(_, g)| self.pick_rank(usage, g)).min() {
+                options.retain(|(_, g)| self.pick_rank(usage, g) == best);
+            }
+            if usage.spread_usage {
+                // When spreading our usage, we don't choose at random: the
+                // rank already favors the guard we selected least recently, so
+                // we take the most preferred of the best-ranked guards.
+                options.truncate(1);
+            }
         } else {
             // If there are no primary guards, parallelism doesn't apply.
             options.truncate(1);
@@ -924,6 +1638,9 @@ impl GuardSet {
 
         match options.choose(&mut rand::thread_rng()) {
             Some((src, g)) => Ok((*src, g.guard_id().clone())),
+            None if self.active_filter.requires_ipv6() && self.guards.values().all(lacks_ipv6) => {
+                Err(PickGuardError::NoIpv6Guards)
+            }
             None => {
                 let retry_at = if running.n_accepted == 0 {
                     self.next_retry(usage)
@@ -941,6 +1658,24 @@ impl GuardSet {
         }
     }
 
+    /// Return how `pick_guard_id` ranks the primary guard `guard` for `usage`.
+    ///
+    /// `pick_guard_id` only chooses between the primary guards with the lowest
+    /// rank, and `selection_order` sorts them by rank.
+    fn pick_rank(&self, usage: &GuardUsage, guard: &Guard) -> PickRank {
+        let in_use = self.in_use.contains(guard.guard_id());
+        PickRank {
+            not_preferred: !self.active_filter.prefers(guard),
+            no_recent_dir_success: usage.kind == GuardUsageKind::OneHopDirectory
+                && !self.has_recent_dir_success(guard),
+            wrong_in_use: in_use == usage.spread_usage,
+            last_selected: usage
+                .spread_usage
+                .then(|| self.last_selected.get(guard.guard_id()).copied())
+                .flatten(),
+        }
+    }
+
     /// Return the guards whose bridge descriptors we should request, given our
     /// current configuration and status.
     ///
@@ -978,6 +1713,79 @@ impl GuardSet {
     }
 }
 
+/// How good a choice a primary guard is for some usage, according to
+/// [`GuardSet::pick_rank`].
+///
+/// Lower ranks are better.  Each field is less important than the one
+/// before it, and is `false` (or `None`) for a better guard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PickRank {
+    /// True if our filter doesn't prefer this guard.
+    not_preferred: bool,
+    /// True if this is for a directory request, and the guard hasn't
+    /// recently served us directory information.
+    no_recent_dir_success: bool,
+    /// True if we're spreading our usage and this guard is already in use
+    /// for other circuits, or if we aren't and it isn't.
+    wrong_in_use: bool,
+    /// If we're spreading our usage, the value of `n_selections` when we last
+    /// selected this guard.  (Guards we've never selected sort first.)
+    last_selected: Option<u64>,
+}
+
+/// Counts of how many guards were rejected by each of the filters in
+/// [`GuardSet::candidates`].
+#[derive(Default)]
+struct CandidateCounts {
+    /// Guards that were down or not ready for use.
+    running: FilterCount,
+    /// Guards that already had an exploratory circuit pending.
+    pending: FilterCount,
+    /// Guards that could not support the requested usage.
+    suitable: FilterCount,
+    /// Guards that our filters or blocklists excluded.
+    filtered: FilterCount,
+}
+
+/// Return the number of guards that `pick_guard_id` should choose between
+/// for `usage`.
+///
+/// If `usage` asks us to spread our circuits across our primary guards, we
+/// choose between all of them, so that successive selections can cycle
+/// through them.
+fn n_options(usage: &GuardUsage, params: &GuardParams) -> usize {
+    if usage.spread_usage {
+        return usize::MAX;
+    }
+    match usage.kind {
+        GuardUsageKind::OneHopDirectory => params.dir_parallelism,
+        GuardUsageKind::Data => params.data_parallelism,
+    }
+}
+
+/// Reorder `options` (which must be in preference order) so that, within each
+/// list, the guards that we're retrying come in order of most recent success.
+///
+/// Every other guard keeps its place, as do retriable guards that have never
+/// succeeded.  See [`Guard::retry_priority`].
+fn order_retries(options: &mut [(ListKind, &Guard)]) {
+    let slots = (0..options.len())
+        .filter(|&i| options[i].1.retry_priority().is_some())
+        .collect_vec();
+    let mut retries = slots.iter().map(|&i| options[i]).collect_vec();
+    // Since `options` is in preference order, sorting by list kind first
+    // keeps every guard within the part of `options` for its own list.
+    retries.sort_by_key(|(src, g)| (*src, std::cmp::Reverse(g.retry_priority())));
+    for (i, option) in slots.into_iter().zip(retries) {
+        options[i] = option;
+    }
+}
+
+/// Return true if `relay` has an Ed25519 identity that is listed in `blocklist`.
+fn is_blocked<T: HasRelayIds + ?Sized>(blocklist: &HashSet<Ed25519Identity>, relay: &T) -> bool {
+    relay.ed_identity().is_some_and(|id| blocklist.contains(id))
+}
+
 use serde::Serializer;
 use tor_persist::JsonValue;
 
@@ -988,6 +1796,13 @@ pub(crate) struct GuardSample<'a> {
     guards: Vec<Cow<'a, Guard>>,
     /// The identities for the confirmed members of `guards`, in confirmed order.
     confirmed: Cow<'a, Vec<GuardId>>,
+    /// The latest wallclock time that we had accepted, if any.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    latest_wallclock: Option<SystemTime>,
     /// Other data from the state file that this version of Arti doesn't recognize.
     #[serde(flatten)]
     remaining: HashMap<String, JsonValue>,
@@ -1029,12 +1844,15 @@ mod test {
     #![allow(clippy::useless_vec)]
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
-    use tor_linkspec::{HasRelayIds, RelayIdType};
+    use tor_linkspec::{HasAddrs, HasChanMethod, HasRelayIds, RelayIdType};
     use tor_netdir::NetDir;
     use tor_netdoc::doc::netstatus::{RelayFlags, RelayWeight};
 
     use super::*;
+    use crate::filter::Ipv6Preference;
+    use crate::usability::decide_usability;
     use crate::FirstHopId;
+    use std::net::SocketAddr;
     use std::time::Duration;
 
     fn netdir() -> NetDir {
@@ -1042,6 +1860,18 @@ mod test {
         testnet::construct_netdir().unwrap_if_sufficient().unwrap()
     }
 
+    /// Return the usability status of a circuit through `id`, assuming that
+    /// every guard with a pending exploratory circuit has a circuit that
+    /// blocks it.
+    fn usability(guards: &GuardSet, id: &GuardId, usage: &GuardUsage) -> Option<bool> {
+        let priority = guards.circ_priority(id, usage, |blocker| {
+            guards
+                .get(blocker)
+                .is_some_and(|g| g.exploratory_circ_pending())
+        });
+        decide_usability(&priority, None, &GuardParams::default(), Instant::now()).into()
+    }
+
     #[test]
     fn sample_test() {
         // Make a test network that gives every relay equal weight, and which
@@ -1092,7 +1922,7 @@ mod test {
         let mut samples: Vec<HashSet<GuardId>> = Vec::new();
         for _ in 0..3 {
             let mut guards = GuardSet::default();
-            guards.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
+            guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
             assert_eq!(guards.guards.len(), params.min_filtered_sample_size);
             assert_eq!(guards.confirmed.len(), 0);
             assert_eq!(guards.primary.len(), 0);
@@ -1111,7 +1941,7 @@ mod test {
             }
 
             // Make sure that the sample doesn't expand any further.
-            guards.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
+            guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
             assert_eq!(guards.guards.len(), params.min_filtered_sample_size);
             guards.assert_consistency();
 
@@ -1135,7 +1965,7 @@ mod test {
         let t2 = t1 + Duration::from_secs(20);
 
         let mut guards = GuardSet::default();
-        guards.extend_sample_as_needed(t1, &params, &netdir);
+        guards.extend_sample_as_needed(t1, Instant::now(), &params, &netdir);
 
         // Pick a guard and mark it as confirmed.
         let id1 = guards.sample[0].clone();
@@ -1180,7 +2010,7 @@ mod test {
         let t3 = t2 + Duration::from_secs(30);
 
         let mut guards = GuardSet::default();
-        guards.extend_sample_as_needed(t1, &params, &netdir);
+        guards.extend_sample_as_needed(t1, Instant::now(), &params, &netdir);
 
         // Pick a guard and mark it as confirmed.
         let id3 = guards.sample[3].clone();
@@ -1191,7 +2021,7 @@ mod test {
         assert_eq!(&guards.confirmed, &[id3.clone(), id1.clone()]);
 
         // Select primary guards and make sure we're obeying the rules.
-        guards.select_primary_guards(&params);
+        guards.select_primary_guards(&params, SystemTime::now());
         assert_eq!(guards.primary.len(), 4);
         assert_eq!(&guards.primary[0], &id3);
         assert_eq!(&guards.primary[1], &id1);
@@ -1210,7 +2040,7 @@ mod test {
         // one of the previous unconfirmed primary guards.
         guards.record_success(&p4, &params, None, t3);
         assert_eq!(&guards.confirmed, &[id3.clone(), id1.clone(), p4.clone()]);
-        guards.select_primary_guards(&params);
+        guards.select_primary_guards(&params, SystemTime::now());
         assert_eq!(guards.primary.len(), 4);
         assert_eq!(&guards.primary[0], &id3);
         assert_eq!(&guards.primary[1], &id1);
@@ -1224,7 +2054,7 @@ mod test {
         let t1 = SystemTime::now();
 
         let mut guards = GuardSet::default();
-        guards.extend_sample_as_needed(t1, &params, &netdir);
+        guards.extend_sample_as_needed(t1, Instant::now(), &params, &netdir);
         // note that there are only 10 Guard+V2Dir nodes in the netdir().
         assert_eq!(guards.sample.len(), 10);
 
@@ -1232,18 +2062,171 @@ mod test {
         // Pick a guard and mark it as confirmed.
         let id1 = guards.sample[0].clone();
         guards.record_success(&id1, &params, None, t1);
-        assert_eq!(&guards.confirmed, &[id1]);
+        assert_eq!(&guards.confirmed, &[id1.clone()]);
 
         let one_day = Duration::from_secs(86400);
-        guards.expire_old_guards(&params, t1 + one_day * 30);
+        let expired = guards.expire_old_guards(&params, t1 + one_day * 30, Instant::now());
         assert_eq!(guards.sample.len(), 10); // nothing has expired.
+        assert!(expired.is_empty());
 
         // This is long enough to make sure that the confirmed guard has expired.
-        guards.expire_old_guards(&params, t1 + one_day * 70);
+        let expired = guards.expire_old_guards(&params, t1 + one_day * 70, Instant::now());
         assert_eq!(guards.sample.len(), 9);
+        assert_eq!(expired, vec![(id1, DropReason::ExpiredConfirmed)]);
 
-        guards.expire_old_guards(&params, t1 + one_day * 200);
+        let expired = guards.expire_old_guards(&params, t1 + one_day * 200, Instant::now());
         assert_eq!(guards.sample.len(), 0);
+        assert_eq!(expired.len(), 9);
+        assert!(expired
+            .iter()
+            .all(|(_, reason)| *reason == DropReason::ExpiredUnconfirmed));
+    }
+
+    #[test]
+    fn expiration_clock_jump() {
+        let netdir = netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let one_day = Duration::from_secs(86400);
+        let one_min = Duration::from_secs(60);
+        let t1 = SystemTime::now();
+        let m1 = Instant::now();
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(t1, m1, &params, &netdir);
+        guards.expire_old_guards(&params, t1, m1);
+        assert_eq!(guards.sample.len(), 5);
+
+        // The wallclock jumps far into the past, and we need more guards.
+        let t_back = t1 - one_day * 200;
+        guards.expire_old_guards(&params, t_back, m1 + one_min);
+        assert_eq!(guards.sample.len(), 5);
+        let params2 = GuardParams {
+            min_filtered_sample_size: 8,
+            ..params.clone()
+        };
+        guards.extend_sample_as_needed(t_back, m1 + one_min, &params2, &netdir);
+        assert_eq!(guards.sample.len(), 8);
+
+        // Once the clock is corrected, the guards we added while it was wrong
+        // must not be treated as 200 days old.
+        guards.expire_old_guards(&params, t1 + one_day, m1 + one_day);
+        assert_eq!(guards.sample.len(), 8);
+
+        // A small backwards jump is tolerated as usual.
+        guards.expire_old_guards(&params, t1 + one_day - one_min, m1 + one_day);
+        assert!(!guards.wallclock_backwards_warned);
+
+        // The latest wallclock time is persisted, so we still notice a
+        // backward jump that happens while we aren't running.
+        let mut reloaded = GuardSet::from(GuardSample::from(&guards));
+        reloaded.expire_old_guards(&params, t1, Instant::now());
+        assert!(reloaded.wallclock_backwards_warned);
+    }
+
+    #[test]
+    fn expiration_clock_jump_forward_then_corrected() {
+        let netdir = netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let one_day = Duration::from_secs(86400);
+        let one_hour = Duration::from_secs(3600);
+        let t1 = SystemTime::now();
+        let m1 = Instant::now();
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(t1, m1, &params, &netdir);
+        assert_eq!(guards.sample.len(), 5);
+
+        // The wallclock jumps ten days forward...
+        guards.expire_old_guards(&params, t1 + one_day * 10, m1 + one_hour);
+        assert_eq!(guards.sample.len(), 5);
+
+        // ...and is then put right.  That isn't a backward jump: we must not
+        // stop expiring guards until real time catches up with the bad clock.
+        guards.expire_old_guards(&params, t1 + one_hour * 2, m1 + one_hour * 2);
+        assert!(!guards.wallclock_backwards_warned);
+        assert_eq!(
+            guards.latest_wallclock.map(|latest| latest.wallclock),
+            Some(t1 + one_hour * 2)
+        );
+
+        // A real backward jump after the correction is still noticed.
+        guards.expire_old_guards(&params, t1 - one_day, m1 + one_hour * 3);
+        assert!(guards.wallclock_backwards_warned);
+
+        // The monotonic clock tells us how far the wallclock should have
+        // advanced, so a wallclock that keeps pace with it is fine again.
+        guards.expire_old_guards(&params, t1 + one_day * 2, m1 + one_day * 2);
+        assert!(!guards.wallclock_backwards_warned);
+    }
+
+    #[test]
+    fn aged_guardset_rotation() {
+        let netdir = netdir();
+        let one_day = Duration::from_secs(86400);
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            max_sample_bw_fraction: 1.0,
+            max_guardset_age: Some(one_day * 20),
+            ..GuardParams::default()
+        };
+        let t1 = SystemTime::now();
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(t1, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, t1);
+        for id in guards.sample.clone().iter().take(3) {
+            guards.record_success(id, &params, None, t1);
+        }
+        assert_eq!(guards.confirmed.len(), 3);
+        let oldest = |guards: &GuardSet| {
+            guards
+                .confirmed
+                .iter()
+                .min_by_key(|id| guards.get(id).unwrap().confirmed_at())
+                .unwrap()
+                .clone()
+        };
+
+        // Nothing happens while the confirmed guards are young enough.
+        guards.expire_old_guards(&params, t1 + one_day * 5, Instant::now());
+        assert_eq!(guards.confirmed.len(), 3);
+
+        // Once they're too old, we rotate out the oldest one...
+        let t2 = t1 + one_day * 35;
+        let first = oldest(&guards);
+        let expired = guards.expire_old_guards(&params, t2, Instant::now());
+        assert_eq!(guards.confirmed.len(), 2);
+        assert!(guards.get(&first).is_none());
+        assert_eq!(expired, vec![(first, DropReason::RotatedOut)]);
+
+        // ...but not the others, until some time has passed.
+        guards.expire_old_guards(&params, t2 + one_day / 2, Instant::now());
+        assert_eq!(guards.confirmed.len(), 2);
+        let second = oldest(&guards);
+        guards.expire_old_guards(&params, t2 + one_day * 2, Instant::now());
+        assert_eq!(guards.confirmed.len(), 1);
+        assert!(guards.get(&second).is_none());
+
+        // Meanwhile, the sample still works.
+        guards.extend_sample_as_needed(t2 + one_day * 2, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, t2 + one_day * 2);
+        assert_eq!(guards.sample.len(), 5);
+        assert!(guards
+            .pick_guard(
+                &GuardSetSelector::Default,
+                &GuardUsage::default(),
+                &params,
+                Instant::now(),
+            )
+            .is_ok());
     }
 
     #[test]
@@ -1260,8 +2243,8 @@ mod test {
         let sec = Duration::from_secs(1);
 
         let mut guards = GuardSet::default();
-        guards.extend_sample_as_needed(st1, &params, &netdir);
-        guards.select_primary_guards(&params);
+        guards.extend_sample_as_needed(st1, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
 
         // First guard: try it, and let it fail.
         let usage = crate::GuardUsageBuilder::default().build().unwrap();
@@ -1271,21 +2254,21 @@ mod test {
         assert_eq!(src, ListKind::Primary);
         assert_eq!(&id, &id1);
 
-        guards.record_attempt(&id, i1);
+        guards.record_attempt(&id, &params, i1);
         guards.record_failure(&id, None, i1 + sec);
 
         // Second guard: try it, and try it again, and have it fail.
         let (src, id) = guards.pick_guard_id(&usage, &params, i1 + sec).unwrap();
         assert_eq!(src, ListKind::Primary);
         assert_eq!(&id, &id2);
-        guards.record_attempt(&id, i1 + sec);
+        guards.record_attempt(&id, &params, i1 + sec);
 
         let (src, id_x) = guards.pick_guard_id(&usage, &params, i1 + sec).unwrap();
         // We get the same guard this (second) time that we pick it too, since
         // it is a primary guard, and is_pending won't block it.
         assert_eq!(id_x, id);
         assert_eq!(src, ListKind::Primary);
-        guards.record_attempt(&id_x, i1 + sec * 2);
+        guards.record_attempt(&id_x, &params, i1 + sec * 2);
         guards.record_failure(&id_x, None, i1 + sec * 3);
         guards.record_failure(&id, None, i1 + sec * 4);
 
@@ -1293,7 +2276,7 @@ mod test {
         let (src, id3) = guards.pick_guard_id(&usage, &params, i1 + sec * 4).unwrap();
         assert_eq!(src, ListKind::Sample);
         assert!(!guards.primary.contains(&id3));
-        guards.record_attempt(&id3, i1 + sec * 5);
+        guards.record_attempt(&id3, &params, i1 + sec * 5);
 
         // Fourth guard: Third guard will be pending, so a different one gets
         // handed out here.
@@ -1301,28 +2284,16 @@ mod test {
         assert_eq!(src, ListKind::Sample);
         assert!(id3 != id4);
         assert!(!guards.primary.contains(&id4));
-        guards.record_attempt(&id4, i1 + sec * 6);
+        guards.record_attempt(&id4, &params, i1 + sec * 6);
 
         // Look at usability status: primary guards should be usable
         // immediately; third guard should be too (since primary
         // guards are down).  Fourth should not have a known status,
         // since third is pending.
-        assert_eq!(
-            guards.circ_usability_status(&id1, &usage, &params, i1 + sec * 6),
-            Some(true)
-        );
-        assert_eq!(
-            guards.circ_usability_status(&id2, &usage, &params, i1 + sec * 6),
-            Some(true)
-        );
-        assert_eq!(
-            guards.circ_usability_status(&id3, &usage, &params, i1 + sec * 6),
-            Some(true)
-        );
-        assert_eq!(
-            guards.circ_usability_status(&id4, &usage, &params, i1 + sec * 6),
-            None
-        );
+        assert_eq!(usability(&guards, &id1, &usage), Some(true));
+        assert_eq!(usability(&guards, &id2, &usage), Some(true));
+        assert_eq!(usability(&guards, &id3, &usage), Some(true));
+        assert_eq!(usability(&guards, &id4, &usage), None);
 
         // Have both guards succeed.
         guards.record_success(&id3, &params, None, st1 + sec * 7);
@@ -1330,7 +2301,7 @@ mod test {
 
         // Check the impact of having both guards succeed.
         assert!(guards.primary_guards_invalidated);
-        guards.select_primary_guards(&params);
+        guards.select_primary_guards(&params, SystemTime::now());
         assert_eq!(&guards.primary, &[id3.clone(), id4.clone()]);
 
         // Next time we ask for a guard, we get a primary guard again.
@@ -1351,10 +2322,7 @@ mod test {
                 .pick_guard_id(&usage, &params, i1 + sec * 10)
                 .unwrap();
             assert_eq!(src, ListKind::Primary);
-            assert_eq!(
-                guards.circ_usability_status(&id, &usage, &params, i1 + sec * 10),
-                Some(true)
-            );
+            assert_eq!(usability(&guards, &id, &usage), Some(true));
             guards.record_attempt_abandoned(&id);
             found.insert(id);
         }
@@ -1363,14 +2331,8 @@ mod test {
         assert!(found.contains(&id4));
 
         // Since the primaries are now up, other guards are not usable.
-        assert_eq!(
-            guards.circ_usability_status(&id1, &usage, &params, i1 + sec * 12),
-            Some(false)
-        );
-        assert_eq!(
-            guards.circ_usability_status(&id2, &usage, &params, i1 + sec * 12),
-            Some(false)
-        );
+        assert_eq!(usability(&guards, &id1, &usage), Some(false));
+        assert_eq!(usability(&guards, &id2, &usage), Some(false));
     }
 
     #[test]
@@ -1389,13 +2351,13 @@ mod test {
 
         let mut guards = GuardSet::default();
 
-        guards.extend_sample_as_needed(st, &params, &netdir);
-        guards.select_primary_guards(&params);
+        guards.extend_sample_as_needed(st, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
 
         assert_eq!(guards.sample.len(), 5);
         for _ in 0..5 {
             let (_, id) = guards.pick_guard_id(&usage, &params, inst).unwrap();
-            guards.record_attempt(&id, inst);
+            guards.record_attempt(&id, &params, inst);
             guards.record_failure(&id, None, inst + sec);
 
             inst += sec * 2;
@@ -1406,11 +2368,169 @@ mod test {
         assert!(matches!(e, Err(PickGuardError::AllGuardsDown { .. })));
 
         // Now in theory we should re-grow when we extend.
-        guards.extend_sample_as_needed(st, &params, &netdir);
-        guards.select_primary_guards(&params);
+        guards.extend_sample_as_needed(st, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
         assert_eq!(guards.sample.len(), 10);
     }
 
+    #[test]
+    fn sample_health() {
+        let netdir = netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            n_primary: 2,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let inst = Instant::now();
+        let sec = Duration::from_secs(1);
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
+
+        let health = guards.sample_health(&params);
+        assert_eq!(health.filtered_sample_size(), 5);
+        assert_eq!(health.min_filtered_sample_size(), 5);
+        assert_eq!(health.n_usable(), 5);
+        assert_eq!(health.n_down(), 0);
+
+        // Make three of the guards fail.
+        for id in guards.sample.clone().iter().take(3) {
+            guards.record_attempt(id, &params, inst);
+            guards.record_failure(id, None, inst + sec);
+        }
+
+        let health = guards.sample_health(&params);
+        assert_eq!(health.filtered_sample_size(), 5);
+        assert_eq!(health.n_usable(), 2);
+        assert_eq!(health.n_down(), 3);
+    }
+
+    /// Return a test network in which only every fourth relay has an IPv6
+    /// address.
+    ///
+    /// (Only the even-numbered relays are directory caches, and so only they
+    /// can be guards: this way, half of the possible guards have IPv6.)
+    fn mixed_ipv6_netdir() -> NetDir {
+        tor_netdir::testnet::construct_custom_netdir(|idx, builder| {
+            if idx % 4 == 0 {
+                let addr = [0x2001, 0xdb8, 0, 0, 0, 0, 0, idx as u16];
+                builder.rs.add_or_port(SocketAddr::from((addr, 9001)));
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap()
+    }
+
+    /// Return true if the guard with `id` in `guards` has an IPv6 address.
+    fn has_ipv6_addr(guards: &GuardSet, id: &GuardId) -> bool {
+        guards.get(id).unwrap().addrs().iter().any(|a| a.is_ipv6())
+    }
+
+    #[test]
+    fn prefer_ipv6() {
+        let netdir = mixed_ipv6_netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 20,
+            n_primary: 2,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let inst = Instant::now();
+        let sec = Duration::from_secs(1);
+        let usage = crate::GuardUsageBuilder::default().build().unwrap();
+
+        let mut filter = GuardFilter::unfiltered();
+        filter.set_ipv6_preference(Ipv6Preference::Prefer);
+        let mut guards = GuardSet::default();
+        guards.set_filter(filter, false);
+        guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+
+        // Preferring IPv6 guards doesn't keep the others out of the sample...
+        assert!(guards.sample.iter().any(|id| !has_ipv6_addr(&guards, id)));
+
+        // ...or change which guards are primary.  Confirm a guard without an
+        // IPv6 address, and then one with an IPv6 address, so that they
+        // become our primary guards, in that order.
+        let find = |ipv6: bool| {
+            guards
+                .sample
+                .iter()
+                .find(|id| has_ipv6_addr(&guards, id) == ipv6)
+                .unwrap()
+                .clone()
+        };
+        let (id4, id6) = (find(false), find(true));
+        for id in [&id4, &id6] {
+            guards.record_success(id, &params, None, SystemTime::now());
+        }
+        guards.select_primary_guards(&params, SystemTime::now());
+        assert_eq!(guards.primary, vec![id4.clone(), id6.clone()]);
+
+        // Among the primary guards, we pick the one with IPv6.
+        for _ in 0..20 {
+            let (src, id) = guards.pick_guard_id(&usage, &params, inst).unwrap();
+            assert_eq!((src, &id), (ListKind::Primary, &id6));
+        }
+
+        // selection_order agrees.
+        let order = guards.selection_order(&usage, &params, inst);
+        assert_eq!(order[0], (ListKind::Primary, id6.clone()));
+        assert_eq!(order[1], (ListKind::Primary, id4.clone()));
+
+        // If it fails, we use the other primary guard, even though there
+        // are non-primary guards with IPv6 addresses.
+        guards.record_attempt(&id6, &params, inst);
+        guards.record_failure(&id6, None, inst + sec);
+        let (src, id) = guards.pick_guard_id(&usage, &params, inst + sec).unwrap();
+        assert_eq!((src, &id), (ListKind::Primary, &id4));
+    }
+
+    #[test]
+    fn require_ipv6() {
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            n_primary: 3,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let usage = crate::GuardUsageBuilder::default().build().unwrap();
+        let mut filter = GuardFilter::unfiltered();
+        filter.set_ipv6_preference(Ipv6Preference::Require);
+
+        let mut guards = GuardSet::default();
+        guards.set_filter(filter.clone(), false);
+        guards.extend_sample_as_needed(
+            SystemTime::now(),
+            Instant::now(),
+            &params,
+            &mixed_ipv6_netdir(),
+        );
+        guards.select_primary_guards(&params, SystemTime::now());
+
+        assert!(guards.primary.iter().all(|id| has_ipv6_addr(&guards, id)));
+        for _ in 0..20 {
+            let (_, first_hop) = guards
+                .pick_guard(&GuardSetSelector::Default, &usage, &params, Instant::now())
+                .unwrap();
+            // We only try to contact the guard over IPv6.
+            let addrs = first_hop.chan_method().socket_addrs().unwrap().to_vec();
+            assert!(!addrs.is_empty());
+            assert!(addrs.iter().all(|a| a.is_ipv6()));
+        }
+
+        // In a network without any IPv6 guards, selection fails.
+        let mut guards = GuardSet::default();
+        guards.set_filter(filter, false);
+        guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir());
+        guards.select_primary_guards(&params, SystemTime::now());
+
+        let e = guards.pick_guard_id(&usage, &params, Instant::now());
+        assert!(matches!(e, Err(PickGuardError::NoIpv6Guards)));
+    }
+
     #[test]
     fn retry_primary() {
         let netdir = netdir();
@@ -1424,8 +2544,8 @@ mod test {
 
         let mut guards = GuardSet::default();
 
-        guards.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
-        guards.select_primary_guards(&params);
+        guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
 
         assert_eq!(guards.primary.len(), 2);
         assert!(!guards.all_primary_guards_are_unreachable());
@@ -1456,6 +2576,107 @@ mod test {
         assert_eq!(p_id3, p_id1);
     }
 
+    #[test]
+    fn retry_order() {
+        let netdir = netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            n_primary: 2,
+            max_sample_bw_fraction: 1.0,
+            ..GuardParams::default()
+        };
+        let usage = crate::GuardUsageBuilder::default().build().unwrap();
+        let t0 = SystemTime::now();
+        let t1 = t0 + Duration::from_secs(600);
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(t0, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, t0);
+        let p1 = guards.primary[0].clone();
+        let p2 = guards.primary[1].clone();
+
+        // Both primary guards work; the second one most recently.
+        guards.record_success(&p1, &params, None, t0);
+        guards.record_success(&p2, &params, None, t1);
+        guards.select_primary_guards(&params, t1);
+        assert_eq!(guards.primary, vec![p1.clone(), p2.clone()]);
+
+        // While both are reachable, we use them in order.
+        let (_, id) = guards
+            .pick_guard_id(&usage, &params, Instant::now())
+            .unwrap();
+        assert_eq!(id, p1);
+
+        // Then the network goes down, and comes back.
+        guards.record_failure(&p1, None, Instant::now());
+        guards.record_failure(&p2, None, Instant::now());
+        assert!(guards.all_primary_guards_are_unreachable());
+        guards.mark_primary_guards_retriable();
+
+        // We retry the guard that worked most recently first.
+        let (kind, id) = guards
+            .pick_guard_id(&usage, &params, Instant::now())
+            .unwrap();
+        assert_eq!(kind, ListKind::Primary);
+        assert_eq!(id, p2);
+    }
+
+    #[test]
+    fn flapping_primary() {
+        let netdir = netdir();
+        let params = GuardParams {
+            min_filtered_sample_size: 5,
+            n_primary: 2,
+            max_sample_bw_fraction: 1.0,
+            primary_demote_threshold: 3,
+            primary_promote_delay: Duration::from_secs(60),
+            ..GuardParams::default()
+        };
+        let st = SystemTime::now();
+        let inst = Instant::now();
+        let sec = Duration::from_secs(1);
+
+        let mut guards = GuardSet::default();
+        guards.extend_sample_as_needed(st, Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, st);
+        let id1 = guards.primary[0].clone();
+        guards.record_success(&id1, &params, None, st);
+        guards.select_primary_guards(&params, st);
+        let primary_orig = guards.primary.clone();
+        assert_eq!(&primary_orig[0], &id1);
+
+        // A few brief blips don't change the primary set.
+        for i in 1..=5 {
+            guards.record_failure(&id1, None, inst + sec * i);
+            guards.select_primary_guards(&params, st + sec * i);
+            assert_eq!(&guards.primary, &primary_orig);
+            guards.record_success(&id1, &params, None, st + sec * i);
+            guards.select_primary_guards(&params, st + sec * i);
+            assert_eq!(&guards.primary, &primary_orig);
+        }
+
+        // Repeated failures demote the guard.
+        for i in 10..13 {
+            guards.record_failure(&id1, None, inst + sec * i);
+        }
+        guards.select_primary_guards(&params, st + sec * 13);
+        assert!(!guards.primary.contains(&id1));
+        let primary_demoted = guards.primary.clone();
+
+        // Coming back briefly isn't enough to be promoted again.
+        guards.record_success(&id1, &params, None, st + sec * 20);
+        guards.select_primary_guards(&params, st + sec * 30);
+        assert_eq!(&guards.primary, &primary_demoted);
+        guards.record_failure(&id1, None, inst + sec * 40);
+        guards.record_success(&id1, &params, None, st + sec * 50);
+        guards.select_primary_guards(&params, st + sec * 100);
+        assert_eq!(&guards.primary, &primary_demoted);
+
+        // But staying reachable for long enough is.
+        guards.select_primary_guards(&params, st + sec * 110);
+        assert_eq!(&guards.primary[0], &id1);
+    }
+
     #[test]
     fn count_missing_mds() {
         let netdir = netdir();
@@ -1467,8 +2688,8 @@ mod test {
         };
         let usage = crate::GuardUsageBuilder::default().build().unwrap();
         let mut guards = GuardSet::default();
-        guards.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
-        guards.select_primary_guards(&params);
+        guards.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+        guards.select_primary_guards(&params, SystemTime::now());
         assert_eq!(guards.primary.len(), 2);
 
         let (_kind, p_id1) = guards
@@ -1501,8 +2722,8 @@ mod test {
             ..GuardParams::default()
         };
         let mut guards1 = GuardSet::default();
-        guards1.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
-        guards1.select_primary_guards(&params);
+        guards1.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+        guards1.select_primary_guards(&params, SystemTime::now());
         let mut guards2 = guards1.clone();
 
         // Make a persistent change in guards1, and a different persistent change in guards2.
@@ -1536,8 +2757,8 @@ mod test {
         for _ in 0..4 {
             // There is roughly a 1-in-5000 chance of getting the same set
             // twice, so we loop until that doesn't happen.
-            guards3.extend_sample_as_needed(SystemTime::now(), &params, &netdir);
-            guards3.select_primary_guards(&params);
+            guards3.extend_sample_as_needed(SystemTime::now(), Instant::now(), &params, &netdir);
+            guards3.select_primary_guards(&params, SystemTime::now());
             g3_set = guards3
                 .guards
                 .values()
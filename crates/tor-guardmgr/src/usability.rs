@@ -0,0 +1,251 @@
+//! Logic for deciding whether a circuit built through a guard may be used.
+//!
+//! This is the decision described in guard-spec.txt, section 4.6 ("Deciding
+//! when to use a circuit").  We keep it in a pure function, separate from the
+//! state that feeds it, so that the rules can be tested directly.
+
+use std::time::Instant;
+
+use crate::guard::Reachable;
+use crate::GuardParams;
+
+/// Whether a circuit built through a guard may be used.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Usability {
+    /// The circuit may be used right away.
+    ///
+    /// (In the spec's terms, it is `<complete>`.)
+    Usable,
+    /// The circuit must not be used: we have, or expect to have, a circuit
+    /// through a guard that we would rather use.
+    Unusable,
+    /// We can't decide yet, since a circuit through a guard that we would
+    /// rather use is still being built, or is itself waiting.
+    ///
+    /// (In the spec's terms, our circuit stays `<waiting_for_better_guard>`.)
+    Pending,
+}
+
+impl From<Usability> for Option<bool> {
+    fn from(u: Usability) -> Option<bool> {
+        match u {
+            Usability::Usable => Some(true),
+            Usability::Unusable => Some(false),
+            Usability::Pending => None,
+        }
+    }
+}
+
+/// What we know about a guard that we would rather use than the one that a
+/// circuit was built through.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct BetterGuard {
+    /// True if this guard is a primary guard.
+    pub(crate) is_primary: bool,
+    /// Whether we believe this guard to be reachable.
+    pub(crate) reachable: Reachable,
+    /// True if there is a circuit through this guard that is still being
+    /// built, or that has been built and is waiting to see whether it may be
+    /// used, and which would serve the same purpose as ours.
+    pub(crate) has_blocking_circuit: bool,
+}
+
+/// Where the guard that a circuit was built through stands in our
+/// preference order.
+#[derive(Debug, Clone)]
+pub(crate) enum GuardPriority {
+    /// The guard is a primary guard.
+    ///
+    /// Earlier primary guards don't block later ones.
+    Primary,
+    /// The guard is a non-primary guard.
+    ///
+    /// Holds every guard that we'd rather use, that we'd be allowed to use
+    /// for the same purpose, most preferred first.
+    NonPrimary(Vec<BetterGuard>),
+    /// The guard isn't one that we would choose at all.
+    Unlisted,
+}
+
+/// Decide whether a circuit through a guard with the given `priority` may be
+/// used.
+///
+/// `waiting_since` is the time at which the circuit was built and began
+/// waiting for a better guard, if it has done so.  If it has waited for at
+/// least the `guard-nonprimary-idle-timeout` parameter, we give up on it.
+//
+// TODO-SPEC: The spec says that a <complete> circuit through a better guard
+// blocks our circuit.  Here we go further, and consider our circuit unusable
+// as soon as any better guard is known to be reachable.
+pub(crate) fn decide_usability(
+    priority: &GuardPriority,
+    waiting_since: Option<Instant>,
+    params: &GuardParams,
+    now: Instant,
+) -> Usability {
+    let expired = waiting_since
+        .and_then(|w| now.checked_duration_since(w))
+        .is_some_and(|d| d >= params.np_idle_timeout);
+    if expired {
+        return Usability::Unusable;
+    }
+
+    // In test-network mode, we don't wait for better guards: a circuit is
+    // usable as soon as it succeeds.
+    if params.test_network {
+        return Usability::Usable;
+    }
+
+    let better = match priority {
+        // Circuits built to primary guards are always usable immediately.
+        GuardPriority::Primary => return Usability::Usable,
+        GuardPriority::NonPrimary(better) => better,
+        GuardPriority::Unlisted => return Usability::Unusable,
+    };
+
+    // We can use the guard if all the guards we'd _rather_ use are either
+    // down, or have no circuits that block ours.
+    for guard in better {
+        match (guard.is_primary, guard.reachable) {
+            (_, Reachable::Reachable) => return Usability::Unusable,
+            (_, Reachable::Unreachable) => {}
+            (true, Reachable::Untried | Reachable::Retriable) => return Usability::Unusable,
+            (false, Reachable::Untried | Reachable::Retriable) => {
+                if guard.has_blocking_circuit {
+                    return Usability::Pending;
+                }
+            }
+        }
+    }
+
+    Usability::Usable
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::time::Duration;
+    use Usability as U;
+
+    /// Return a `BetterGuard`.
+    fn better(is_primary: bool, reachable: Reachable, has_blocking_circuit: bool) -> BetterGuard {
+        BetterGuard {
+            is_primary,
+            reachable,
+            has_blocking_circuit,
+        }
+    }
+
+    /// Return the verdict for a circuit that isn't waiting, with default
+    /// parameters.
+    fn decide(priority: GuardPriority) -> Usability {
+        decide_usability(&priority, None, &GuardParams::default(), Instant::now())
+    }
+
+    #[test]
+    fn complete() {
+        assert_eq!(decide(GuardPriority::Primary), U::Usable);
+        assert_eq!(decide(GuardPriority::NonPrimary(vec![])), U::Usable);
+        // Better guards that are down, or that have no circuits that could
+        // beat ours, don't block us.
+        assert_eq!(
+            decide(GuardPriority::NonPrimary(vec![
+                better(true, Reachable::Unreachable, true),
+                better(false, Reachable::Untried, false),
+                better(false, Reachable::Retriable, false),
+            ])),
+            U::Usable
+        );
+        assert_eq!(decide(GuardPriority::Unlisted), U::Unusable);
+    }
+
+    #[test]
+    fn better_guard() {
+        // A better guard that works, or a primary guard that might, makes us
+        // unusable.
+        assert_eq!(
+            decide(GuardPriority::NonPrimary(vec![better(
+                false,
+                Reachable::Reachable,
+                false
+            )])),
+            U::Unusable
+        );
+        assert_eq!(
+            decide(GuardPriority::NonPrimary(vec![better(
+                true,
+                Reachable::Untried,
+                false
+            )])),
+            U::Unusable
+        );
+    }
+
+    #[test]
+    fn waiting_for_better_guard() {
+        let blocked = GuardPriority::NonPrimary(vec![
+            better(false, Reachable::Unreachable, true),
+            better(false, Reachable::Untried, true),
+        ]);
+        assert_eq!(decide(blocked), U::Pending);
+
+        // Only the first guard that settles the matter counts: here, we wait
+        // for it, even though a guard after it is reachable.
+        let blocked = GuardPriority::NonPrimary(vec![
+            better(false, Reachable::Retriable, true),
+            better(false, Reachable::Reachable, false),
+        ]);
+        assert_eq!(decide(blocked), U::Pending);
+    }
+
+    #[test]
+    fn usable_if_no_better_guard_timeout() {
+        let params = GuardParams::default();
+        let now = Instant::now();
+        let blocked = GuardPriority::NonPrimary(vec![better(false, Reachable::Untried, true)]);
+
+        let waiting_since = now - params.np_idle_timeout + Duration::from_secs(1);
+        assert_eq!(
+            decide_usability(&blocked, Some(waiting_since), &params, now),
+            U::Pending
+        );
+
+        // Once we've waited too long, we give up on the circuit, even if it
+        // would otherwise be usable.
+        let waiting_since = now - params.np_idle_timeout;
+        assert_eq!(
+            decide_usability(&blocked, Some(waiting_since), &params, now),
+            U::Unusable
+        );
+        assert_eq!(
+            decide_usability(&GuardPriority::Primary, Some(waiting_since), &params, now),
+            U::Unusable
+        );
+    }
+
+    #[test]
+    fn test_network() {
+        let params = GuardParams {
+            test_network: true,
+            ..GuardParams::default()
+        };
+        let blocked = GuardPriority::NonPrimary(vec![better(false, Reachable::Reachable, true)]);
+        assert_eq!(
+            decide_usability(&blocked, None, &params, Instant::now()),
+            U::Usable
+        );
+    }
+}
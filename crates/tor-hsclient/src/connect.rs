@@ -17,7 +17,6 @@ use tor_bytes::Writeable;
 use tor_cell::relaycell::hs::intro_payload::{self, IntroduceHandshakePayload};
 use tor_cell::relaycell::msg::{AnyRelayMsg, Introduce1, Rendezvous2};
 use tor_error::{debug_report, warn_report, Bug};
-use tor_hscrypto::Subcredential;
 use tor_proto::circuit::handshake::hs_ntor;
 use tracing::{debug, trace};
 
@@ -34,12 +33,12 @@ use tor_circmgr::timeouts::Action as TimeoutsAction;
 use tor_dirclient::request::Requestable as _;
 use tor_error::{internal, into_internal};
 use tor_error::{HasRetryTime as _, RetryTime};
-use tor_hscrypto::pk::{HsBlindId, HsId, HsIdKey};
+use tor_hscrypto::pk::{HsId, HsIdKey};
 use tor_hscrypto::RendCookie;
 use tor_linkspec::{CircTarget, HasRelayIds, OwnedCircTarget, RelayId};
 use tor_llcrypto::pk::ed25519::Ed25519Identity;
 use tor_netdir::{NetDir, Relay};
-use tor_netdoc::doc::hsdesc::{HsDesc, IntroPointDesc};
+use tor_netdoc::doc::hsdesc::{HsDesc, HsDescRequest, IntroPointDesc};
 use tor_proto::circuit::{
     CircParameters, ClientCirc, ConversationInHandler, MetaCellDisposition, MsgHandler,
 };
@@ -194,10 +193,8 @@ struct Context<'c, R: Runtime, M: MocksForConnect<R>> {
     secret_keys: HsClientSecretKeys,
     /// HS ID
     hsid: HsId,
-    /// Blinded HS ID
-    hs_blind_id: HsBlindId,
-    /// The subcredential to use during this time period
-    subcredential: Subcredential,
+    /// Blinded HS ID and subcredential to use during this time period
+    desc_request: HsDescRequest,
     /// Mock data
     mocks: M,
 }
@@ -366,23 +363,19 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         mocks: M,
     ) -> Result<Self, ConnError> {
         let time_period = netdir.hs_time_period();
-        let (hs_blind_id_key, subcredential) = HsIdKey::try_from(hsid)
-            .map_err(|_| CE::InvalidHsId)?
-            .compute_blinded_key(time_period)
-            .map_err(
-                // TODO HS what on earth do these errors mean, in practical terms ?
-                // In particular, we'll want to convert them to a ConnError variant,
-                // but what ErrorKind should they have ?
-                into_internal!("key blinding error, don't know how to handle"),
-            )?;
-        let hs_blind_id = hs_blind_id_key.id();
+        let hs_id_key = HsIdKey::try_from(hsid).map_err(|_| CE::InvalidHsId)?;
+        let desc_request = HsDescRequest::new(&hs_id_key, time_period).map_err(
+            // TODO HS what on earth do these errors mean, in practical terms ?
+            // In particular, we'll want to convert them to a ConnError variant,
+            // but what ErrorKind should they have ?
+            into_internal!("key blinding error, don't know how to handle"),
+        )?;
 
         Ok(Context {
             netdir,
             config,
             hsid,
-            hs_blind_id,
-            subcredential,
+            desc_request,
             circpool,
             runtime,
             secret_keys,
@@ -469,7 +462,7 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         }
 
         let hs_dirs = self.netdir.hs_dirs_download(
-            self.hs_blind_id,
+            *self.desc_request.blinded_id(),
             self.netdir.hs_time_period(),
             &mut self.mocks.thread_rng(),
         )?;
@@ -555,7 +548,8 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
             .try_into()
             .map_err(into_internal!("BoundedInt was not truly bounded!"))?;
         let request = {
-            let mut r = tor_dirclient::request::HsDescDownloadRequest::new(self.hs_blind_id);
+            let mut r =
+                tor_dirclient::request::HsDescDownloadRequest::new(*self.desc_request.blinded_id());
             r.set_max_len(max_len);
             r
         };
@@ -600,14 +594,8 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
 
         let now = self.runtime.wallclock();
 
-        HsDesc::parse_decrypt_validate(
-            &desc_text,
-            &self.hs_blind_id,
-            now,
-            &self.subcredential,
-            hsc_desc_enc,
-        )
-        .map_err(DescriptorErrorDetail::from)
+        HsDesc::parse_decrypt_validate(&desc_text, &self.desc_request, now, hsc_desc_enc)
+            .map_err(DescriptorErrorDetail::from)
     }
 
     /// Given the descriptor, try to connect to service
@@ -1118,7 +1106,7 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         let service_info = hs_ntor::HsNtorServiceInfo::new(
             ipt.intro_desc.svc_ntor_key().clone(),
             ipt.intro_desc.ipt_sid_key().clone(),
-            self.subcredential,
+            *self.desc_request.subcredential(),
         );
         let handshake_state =
             hs_ntor::HsNtorClientState::new(&mut self.mocks.thread_rng(), service_info);
@@ -1662,19 +1650,15 @@ mod test {
             .catch_unwind() // TODO HS TESTS: remove this and the AssertUnwindSafe
             .await;
 
-        let (hs_blind_id_key, subcredential) = HsIdKey::try_from(hsid)
-            .unwrap()
-            .compute_blinded_key(time_period)
-            .unwrap();
-        let hs_blind_id = hs_blind_id_key.id();
+        let desc_request =
+            HsDescRequest::new(&HsIdKey::try_from(hsid).unwrap(), time_period).unwrap();
 
         let sk = curve25519::StaticSecret::from(test_data::TEST_SECKEY_2).into();
 
         let hsdesc = HsDesc::parse_decrypt_validate(
             test_data::TEST_DATA_2,
-            &hs_blind_id,
+            &desc_request,
             now,
-            &subcredential,
             Some(&HsClientDescEncKeypair::new(pk, sk)),
         )
         .unwrap()
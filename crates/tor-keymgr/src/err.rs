@@ -24,6 +24,25 @@ pub enum Error {
     #[error("{0}")]
     Keystore(#[from] Arc<dyn KeystoreError>),
 
+    /// A [`Keystore`](crate::Keystore) contains the requested key,
+    /// but we could not decode it.
+    ///
+    /// This can mean that the key is corrupted,
+    /// or that it is not of the expected [`KeyType`](crate::KeyType).
+    #[error("{0}")]
+    KeyDecode(Arc<dyn KeystoreError>),
+
+    /// A [`Keystore`](crate::Keystore) could not be read or written,
+    /// because of an IO error (or because of bad permissions).
+    #[error("{0}")]
+    KeystoreIo(Arc<dyn KeystoreError>),
+
+    /// An IO error while writing an encoded key.
+    ///
+    /// See [`EncodableKey::write_to`](crate::EncodableKey::write_to).
+    #[error("Unable to write encoded key")]
+    Io(#[source] Arc<std::io::Error>),
+
     /// An error returned when the [`KeyMgr`](crate::KeyMgr) is asked to generate a key that already exists.
     ///
     /// Note that because there is no locking of the keystore,
@@ -56,8 +75,9 @@ impl HasKind for Error {
         use Error as E;
 
         match self {
-            E::Keystore(e) => e.kind(),
+            E::Keystore(e) | E::KeyDecode(e) | E::KeystoreIo(e) => e.kind(),
             E::Corruption(_) => EK::KeystoreCorrupted,
+            E::Io(_) => EK::KeystoreAccessFailed,
             E::KeyAlreadyExists => EK::BadApiUsage, // TODO: not strictly right
             E::UnsupportedKeyAlgorithm(_) => EK::BadApiUsage,
             E::Bug(e) => e.kind(),
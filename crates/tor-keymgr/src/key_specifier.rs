@@ -318,6 +318,26 @@ pub trait KeySpecifier {
     /// This function should return `None` for keys that are recognized by Arti's key stores, but
     /// not by C Tor's key store (such as `HsClientIntroAuthKeypair`).
     fn ctor_path(&self) -> Option<CTorPath>;
+
+    /// Check that this specifier describes a well-formed key path,
+    /// without accessing any key store.
+    ///
+    /// This fails if [`arti_path()`](KeySpecifier::arti_path) fails,
+    /// unless that is only because the key has no [`ArtiPath`],
+    /// and it has a [`CTorPath`] instead.
+    ///
+    /// Services can use this at startup to detect bad key naming
+    /// (for example, a component that can't be represented in an `ArtiPath`)
+    /// before they first try to use the key.
+    fn validate(&self) -> StdResult<(), ArtiPathUnavailableError> {
+        match self.arti_path() {
+            Ok(_) => Ok(()),
+            Err(ArtiPathUnavailableError::ArtiPathUnavailable) if self.ctor_path().is_some() => {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
 }
 
 /// A trait for serializing and deserializing specific types of [`Slug`]s.
@@ -847,6 +867,37 @@ KeyPathInfo {
         );
     }
 
+    #[test]
+    fn validate_key_specifier() {
+        #[derive(Deftly, Debug, PartialEq)]
+        #[derive_deftly(KeySpecifier)]
+        #[deftly(prefix = "hss")]
+        #[deftly(role = "ks_hs_id")]
+        #[deftly(summary = "test key")]
+        struct TestSpecifier {
+            nickname: String,
+        }
+
+        let good = TestSpecifier {
+            nickname: "allium-cepa".into(),
+        };
+        assert!(good.validate().is_ok());
+
+        // A nickname that isn't a valid Slug can't appear in an ArtiPath.
+        let bad = TestSpecifier {
+            nickname: "no spaces please".into(),
+        };
+        assert!(matches!(
+            bad.validate(),
+            Err(ArtiPathUnavailableError::Bug(_))
+        ));
+
+        // A key that only has a CTorPath is fine too.
+        let ctor_path = CTorPath("private_key".into());
+        assert!(ctor_path.validate().is_ok());
+        assert!(KeyPath::CTor(ctor_path).validate().is_ok());
+    }
+
     #[test]
     fn define_key_specifier_with_denotator() {
         #[derive(Deftly, Debug, PartialEq)]
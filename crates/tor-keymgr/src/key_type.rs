@@ -141,6 +141,13 @@ impl KeyType {
     /// Returns an error if the [`KeypairData`] is of an unsupported type.
     pub(crate) fn try_from_keypair_data(key: &KeypairData) -> Result<KeyType> {
         let algo = key.algorithm().map_err(|e| internal!("invalid algr {e}"))?;
+        Self::try_from_keypair_algorithm(&algo)
+    }
+
+    /// Return the keypair `KeyType` corresponding to the specified [`Algorithm`].
+    ///
+    /// Returns an error if the [`Algorithm`] is not one we support for keypairs.
+    pub(crate) fn try_from_keypair_algorithm(algo: &Algorithm) -> Result<KeyType> {
         match algo {
             Algorithm::Ed25519 => Ok(KeyType::Ed25519Keypair),
             Algorithm::Other(algo) if algo.as_str() == X25519_ALGORITHM_NAME => {
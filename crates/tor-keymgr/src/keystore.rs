@@ -1,6 +1,8 @@
 //! The [`Keystore`] trait and its implementations.
 
 pub(crate) mod arti;
+pub(crate) mod composite;
+pub(crate) mod encrypted;
 pub(crate) mod ephemeral;
 
 use rand::{CryptoRng, RngCore};
@@ -9,8 +11,9 @@ use ssh_key::public::{Ed25519PublicKey, KeyData, OpaquePublicKey};
 use ssh_key::{Algorithm, AlgorithmName, LineEnding, PrivateKey, PublicKey};
 use tor_error::{internal, into_internal};
 use tor_hscrypto::pk::{
-    HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKeypair, HsDescSigningKeypair, HsIdKey,
-    HsIdKeypair, HsIntroPtSessionIdKeypair, HsSvcNtorKeypair,
+    HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKey, HsClientDescEncKeypair, HsDescSigningKey,
+    HsDescSigningKeypair, HsIdKey, HsIdKeypair, HsIntroPtSessionIdKey, HsIntroPtSessionIdKeypair,
+    HsSvcNtorKey, HsSvcNtorKeypair,
 };
 use tor_llcrypto::pk::{curve25519, ed25519};
 
@@ -19,6 +22,8 @@ use crate::ssh::{SshKeyAlgorithm, ED25519_EXPANDED_ALGORITHM_NAME, X25519_ALGORI
 use crate::{Error, KeyPath, KeySpecifier, KeystoreId, Result};
 
 use downcast_rs::{impl_downcast, Downcast};
+use std::io;
+use std::sync::Arc;
 
 /// A type-erased key returned by a [`Keystore`].
 pub type ErasedKey = Box<dyn EncodableKey>;
@@ -37,7 +42,13 @@ pub trait Keystore: Send + Sync + 'static {
     fn id(&self) -> &KeystoreId;
 
     /// Check if the key identified by `key_spec` exists in this key store.
-    fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool>;
+    ///
+    /// The default implementation calls [`get`](Keystore::get) and discards the result.
+    /// Key stores that can determine whether a key is present without reading and decoding
+    /// the key material (for example, by checking whether a file exists) should override it.
+    fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
+        self.get(key_spec, key_type).map(|k| k.is_some())
+    }
 
     /// Retrieve the key identified by `key_spec`.
     ///
@@ -227,6 +238,11 @@ enum SshKeyDataInner {
     Public(KeyData),
     /// The [`KeypairData`] of a private key.
     Private(KeypairData),
+    /// A passphrase-encrypted private key.
+    ///
+    /// Only the public part of an encrypted key can be inspected without
+    /// decrypting it first.
+    Encrypted(Box<PrivateKey>),
 }
 
 impl SshKeyData {
@@ -268,6 +284,23 @@ impl SshKeyData {
         Ok(Self(SshKeyDataInner::Private(key)))
     }
 
+    /// Try to convert a [`PrivateKey`] to [`SshKeyData`].
+    ///
+    /// Unlike [`try_from_keypair_data`](SshKeyData::try_from_keypair_data),
+    /// this preserves the encryption parameters of passphrase-encrypted keys.
+    ///
+    /// Returns an error if the algorithm of the key is not supported.
+    pub(crate) fn try_from_private_key(key: PrivateKey) -> Result<Self> {
+        if !key.is_encrypted() {
+            return Self::try_from_keypair_data(key.key_data().clone());
+        }
+
+        // Make sure we know how to handle the key once it's decrypted.
+        let _: KeyType = KeyType::try_from_keypair_algorithm(&key.algorithm())?;
+
+        Ok(Self(SshKeyDataInner::Encrypted(Box::new(key))))
+    }
+
     /// Encode this key as an OpenSSH-formatted key using the specified `comment`
     pub(crate) fn to_openssh_string(&self, comment: &str) -> Result<String> {
         let openssh_key = match &self.0 {
@@ -287,6 +320,13 @@ impl SshKeyData {
                     .map_err(|_| tor_error::internal!("failed to encode SSH key"))?
                     .to_string()
             }
+            SshKeyDataInner::Encrypted(key) => {
+                // The comment of an encrypted key is part of its encrypted payload,
+                // so we can't change it here.
+                key.to_openssh(LineEnding::LF)
+                    .map_err(|_| tor_error::internal!("failed to encode SSH key"))?
+                    .to_string()
+            }
         };
 
         Ok(openssh_key)
@@ -308,6 +348,7 @@ impl SshKeyData {
                 let algorithm = key.algorithm();
                 ssh_to_internal_erased!(PUBLIC key, algorithm)
             }
+            SshKeyDataInner::Encrypted(key) => Ok(Box::new(encrypted::EncryptedKeypair(*key))),
         }
     }
 
@@ -319,6 +360,7 @@ impl SshKeyData {
         match &self.0 {
             SshKeyDataInner::Public(k) => KeyType::try_from_key_data(k),
             SshKeyDataInner::Private(k) => KeyType::try_from_keypair_data(k),
+            SshKeyDataInner::Encrypted(k) => KeyType::try_from_keypair_algorithm(&k.algorithm()),
         }
     }
 }
@@ -352,6 +394,24 @@ pub trait EncodableKey: Downcast + Sealed {
 
     /// Return the [`SshKeyData`] of this key.
     fn as_ssh_key_data(&self) -> Result<SshKeyData>;
+
+    /// Write this key to `w`, in the OpenSSH format used by the Arti keystore.
+    ///
+    /// The default implementation encodes the whole key in memory
+    /// (using [`as_ssh_key_data`](EncodableKey::as_ssh_key_data))
+    /// before writing it.
+    /// Key types with large encodings can override this to write
+    /// their encoding piece by piece instead,
+    /// so that keystores can stream them straight to disk.
+    ///
+    /// Errors from `w` are returned as [`Error::Io`].
+    fn write_to(&self, w: &mut dyn io::Write) -> Result<()> {
+        // TODO (#1095): decide what information, if any, to put in the comment
+        let comment = "";
+        let openssh_key = self.as_ssh_key_data()?.to_openssh_string(comment)?;
+        w.write_all(openssh_key.as_bytes())
+            .map_err(|e| Error::Io(Arc::new(e)))
+    }
 }
 
 impl_downcast!(EncodableKey);
@@ -520,6 +580,21 @@ pub trait ToEncodableKey {
     fn from_encodable_key(key: Self::Key) -> Self;
 }
 
+/// A keypair from which we can derive a public key.
+///
+/// This is used by [`KeyMgr::get_public`](crate::KeyMgr::get_public), to
+/// look up the public part of a stored keypair.
+pub trait ToEncodableKeypair: ToEncodableKey {
+    /// The type of the public part of this keypair.
+    type PublicKey;
+
+    /// Return the public part of this keypair.
+    fn public_key(&self) -> Self::PublicKey;
+}
+
+/// The type of the public part of the keypair `K`.
+pub type PublicKeyOf<K> = <K as ToEncodableKeypair>::PublicKey;
+
 impl ToEncodableKey for HsClientDescEncKeypair {
     type Key = curve25519::StaticKeypair;
 
@@ -615,3 +690,51 @@ impl ToEncodableKey for HsSvcNtorKeypair {
         key.into()
     }
 }
+
+impl ToEncodableKeypair for HsClientDescEncKeypair {
+    type PublicKey = HsClientDescEncKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.into()
+    }
+}
+
+impl ToEncodableKeypair for HsBlindIdKeypair {
+    type PublicKey = HsBlindIdKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.into()
+    }
+}
+
+impl ToEncodableKeypair for HsIdKeypair {
+    type PublicKey = HsIdKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.into()
+    }
+}
+
+impl ToEncodableKeypair for HsDescSigningKeypair {
+    type PublicKey = HsDescSigningKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.as_ref().verifying_key().into()
+    }
+}
+
+impl ToEncodableKeypair for HsIntroPtSessionIdKeypair {
+    type PublicKey = HsIntroPtSessionIdKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.as_ref().verifying_key().into()
+    }
+}
+
+impl ToEncodableKeypair for HsSvcNtorKeypair {
+    type PublicKey = HsSvcNtorKey;
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.public().clone()
+    }
+}
@@ -5,10 +5,12 @@
 pub(crate) mod err;
 pub(crate) mod ssh;
 
-use std::io::{self, ErrorKind};
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write as _};
 use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use crate::keystore::{EncodableKey, ErasedKey, KeySpecifier, Keystore};
 use crate::{arti_path, ArtiPath, ArtiPathUnavailableError, KeyPath, KeyType, KeystoreId, Result};
@@ -114,6 +116,7 @@ impl Keystore for ArtiNativeKeystore {
         &self.id
     }
 
+    // Overridden to avoid reading and parsing the key file just to check if it exists.
     fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
         let path = rel_path_if_supported!(self.rel_path(key_spec, key_type), Ok(false));
         let abs_path =
@@ -169,20 +172,44 @@ impl Keystore for ArtiNativeKeystore {
             })?;
         }
 
-        let key = key.as_ssh_key_data()?;
-        // TODO (#1095): decide what information, if any, to put in the comment
-        let comment = "";
-
-        let openssh_key = key.to_openssh_string(comment)?;
-
-        Ok(self
+        // Like CheckedDir::write_and_replace, we write to a temporary file
+        // and then move it into place; but we let the key write itself to the
+        // file, rather than encoding all of it in memory first.
+        let tmp_path = path.with_extension("tmp");
+        let file = self
             .keystore_dir
-            .write_and_replace(&path, openssh_key)
+            .open(
+                &tmp_path,
+                OpenOptions::new().create(true).truncate(true).write(true),
+            )
             .map_err(|err| ArtiNativeKeystoreError::FsMistrust {
                 action: FilesystemAction::Write,
-                path,
+                path: tmp_path.clone(),
                 err: err.into(),
-            })?)
+            })?;
+        let io_err = |err| ArtiNativeKeystoreError::Filesystem {
+            action: FilesystemAction::Write,
+            path: tmp_path.clone(),
+            err,
+        };
+
+        let mut writer = io::BufWriter::new(file);
+        key.write_to(&mut writer).map_err(|e| match e {
+            crate::Error::Io(err) => io_err(err).into(),
+            e => e,
+        })?;
+        writer.flush().map_err(|err| io_err(Arc::new(err)))?;
+        drop(writer);
+
+        let dir = self.keystore_dir.as_path();
+        fs::rename(dir.join(&tmp_path), dir.join(&path)).map_err(|err| {
+            ArtiNativeKeystoreError::Filesystem {
+                action: FilesystemAction::Write,
+                path,
+                err: Arc::new(err),
+            }
+        })?;
+        Ok(())
     }
 
     fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>> {
@@ -347,6 +374,7 @@ mod tests {
                 assert!($key_store.contains($key_spec, $key_type).unwrap());
             } else {
                 assert!(res.is_none());
+                assert!(!$key_store.contains($key_spec, $key_type).unwrap());
             }
         }};
     }
@@ -419,9 +447,10 @@ mod tests {
 
         // Make the permissions of the test key too permissive
         fs::set_permissions(&key_path, fs::Permissions::from_mode(0o777)).unwrap();
-        assert!(key_store
-            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
-            .is_err());
+        let Err(err) = key_store.get(&TestSpecifier::default(), &KeyType::Ed25519Keypair) else {
+            panic!("got a key despite the bad permissions")
+        };
+        assert!(matches!(err, crate::Error::KeystoreIo(_)));
 
         // Make the permissions of the parent directory too lax
         fs::set_permissions(
@@ -449,6 +478,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_undecodable() {
+        use tor_error::{ErrorKind, HasKind as _};
+
+        let (key_store, _keystore_dir) = init_keystore(true);
+        let key_spec = TestSpecifier::default();
+
+        // A key file that isn't a key at all.
+        let ed_path = key_path(&key_store, &KeyType::Ed25519Keypair);
+        fs::write(ed_path, "this is not an OpenSSH key").unwrap();
+        let Err(err) = key_store.get(&key_spec, &KeyType::Ed25519Keypair) else {
+            panic!("decoded a key from a file that isn't a key")
+        };
+        assert!(matches!(err, crate::Error::KeyDecode(_)), "{err:?}");
+        assert_eq!(err.kind(), ErrorKind::KeystoreCorrupted);
+
+        // A valid key of the wrong type.
+        let x_path = key_path(&key_store, &KeyType::X25519StaticKeypair);
+        fs::write(x_path, OPENSSH_ED25519).unwrap();
+        let Err(err) = key_store.get(&key_spec, &KeyType::X25519StaticKeypair) else {
+            panic!("decoded a key of the wrong type")
+        };
+        assert!(matches!(err, crate::Error::KeyDecode(_)), "{err:?}");
+    }
+
     #[test]
     fn get() {
         // Initialize an empty key store
@@ -523,6 +577,74 @@ mod tests {
         assert_contains_arti_paths!([TestSpecifier::path_prefix(),], key_store.list().unwrap());
     }
 
+    #[test]
+    fn write_to() {
+        let (key_store, keystore_dir) = init_keystore(false);
+        let mut rng = tor_basic_utils::test_rng::testing_rng();
+        let keypair = ed25519::Keypair::generate(&mut rng);
+        let public = tor_llcrypto::pk::curve25519::PublicKey::from([7; 32]);
+
+        for (key, key_type) in [
+            (&keypair as &dyn EncodableKey, KeyType::Ed25519Keypair),
+            (&public, KeyType::X25519PublicKey),
+        ] {
+            let expected = key
+                .as_ssh_key_data()
+                .unwrap()
+                .to_openssh_string("")
+                .unwrap();
+            let mut written = Vec::new();
+            key.write_to(&mut written).unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), expected);
+
+            // The keystore stores exactly the same thing.
+            let key_spec = TestSpecifier::default();
+            key_store.insert(key, &key_spec, &key_type).unwrap();
+            let path = keystore_dir
+                .as_ref()
+                .join(key_store.rel_path(&key_spec, &key_type).unwrap());
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+            assert!(!path.with_extension("tmp").exists());
+        }
+    }
+
+    #[test]
+    fn insert_expanded_round_trip() {
+        let (key_store, keystore_dir) = init_keystore(false);
+
+        let mut rng = tor_basic_utils::test_rng::testing_rng();
+        let keypair = ed25519::Keypair::generate(&mut rng);
+        let expanded: ed25519::ExpandedKeypair = (&keypair).into();
+
+        let key_spec = TestSpecifier::default();
+        let key_type = &KeyType::Ed25519ExpandedKeypair;
+        assert_found!(key_store, &key_spec, key_type, false);
+
+        assert!(key_store.insert(&expanded, &key_spec, key_type).is_ok());
+        let path = keystore_dir
+            .as_ref()
+            .join(key_store.rel_path(&key_spec, key_type).unwrap());
+        assert_eq!(path.extension().unwrap(), "ed25519_expanded_private");
+        assert!(path.exists());
+
+        // The key is only found under its own key type.
+        assert_found!(key_store, &key_spec, key_type, true);
+        assert_found!(key_store, &key_spec, &KeyType::Ed25519Keypair, false);
+
+        let Ok(key) = key_store
+            .get(&key_spec, key_type)
+            .unwrap()
+            .unwrap()
+            .downcast::<ed25519::ExpandedKeypair>()
+        else {
+            panic!("failed to downcast key to ed25519::ExpandedKeypair")
+        };
+        assert_eq!(key.public(), expanded.public());
+        assert_eq!(key.to_secret_key_bytes(), expanded.to_secret_key_bytes());
+
+        assert_contains_arti_paths!([TestSpecifier::path_prefix(),], key_store.list().unwrap());
+    }
+
     #[test]
     fn remove() {
         // Initialize the key store
@@ -135,6 +135,16 @@ impl HasKind for ArtiNativeKeystoreError {
 
 impl From<ArtiNativeKeystoreError> for crate::Error {
     fn from(e: ArtiNativeKeystoreError) -> Self {
-        crate::Error::Keystore(Arc::new(e))
+        use ArtiNativeKeystoreError as KE;
+
+        match e {
+            KE::Filesystem { .. } | KE::FsMistrust { .. } => crate::Error::KeystoreIo(Arc::new(e)),
+            KE::SshKeyParse { .. } | KE::UnexpectedSshKeyType { .. } => {
+                crate::Error::KeyDecode(Arc::new(e))
+            }
+            KE::MalformedPath { .. } | KE::UnknownKeyType(_) | KE::Bug(_) => {
+                crate::Error::Keystore(Arc::new(e))
+            }
+        }
     }
 }
@@ -1,7 +1,9 @@
 //! Traits for converting keys to and from OpenSSH format.
 //
-// TODO #902: OpenSSH keys can have passphrases. While the current implementation isn't able to
-// handle such keys, we will eventually need to support them (this will be a breaking API change).
+// Note: passphrase-encrypted OpenSSH keys are parsed into an encrypted `SshKeyData`, which is
+// only useful to an `EncryptedKeystore` (which knows the passphrase).
+//
+// TODO #902: we might eventually want to support OpenSSH keys with passphrases more generally.
 
 use crate::keystore::arti::err::ArtiNativeKeystoreError;
 use crate::ssh::SshKeyAlgorithm;
@@ -29,11 +31,11 @@ pub(super) struct UnparsedOpenSshKey {
 /// Parse an OpenSSH key, returning its corresponding [`SshKeyData`].
 macro_rules! parse_openssh {
     (PRIVATE $key:expr, $key_type:expr) => {{
-        SshKeyData::try_from_keypair_data(parse_openssh!(
+        SshKeyData::try_from_private_key(parse_openssh!(
             $key,
             $key_type,
             ssh_key::private::PrivateKey::from_openssh
-        ).key_data().clone())?
+        ))?
     }};
 
     (PUBLIC $key:expr, $key_type:expr) => {{
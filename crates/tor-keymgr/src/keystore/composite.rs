@@ -0,0 +1,268 @@
+//! CompositeKeystore implementation (a key store that searches several other key stores)
+
+use std::collections::HashSet;
+
+use tor_error::bad_api_usage;
+
+use crate::{
+    EncodableKey, ErasedKey, KeyPath, KeySpecifier, KeyType, Keystore, KeystoreId, Result,
+};
+
+/// A key store made of an ordered list of other key stores.
+///
+/// This can be used to layer key stores: for example, an in-memory cache,
+/// followed by an encrypted on-disk store, followed by a read-only system store.
+///
+/// The backends are used as follows:
+///
+///   * [`get`](Keystore::get) searches the backends in order,
+///     and returns the key from the first backend that has it.
+///     Keys in earlier backends therefore shadow keys in later ones.
+///   * [`contains`](Keystore::contains) returns true if any backend has the key.
+///   * [`insert`](Keystore::insert) writes only to the designated *primary* backend,
+///     which need not be the first one.
+///   * [`remove`](Keystore::remove) removes the key from every backend that has it.
+///   * [`list`](Keystore::list) returns the union of the keys in all the backends,
+///     in backend order, without duplicates.
+///
+/// Except for `remove`, an error from any backend is returned immediately,
+/// without consulting the remaining backends.
+pub struct CompositeKeystore {
+    /// The identifier of this key store.
+    id: KeystoreId,
+    /// The backends, in the order in which they are searched.
+    stores: Vec<Box<dyn Keystore>>,
+    /// The index in `stores` of the backend that new keys are written to.
+    primary: usize,
+}
+
+impl CompositeKeystore {
+    /// Create a new [`CompositeKeystore`] with identifier `id`, backed by `stores`.
+    ///
+    /// `stores` are searched in the order given.
+    /// New keys are inserted into `stores[primary]`.
+    ///
+    /// Returns an error if `primary` is not a valid index into `stores`.
+    pub fn new(id: String, stores: Vec<Box<dyn Keystore>>, primary: usize) -> Result<Self> {
+        if primary >= stores.len() {
+            return Err(bad_api_usage!(
+                "primary key store index {primary} out of range (have {} key stores)",
+                stores.len()
+            )
+            .into());
+        }
+
+        Ok(Self {
+            id: KeystoreId(id),
+            stores,
+            primary,
+        })
+    }
+}
+
+impl Keystore for CompositeKeystore {
+    fn id(&self) -> &KeystoreId {
+        &self.id
+    }
+
+    fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
+        for store in &self.stores {
+            if store.contains(key_spec, key_type)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn get(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<ErasedKey>> {
+        for store in &self.stores {
+            if let Some(key) = store.get(key_spec, key_type)? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<()> {
+        self.stores[self.primary].insert(key, key_spec, key_type)
+    }
+
+    fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>> {
+        // Try every backend, even if one of them fails, so that a failure
+        // (for example, from a read-only store) doesn't leave the key
+        // in the backends after it.
+        let mut removed = None;
+        let mut first_err = None;
+        for store in &self.stores {
+            match store.remove(key_spec, key_type) {
+                Ok(Some(())) => removed = Some(()),
+                Ok(None) => {}
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(removed),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<(KeyPath, KeyType)>> {
+        let mut seen = HashSet::new();
+        let mut keys = vec![];
+        for store in &self.stores {
+            for entry in store.list()? {
+                if seen.insert(entry.clone()) {
+                    keys.push(entry);
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_llcrypto::pk::ed25519;
+
+    use super::*;
+
+    use crate::test_utils::TestSpecifier;
+    use crate::ArtiEphemeralKeystore;
+
+    fn key_type() -> &'static KeyType {
+        &KeyType::Ed25519Keypair
+    }
+
+    fn key_spec() -> Box<dyn KeySpecifier> {
+        Box::<TestSpecifier>::default()
+    }
+
+    fn other_key_spec() -> Box<dyn KeySpecifier> {
+        Box::new(TestSpecifier::new("-other"))
+    }
+
+    /// Return an in-memory key store containing `keys` (stored under `key_spec()`,
+    /// then `other_key_spec()`).
+    fn store_with(id: &str, keys: &[&ed25519::Keypair]) -> Box<dyn Keystore> {
+        let store = ArtiEphemeralKeystore::new(id.to_string());
+        for (key, spec) in keys.iter().zip([key_spec(), other_key_spec()]) {
+            store.insert(*key, spec.as_ref(), key_type()).unwrap();
+        }
+        Box::new(store)
+    }
+
+    fn get_public(store: &dyn Keystore, spec: &dyn KeySpecifier) -> Option<ed25519::PublicKey> {
+        store.get(spec, key_type()).unwrap().map(|k| {
+            let Ok(k) = k.downcast::<ed25519::Keypair>() else {
+                panic!("failed to downcast key to ed25519::Keypair")
+            };
+            k.verifying_key()
+        })
+    }
+
+    #[test]
+    fn bad_primary() {
+        assert!(CompositeKeystore::new("composite".into(), vec![], 0).is_err());
+        let stores = vec![store_with("a", &[])];
+        assert!(CompositeKeystore::new("composite".into(), stores, 1).is_err());
+    }
+
+    #[test]
+    fn get_precedence() {
+        let mut rng = testing_rng();
+        let k1 = ed25519::Keypair::generate(&mut rng);
+        let k2 = ed25519::Keypair::generate(&mut rng);
+        let k3 = ed25519::Keypair::generate(&mut rng);
+
+        let stores = vec![
+            store_with("cache", &[]),
+            store_with("disk", &[&k1]),
+            store_with("system", &[&k2, &k3]),
+        ];
+        let composite = CompositeKeystore::new("composite".into(), stores, 1).unwrap();
+        assert_eq!(composite.id(), &KeystoreId("composite".into()));
+
+        // The first store that has the key wins.
+        assert_eq!(
+            get_public(&composite, key_spec().as_ref()),
+            Some(k1.verifying_key())
+        );
+        // Keys only found in later stores are still found.
+        assert_eq!(
+            get_public(&composite, other_key_spec().as_ref()),
+            Some(k3.verifying_key())
+        );
+        assert!(composite
+            .contains(other_key_spec().as_ref(), key_type())
+            .unwrap());
+
+        // The key present in two stores is only listed once.
+        assert_eq!(composite.list().unwrap().len(), 2);
+
+        // Removing a key removes it from all the stores.
+        assert_eq!(
+            composite.remove(key_spec().as_ref(), key_type()).unwrap(),
+            Some(())
+        );
+        assert!(get_public(&composite, key_spec().as_ref()).is_none());
+        assert!(composite
+            .stores
+            .iter()
+            .all(|s| !s.contains(key_spec().as_ref(), key_type()).unwrap()));
+        assert_eq!(
+            composite.remove(key_spec().as_ref(), key_type()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn insert_targets_primary() {
+        let mut rng = testing_rng();
+        let k1 = ed25519::Keypair::generate(&mut rng);
+
+        let stores = vec![
+            store_with("cache", &[]),
+            store_with("disk", &[]),
+            store_with("system", &[]),
+        ];
+        let composite = CompositeKeystore::new("composite".into(), stores, 1).unwrap();
+
+        composite
+            .insert(&k1, key_spec().as_ref(), key_type())
+            .unwrap();
+
+        let present = composite
+            .stores
+            .iter()
+            .map(|s| s.contains(key_spec().as_ref(), key_type()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(present, vec![false, true, false]);
+        assert_eq!(
+            get_public(&composite, key_spec().as_ref()),
+            Some(k1.verifying_key())
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! EncryptedKeystore implementation (passphrase-encrypted wrapper around another key store)
+
+pub(crate) mod err;
+
+use std::sync::Arc;
+
+use ssh_key::PrivateKey;
+use tor_error::internal;
+use zeroize::Zeroizing;
+
+use crate::keystore::encrypted::err::EncryptedKeystoreError;
+use crate::keystore::{Sealed, SshKeyDataInner};
+use crate::{
+    EncodableKey, ErasedKey, KeyPath, KeySpecifier, KeyType, Keystore, KeystoreId, Result,
+    SshKeyData,
+};
+
+/// A key store that encrypts the private keys it stores using a passphrase.
+///
+/// This is a wrapper around another [`Keystore`], which is used for the actual storage.
+/// Private keys are encrypted before being passed to the underlying key store,
+/// and decrypted when they are retrieved from it.
+///
+/// Keys are encrypted using the standard OpenSSH private key encryption scheme:
+/// the encryption key is derived from the passphrase using `bcrypt-pbkdf`,
+/// and the key material is encrypted with `aes256-ctr`.
+/// This means the keys written by an `EncryptedKeystore` wrapping an
+/// [`ArtiNativeKeystore`](crate::ArtiNativeKeystore) are regular passphrase-protected
+/// OpenSSH keys.
+///
+/// Public keys are not secret, so they are stored unencrypted.
+pub struct EncryptedKeystore {
+    /// The underlying key store.
+    inner: Box<dyn Keystore>,
+    /// The passphrase used for encrypting and decrypting keys.
+    passphrase: Zeroizing<String>,
+}
+
+impl EncryptedKeystore {
+    /// Create a new [`EncryptedKeystore`] that stores its keys in `inner`,
+    /// encrypted using `passphrase`.
+    ///
+    /// The [`id`](Keystore::id) of the new key store is that of `inner`.
+    pub fn new(inner: Box<dyn Keystore>, passphrase: impl Into<String>) -> Self {
+        Self {
+            inner,
+            passphrase: Zeroizing::new(passphrase.into()),
+        }
+    }
+}
+
+impl Keystore for EncryptedKeystore {
+    fn id(&self) -> &KeystoreId {
+        self.inner.id()
+    }
+
+    fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
+        self.inner.contains(key_spec, key_type)
+    }
+
+    fn get(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<ErasedKey>> {
+        let Some(key) = self.inner.get(key_spec, key_type)? else {
+            return Ok(None);
+        };
+
+        match key.downcast::<EncryptedKeypair>() {
+            Ok(key) => {
+                let decrypted = key
+                    .0
+                    .decrypt(self.passphrase.as_bytes())
+                    .map_err(|e| EncryptedKeystoreError::Decrypt(Arc::new(e)))?;
+
+                SshKeyData::try_from_keypair_data(decrypted.key_data().clone())?
+                    .into_erased()
+                    .map(Some)
+            }
+            Err(key) => match key_type {
+                KeyType::Ed25519PublicKey | KeyType::X25519PublicKey => Ok(Some(key)),
+                _ => Err(EncryptedKeystoreError::NotEncrypted(key_type.clone()).into()),
+            },
+        }
+    }
+
+    fn insert(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<()> {
+        match key.as_ssh_key_data()?.0 {
+            SshKeyDataInner::Public(_) => self.inner.insert(key, key_spec, key_type),
+            SshKeyDataInner::Private(keypair) => {
+                // TODO (#1095): decide what information, if any, to put in the comment
+                let comment = "";
+                let key = PrivateKey::new(keypair, comment)
+                    .map_err(|_| internal!("failed to create SSH private key"))?;
+                let encrypted = key
+                    .encrypt(&mut rand::thread_rng(), self.passphrase.as_bytes())
+                    .map_err(|e| EncryptedKeystoreError::Encrypt(Arc::new(e)))?;
+
+                self.inner
+                    .insert(&EncryptedKeypair(encrypted), key_spec, key_type)
+            }
+            SshKeyDataInner::Encrypted(_) => {
+                Err(internal!("tried to insert an already encrypted key?!").into())
+            }
+        }
+    }
+
+    fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>> {
+        self.inner.remove(key_spec, key_type)
+    }
+
+    fn list(&self) -> Result<Vec<(KeyPath, KeyType)>> {
+        self.inner.list()
+    }
+}
+
+/// A passphrase-encrypted keypair.
+///
+/// This is what an [`EncryptedKeystore`] hands to its underlying key store,
+/// and what the underlying key store returns for keys written by an `EncryptedKeystore`.
+pub(crate) struct EncryptedKeypair(pub(crate) PrivateKey);
+
+impl Sealed for EncryptedKeypair {}
+
+impl EncodableKey for EncryptedKeypair {
+    fn key_type() -> KeyType
+    where
+        Self: Sized,
+    {
+        // The actual type of the key is only known at runtime (see SshKeyData::key_type).
+        // This is never called, because EncryptedKeypair is never passed to KeyMgr.
+        KeyType::Unknown {
+            arti_extension: "encrypted".into(),
+        }
+    }
+
+    fn as_ssh_key_data(&self) -> Result<SshKeyData> {
+        SshKeyData::try_from_private_key(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use std::fs;
+
+    use fs_mistrust::Mistrust;
+    use tempfile::{tempdir, TempDir};
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_error::{ErrorKind, HasKind};
+    use tor_llcrypto::pk::{curve25519, ed25519};
+
+    use super::*;
+
+    use crate::test_utils::TestSpecifier;
+    use crate::{ArtiEphemeralKeystore, ArtiNativeKeystore};
+
+    /// Create an `ArtiNativeKeystore` in a fresh temporary directory.
+    fn native_keystore(keystore_dir: &TempDir) -> Box<dyn Keystore> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(keystore_dir, fs::Permissions::from_mode(0o700)).unwrap();
+        }
+
+        Box::new(
+            ArtiNativeKeystore::from_path_and_mistrust(keystore_dir, &Mistrust::default()).unwrap(),
+        )
+    }
+
+    fn ephemeral_keystore(passphrase: &str) -> EncryptedKeystore {
+        let inner = ArtiEphemeralKeystore::new("test-ephemeral".to_string());
+        EncryptedKeystore::new(Box::new(inner), passphrase)
+    }
+
+    #[test]
+    fn round_trip() {
+        let key_store = ephemeral_keystore("correct horse battery staple");
+        let mut rng = testing_rng();
+
+        let keypair = ed25519::Keypair::generate(&mut rng);
+        key_store
+            .insert(
+                &keypair,
+                &TestSpecifier::default(),
+                &KeyType::Ed25519Keypair,
+            )
+            .unwrap();
+
+        let key = key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap()
+            .unwrap();
+        let Ok(key) = key.downcast::<ed25519::Keypair>() else {
+            panic!("failed to downcast key to ed25519::Keypair")
+        };
+        assert_eq!(key.to_bytes(), keypair.to_bytes());
+
+        let secret = curve25519::StaticSecret::random_from_rng(&mut rng);
+        let keypair = curve25519::StaticKeypair {
+            public: curve25519::PublicKey::from(&secret),
+            secret,
+        };
+        key_store
+            .insert(
+                &keypair,
+                &TestSpecifier::default(),
+                &KeyType::X25519StaticKeypair,
+            )
+            .unwrap();
+
+        let key = key_store
+            .get(&TestSpecifier::default(), &KeyType::X25519StaticKeypair)
+            .unwrap()
+            .unwrap();
+        let Ok(key) = key.downcast::<curve25519::StaticKeypair>() else {
+            panic!("failed to downcast key to curve25519::StaticKeypair")
+        };
+        assert_eq!(key.secret.to_bytes(), keypair.secret.to_bytes());
+        assert_eq!(key.public, keypair.public);
+
+        assert_eq!(key_store.list().unwrap().len(), 2);
+        assert!(key_store
+            .remove(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap()
+            .is_some());
+        assert!(key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn public_keys_unencrypted() {
+        let key_store = ephemeral_keystore("passphrase");
+        let public = ed25519::Keypair::generate(&mut testing_rng()).verifying_key();
+
+        key_store
+            .insert(
+                &public,
+                &TestSpecifier::default(),
+                &KeyType::Ed25519PublicKey,
+            )
+            .unwrap();
+
+        let key = key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519PublicKey)
+            .unwrap()
+            .unwrap();
+        let Ok(key) = key.downcast::<ed25519::PublicKey>() else {
+            panic!("failed to downcast key to ed25519::PublicKey")
+        };
+        assert_eq!(*key, public);
+    }
+
+    #[test]
+    fn encrypted_on_disk() {
+        let keystore_dir = tempdir().unwrap();
+        let key_store = EncryptedKeystore::new(native_keystore(&keystore_dir), "passphrase");
+        let keypair = ed25519::Keypair::generate(&mut testing_rng());
+
+        key_store
+            .insert(
+                &keypair,
+                &TestSpecifier::default(),
+                &KeyType::Ed25519Keypair,
+            )
+            .unwrap();
+
+        // The unencrypted key store can see that the key exists, but can't decode it.
+        let plain_store = native_keystore(&keystore_dir);
+        assert!(plain_store
+            .contains(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap());
+        let key = plain_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap()
+            .unwrap();
+        assert!(key.downcast::<ed25519::Keypair>().is_err());
+
+        let key = key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap()
+            .unwrap();
+        let Ok(key) = key.downcast::<ed25519::Keypair>() else {
+            panic!("failed to downcast key to ed25519::Keypair")
+        };
+        assert_eq!(key.to_bytes(), keypair.to_bytes());
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let keystore_dir = tempdir().unwrap();
+        let key_store = EncryptedKeystore::new(native_keystore(&keystore_dir), "passphrase");
+        let keypair = ed25519::Keypair::generate(&mut testing_rng());
+
+        key_store
+            .insert(
+                &keypair,
+                &TestSpecifier::default(),
+                &KeyType::Ed25519Keypair,
+            )
+            .unwrap();
+
+        let key_store =
+            EncryptedKeystore::new(native_keystore(&keystore_dir), "not the passphrase");
+        let err = key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .map(|_| "<type erased key>")
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::KeystoreAccessFailed);
+        assert_eq!(err.to_string(), "Failed to decrypt key (wrong passphrase?)");
+    }
+
+    #[test]
+    fn unencrypted_private_key() {
+        let keystore_dir = tempdir().unwrap();
+        let keypair = ed25519::Keypair::generate(&mut testing_rng());
+
+        native_keystore(&keystore_dir)
+            .insert(
+                &keypair,
+                &TestSpecifier::default(),
+                &KeyType::Ed25519Keypair,
+            )
+            .unwrap();
+
+        let key_store = EncryptedKeystore::new(native_keystore(&keystore_dir), "passphrase");
+        let err = key_store
+            .get(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .map(|_| "<type erased key>")
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::KeystoreCorrupted);
+    }
+}
@@ -0,0 +1,47 @@
+//! An error type for [`EncryptedKeystore`](crate::EncryptedKeystore).
+
+use std::sync::Arc;
+
+use tor_error::{ErrorKind, HasKind};
+
+use crate::{KeyType, KeystoreError};
+
+/// An error returned by [`EncryptedKeystore`](crate::EncryptedKeystore)'s
+/// [`Keystore`](crate::Keystore) implementation.
+#[derive(thiserror::Error, Debug, Clone)]
+pub(crate) enum EncryptedKeystoreError {
+    /// Failed to encrypt a key before handing it to the underlying key store.
+    #[error("Failed to encrypt key")]
+    Encrypt(#[source] Arc<ssh_key::Error>),
+
+    /// Failed to decrypt a key retrieved from the underlying key store.
+    ///
+    /// This usually means the passphrase is wrong.
+    #[error("Failed to decrypt key (wrong passphrase?)")]
+    Decrypt(#[source] Arc<ssh_key::Error>),
+
+    /// The underlying key store returned a private key that wasn't encrypted.
+    #[error("Found unencrypted key of type {0:?} in encrypted keystore")]
+    NotEncrypted(KeyType),
+}
+
+impl KeystoreError for EncryptedKeystoreError {}
+
+impl HasKind for EncryptedKeystoreError {
+    fn kind(&self) -> ErrorKind {
+        use EncryptedKeystoreError as E;
+
+        match self {
+            E::Encrypt(_) => ErrorKind::Internal,
+            // TODO: we might want a separate ErrorKind for "wrong passphrase"
+            E::Decrypt(_) => ErrorKind::KeystoreAccessFailed,
+            E::NotEncrypted(_) => ErrorKind::KeystoreCorrupted,
+        }
+    }
+}
+
+impl From<EncryptedKeystoreError> for crate::Error {
+    fn from(e: EncryptedKeystoreError) -> Self {
+        crate::Error::Keystore(Arc::new(e))
+    }
+}
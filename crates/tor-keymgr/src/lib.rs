@@ -77,9 +77,14 @@ pub use ssh::SshKeyAlgorithm;
 pub use {
     key_type::{KeyType, UnknownKeyTypeError},
     keystore::arti::ArtiNativeKeystore,
+    keystore::composite::CompositeKeystore,
+    keystore::encrypted::EncryptedKeystore,
     keystore::ephemeral::ArtiEphemeralKeystore,
-    keystore::{EncodableKey, ErasedKey, Keygen, KeygenRng, Keystore, SshKeyData, ToEncodableKey},
-    mgr::{KeyMgr, KeyMgrBuilder, KeyMgrBuilderError, KeystoreEntry},
+    keystore::{
+        EncodableKey, ErasedKey, Keygen, KeygenRng, Keystore, PublicKeyOf, SshKeyData,
+        ToEncodableKey, ToEncodableKeypair,
+    },
+    mgr::{KeyMgr, KeyMgrBuilder, KeyMgrBuilderError, KeystoreEntry, MigrationReport},
     ssh_key,
 };
 
@@ -3,9 +3,9 @@
 //! See the [`KeyMgr`] docs for more details.
 
 use crate::{
-    BoxedKeystore, EncodableKey, KeyPath, KeyPathError, KeyPathInfo, KeyPathInfoExtractor,
-    KeyPathPattern, KeySpecifier, KeyType, Keygen, KeygenRng, KeystoreId, KeystoreSelector, Result,
-    ToEncodableKey,
+    BoxedKeystore, EncodableKey, ErasedKey, Error, KeyPath, KeyPathError, KeyPathInfo,
+    KeyPathInfoExtractor, KeyPathPattern, KeySpecifier, KeyType, Keygen, KeygenRng, KeystoreId,
+    KeystoreSelector, PublicKeyOf, Result, ToEncodableKey, ToEncodableKeypair,
 };
 
 use itertools::Itertools;
@@ -70,6 +70,18 @@ pub struct KeystoreEntry<'a> {
     keystore_id: &'a KeystoreId,
 }
 
+/// The outcome of a [`KeyMgr::migrate`] operation.
+#[derive(Clone, Debug, Default, amplify::Getters)]
+pub struct MigrationReport<'a> {
+    /// The entries that were migrated, described using their new [`KeyType`].
+    migrated: Vec<KeystoreEntry<'a>>,
+    /// The entries that could not be migrated, described using their old [`KeyType`],
+    /// together with the reason.
+    ///
+    /// These entries were left untouched.
+    failed: Vec<(KeystoreEntry<'a>, Error)>,
+}
+
 impl KeyMgrBuilder {
     /// Construct a [`KeyMgr`] from this builder.
     pub fn build(self) -> StdResult<KeyMgr, KeyMgrBuilderError> {
@@ -132,6 +144,23 @@ impl KeyMgr {
         self.get_from_store(key_spec, &K::Key::key_type(), self.all_stores())
     }
 
+    /// Read a keypair from one of the key stores, and return only its public part.
+    ///
+    /// This is meant for operations that only need the public key (such as
+    /// displaying a fingerprint), so that they don't need to hold on to the
+    /// secret key.  The keypair is dropped (and so its secret key zeroized) as
+    /// soon as we have extracted the public key from it.
+    ///
+    /// The keypair is looked up exactly as with [`get()`](KeyMgr::get).
+    ///
+    /// Returns `Ok(None)` if none of the key stores have the requested key.
+    pub fn get_public<K: ToEncodableKeypair>(
+        &self,
+        key_spec: &dyn KeySpecifier,
+    ) -> Result<Option<PublicKeyOf<K>>> {
+        Ok(self.get::<K>(key_spec)?.map(|keypair| keypair.public_key()))
+    }
+
     /// Retrieve the specified keystore entry, and try to deserialize it as `K::Key`.
     ///
     /// The key returned is retrieved from the key store specified in the [`KeystoreEntry`].
@@ -303,6 +332,71 @@ impl KeyMgr {
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Re-encode every key of type `from`, in _all_ keystores, as a key of type `to`.
+    ///
+    /// This is meant for use when the stored representation of a [`KeyType`] changes:
+    /// each key of type `from` is read, passed to `convert`,
+    /// and the result is written back under the same [`KeyPath`] with type `to`.
+    /// The old entry is then removed (unless `from` and `to` are the same).
+    ///
+    /// Each key is migrated separately: the new entry is written before the old one is
+    /// removed, so a key is never lost, even if the migration is interrupted.
+    /// (If it is interrupted, the key may be present with both types; running the migration
+    /// again will finish the job.)
+    ///
+    /// If `convert` fails for a key, or the key can't be decoded as `from`,
+    /// the key is left as it was, and listed in [`MigrationReport::failed`].
+    /// Any other error (for example, failing to read or write a keystore)
+    /// stops the migration and is returned.
+    pub fn migrate(
+        &self,
+        from: &KeyType,
+        to: &KeyType,
+        convert: impl Fn(ErasedKey) -> Result<ErasedKey>,
+    ) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+
+        for store in self.all_stores() {
+            for (key_path, key_type) in store.list()? {
+                if &key_type != from {
+                    continue;
+                }
+
+                let entry = KeystoreEntry {
+                    key_path,
+                    key_type,
+                    keystore_id: store.id(),
+                };
+                let converted = match store.get(&entry.key_path, from) {
+                    // The key was removed after we listed it.
+                    Ok(None) => continue,
+                    Ok(Some(key)) => convert(key),
+                    Err(e @ Error::KeyDecode(_)) => Err(e),
+                    Err(e) => return Err(e),
+                };
+                let key = match converted {
+                    Ok(key) => key,
+                    Err(e) => {
+                        report.failed.push((entry, e));
+                        continue;
+                    }
+                };
+
+                store.insert(&*key, &entry.key_path, to)?;
+                if from != to {
+                    let _: Option<()> = store.remove(&entry.key_path, from)?;
+                }
+
+                report.migrated.push(KeystoreEntry {
+                    key_type: to.clone(),
+                    ..entry
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
     /// Describe the specified key.
     ///
     /// Returns [`KeyPathError::Unrecognized`] if none of the registered
@@ -409,12 +503,16 @@ mod tests {
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
     use crate::keystore::Sealed;
-    use crate::{ArtiPath, ArtiPathUnavailableError, ErasedKey, KeyPath, KeyType, SshKeyData};
+    use crate::{
+        ArtiEphemeralKeystore, ArtiPath, ArtiPathUnavailableError, ErasedKey, KeyPath, KeyType,
+        SshKeyData,
+    };
     use std::collections::HashMap;
     use std::result::Result as StdResult;
     use std::str::FromStr;
     use std::sync::RwLock;
     use tor_basic_utils::test_rng::testing_rng;
+    use tor_hscrypto::pk::HsDescSigningKeypair;
     use tor_llcrypto::pk::ed25519;
 
     /// The type of "key" stored in the test key stores.
@@ -536,19 +634,9 @@ mod tests {
                 }
             }
 
+            // Note: this doesn't override Keystore::contains, so the tests below
+            // exercise its default implementation.
             impl crate::Keystore for $name {
-                fn contains(
-                    &self,
-                    key_spec: &dyn KeySpecifier,
-                    key_type: &KeyType,
-                ) -> Result<bool> {
-                    Ok(self
-                        .inner
-                        .read()
-                        .unwrap()
-                        .contains_key(&(key_spec.arti_path().unwrap(), key_type.clone())))
-                }
-
                 fn id(&self) -> &KeystoreId {
                     &self.id
                 }
@@ -746,6 +834,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn contains() {
+        let mgr = KeyMgrBuilder::default()
+            .default_store(Box::<Keystore1>::default())
+            .build()
+            .unwrap();
+        let store = mgr.select_keystore(&KeystoreSelector::Default).unwrap();
+        let key_type = TestKey::key_type();
+
+        assert!(!store.contains(&TestKeySpecifier1, &key_type).unwrap());
+
+        mgr.insert(
+            TestKey::new("coot"),
+            &TestKeySpecifier1,
+            KeystoreSelector::Default,
+        )
+        .unwrap();
+
+        assert!(store.contains(&TestKeySpecifier1, &key_type).unwrap());
+        // Keys with a different specifier or type are unaffected
+        assert!(!store.contains(&TestKeySpecifier2, &key_type).unwrap());
+        assert!(!store
+            .contains(&TestKeySpecifier1, &KeyType::X25519StaticKeypair)
+            .unwrap());
+    }
+
     #[test]
     fn remove() {
         let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
@@ -966,4 +1080,122 @@ mod tests {
         assert!(mgr.get_entry::<TestKey>(&entry_desc2).unwrap().is_none());
         assert!(mgr.remove_entry(&entry_desc2).unwrap().is_none());
     }
+
+    #[test]
+    fn get_public() {
+        let mgr = KeyMgrBuilder::default()
+            .default_store(Box::new(ArtiEphemeralKeystore::new("ephemeral".into())))
+            .build()
+            .unwrap();
+
+        assert!(mgr
+            .get_public::<HsDescSigningKeypair>(&TestKeySpecifier1)
+            .unwrap()
+            .is_none());
+
+        let keypair = ed25519::Keypair::generate(&mut testing_rng());
+        let expected = keypair.verifying_key();
+        mgr.insert(
+            HsDescSigningKeypair::from(keypair),
+            &TestKeySpecifier1,
+            KeystoreSelector::Default,
+        )
+        .unwrap();
+
+        let public = mgr
+            .get_public::<HsDescSigningKeypair>(&TestKeySpecifier1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*public, expected);
+    }
+
+    #[test]
+    fn migrate() {
+        let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
+        builder.secondary_stores().push(Keystore2::new_boxed());
+        let mgr = builder.build().unwrap();
+
+        // Store some keys using a (pretend) old representation.
+        let old_type = KeyType::Ed25519ExpandedKeypair;
+        let new_type = TestKey::key_type();
+        let insert = |store: &BoxedKeystore, spec: &dyn KeySpecifier, meta: &str| {
+            store.insert(&TestKey::new(meta), spec, &old_type).unwrap();
+        };
+        insert(&mgr.default_store, &TestKeySpecifier1, "coot");
+        insert(&mgr.default_store, &TestKeySpecifier2, "unconvertible");
+        insert(&mgr.secondary_stores[0], &TestKeySpecifier1, "gull");
+        // A key that already has the new type isn't touched.
+        mgr.insert(
+            TestKey::new("moorhen"),
+            &TestKeySpecifier3,
+            KeystoreSelector::Default,
+        )
+        .unwrap();
+
+        // Until they are migrated, we can't find the old keys.
+        assert!(mgr.get::<TestKey>(&TestKeySpecifier1).unwrap().is_none());
+
+        let report = mgr
+            .migrate(&old_type, &new_type, |key| {
+                let key = key
+                    .downcast::<TestKey>()
+                    .map_err(|_| internal!("not a TestKey"))?;
+                if key.meta.ends_with("unconvertible") {
+                    return Err(internal!("cannot convert key").into());
+                }
+                Ok(Box::new(TestKey {
+                    meta: format!("migrated_{}", key.meta),
+                    ..*key
+                }))
+            })
+            .unwrap();
+
+        let keystore1 = KeystoreId::from_str("keystore1").unwrap();
+        let keystore2 = KeystoreId::from_str("keystore2").unwrap();
+        let mut migrated = report.migrated().clone();
+        migrated.sort_by_key(|e| e.keystore_id().to_string());
+        assert_eq!(
+            migrated,
+            vec![
+                entry_descriptor(TestKeySpecifier1, &keystore1),
+                entry_descriptor(TestKeySpecifier1, &keystore2),
+            ]
+        );
+        let [(failed, _)] = &report.failed()[..] else {
+            panic!("expected exactly one failure");
+        };
+        let path: KeyPath = TestKeySpecifier2.arti_path().unwrap().into();
+        assert_eq!(failed.key_path(), &path);
+        assert_eq!(failed.key_type(), &old_type);
+
+        // The migrated keys decode as the new type.
+        assert_eq!(
+            mgr.get::<TestKey>(&TestKeySpecifier1)
+                .unwrap()
+                .map(|k| k.meta),
+            Some("keystore1_migrated_keystore1_coot".to_string())
+        );
+        assert_eq!(
+            mgr.get_entry::<TestKey>(&entry_descriptor(TestKeySpecifier1, &keystore2))
+                .unwrap()
+                .map(|k| k.meta),
+            Some("keystore2_migrated_keystore2_gull".to_string())
+        );
+        assert_eq!(
+            mgr.get::<TestKey>(&TestKeySpecifier3)
+                .unwrap()
+                .map(|k| k.meta),
+            Some("keystore1_moorhen".to_string())
+        );
+
+        // The old entries are gone, except for the one we couldn't convert.
+        let mut remaining = mgr
+            .list_matching(&KeyPathPattern::Arti("*".to_string()))
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.key_type() == &old_type)
+            .collect_vec();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining.pop().as_ref(), Some(failed));
+    }
 }
@@ -0,0 +1,168 @@
+//! `Budget`: a simple, synchronous, bound on memory use
+//!
+//! Unlike the [`MemoryQuotaTracker`](crate::MemoryQuotaTracker),
+//! a `Budget` does not reclaim anything:
+//! it simply refuses to let the total charged to it exceed its maximum.
+
+use crate::internal_prelude::*;
+
+/// A maximum quantity of memory, and how much of it is currently in use
+///
+/// Memory is charged to the budget with [`try_charge`](Budget::try_charge),
+/// which returns a [`Charge`].
+/// The charged memory is refunded when the `Charge` is dropped.
+#[derive(Debug)]
+pub struct Budget {
+    /// The most that may be charged at once
+    max: Qty,
+    /// The total of all the outstanding `Charge`s
+    used: Mutex<Qty>,
+}
+
+/// Memory charged to a [`Budget`], refunded on drop
+#[derive(Debug)]
+#[must_use = "the charge is refunded as soon as it is dropped"]
+pub struct Charge<'b> {
+    /// The budget we were charged to
+    budget: &'b Budget,
+    /// How much we were charged
+    amount: Qty,
+}
+
+/// Charging memory to a [`Budget`] would exceed its maximum
+#[derive(Debug, Clone, Error)]
+#[error("memory budget exceeded: wanted {wanted} more, but {used} of {max} already used")]
+pub struct OverBudget {
+    /// The amount that we tried to charge
+    wanted: Qty,
+    /// The amount already charged
+    used: Qty,
+    /// The budget's maximum
+    max: Qty,
+}
+
+impl Budget {
+    /// Make a new `Budget`, with nothing charged, that permits up to `max` bytes
+    pub fn new(max: usize) -> Self {
+        Budget {
+            max: Qty(max),
+            used: Mutex::new(Qty(0)),
+        }
+    }
+
+    /// Return the maximum, in bytes
+    pub fn max(&self) -> usize {
+        self.max.as_usize()
+    }
+
+    /// Return the amount currently charged, in bytes
+    pub fn used(&self) -> usize {
+        self.lock_used().as_usize()
+    }
+
+    /// Charge `amount` bytes to this budget
+    ///
+    /// Fails, charging nothing, if that would take the total above the maximum.
+    pub fn try_charge(&self, amount: usize) -> Result<Charge<'_>, OverBudget> {
+        let mut used = self.lock_used();
+        let over = || OverBudget {
+            wanted: Qty(amount),
+            used: *used,
+            max: self.max,
+        };
+        let new_used = used.checked_add(amount).ok_or_else(over)?;
+        if new_used > self.max.as_usize() {
+            return Err(over());
+        }
+        *used = Qty(new_used);
+        Ok(Charge {
+            budget: self,
+            amount: Qty(amount),
+        })
+    }
+
+    /// Obtain the lock on `used`
+    ///
+    /// A panic while the lock was held can't have left `used` inconsistent,
+    /// since we only ever update it with a single assignment.
+    fn lock_used(&self) -> MutexGuard<'_, Qty> {
+        self.used.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Charge<'_> {
+    /// Return the amount charged, in bytes
+    pub fn amount(&self) -> usize {
+        self.amount.as_usize()
+    }
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.lock_used();
+        match used.checked_sub(*self.amount) {
+            Some(new_used) => *used = Qty(new_used),
+            None => {
+                // Every `Charge` added its amount, so this is impossible.
+                error_report!(
+                    internal!("refunding {} but only {} used", self.amount, *used),
+                    "memory budget corrupted"
+                );
+                *used = Qty(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn charge_and_refund() {
+        let budget = Budget::new(100);
+
+        // We can charge right up to the limit.
+        let a = budget.try_charge(60).unwrap();
+        let b = budget.try_charge(40).unwrap();
+        assert_eq!(a.amount(), 60);
+        assert_eq!(budget.used(), 100);
+
+        // But not beyond it, even by a byte; and a failed attempt charges nothing.
+        let e = budget.try_charge(1).unwrap_err();
+        assert_eq!((e.wanted, e.used, e.max), (Qty(1), Qty(100), Qty(100)));
+        assert_eq!(budget.used(), 100);
+
+        // Dropping a charge refunds it.
+        drop(a);
+        assert_eq!(budget.used(), 40);
+        let c = budget.try_charge(60).unwrap();
+        assert!(budget.try_charge(1).is_err());
+        drop((b, c));
+        assert_eq!(budget.used(), 0);
+
+        // Overflow is rejected, not wrapped.
+        let _d = budget.try_charge(1).unwrap();
+        assert!(budget.try_charge(usize::MAX).is_err());
+        assert_eq!(budget.used(), 1);
+
+        // A zero budget permits only empty charges.
+        let zero = Budget::new(0);
+        assert!(zero.try_charge(0).is_ok());
+        assert!(zero.try_charge(1).is_err());
+    }
+}
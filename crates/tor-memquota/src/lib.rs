@@ -196,6 +196,7 @@ mod refcount;
 mod utils;
 
 // Modules with public items
+mod budget;
 mod config;
 mod error;
 pub mod memory_cost;
@@ -204,6 +205,7 @@ mod stream_peek;
 
 //---------- re-exports at the crate root ----------
 
+pub use budget::{Budget, Charge, OverBudget};
 pub use config::{Config, ConfigBuilder};
 pub use error::{Error, MemoryReclaimedError, StartupError};
 pub use memory_cost::HasMemoryCost;
@@ -25,15 +25,22 @@ use crate::{NetdocErrorKind as EK, Result};
 use tor_checkable::signed::{self, SignatureGated};
 use tor_checkable::timed::{self, TimerangeBound};
 use tor_checkable::{SelfSigned, Timebound};
-use tor_hscrypto::pk::{HsBlindId, HsClientDescEncKeypair, HsIntroPtSessionIdKey, HsSvcNtorKey};
+use tor_hscrypto::pk::{
+    HsBlindId, HsClientDescEncKeypair, HsIdKey, HsIntroPtSessionIdKey, HsSvcNtorKey,
+};
+use tor_hscrypto::time::TimePeriod;
 use tor_hscrypto::{RevisionCounter, Subcredential};
-use tor_linkspec::EncodedLinkSpec;
+use tor_linkspec::{ChanTarget, ChannelMethod, EncodedLinkSpec, LinkSpec};
 use tor_llcrypto::pk::curve25519;
+use tor_llcrypto::pk::keymanip::BlindingError;
 use tor_units::IntegerMinutes;
 
 use derive_builder::Builder;
+use itertools::Itertools;
 use smallvec::SmallVec;
 
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::result::Result as StdResult;
 use std::time::SystemTime;
 
@@ -95,9 +102,11 @@ struct IndexInfo {
 /// introduction points and public keys.
 #[derive(Debug, Clone)]
 pub struct HsDesc {
+    /// The blinded onion identity for which this descriptor was published.
+    blinded_id: HsBlindId,
+
     /// Information about the expiration and revision counter for this
     /// descriptor.
-    #[allow(dead_code)] // TODO RELAY: Remove this if there turns out to be no need for it.
     idx_info: IndexInfo,
 
     /// The list of authentication types that this onion service supports.
@@ -108,6 +117,12 @@ pub struct HsDesc {
 
     /// One or more introduction points used to contact the onion service.
     intro_points: Vec<IntroPointDesc>,
+
+    /// The flow-control parameters that this onion service advertises, if any.
+    flow_control: Option<FlowControlParams>,
+
+    /// The proof-of-work parameters that this onion service advertises, if any.
+    pow_params: Option<PowParams>,
     // /// A list of recognized CREATE handshakes that this onion service supports.
     //
     // TODO:  When someday we add a "create2 format" other than "hs-ntor", we
@@ -125,6 +140,52 @@ pub enum IntroAuthType {
     Ed25519,
 }
 
+/// Flow-control parameters that an onion service advertises in the
+/// `flow-control` item of its descriptor.
+///
+/// Clients use these to decide how to negotiate flow control and congestion
+/// control on their circuits to the onion service.
+#[derive(Debug, Clone, Eq, PartialEq, amplify::Getters)]
+pub struct FlowControlParams {
+    /// The range of `FlowCtrl` protocol versions that the onion service
+    /// supports.
+    versions: RangeInclusive<u32>,
+    /// The SENDME increment that the onion service uses for congestion control.
+    #[getter(as_copy)]
+    sendme_inc: u8,
+}
+
+/// A proof-of-work scheme that an onion service can ask its clients to use.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, derive_more::Display)]
+pub enum PowScheme {
+    /// The `v1` scheme, based on the Equi-X puzzle.
+    #[display(fmt = "v1")]
+    V1,
+}
+
+/// Proof-of-work parameters that an onion service advertises in a
+/// `pow-params` item of its descriptor.
+///
+/// As a defense against denial-of-service attacks, clients solve a puzzle
+/// based on these parameters before introducing themselves to the onion
+/// service.
+#[derive(Debug, Clone, Eq, PartialEq, amplify::Getters)]
+pub struct PowParams {
+    /// The proof-of-work scheme that these parameters are for.
+    #[getter(as_copy)]
+    scheme: PowScheme,
+    /// The seed to use when solving the puzzle.
+    seed: [u8; 32],
+    /// The effort that the onion service suggests its clients use.
+    #[getter(as_copy)]
+    suggested_effort: u32,
+    /// The time after which the onion service will no longer accept
+    /// solutions that use `seed`.
+    #[getter(as_copy)]
+    expiration: SystemTime,
+}
+
 /// Information in an onion service descriptor about a single
 /// introduction point.
 #[derive(Debug, Clone, amplify::Getters, Builder)]
@@ -162,6 +223,21 @@ pub struct IntroPointDesc {
     svc_ntor_key: HsSvcNtorKey,
 }
 
+/// The blinded identity and subcredential that a client needs in order to
+/// fetch, parse, and decrypt an onion service descriptor for a single time
+/// period.
+///
+/// Both values are derived together from the onion service's identity key, so
+/// that a caller can't accidentally pair a blinded identity with the
+/// subcredential for a different service or time period.
+#[derive(Debug, Clone)]
+pub struct HsDescRequest {
+    /// The blinded onion service identity for the time period.
+    blinded_id: HsBlindId,
+    /// The subcredential for the time period.
+    subcredential: Subcredential,
+}
+
 /// An onion service after it has been parsed by the client, but not yet decrypted.
 pub struct EncryptedHsDesc {
     /// The un-decoded outer document of our onion service descriptor.
@@ -184,6 +260,22 @@ impl StoredHsDescMeta {
             timebound.dangerously_map(|outer| StoredHsDescMeta::from_outer_doc(&outer))
         }))
     }
+
+    /// As [`StoredHsDescMeta::parse`], but take the descriptor as bytes.
+    ///
+    /// Fails if `input` is not valid UTF-8.
+    pub fn parse_from_bytes(input: &[u8]) -> Result<UncheckedStoredHsDescMeta> {
+        Self::parse(desc_as_str(input, None)?)
+    }
+
+    /// As [`StoredHsDescMeta::parse_from_bytes`], but reject `input`
+    /// without parsing it if it is longer than `max_len` bytes.
+    pub fn parse_from_bytes_limited(
+        input: &[u8],
+        max_len: usize,
+    ) -> Result<UncheckedStoredHsDescMeta> {
+        Self::parse(desc_as_str(input, Some(max_len))?)
+    }
 }
 
 impl HsDesc {
@@ -230,21 +322,92 @@ impl HsDesc {
         // a nasty pattern where we forget to check that we got the right one.
         blinded_onion_id: &HsBlindId,
     ) -> Result<UncheckedEncryptedHsDesc> {
+        let (desc_id, result) = Self::parse_with_unchecked_id(input)?;
+        if blinded_onion_id != &desc_id {
+            return Err(
+                EK::BadObjectVal.with_msg("onion service descriptor did not have the expected ID")
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// As [`HsDesc::parse`], but instead of a single blinded ID, accept the
+    /// blinded ID of the onion service `hs_id` in any of `time_periods`.
+    ///
+    /// On success, return the first of `time_periods` whose blinded ID matches
+    /// the descriptor, along with the descriptor.  (The caller will need that
+    /// time period's subcredential to decrypt the descriptor.)
+    ///
+    /// This lets a client that is near the boundary between two time periods
+    /// recover if it guessed the wrong one.  If none of `time_periods` match,
+    /// we return an error of kind
+    /// [`NoMatchingTimePeriod`](crate::NetdocErrorKind::NoMatchingTimePeriod),
+    /// rather than a confusing complaint about the blinded ID.
+    ///
+    /// Time periods for which `hs_id` cannot be blinded are skipped.
+    pub fn parse_for_time_periods(
+        input: &str,
+        hs_id: &HsIdKey,
+        time_periods: impl IntoIterator<Item = TimePeriod>,
+    ) -> Result<(TimePeriod, UncheckedEncryptedHsDesc)> {
+        let (desc_id, result) = Self::parse_with_unchecked_id(input)?;
+        let period = time_periods
+            .into_iter()
+            .find(|period| {
+                hs_id
+                    .compute_blinded_key(*period)
+                    .is_ok_and(|(blinded_key, _)| blinded_key.id() == desc_id)
+            })
+            .ok_or_else(|| {
+                EK::NoMatchingTimePeriod.with_msg(
+                    "onion service descriptor was not for any of the expected time periods",
+                )
+            })?;
+
+        Ok((period, result))
+    }
+
+    /// Parse the outermost document of the descriptor in `input`, and return
+    /// the blinded ID that it claims to be for, along with the descriptor.
+    ///
+    /// The caller must check that the blinded ID is one that it expected.
+    fn parse_with_unchecked_id(input: &str) -> Result<(HsBlindId, UncheckedEncryptedHsDesc)> {
         let outer = outer::HsDescOuter::parse(input)?;
-        let mut id_matches = false;
+        let mut desc_id = None;
         let result = outer.dangerously_map(|timebound| {
             timebound.dangerously_map(|outer| {
-                id_matches = blinded_onion_id == &outer.blinded_id();
+                desc_id = Some(outer.blinded_id());
                 EncryptedHsDesc::from_outer_doc(outer)
             })
         });
-        if !id_matches {
-            return Err(
-                EK::BadObjectVal.with_msg("onion service descriptor did not have the expected ID")
-            );
-        }
+        let desc_id = desc_id.ok_or_else(|| internal!("descriptor was never examined"))?;
 
-        Ok(result)
+        Ok((desc_id, result))
+    }
+
+    /// As [`HsDesc::parse`], but take the descriptor as bytes.
+    ///
+    /// The input is checked for UTF-8 validity once, up front, and is not copied.
+    /// Fails if `input` is not valid UTF-8.
+    pub fn parse_from_bytes(
+        input: &[u8],
+        blinded_onion_id: &HsBlindId,
+    ) -> Result<UncheckedEncryptedHsDesc> {
+        Self::parse(desc_as_str(input, None)?, blinded_onion_id)
+    }
+
+    /// As [`HsDesc::parse_from_bytes`], but reject `input` without parsing it
+    /// if it is longer than `max_len` bytes.
+    ///
+    /// (Onion service directories limit descriptors to the size given by the
+    /// `HSV3MaxDescriptorSize` consensus parameter.)
+    pub fn parse_from_bytes_limited(
+        input: &[u8],
+        max_len: usize,
+        blinded_onion_id: &HsBlindId,
+    ) -> Result<UncheckedEncryptedHsDesc> {
+        Self::parse(desc_as_str(input, Some(max_len))?, blinded_onion_id)
     }
 
     /// A convenience function for parsing, decrypting and validating HS descriptors.
@@ -259,16 +422,19 @@ impl HsDesc {
     ///   * checks if both layers are valid at the `valid_at` timestamp
     ///   * validates the signatures on both layers
     ///
+    /// The blinded identity and subcredential are both taken from `request`.
+    ///
     /// Returns an error if the descriptor cannot be parsed, or if one of the validation steps
     /// fails.
     pub fn parse_decrypt_validate(
         input: &str,
-        blinded_onion_id: &HsBlindId,
+        request: &HsDescRequest,
         valid_at: SystemTime,
-        subcredential: &Subcredential,
         hsc_desc_enc: Option<&HsClientDescEncKeypair>,
     ) -> StdResult<TimerangeBound<Self>, HsDescError> {
         use HsDescError as E;
+        let blinded_onion_id = request.blinded_id();
+        let subcredential = request.subcredential();
         let unchecked_desc = Self::parse(input, blinded_onion_id)
             .map_err(E::OuterParsing)?
             .check_signature()
@@ -332,6 +498,35 @@ impl HsDesc {
         self.is_single_onion_service
     }
 
+    /// If this onion service claims to be a "single onion service", return the
+    /// IP addresses and ports listed in the link specifiers of its
+    /// introduction points.
+    ///
+    /// A single onion service doesn't hide its own location, so clients may
+    /// want to treat its introduction points differently;
+    /// this lets them see which addresses are involved.
+    /// Each address appears only once, in the order in which we first saw it.
+    /// Link specifiers that don't name an address (or that we can't decode)
+    /// are skipped.
+    ///
+    /// Returns `None` if this is not a single onion service.
+    pub fn single_onion_addresses(&self) -> Option<Vec<SocketAddr>> {
+        if !self.is_single_onion_service {
+            return None;
+        }
+        let addrs = self
+            .intro_points
+            .iter()
+            .flat_map(|ipt| &ipt.link_specifiers)
+            .filter_map(|ls| match ls.parse() {
+                Ok(LinkSpec::OrPort(addr, port)) => Some(SocketAddr::new(addr, port)),
+                _ => None,
+            })
+            .unique()
+            .collect();
+        Some(addrs)
+    }
+
     /// Return true if this onion service claims that it needs user authentication
     /// of some kind in its INTRODUCE messages.
     ///
@@ -339,6 +534,41 @@ impl HsDesc {
     pub fn requires_intro_authentication(&self) -> bool {
         self.auth_required.is_some()
     }
+
+    /// Return the flow-control parameters that this onion service advertises,
+    /// or `None` if its descriptor doesn't include any.
+    pub fn flow_control_params(&self) -> Option<&FlowControlParams> {
+        self.flow_control.as_ref()
+    }
+
+    /// Return the proof-of-work parameters that this onion service
+    /// advertises, or `None` if it doesn't advertise any for a scheme that
+    /// we recognize.
+    ///
+    /// If these are present, a client should solve the required puzzle before
+    /// connecting, since the onion service may be prioritizing (or only
+    /// accepting) introductions that include a solution.
+    pub fn pow_params(&self) -> Option<&PowParams> {
+        self.pow_params.as_ref()
+    }
+
+    /// Return the revision counter of this descriptor.
+    ///
+    /// Among descriptors for the same blinded identity, one with a higher
+    /// revision counter is more recent.
+    pub fn revision_counter(&self) -> RevisionCounter {
+        self.idx_info.revision
+    }
+
+    /// Return true if this descriptor should replace `other`.
+    ///
+    /// That is the case when both descriptors were published for the same
+    /// blinded identity, and this one has a strictly higher revision counter.
+    /// A descriptor never supersedes one with the same revision counter, so
+    /// refetching a descriptor we already have doesn't replace it.
+    pub fn supersedes(&self, other: &HsDesc) -> bool {
+        self.blinded_id == other.blinded_id && self.idx_info.revision > other.idx_info.revision
+    }
 }
 
 /// An error returned by [`HsDesc::parse_decrypt_validate`], indicating what
@@ -423,6 +653,45 @@ impl tor_error::HasKind for HsDescError {
     }
 }
 
+/// Return `input` as a string, for parsing as an onion service descriptor.
+///
+/// Fails if `input` is longer than `max_len` bytes (if provided), or is not valid UTF-8.
+fn desc_as_str(input: &[u8], max_len: Option<usize>) -> Result<&str> {
+    if let Some(max_len) = max_len {
+        if input.len() > max_len {
+            return Err(EK::DocumentTooLong.with_msg(format!(
+                "onion service descriptor is {} bytes long, but the limit is {}",
+                input.len(),
+                max_len
+            )));
+        }
+    }
+    std::str::from_utf8(input)
+        .map_err(|_| EK::BadObjectVal.with_msg("Bad utf-8 in onion service descriptor"))
+}
+
+impl HsDescRequest {
+    /// Compute the blinded identity and subcredential for the onion service
+    /// with identity `hs_id` during `time_period`.
+    pub fn new(hs_id: &HsIdKey, time_period: TimePeriod) -> StdResult<Self, BlindingError> {
+        let (blinded_key, subcredential) = hs_id.compute_blinded_key(time_period)?;
+        Ok(HsDescRequest {
+            blinded_id: blinded_key.id(),
+            subcredential,
+        })
+    }
+
+    /// Return the blinded onion service identity for this request's time period.
+    pub fn blinded_id(&self) -> &HsBlindId {
+        &self.blinded_id
+    }
+
+    /// Return the subcredential for this request's time period.
+    pub fn subcredential(&self) -> &Subcredential {
+        &self.subcredential
+    }
+}
+
 impl IntroPointDesc {
     /// Start building a description of an intro point
     pub fn builder() -> IntroPointDescBuilder {
@@ -442,6 +711,30 @@ impl IntroPointDesc {
     }
 }
 
+impl IntroPointDescBuilder {
+    /// Set the link specifiers for this intro point from the identities and
+    /// addresses of `target`.
+    ///
+    /// The link specifiers are put in the order required by tor-spec.
+    ///
+    /// Returns an error if any of the link specifiers could not be encoded.
+    pub fn chan_target(self, target: &impl ChanTarget) -> tor_bytes::EncodeResult<Self> {
+        let mut link_specifiers: Vec<LinkSpec> =
+            target.identities().map(|id| id.to_owned().into()).collect();
+        #[allow(irrefutable_let_patterns)]
+        if let ChannelMethod::Direct(addrs) = target.chan_method() {
+            link_specifiers.extend(addrs.into_iter().map(LinkSpec::from));
+        }
+        LinkSpec::sort_by_type(&mut link_specifiers[..]);
+        let link_specifiers = link_specifiers
+            .into_iter()
+            .map(|ls| ls.encode())
+            .collect::<tor_bytes::EncodeResult<Vec<_>>>()?;
+
+        Ok(self.link_specifiers(link_specifiers))
+    }
+}
+
 impl EncryptedHsDesc {
     /// Attempt to decrypt both layers of encryption in this onion service
     /// descriptor.
@@ -461,33 +754,14 @@ impl EncryptedHsDesc {
         hsc_desc_enc: Option<&HsClientDescEncKeypair>,
     ) -> StdResult<TimerangeBound<SignatureGated<HsDesc>>, HsDescError> {
         use HsDescError as E;
-        let blinded_id = self.outer_doc.blinded_id();
-        let revision_counter = self.outer_doc.revision_counter();
         let kp_desc_sign = self.outer_doc.desc_sign_key_id();
 
-        // Decrypt the superencryption layer; parse the middle document.
-        let middle = self
-            .outer_doc
-            .decrypt_body(subcredential)
-            .map_err(|_| E::DecryptionFailed)?;
-        let middle = std::str::from_utf8(&middle[..]).map_err(|_| {
-            E::InnerParsing(EK::BadObjectVal.with_msg("Bad utf-8 in middle document"))
-        })?;
-        let middle = middle::HsDescMiddle::parse(middle).map_err(E::InnerParsing)?;
-
-        // Decrypt the encryption layer and parse the inner document.
-        let inner = middle.decrypt_inner(
-            &blinded_id,
-            revision_counter,
-            subcredential,
-            hsc_desc_enc.map(|keys| keys.secret()),
-        )?;
+        let inner = self.decrypt_to_inner_bytes(subcredential, hsc_desc_enc)?;
         let inner = std::str::from_utf8(&inner[..]).map_err(|_| {
             E::InnerParsing(EK::BadObjectVal.with_msg("Bad utf-8 in inner document"))
         })?;
         let (cert_signing_key, time_bound) =
             inner::HsDescInner::parse(inner).map_err(E::InnerParsing)?;
-
         if cert_signing_key.as_ref() != Some(kp_desc_sign) {
             return Err(E::InnerValidation(EK::BadObjectVal.with_msg(
                 "Signing keys in inner document did not match those in outer document",
@@ -497,15 +771,72 @@ impl EncryptedHsDesc {
         // Construct the HsDesc!
         let time_bound = time_bound.dangerously_map(|sig_bound| {
             sig_bound.dangerously_map(|inner| HsDesc {
+                blinded_id: self.outer_doc.blinded_id(),
                 idx_info: IndexInfo::from_outer_doc(&self.outer_doc),
                 auth_required: inner.intro_auth_types,
                 is_single_onion_service: inner.single_onion_service,
                 intro_points: inner.intro_points,
+                flow_control: inner.flow_control,
+                pow_params: inner.pow_params,
             })
         });
         Ok(time_bound)
     }
 
+    /// Decrypt both layers of encryption in this onion service descriptor,
+    /// and return the plaintext of the inner document, without parsing it.
+    ///
+    /// Arguments are as for [`EncryptedHsDesc::decrypt`].
+    ///
+    /// This is meant for debugging descriptors that fail to parse or
+    /// validate.
+    ///
+    /// # Security
+    ///
+    /// The returned bytes are the onion service's private data: they list the
+    /// service's introduction points and keys.  Do not log or store them
+    /// unless you are certain that doing so is safe.
+    #[cfg(feature = "hsdesc-inner-docs")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hsdesc-inner-docs")))]
+    pub fn dangerously_decrypt_inner_bytes(
+        &self,
+        subcredential: &Subcredential,
+        hsc_desc_enc: Option<&HsClientDescEncKeypair>,
+    ) -> StdResult<Vec<u8>, HsDescError> {
+        self.decrypt_to_inner_bytes(subcredential, hsc_desc_enc)
+    }
+
+    /// Decrypt the superencryption layer, parse the middle document, and
+    /// decrypt the encryption layer; return the inner document's plaintext.
+    fn decrypt_to_inner_bytes(
+        &self,
+        subcredential: &Subcredential,
+        hsc_desc_enc: Option<&HsClientDescEncKeypair>,
+    ) -> StdResult<Vec<u8>, HsDescError> {
+        use HsDescError as E;
+        let blinded_id = self.outer_doc.blinded_id();
+        let revision_counter = self.outer_doc.revision_counter();
+
+        // Decrypt the superencryption layer; parse the middle document.
+        let middle = self
+            .outer_doc
+            .decrypt_body(subcredential, desc_enc::DEFAULT_MAX_DECRYPTED_LEN)
+            .map_err(|_| E::DecryptionFailed)?;
+        let middle = std::str::from_utf8(&middle[..]).map_err(|_| {
+            E::InnerParsing(EK::BadObjectVal.with_msg("Bad utf-8 in middle document"))
+        })?;
+        let middle = middle::HsDescMiddle::parse(middle).map_err(E::InnerParsing)?;
+
+        // Decrypt the encryption layer.
+        middle.decrypt_inner(
+            &blinded_id,
+            revision_counter,
+            subcredential,
+            hsc_desc_enc.map(|keys| keys.secret()),
+            desc_enc::DEFAULT_MAX_DECRYPTED_LEN,
+        )
+    }
+
     /// Create a new `IndexInfo` from the outer part of an onion service descriptor.
     fn from_outer_doc(outer_layer: outer::HsDescOuter) -> Self {
         EncryptedHsDesc {
@@ -636,6 +967,52 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_desc_bytes() -> Result<()> {
+        let blinded_id = TEST_DATA_HS_BLIND_ID.into();
+        let bytes = TEST_DATA.as_bytes();
+
+        let desc = HsDesc::parse_from_bytes(bytes, &blinded_id)?
+            .check_signature()?
+            .check_valid_at(&humantime::parse_rfc3339("2023-01-23T15:00:00Z").unwrap())
+            .unwrap();
+        assert!(desc.decrypt(&TEST_SUBCREDENTIAL.into(), None).is_ok());
+
+        // Exactly at the limit is fine.
+        assert!(HsDesc::parse_from_bytes_limited(bytes, bytes.len(), &blinded_id).is_ok());
+
+        let mut bad_utf8 = bytes.to_vec();
+        bad_utf8[0] = 0xff;
+        assert!(HsDesc::parse_from_bytes(&bad_utf8, &blinded_id).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_desc_oversized() {
+        let blinded_id = TEST_DATA_HS_BLIND_ID.into();
+        let bytes = TEST_DATA.as_bytes();
+
+        let err = HsDesc::parse_from_bytes_limited(bytes, bytes.len() - 1, &blinded_id)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.netdoc_error_kind(), EK::DocumentTooLong);
+
+        // The limit is checked before anything else.
+        let err = HsDesc::parse_from_bytes_limited(&[0xff; 1000], 999, &blinded_id)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.netdoc_error_kind(), EK::DocumentTooLong);
+
+        #[cfg(feature = "hs-dir")]
+        {
+            let err = StoredHsDescMeta::parse_from_bytes_limited(bytes, 100)
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(err.netdoc_error_kind(), EK::DocumentTooLong);
+        }
+    }
+
     #[test]
     fn parse_desc_good() -> Result<()> {
         let wrong_blinded_id = [12; 32].into();
@@ -654,8 +1031,12 @@ mod test {
         assert_eq!(desc.idx_info.revision, RevisionCounter::from(19655750));
         assert!(desc.auth_required.is_none());
         assert_eq!(desc.is_single_onion_service, false);
+        assert_eq!(desc.single_onion_addresses(), None);
         assert_eq!(desc.intro_points.len(), 3);
 
+        // This test vector predates the flow-control item.
+        assert!(desc.flow_control_params().is_none());
+
         let ipt0 = &desc.intro_points()[0];
         assert_eq!(
             ipt0.ipt_ntor_key().as_bytes(),
@@ -693,6 +1074,121 @@ mod test {
             .unwrap()
     }
 
+    #[test]
+    fn parse_for_time_periods() {
+        let id: HsIdKey = ed25519::PublicKey::from_bytes(&TEST_HSID_2).unwrap().into();
+        // TEST_DATA_2 was published shortly after the start of its time period.
+        let period = TimePeriod::new(
+            humantime::parse_duration("24 hours").unwrap(),
+            humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap(),
+            humantime::parse_duration("12 hours").unwrap(),
+        )
+        .unwrap();
+        let prev = period.prev().unwrap();
+        let next = period.next().unwrap();
+
+        // Only the right time period gives us the expected ID.
+        assert!(HsDesc::parse(
+            TEST_DATA_2,
+            HsDescRequest::new(&id, prev).unwrap().blinded_id()
+        )
+        .is_err());
+
+        // A client on either side of the boundary can recover.
+        for candidates in [[prev, period], [period, next]] {
+            let (found, desc) =
+                HsDesc::parse_for_time_periods(TEST_DATA_2, &id, candidates).unwrap();
+            assert_eq!(found, period);
+            assert!(desc.check_signature().is_ok());
+            let request = HsDescRequest::new(&id, found).unwrap();
+            assert_eq!(request.subcredential().as_ref(), &TEST_SUBCREDENTIAL_2);
+        }
+
+        let err = HsDesc::parse_for_time_periods(TEST_DATA_2, &id, [prev, next])
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.netdoc_error_kind(), EK::NoMatchingTimePeriod);
+        let err = HsDesc::parse_for_time_periods(TEST_DATA_2, &id, [])
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.netdoc_error_kind(), EK::NoMatchingTimePeriod);
+    }
+
+    #[test]
+    fn desc_request() {
+        let id: HsIdKey = ed25519::PublicKey::from_bytes(&TEST_HSID_2).unwrap().into();
+        let period = TimePeriod::new(
+            humantime::parse_duration("24 hours").unwrap(),
+            humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap(),
+            humantime::parse_duration("12 hours").unwrap(),
+        )
+        .unwrap();
+        let request = HsDescRequest::new(&id, period).unwrap();
+
+        assert_eq!(
+            request.blinded_id().as_ref(),
+            &hex!("706628758208395D461AA0F460A5E76E7B828C66B5E794768592B451302E961D")
+        );
+        assert_eq!(request.subcredential().as_ref(), &TEST_SUBCREDENTIAL_2);
+
+        let pk = curve25519::PublicKey::from(TEST_PUBKEY_2).into();
+        let sk = curve25519::StaticSecret::from(TEST_SECKEY_2).into();
+        let desc = HsDesc::parse_decrypt_validate(
+            TEST_DATA_2,
+            &request,
+            humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap(),
+            Some(&HsClientDescEncKeypair::new(pk, sk)),
+        )
+        .unwrap()
+        .dangerously_assume_timely();
+        assert_eq!(desc.intro_points.len(), 3);
+    }
+
+    #[test]
+    fn intro_point_from_chan_target() {
+        use tor_basic_utils::test_rng::testing_rng;
+        use tor_linkspec::OwnedChanTarget;
+        use tor_llcrypto::pk::rsa::RsaIdentity;
+
+        let ed_id = ed25519::Ed25519Identity::from([7; 32]);
+        let rsa_id = RsaIdentity::from([9; 20]);
+        let addrs = vec![
+            "127.0.0.1:9001".parse().unwrap(),
+            "[::1]:9002".parse().unwrap(),
+        ];
+        let target = OwnedChanTarget::builder()
+            .addrs(addrs.clone())
+            .ed_identity(ed_id)
+            .rsa_identity(rsa_id)
+            .build()
+            .unwrap();
+
+        let mut rng = testing_rng();
+        let ipt = IntroPointDesc::builder()
+            .chan_target(&target)
+            .unwrap()
+            .ipt_kp_ntor(curve25519::PublicKey::from([1; 32]))
+            .kp_hs_ipt_sid(ed25519::Keypair::generate(&mut rng).verifying_key().into())
+            .kp_hss_ntor(curve25519::PublicKey::from([2; 32]).into())
+            .build()
+            .unwrap();
+
+        let decoded: Vec<LinkSpec> = ipt
+            .link_specifiers()
+            .iter()
+            .map(|ls| ls.parse().unwrap())
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                LinkSpec::OrPort(addrs[0].ip(), addrs[0].port()),
+                LinkSpec::RsaId(rsa_id),
+                LinkSpec::Ed25519Id(ed_id),
+                LinkSpec::OrPort(addrs[1].ip(), addrs[1].port()),
+            ]
+        );
+    }
+
     #[test]
     fn parse_desc_auth_missing() {
         // If we try to decrypt TEST_DATA_2 with no ClientDescEncKey, we get a
@@ -721,4 +1217,35 @@ mod test {
         let desc = desc.check_signature().unwrap();
         assert_eq!(desc.intro_points.len(), 3);
     }
+
+    #[test]
+    #[cfg(feature = "hsdesc-inner-docs")]
+    fn decrypt_inner_bytes() {
+        let encrypted = get_test2_encrypted();
+        let subcredential = TEST_SUBCREDENTIAL_2.into();
+        let pk = curve25519::PublicKey::from(TEST_PUBKEY_2).into();
+        let sk = curve25519::StaticSecret::from(TEST_SECKEY_2).into();
+        let keys = HsClientDescEncKeypair::new(pk, sk);
+
+        // Without the right key, we can't get at the inner document.
+        assert!(encrypted
+            .dangerously_decrypt_inner_bytes(&subcredential, None)
+            .is_err());
+
+        let inner = encrypted
+            .dangerously_decrypt_inner_bytes(&subcredential, Some(&keys))
+            .unwrap();
+        let inner = std::str::from_utf8(&inner).unwrap();
+        let (cert_signing_key, inner) = HsDescInner::parse(inner).unwrap();
+        assert_eq!(
+            cert_signing_key.as_ref(),
+            Some(encrypted.outer_doc.desc_sign_key_id())
+        );
+        let inner = inner
+            .check_valid_at(&humantime::parse_rfc3339("2023-01-24T03:00:00Z").unwrap())
+            .unwrap()
+            .check_signature()
+            .unwrap();
+        assert_eq!(inner.intro_points.len(), 3);
+    }
 }
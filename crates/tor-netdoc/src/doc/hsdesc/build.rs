@@ -27,7 +27,12 @@ use self::inner::HsDescInner;
 use self::middle::HsDescMiddle;
 use self::outer::HsDescOuter;
 
-use super::desc_enc::{HsDescEncNonce, HsDescEncryption, HS_DESC_ENC_NONCE_LEN};
+use super::desc_enc::{encrypted_len, HsDescEncNonce, HsDescEncryption, HS_DESC_ENC_NONCE_LEN};
+
+/// The superencrypted field must be padded to the nearest multiple of 10k bytes
+///
+/// rend-spec-v3 2.5.1.1
+const SUPERENCRYPTED_ALIGN: usize = 10 * (1 << 10);
 
 /// An intermediary type for encoding hidden service descriptors.
 ///
@@ -131,33 +136,51 @@ impl<'a> ClientAuth<'a> {
             descriptor_cookie,
         })
     }
+
+    /// Check that this client authorization material is internally consistent.
+    ///
+    /// Returns an error if `ephemeral_key` is not a valid keypair, or if the key of any of the
+    /// `auth_clients` is one with which we can't derive a secret shared with that client:
+    /// in either case, the descriptor we'd build would not be decryptable by
+    /// (or not private to) the authorized clients.
+    fn validate(&self) -> Result<(), EncodeError> {
+        let secret: &curve25519::StaticSecret = self.ephemeral_key.secret.as_ref();
+        if curve25519::PublicKey::from(secret) != *self.ephemeral_key.public {
+            return Err(tor_error::internal!(
+                "client auth ephemeral public key does not match its secret key"
+            )
+            .into());
+        }
+
+        if let Some(idx) = self
+            .auth_clients
+            .iter()
+            .position(|client| !secret.diffie_hellman(client).was_contributory())
+        {
+            return Err(tor_error::bad_api_usage!(
+                "the key of authorized client #{idx} is not a valid curve25519 public key"
+            )
+            .into());
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> NetdocBuilder for HsDescBuilder<'a> {
     fn build_sign<R: RngCore + CryptoRng>(self, rng: &mut R) -> Result<String, EncodeError> {
-        /// The superencrypted field must be padded to the nearest multiple of 10k bytes
-        ///
-        /// rend-spec-v3 2.5.1.1
-        const SUPERENCRYPTED_ALIGN: usize = 10 * (1 << 10);
-
         let hs_desc = self
             .build()
             .map_err(into_bad_api_usage!("the HsDesc could not be built"))?;
 
         let client_auth = ClientAuth::new(hs_desc.auth_clients, rng);
+        if let Some(client_auth) = &client_auth {
+            client_auth.validate()?;
+        }
 
         // Construct the inner (second layer) plaintext. This is the unencrypted value of the
         // "encrypted" field.
-        let inner_plaintext = HsDescInner {
-            hs_desc_sign: hs_desc.hs_desc_sign,
-            create2_formats: hs_desc.create2_formats,
-            auth_required: hs_desc.auth_required.as_ref(),
-            is_single_onion_service: hs_desc.is_single_onion_service,
-            intro_points: hs_desc.intro_points,
-            intro_auth_key_cert_expiry: hs_desc.intro_auth_key_cert_expiry,
-            intro_enc_key_cert_expiry: hs_desc.intro_enc_key_cert_expiry,
-        }
-        .build_sign(rng)?;
+        let inner_plaintext = hs_desc.inner_doc().build_sign(rng)?;
 
         let desc_enc_nonce = client_auth
             .as_ref()
@@ -208,6 +231,55 @@ impl<'a> NetdocBuilder for HsDescBuilder<'a> {
     }
 }
 
+impl<'a> HsDescBuilder<'a> {
+    /// Return the approximate length of the descriptor that
+    /// [`build_sign`](NetdocBuilder::build_sign) would produce from this builder.
+    ///
+    /// This is meant for warning about descriptors that will be too large
+    /// for the HsDirs to accept (for example, because they have very many
+    /// introduction points or authorized clients).
+    /// It takes into account the padding of the superencrypted layer
+    /// to a multiple of 10 KiB.
+    ///
+    /// We compute this by encoding each layer of the descriptor
+    /// (including signatures and certificates, whose lengths are fixed),
+    /// but with placeholder ciphertext in place of the encrypted layers.
+    /// The result is therefore usually exact,
+    /// but since some fields are randomized, it is not guaranteed to be.
+    ///
+    /// Returns an error in the same cases as `build_sign`.
+    pub fn estimate_encoded_size(&self) -> Result<usize, EncodeError> {
+        let mut rng = rand::thread_rng();
+        let hs_desc = self
+            .clone()
+            .build()
+            .map_err(into_bad_api_usage!("the HsDesc could not be built"))?;
+
+        let client_auth = ClientAuth::new(hs_desc.auth_clients, &mut rng);
+        let inner_plaintext = hs_desc.inner_doc().build_sign(&mut rng)?;
+
+        let middle_plaintext = HsDescMiddle {
+            client_auth: client_auth.as_ref(),
+            subcredential: hs_desc.subcredential,
+            encrypted: vec![0; encrypted_len(inner_plaintext.len())],
+        }
+        .build_sign(&mut rng)?;
+        let middle_padded_len =
+            middle_plaintext.len() + padding_to_align(middle_plaintext.len(), SUPERENCRYPTED_ALIGN);
+
+        let outer = HsDescOuter {
+            hs_desc_sign: hs_desc.hs_desc_sign,
+            hs_desc_sign_cert: hs_desc.hs_desc_sign_cert,
+            lifetime: hs_desc.lifetime,
+            revision_counter: hs_desc.revision_counter,
+            superencrypted: vec![0; encrypted_len(middle_padded_len)],
+        }
+        .build_sign(&mut rng)?;
+
+        Ok(outer.len())
+    }
+}
+
 /// Create the descriptor signing key certificate.
 ///
 /// Returns the encoded representation of the certificate
@@ -232,6 +304,20 @@ pub fn create_desc_sign_key_cert(
 }
 
 impl<'a> HsDesc<'a> {
+    /// Return the inner (second layer) document, whose encoding is the
+    /// plaintext of the "encrypted" field.
+    fn inner_doc(&self) -> HsDescInner<'_> {
+        HsDescInner {
+            hs_desc_sign: self.hs_desc_sign,
+            create2_formats: self.create2_formats,
+            auth_required: self.auth_required.as_ref(),
+            is_single_onion_service: self.is_single_onion_service,
+            intro_points: self.intro_points,
+            intro_auth_key_cert_expiry: self.intro_auth_key_cert_expiry,
+            intro_enc_key_cert_expiry: self.intro_enc_key_cert_expiry,
+        }
+    }
+
     /// Encrypt the specified plaintext using the algorithm described in section
     /// `[HS-DESC-ENCRYPTION-KEYS]` of rend-spec-v3.txt.
     fn encrypt_field<R: RngCore + CryptoRng>(
@@ -253,9 +339,14 @@ impl<'a> HsDesc<'a> {
     }
 }
 
+/// Return the number of bytes needed to pad `len` to the next multiple of `alignment`.
+fn padding_to_align(len: usize, alignment: usize) -> usize {
+    (alignment - (len % alignment)) % alignment
+}
+
 /// Pad `v` with zeroes to the next multiple of `alignment`.
 fn pad_with_zero_to_align(v: &[u8], alignment: usize) -> Cow<[u8]> {
-    let padding = (alignment - (v.len() % alignment)) % alignment;
+    let padding = padding_to_align(v.len(), alignment);
 
     if padding > 0 {
         let padded = v
@@ -430,6 +521,33 @@ mod test {
             &subcredential,
             None, /* No client auth */
         );
+        assert!(desc.is_single_onion_service());
+        assert_eq!(
+            desc.single_onion_addresses(),
+            Some(vec!["127.0.0.1:9999".parse().unwrap()])
+        );
+        assert_eq!(desc.revision_counter(), REVISION_COUNT.into());
+
+        // A descriptor with the same revision counter doesn't replace this one...
+        assert!(!desc.supersedes(&desc));
+        // ...but one with a higher revision counter does.
+        let newer_desc = parse_hsdesc(
+            builder
+                .clone()
+                .revision_counter((REVISION_COUNT + 1).into())
+                .build_sign(&mut Config::Deterministic.into_rng())
+                .unwrap()
+                .as_str(),
+            *blinded_id.as_ref().public(),
+            &subcredential,
+            None,
+        );
+        assert!(newer_desc.supersedes(&desc));
+        assert!(!desc.supersedes(&newer_desc));
+        // A descriptor for some other blinded identity never replaces this one.
+        let other_desc = crate::doc::hsdesc::test_data::test_parsed_hsdesc().unwrap();
+        assert!(other_desc.revision_counter() > newer_desc.revision_counter());
+        assert!(!other_desc.supersedes(&newer_desc));
 
         let hs_desc_sign_cert =
             create_desc_sign_key_cert(&hs_desc_sign.verifying_key(), &blinded_id, expiry).unwrap();
@@ -498,4 +616,150 @@ mod test {
 
         assert_eq!(&*encoded_desc, &*reencoded_desc);
     }
+
+    #[test]
+    fn estimate_size() {
+        const CREATE2_FORMATS: &[HandshakeType] = &[HandshakeType::NTOR];
+
+        let mut rng = Config::Deterministic.into_rng();
+        let hs_id = ed25519::Keypair::generate(&mut rng);
+        let hs_desc_sign = ed25519::Keypair::generate(&mut rng);
+        let period = TimePeriod::new(
+            humantime::parse_duration("24 hours").unwrap(),
+            humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap(),
+            humantime::parse_duration("12 hours").unwrap(),
+        )
+        .unwrap();
+        let (_, blinded_id, subcredential) = HsIdKeypair::from(ExpandedKeypair::from(&hs_id))
+            .compute_blinded_key(period)
+            .unwrap();
+        let blinded_pk = (&blinded_id).into();
+        let expiry = SystemTime::now() + Duration::from_secs(60 * 60);
+
+        let link_specifiers = [LinkSpec::OrPort(Ipv4Addr::LOCALHOST.into(), 9999)];
+        let intro_points = (0..20)
+            .map(|_| create_intro_point_descriptor(&mut rng, &link_specifiers))
+            .collect::<Vec<_>>();
+        let auth_clients = (0..50)
+            .map(|_| create_curve25519_pk(&mut rng))
+            .collect::<Vec<_>>();
+
+        let builder = |n_intro_points: usize| {
+            let hs_desc_sign_cert =
+                create_desc_sign_key_cert(&hs_desc_sign.verifying_key(), &blinded_id, expiry)
+                    .unwrap();
+            HsDescBuilder::default()
+                .blinded_id(&blinded_pk)
+                .hs_desc_sign(&hs_desc_sign)
+                .hs_desc_sign_cert(hs_desc_sign_cert)
+                .create2_formats(CREATE2_FORMATS)
+                .auth_required(None)
+                .is_single_onion_service(false)
+                .intro_points(&intro_points[..n_intro_points])
+                .intro_auth_key_cert_expiry(expiry)
+                .intro_enc_key_cert_expiry(expiry)
+                .lifetime(180.into())
+                .revision_counter(7.into())
+                .subcredential(subcredential)
+        };
+
+        let chk = |n_intro_points: usize, auth_clients: Option<&[curve25519::PublicKey]>| {
+            let builder = builder(n_intro_points).auth_clients(auth_clients);
+            let estimate = builder.estimate_encoded_size().unwrap();
+            let actual = builder
+                .build_sign(&mut Config::Deterministic.into_rng())
+                .unwrap()
+                .len();
+            assert!(
+                estimate.abs_diff(actual) <= 64,
+                "estimate={estimate} actual={actual}"
+            );
+            actual
+        };
+
+        let small = chk(1, None);
+        chk(3, Some(&auth_clients[..1]));
+        // Enough to need more than one block of superencrypted padding.
+        let large = chk(20, Some(&auth_clients));
+        assert!(large > small + SUPERENCRYPTED_ALIGN);
+
+        // Client authentication without any clients can't be built, so it
+        // can't be estimated either.
+        let empty = builder(3).auth_clients(Some(&[]));
+        let err = empty.estimate_encoded_size().unwrap_err();
+        assert!(expect_bug(err).contains("no authorized clients"));
+        let err = empty
+            .build_sign(&mut Config::Deterministic.into_rng())
+            .unwrap_err();
+        assert!(expect_bug(err).contains("no authorized clients"));
+
+        // An incomplete builder can't be estimated.
+        let err = HsDescBuilder::default()
+            .estimate_encoded_size()
+            .unwrap_err();
+        assert!(expect_bug(err).contains("could not be built"));
+    }
+
+    #[test]
+    fn client_auth_validation() {
+        const CREATE2_FORMATS: &[HandshakeType] = &[HandshakeType::NTOR];
+
+        let mut rng = Config::Deterministic.into_rng();
+        let auth_clients = [create_curve25519_pk(&mut rng)];
+
+        // An ephemeral keypair whose halves don't match.
+        let secret = curve25519::StaticSecret::random_from_rng(&mut rng);
+        let client_auth = ClientAuth {
+            ephemeral_key: HsSvcDescEncKeypair {
+                public: create_curve25519_pk(&mut rng).into(),
+                secret: secret.into(),
+            },
+            auth_clients: &auth_clients,
+            descriptor_cookie: rand::Rng::gen::<[u8; HS_DESC_ENC_NONCE_LEN]>(&mut rng),
+        };
+        let err = client_auth.validate().unwrap_err();
+        assert!(expect_bug(err).contains("does not match its secret key"));
+
+        // Consistent material is accepted.
+        let client_auth = ClientAuth::new(Some(&auth_clients), &mut rng).unwrap();
+        client_auth.validate().unwrap();
+
+        // A low-order client key, with which we would share no secret.
+        let hs_id = ed25519::Keypair::generate(&mut rng);
+        let hs_desc_sign = ed25519::Keypair::generate(&mut rng);
+        let period = TimePeriod::new(
+            humantime::parse_duration("24 hours").unwrap(),
+            humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap(),
+            humantime::parse_duration("12 hours").unwrap(),
+        )
+        .unwrap();
+        let (_, blinded_id, subcredential) = HsIdKeypair::from(ExpandedKeypair::from(&hs_id))
+            .compute_blinded_key(period)
+            .unwrap();
+        let expiry = SystemTime::now() + Duration::from_secs(60 * 60);
+        let link_specifiers = [LinkSpec::OrPort(Ipv4Addr::LOCALHOST.into(), 9999)];
+        let intro_points = [create_intro_point_descriptor(&mut rng, &link_specifiers)];
+        let bad_clients = [auth_clients[0], curve25519::PublicKey::from([0_u8; 32])];
+
+        let err = HsDescBuilder::default()
+            .blinded_id(&(&blinded_id).into())
+            .hs_desc_sign(&hs_desc_sign)
+            .hs_desc_sign_cert(
+                create_desc_sign_key_cert(&hs_desc_sign.verifying_key(), &blinded_id, expiry)
+                    .unwrap(),
+            )
+            .create2_formats(CREATE2_FORMATS)
+            .auth_required(None)
+            .is_single_onion_service(false)
+            .intro_points(&intro_points)
+            .intro_auth_key_cert_expiry(expiry)
+            .intro_enc_key_cert_expiry(expiry)
+            .auth_clients(Some(&bad_clients))
+            .lifetime(180.into())
+            .revision_counter(7.into())
+            .subcredential(subcredential)
+            .build_sign(&mut rng)
+            .unwrap_err();
+        assert!(expect_bug(err).contains("authorized client #1 is not a valid"));
+    }
 }
@@ -63,6 +63,28 @@ const SALT_LEN: usize = 16;
 /// Length of our ersatz MAC.
 const MAC_LEN: usize = 32;
 
+/// The largest plaintext that we decrypt from a layer of an onion service
+/// descriptor, unless we are told otherwise.
+///
+/// A layer's plaintext is never longer than the descriptor that contains it,
+/// so this is the default value of the `HSV3MaxDescriptorSize` consensus
+/// parameter, which limits the size of the descriptors that HSDirs accept.
+pub(super) const DEFAULT_MAX_DECRYPTED_LEN: usize = 50_000;
+
+/// Return the length of the plaintext that [`HsDescEncryption::decrypt`]
+/// would produce from `data_len` bytes of ciphertext, or `None` if that is
+/// too short to be a valid ciphertext.
+pub(super) fn decrypted_len(data_len: usize) -> Option<usize> {
+    data_len.checked_sub(SALT_LEN + MAC_LEN)
+}
+
+/// Return the length of the ciphertext that [`HsDescEncryption::encrypt`]
+/// produces for a plaintext of `plaintext_len` bytes.
+#[cfg(feature = "hs-service")]
+pub(super) const fn encrypted_len(plaintext_len: usize) -> usize {
+    plaintext_len + SALT_LEN + MAC_LEN
+}
+
 impl<'a> HsDescEncryption<'a> {
     /// Length of our MAC key.
     const MAC_KEY_LEN: usize = 32;
@@ -93,11 +115,14 @@ impl<'a> HsDescEncryption<'a> {
     }
     /// Decrypt a given bytestring that was first encrypted using these
     /// encryption parameters.
-    pub(super) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, DecryptionError> {
-        if data.len() < SALT_LEN + MAC_LEN {
-            return Err(DecryptionError::default());
-        }
-        let msg_len = data.len() - SALT_LEN - MAC_LEN;
+    ///
+    /// Fails without allocating anything if the plaintext would be longer than
+    /// `max_len` bytes.
+    pub(super) fn decrypt(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, DecryptionError> {
+        let msg_len = match decrypted_len(data.len()) {
+            Some(msg_len) if msg_len <= max_len => msg_len,
+            _ => return Err(DecryptionError::default()),
+        };
 
         let salt = data[0..SALT_LEN]
             .try_into()
@@ -265,15 +290,18 @@ mod test {
         for message in [&b""[..], &b"hello world"[..], &bigmsg[..]] {
             let mut encrypted = params.encrypt(&mut rng, message);
             assert_eq!(encrypted.len(), message.len() + 48);
-            let decrypted = params.decrypt(&encrypted[..]).unwrap();
+            let decrypted = params
+                .decrypt(&encrypted[..], DEFAULT_MAX_DECRYPTED_LEN)
+                .unwrap();
             assert_eq!(message, &decrypted);
 
             // Make sure we can't decrypt a partial input.
-            let decryption_err = params.decrypt(&encrypted[..encrypted.len() - 1]);
+            let decryption_err =
+                params.decrypt(&encrypted[..encrypted.len() - 1], DEFAULT_MAX_DECRYPTED_LEN);
             assert!(decryption_err.is_err());
             // Frob a point in the encrypted form and ensure we won't decrypt.
             encrypted[7] ^= 3;
-            let decryption_err = params.decrypt(&encrypted[..]);
+            let decryption_err = params.decrypt(&encrypted[..], DEFAULT_MAX_DECRYPTED_LEN);
             assert!(decryption_err.is_err());
         }
     }
@@ -292,7 +320,35 @@ mod test {
             string_const: string_const.as_bytes(),
         };
 
-        assert!(params.decrypt(b"").is_err());
-        assert!(params.decrypt(&[0_u8; 47]).is_err());
+        assert!(params.decrypt(b"", DEFAULT_MAX_DECRYPTED_LEN).is_err());
+        assert!(params
+            .decrypt(&[0_u8; 47], DEFAULT_MAX_DECRYPTED_LEN)
+            .is_err());
+    }
+
+    #[test]
+    fn too_long() {
+        let blinded_id = [7; 32].into();
+        let subcredential = [11; 32].into();
+        let revision = 13.into();
+        let string_const = "greetings puny humans";
+        let params = HsDescEncryption {
+            blinded_id: &blinded_id,
+            desc_enc_nonce: None,
+            subcredential: &subcredential,
+            revision,
+            string_const: string_const.as_bytes(),
+        };
+        let mut rng = testing_rng();
+
+        let message = vec![0x42_u8; 1000];
+        let encrypted = params.encrypt(&mut rng, &message);
+        assert_eq!(params.decrypt(&encrypted, 1000).unwrap(), message);
+        assert!(params.decrypt(&encrypted, 999).is_err());
+
+        // An over-large ciphertext is rejected by its length alone, before we
+        // even check its MAC.
+        let huge = vec![0_u8; DEFAULT_MAX_DECRYPTED_LEN + 49];
+        assert!(params.decrypt(&huge, DEFAULT_MAX_DECRYPTED_LEN).is_err());
     }
 }
@@ -2,11 +2,11 @@
 
 use std::time::SystemTime;
 
-use super::{IntroAuthType, IntroPointDesc};
+use super::{FlowControlParams, IntroAuthType, IntroPointDesc, PowParams, PowScheme};
 use crate::batching_split_before::IteratorExt as _;
 use crate::parse::tokenize::{ItemResult, NetDocReader};
 use crate::parse::{keyword::Keyword, parser::SectionRules};
-use crate::types::misc::{UnvalidatedEdCert, B64};
+use crate::types::misc::{Iso8601TimeNoSp, UnvalidatedEdCert, B64};
 use crate::{NetdocErrorKind as EK, Result};
 
 use itertools::Itertools as _;
@@ -36,6 +36,11 @@ pub(crate) struct HsDescInner {
     /// (A "single onion service" is one that is not attempting to anonymize
     /// itself.)
     pub(super) single_onion_service: bool,
+    /// The flow-control parameters that this onion service advertises, if any.
+    pub(super) flow_control: Option<FlowControlParams>,
+    /// The proof-of-work parameters that this onion service advertises, if
+    /// any.
+    pub(super) pow_params: Option<PowParams>,
     /// A list of advertised introduction points and their contact info.
     //
     // Always has >= 1 and <= NUM_INTRO_POINT_MAX entries
@@ -47,6 +52,8 @@ decl_keyword! {
         "create2-formats" => CREATE2_FORMATS,
         "intro-auth-required" => INTRO_AUTH_REQUIRED,
         "single-onion-service" => SINGLE_ONION_SERVICE,
+        "flow-control" => FLOW_CONTROL,
+        "pow-params" => POW_PARAMS,
         "introduction-point" => INTRODUCTION_POINT,
         "onion-key" => ONION_KEY,
         "auth-key" => AUTH_KEY,
@@ -66,6 +73,8 @@ static HS_INNER_HEADER_RULES: Lazy<SectionRules<HsInnerKwd>> = Lazy::new(|| {
     rules.add(CREATE2_FORMATS.rule().required().args(1..));
     rules.add(INTRO_AUTH_REQUIRED.rule().args(1..));
     rules.add(SINGLE_ONION_SERVICE.rule());
+    rules.add(FLOW_CONTROL.rule().args(2..));
+    rules.add(POW_PARAMS.rule().may_repeat().args(1..));
     rules.add(UNRECOGNIZED.rule().may_repeat().obj_optional());
 
     rules.build()
@@ -255,6 +264,53 @@ impl HsDescInner {
         // Recognize `single-onion-service` if it's there.
         let is_single_onion_service = header.get(SINGLE_ONION_SERVICE).is_some();
 
+        // Parse `flow-control` if it's there.  Any arguments after the ones
+        // we know about are ignored.
+        let flow_control = if let Some(tok) = header.get(FLOW_CONTROL) {
+            let versions = {
+                let range = tok.required_arg(0)?;
+                let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+                let lo: u32 = lo.parse()?;
+                let hi: u32 = hi.parse()?;
+                if lo > hi {
+                    return Err(EK::BadArgument
+                        .at_pos(tok.pos())
+                        .with_msg("Empty flow-control version range."));
+                }
+                lo..=hi
+            };
+            let sendme_inc = tok.parse_arg::<u8>(1)?;
+            Some(FlowControlParams {
+                versions,
+                sendme_inc,
+            })
+        } else {
+            None
+        };
+
+        // Parse the first `pow-params` item with a scheme that we recognize,
+        // if there is one.  Items for other schemes are ignored, whatever their
+        // arguments.
+        let mut pow_params = None;
+        for tok in header.slice(POW_PARAMS) {
+            let scheme = match tok.required_arg(0)? {
+                "v1" => PowScheme::V1,
+                _ => continue,
+            };
+            if pow_params.is_some() {
+                continue;
+            }
+            let seed = tok.parse_arg::<B64>(1)?.into_array()?;
+            let suggested_effort = tok.parse_arg::<u32>(2)?;
+            let expiration = tok.parse_arg::<Iso8601TimeNoSp>(3)?.into();
+            pow_params = Some(PowParams {
+                scheme,
+                seed,
+                suggested_effort,
+                expiration,
+            });
+        }
+
         let mut signatures = Vec::new();
         let mut expirations = Vec::new();
         let mut cert_signing_key: Option<Ed25519Identity> = None;
@@ -417,6 +473,8 @@ impl HsDescInner {
         let inner = HsDescInner {
             intro_auth_types: auth_types,
             single_onion_service: is_single_onion_service,
+            flow_control,
+            pow_params,
             intro_points,
         };
         let sig_gated = SignatureGated::new(inner, signatures);
@@ -453,6 +511,7 @@ mod test {
 
     use super::*;
     use crate::doc::hsdesc::{
+        desc_enc::DEFAULT_MAX_DECRYPTED_LEN,
         middle::HsDescMiddle,
         outer::HsDescOuter,
         test_data::{TEST_DATA, TEST_SUBCREDENTIAL},
@@ -495,18 +554,111 @@ mod test {
         }
     }
 
+    #[test]
+    fn flow_control() {
+        let parse_flow_control = |line: &str| {
+            let text = TEST_DATA_INNER.replace("flow-control 1-2 31\n", line);
+            HsDescInner::parse(&text).map(|(_, desc)| {
+                desc.dangerously_into_parts()
+                    .0
+                    .dangerously_assume_wellsigned()
+                    .flow_control
+            })
+        };
+
+        let params = |versions, sendme_inc| {
+            Some(FlowControlParams {
+                versions,
+                sendme_inc,
+            })
+        };
+        assert_eq!(parse_flow_control("").unwrap(), None);
+        assert_eq!(
+            parse_flow_control("flow-control 1 31\n").unwrap(),
+            params(1..=1, 31)
+        );
+        assert_eq!(
+            parse_flow_control("flow-control 1-3 20 something-new\n").unwrap(),
+            params(1..=3, 20)
+        );
+        for bad in [
+            "flow-control 1-2\n",
+            "flow-control 2-1 31\n",
+            "flow-control x-2 31\n",
+            "flow-control 1-2 999\n",
+        ] {
+            assert!(parse_flow_control(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn pow_params() {
+        let parse_pow_params = |lines: &str| {
+            let text = TEST_DATA_INNER.replace(
+                "flow-control 1-2 31\n",
+                &format!("flow-control 1-2 31\n{lines}"),
+            );
+            HsDescInner::parse(&text).map(|(_, desc)| {
+                desc.dangerously_into_parts()
+                    .0
+                    .dangerously_assume_wellsigned()
+                    .pow_params
+            })
+        };
+        const SEED: &str = "AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA";
+        let expected = Some(PowParams {
+            scheme: PowScheme::V1,
+            seed: std::array::from_fn(|i| i as u8 + 1),
+            suggested_effort: 250,
+            expiration: humantime::parse_rfc3339("2023-01-24T12:00:00Z").unwrap(),
+        });
+
+        assert_eq!(parse_pow_params("").unwrap(), None);
+        assert_eq!(
+            parse_pow_params(&format!("pow-params v1 {SEED} 250 2023-01-24T12:00:00\n")).unwrap(),
+            expected
+        );
+        // Unknown schemes are ignored, even when their arguments make no sense
+        // to us.
+        assert_eq!(parse_pow_params("pow-params v99 whatever\n").unwrap(), None);
+        assert_eq!(
+            parse_pow_params(&format!(
+                "pow-params v99 whatever\npow-params v1 {SEED} 250 2023-01-24T12:00:00 extra\n"
+            ))
+            .unwrap(),
+            expected
+        );
+        for bad in [
+            "pow-params\n".to_string(),
+            "pow-params v1 AQID 250 2023-01-24T12:00:00\n".to_string(),
+            format!("pow-params v1 {SEED} lots 2023-01-24T12:00:00\n"),
+            format!("pow-params v1 {SEED} 250 2023-01-24 12:00:00\n"),
+            format!("pow-params v1 {SEED} 250\n"),
+        ] {
+            assert!(parse_pow_params(&bad).is_err(), "{bad:?}");
+        }
+    }
+
     #[test]
     fn parse_good() -> Result<()> {
         let desc = HsDescOuter::parse(TEST_DATA)?
             .dangerously_assume_wellsigned()
             .dangerously_assume_timely();
         let subcred = TEST_SUBCREDENTIAL.into();
-        let body = desc.decrypt_body(&subcred).unwrap();
+        let body = desc
+            .decrypt_body(&subcred, DEFAULT_MAX_DECRYPTED_LEN)
+            .unwrap();
         let body = std::str::from_utf8(&body[..]).unwrap();
 
         let middle = HsDescMiddle::parse(body)?;
         let inner_body = middle
-            .decrypt_inner(&desc.blinded_id(), desc.revision_counter(), &subcred, None)
+            .decrypt_inner(
+                &desc.blinded_id(),
+                desc.revision_counter(),
+                &subcred,
+                None,
+                DEFAULT_MAX_DECRYPTED_LEN,
+            )
             .unwrap();
         let inner_body = std::str::from_utf8(&inner_body).unwrap();
         let (ed_id, inner) = HsDescInner::parse(inner_body)?;
@@ -521,6 +673,8 @@ mod test {
         assert!(inner.intro_auth_types.is_none());
         assert_eq!(inner.single_onion_service, false);
         assert_eq!(inner.intro_points.len(), 3);
+        // This test vector predates the flow-control item.
+        assert_eq!(inner.flow_control, None);
 
         let ipt0 = &inner.intro_points[0];
         assert_eq!(
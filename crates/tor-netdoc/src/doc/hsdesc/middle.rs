@@ -14,7 +14,8 @@ use crate::types::misc::B64;
 use crate::{Pos, Result};
 
 use super::desc_enc::{
-    HsDescEncNonce, HsDescEncryption, HS_DESC_CLIENT_ID_LEN, HS_DESC_ENC_NONCE_LEN, HS_DESC_IV_LEN,
+    decrypted_len, HsDescEncNonce, HsDescEncryption, HS_DESC_CLIENT_ID_LEN, HS_DESC_ENC_NONCE_LEN,
+    HS_DESC_IV_LEN,
 };
 use super::HsDescError;
 
@@ -54,6 +55,9 @@ impl HsDescMiddle {
     ///
     /// A failure may mean either that the encryption was corrupted, or that we
     /// didn't have the right key.
+    ///
+    /// Fails with [`HsDescError::DecryptionFailed`] if the inner document
+    /// would be longer than `max_len` bytes.
     #[cfg_attr(feature = "hsdesc-inner-docs", visibility::make(pub))]
     pub(super) fn decrypt_inner(
         &self,
@@ -61,7 +65,13 @@ impl HsDescMiddle {
         revision: RevisionCounter,
         subcredential: &Subcredential,
         key: Option<&HsClientDescEncSecretKey>,
+        max_len: usize,
     ) -> std::result::Result<Vec<u8>, super::HsDescError> {
+        // (We check this first, so that we don't blame a missing or wrong key
+        // for an over-long document.)
+        if decrypted_len(self.encrypted.len()).is_some_and(|len| len > max_len) {
+            return Err(HsDescError::DecryptionFailed);
+        }
         let desc_enc_nonce = key.and_then(|k| self.find_cookie(subcredential, k));
         let decrypt = HsDescEncryption {
             blinded_id,
@@ -71,7 +81,7 @@ impl HsDescMiddle {
             string_const: b"hsdir-encrypted-data",
         };
 
-        match decrypt.decrypt(&self.encrypted) {
+        match decrypt.decrypt(&self.encrypted, max_len) {
             Ok(mut v) => {
                 // Work around a bug in an implementation we presume to be
                 // OnionBalance: it doesn't NL-terminate the final line of the
@@ -264,6 +274,7 @@ mod test {
 
     use super::*;
     use crate::doc::hsdesc::{
+        desc_enc::DEFAULT_MAX_DECRYPTED_LEN,
         outer::HsDescOuter,
         test_data::{TEST_DATA, TEST_SUBCREDENTIAL},
     };
@@ -274,7 +285,9 @@ mod test {
             .dangerously_assume_wellsigned()
             .dangerously_assume_timely();
         let subcred = TEST_SUBCREDENTIAL.into();
-        let body = desc.decrypt_body(&subcred).unwrap();
+        let body = desc
+            .decrypt_body(&subcred, DEFAULT_MAX_DECRYPTED_LEN)
+            .unwrap();
         let body = std::str::from_utf8(&body[..]).unwrap();
 
         let middle = HsDescMiddle::parse(body)?;
@@ -293,9 +306,26 @@ mod test {
         // We test the case where a HsClientDescEncSecretKey is needed
         // elsewhere, in `hsdesc::test::parse_desc_auth_good`.
         let _inner_body = middle
-            .decrypt_inner(&desc.blinded_id(), desc.revision_counter(), &subcred, None)
+            .decrypt_inner(
+                &desc.blinded_id(),
+                desc.revision_counter(),
+                &subcred,
+                None,
+                DEFAULT_MAX_DECRYPTED_LEN,
+            )
             .unwrap();
 
+        // The inner document is much smaller than this, but it's too large for
+        // a limit of 100 bytes.
+        let too_long = middle.decrypt_inner(
+            &desc.blinded_id(),
+            desc.revision_counter(),
+            &subcred,
+            None,
+            100,
+        );
+        assert!(matches!(too_long, Err(HsDescError::DecryptionFailed)));
+
         Ok(())
     }
 }
@@ -76,9 +76,12 @@ impl HsDescOuter {
 
     /// Decrypt and return the encrypted (middle document) body of this onion
     /// service descriptor.
+    ///
+    /// Fails if the body would be longer than `max_len` bytes.
     pub(super) fn decrypt_body(
         &self,
         subcredential: &Subcredential,
+        max_len: usize,
     ) -> std::result::Result<Vec<u8>, desc_enc::DecryptionError> {
         let decrypt = desc_enc::HsDescEncryption {
             blinded_id: &self.blinded_id(),
@@ -88,7 +91,7 @@ impl HsDescOuter {
             string_const: b"hsdir-superencrypted-data",
         };
 
-        let mut body = decrypt.decrypt(&self.superencrypted[..])?;
+        let mut body = decrypt.decrypt(&self.superencrypted[..], max_len)?;
         let n_padding = body.iter().rev().take_while(|n| **n == 0).count();
         body.truncate(body.len() - n_padding);
         // Work around a bug in the C tor implementation: it doesn't
@@ -320,7 +323,11 @@ mod test {
         );
 
         let subcred: tor_hscrypto::Subcredential = TEST_SUBCREDENTIAL.into();
-        let inner = desc.decrypt_body(&subcred).unwrap();
+        let inner = desc
+            .decrypt_body(&subcred, desc_enc::DEFAULT_MAX_DECRYPTED_LEN)
+            .unwrap();
+
+        assert!(desc.decrypt_body(&subcred, 100).is_err());
 
         assert!(std::str::from_utf8(&inner)
             .unwrap()
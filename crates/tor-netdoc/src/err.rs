@@ -279,6 +279,13 @@ pub enum NetdocErrorKind {
     /// Found an empty line in the middle of a document
     #[display(fmt = "Empty line")]
     EmptyLine,
+    /// A document was longer than we were willing to parse
+    #[display(fmt = "Document too long")]
+    DocumentTooLong,
+    /// An onion service descriptor was not for any of the time periods we
+    /// expected
+    #[display(fmt = "Descriptor not for any expected time period")]
+    NoMatchingTimePeriod,
 }
 
 /// The underlying source for an [`Error`](struct@Error).
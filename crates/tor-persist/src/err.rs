@@ -113,6 +113,10 @@ pub enum ErrorSource {
     #[error("JSON error")]
     Serde(#[from] Arc<serde_json::Error>),
 
+    /// Tried to store durably, but this storage can't guarantee durability.
+    #[error("Durable storage not supported")]
+    DurabilityUnsupported,
+
     /// Another task or process holds this persistent state lock, but we need exclusive access
     #[error("State already lockedr")]
     AlreadyLocked,
@@ -184,6 +188,7 @@ impl tor_error::HasKind for Error {
             E::Permissions(e)  => e.state_error_kind(),
             E::Inaccessible(e) => e.state_error_kind(),
             E::NoLock          => K::BadApiUsage,
+            E::DurabilityUnsupported => K::NotImplemented,
             E::AlreadyLocked   => K::LocalResourceAlreadyInUse,
             E::Bug(e)          => e.kind(),
             E::Serde(..) if self.action == Action::Storing  => K::Internal,
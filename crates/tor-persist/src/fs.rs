@@ -259,6 +259,21 @@ impl StateMgr for FsStateMgr {
 
         self.with_load_store_target(key, Action::Storing, |t| t.store(val))
     }
+
+    fn store_durable<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        if !self.can_store() {
+            return Err(Error::new(
+                ErrorSource::NoLock,
+                Action::Storing,
+                Resource::Manager,
+            ));
+        }
+
+        self.with_load_store_target(key, Action::Storing, |t| t.store_durable(val))
+    }
 }
 
 #[cfg(test)]
@@ -321,6 +336,28 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn durable() -> Result<()> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsStateMgr::from_path(dir.path())?;
+        let stuff: HashMap<_, _> = vec![("hello".to_string(), "world".to_string())]
+            .into_iter()
+            .collect();
+
+        assert!(matches!(
+            store.store_durable("xyz", &stuff).unwrap_err().source(),
+            ErrorSource::NoLock
+        ));
+
+        assert_eq!(store.try_lock()?, LockStatus::NewlyAcquired);
+        store.store_durable("xyz", &stuff)?;
+
+        let stuff2: Option<HashMap<String, String>> = store.load("xyz")?;
+        assert_eq!(Some(stuff), stuff2);
+
+        Ok(())
+    }
+
     #[test]
     fn clean_successful() -> Result<()> {
         let dir = tempfile::TempDir::new().unwrap();
@@ -20,6 +20,20 @@ pub trait StorageHandle<T: Serialize + DeserializeOwned> {
     /// Try to store a value into storage.
     fn store(&self, val: &T) -> Result<()>;
 
+    /// Try to store a value into storage, and make sure that it has reached
+    /// stable storage; see [`StateMgr::store_durable`].
+    ///
+    /// The default implementation always fails, since it can't guarantee
+    /// durability.
+    fn store_durable(&self, val: &T) -> Result<()> {
+        let _ = val;
+        Err(crate::Error::new(
+            crate::ErrorSource::DurabilityUnsupported,
+            crate::err::Action::Storing,
+            crate::err::Resource::Manager,
+        ))
+    }
+
     /// Return true if we have the lock; see [`StateMgr::can_store`].
     fn can_store(&self) -> bool;
 }
@@ -63,6 +77,9 @@ where
     fn store(&self, val: &T) -> Result<()> {
         self.mgr.store(&self.key, val)
     }
+    fn store_durable(&self, val: &T) -> Result<()> {
+        self.mgr.store_durable(&self.key, val)
+    }
     fn can_store(&self) -> bool {
         self.mgr.can_store()
     }
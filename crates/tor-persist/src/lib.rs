@@ -92,6 +92,26 @@ pub trait StateMgr: Clone {
     fn store<S>(&self, key: &str, val: &S) -> Result<()>
     where
         S: Serialize;
+    /// As [`StateMgr::store`], but do not return until `val` has reached
+    /// stable storage, so that it will survive a crash or a power failure.
+    ///
+    /// This is much slower than `store`: it has to wait for the underlying
+    /// storage device.
+    ///
+    /// The default implementation always fails with an error of kind
+    /// [`NotImplemented`](tor_error::ErrorKind::NotImplemented), since it can't
+    /// guarantee durability.
+    fn store_durable<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let _ = (key, val);
+        Err(Error::new(
+            ErrorSource::DurabilityUnsupported,
+            err::Action::Storing,
+            err::Resource::Manager,
+        ))
+    }
     /// Return true if this is a read-write state manager.
     ///
     /// If it returns false, then attempts to `store` will fail with
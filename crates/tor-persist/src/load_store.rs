@@ -79,6 +79,33 @@ impl Target<'_> {
         Ok(())
     }
 
+    /// As [`Target::store`], but also use `fsync` to make sure that the new
+    /// data has reached stable storage before returning.
+    pub(crate) fn store_durable<S: Serialize>(&self, val: &S) -> Result<(), ErrorSource> {
+        self.store(val)?;
+
+        trace!("syncing {self}");
+        // We need write access here: on Windows, `sync_all` (that is,
+        // `FlushFileBuffers`) fails on a handle that is only open for reading.
+        let file = self
+            .dir
+            .open(self.rel_fname, std::fs::OpenOptions::new().write(true))?;
+        file.sync_all()?;
+
+        // On Unix, the rename that replaced the file is only durable once the
+        // directory containing it has been synced too.  (Windows has no
+        // equivalent: there, we can't open a directory as a file at all.)
+        #[cfg(target_family = "unix")]
+        {
+            let path = self.dir.join(self.rel_fname)?;
+            if let Some(parent) = path.parent() {
+                std::fs::File::open(parent)?.sync_all()?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Delete the file specified by `self`
     pub(crate) fn delete(&self) -> Result<(), ErrorSource> {
         trace!("deleting {self}");
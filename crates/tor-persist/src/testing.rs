@@ -136,6 +136,15 @@ impl StateMgr for TestingStateMgr {
         Ok(())
     }
 
+    /// Since this manager only simulates persistence, storing durably is the
+    /// same as storing.
+    fn store_durable<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        self.store(key, val)
+    }
+
     fn can_store(&self) -> bool {
         let inner = self.inner.lock().expect("Lock poisoned.");
 
@@ -48,7 +48,7 @@
 //! ```
 
 use std::any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -76,6 +76,12 @@ pub type RpcSendResult = Result<RpcValue, SendUpdateError>;
 pub type RpcResultFuture = BoxFuture<'static, RpcResult>;
 
 /// A boxed sink on which updates can be sent.
+///
+/// If the sink is closed while an update is still pending
+/// (that is, after `start_send` but before any flush),
+/// that update is the last one for its request,
+/// and implementations should mark it as such.
+/// See [`send_final_update`](crate::send_final_update).
 pub type BoxedUpdateSink = Pin<Box<dyn Sink<RpcValue, Error = SendUpdateError> + Send>>;
 
 /// A boxed sink on which updates of a particular type can be sent.
@@ -587,6 +593,10 @@ pub struct DispatchTable {
     /// An internal HashMap used to look up the correct function for a given
     /// method/object pair.
     map: HashMap<FuncType, InvokerEnt>,
+    /// The capabilities that are enabled for this table.
+    ///
+    /// See [`DynMethod::required_capability`].
+    capabilities: HashSet<String>,
 }
 
 impl DispatchTable {
@@ -596,10 +606,23 @@ impl DispatchTable {
     /// # Panics
     ///
     /// Panics if two entries are found for the same (method,object) types.
+    ///
+    /// In debug builds, also panics if two method types have been registered
+    /// with the same name: see [`detect_duplicate_method_names`](crate::detect_duplicate_method_names).
     pub fn from_inventory() -> Self {
+        // (This crate's own tests register a duplicate method name on purpose,
+        // to test `detect_duplicate_method_names`.)
+        #[cfg(not(test))]
+        debug_assert_eq!(
+            crate::detect_duplicate_method_names(),
+            Vec::<&str>::new(),
+            "Some RPC method names were registered more than once"
+        );
+
         // We want to assert that there are no duplicates, so we can't use "collect"
         let mut this = Self {
             map: HashMap::new(),
+            capabilities: HashSet::new(),
         };
         for ent in inventory::iter::<InvokerEnt>() {
             let old_val = this.insert_inner(*ent);
@@ -646,6 +669,24 @@ impl DispatchTable {
         ents.into_iter().for_each(|e| self.insert(e));
     }
 
+    /// Enable `capability` on this DispatchTable,
+    /// so that methods requiring it can be invoked.
+    ///
+    /// See [`DynMethod::required_capability`].
+    pub fn enable_capability(&mut self, capability: impl Into<String>) {
+        self.capabilities.insert(capability.into());
+    }
+
+    /// Disable `capability` on this DispatchTable.
+    pub fn disable_capability(&mut self, capability: &str) {
+        self.capabilities.remove(capability);
+    }
+
+    /// Return true if `capability` is enabled on this DispatchTable.
+    pub fn capability_enabled(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+
     /// Helper: Return the invoker for a given RPC object and a given method type,
     /// if there is one.
     pub(crate) fn rpc_invoker(
@@ -653,6 +694,11 @@ impl DispatchTable {
         obj: &dyn Object,
         method: &dyn DynMethod,
     ) -> Result<&'static dyn RpcInvocable, InvokeError> {
+        if let Some(capability) = method.required_capability() {
+            if !self.capability_enabled(capability) {
+                return Err(InvokeError::MethodUnavailable(capability));
+            }
+        }
         let func_type = FuncType {
             obj_id: obj.type_id(),
             method_id: method.type_id(),
@@ -692,6 +738,11 @@ pub enum InvokeError {
     #[error("No implementation for provided object and method types.")]
     NoImpl,
 
+    /// The method requires a capability that is not enabled
+    /// in this build or configuration.
+    #[error("Method not available in this build or configuration (requires {0:?})")]
+    MethodUnavailable(&'static str),
+
     /// An internal problem occurred while invoking a method.
     #[error("Internal error")]
     Bug(#[from] tor_error::Bug),
@@ -702,6 +753,7 @@ impl tor_error::HasKind for InvokeError {
         use tor_error::ErrorKind as EK;
         match self {
             InvokeError::NoImpl => EK::RpcMethodNotFound,
+            InvokeError::MethodUnavailable(_) => EK::RpcMethodUnavailable,
             InvokeError::Bug(e) => e.kind(),
         }
     }
@@ -850,14 +902,23 @@ pub(crate) mod test {
 
     pub(crate) struct Ctx {
         table: Arc<RwLock<DispatchTable>>,
+        principal: Option<crate::Principal>,
     }
     impl From<DispatchTable> for Ctx {
         fn from(table: DispatchTable) -> Self {
             Self {
                 table: Arc::new(RwLock::new(table)),
+                principal: None,
             }
         }
     }
+    impl Ctx {
+        /// Make this context report `principal` as its caller.
+        pub(crate) fn with_principal(mut self, principal: crate::Principal) -> Self {
+            self.principal = Some(principal);
+            self
+        }
+    }
 
     impl crate::Context for Ctx {
         fn lookup_object(
@@ -881,6 +942,10 @@ pub(crate) mod test {
         fn dispatch_table(&self) -> &Arc<RwLock<crate::DispatchTable>> {
             &self.table
         }
+
+        fn principal(&self) -> Option<&crate::Principal> {
+            self.principal.as_ref()
+        }
     }
 
     #[derive(Deftly, Clone)]
@@ -1074,6 +1139,133 @@ pub(crate) mod test {
         .unwrap();
     }
 
+    // A method that is only available with a capability.
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(
+        method_name = "x-test:getsecret",
+        requires_capability = "x-test-secrets"
+    ))]
+    pub(crate) struct GetSecret;
+
+    impl RpcMethod for GetSecret {
+        type Output = Outcome;
+        type Update = NoUpdates;
+    }
+
+    async fn getsecret_swan(
+        _obj: Arc<Swan>,
+        _method: Box<GetSecret>,
+        _ctx: Arc<dyn crate::Context>,
+    ) -> Result<Outcome, crate::RpcError> {
+        Ok(Outcome {
+            v: "actually a goose".to_string(),
+        })
+    }
+    static_rpc_invoke_fn! { getsecret_swan; }
+
+    #[async_test]
+    async fn try_invoke_unavailable() {
+        use super::*;
+        let ctx: Arc<dyn Context> = Arc::new(Ctx::from(DispatchTable::from_inventory()));
+        let invoke = |obj: Arc<dyn Object>| {
+            let discard = Box::pin(futures::sink::drain().sink_err_into());
+            crate::invoke_rpc_method(Arc::clone(&ctx), obj, Box::new(GetSecret), discard)
+        };
+
+        // The method is listed, but as conditionally available.
+        assert!(crate::is_method_name("x-test:getsecret"));
+        assert!(
+            crate::iter_conditional_methods().any(|m| m == ("x-test:getsecret", "x-test-secrets"))
+        );
+        assert!(!crate::iter_conditional_methods().any(|(name, _)| name == "x-test:getname"));
+
+        // Without the capability, we get a distinct error, even on an object
+        // that doesn't implement the method.
+        for obj in [Arc::new(Swan) as Arc<dyn Object>, Arc::new(Brick)] {
+            let err = invoke(obj).err().unwrap();
+            assert!(matches!(
+                err,
+                InvokeError::MethodUnavailable("x-test-secrets")
+            ));
+            assert_eq!(err.kind(), ErrorKind::RpcMethodUnavailable);
+        }
+
+        // With the capability, it works as usual.
+        ctx.dispatch_table()
+            .write()
+            .unwrap()
+            .enable_capability("x-test-secrets");
+        let res = invoke(Arc::new(Swan)).unwrap().await.unwrap();
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            r#"{"v":"actually a goose"}"#
+        );
+        assert!(matches!(invoke(Arc::new(Brick)), Err(InvokeError::NoImpl)));
+
+        ctx.dispatch_table()
+            .write()
+            .unwrap()
+            .disable_capability("x-test-secrets");
+        assert!(matches!(
+            invoke(Arc::new(Swan)),
+            Err(InvokeError::MethodUnavailable(_))
+        ));
+    }
+
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(method_name = "x-test:whoami"))]
+    struct WhoAmI;
+    impl RpcMethod for WhoAmI {
+        type Output = Outcome;
+        type Update = NoUpdates;
+    }
+
+    async fn whoami_swan(
+        _obj: Arc<Swan>,
+        _method: Box<WhoAmI>,
+        ctx: Arc<dyn crate::Context>,
+    ) -> Result<Outcome, crate::RpcError> {
+        let v = match ctx.principal() {
+            Some(p) if p.has_scope("x-test:admin") => format!("{} (admin)", p.session_id()),
+            Some(p) => p.session_id().to_string(),
+            None => "nobody".to_string(),
+        };
+        Ok(Outcome { v })
+    }
+    static_rpc_invoke_fn! { whoami_swan; }
+
+    #[async_test]
+    async fn principal() {
+        use super::*;
+        async fn whoami(ctx: Ctx) -> String {
+            let ctx: Arc<dyn Context> = Arc::new(ctx);
+            let discard = Box::pin(futures::sink::drain().sink_err_into());
+            let res = crate::invoke_rpc_method(ctx, Arc::new(Swan), Box::new(WhoAmI), discard)
+                .unwrap()
+                .await
+                .unwrap();
+            serde_json::to_string(&res).unwrap()
+        }
+
+        let ctx = || Ctx::from(DispatchTable::from_inventory());
+        assert_eq!(whoami(ctx()).await, r#"{"v":"nobody"}"#);
+
+        let principal = crate::Principal::new("session-1");
+        assert_eq!(
+            whoami(ctx().with_principal(principal.clone())).await,
+            r#"{"v":"session-1"}"#
+        );
+
+        let principal = principal.with_scope("x-test:admin");
+        assert_eq!(principal.scopes().collect::<Vec<_>>(), ["x-test:admin"]);
+        assert_eq!(
+            whoami(ctx().with_principal(principal)).await,
+            r#"{"v":"session-1 (admin)"}"#
+        );
+    }
+
     #[test]
     fn invoke_poorly() {
         // Make sure that our invoker function invocations return plausible bugs warnings on
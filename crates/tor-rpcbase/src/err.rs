@@ -208,4 +208,41 @@ mod test {
         "#;
         assert_json_eq!(&serialized, expected);
     }
+
+    #[test]
+    fn lookup_error() {
+        use crate::{LookupError, ObjectId};
+
+        // Make sure that `?` works on a LookupError.
+        fn lookup(err: LookupError) -> Result<(), RpcError> {
+            Err(err)?;
+            Ok(())
+        }
+
+        let err = lookup(LookupError::NoObject(ObjectId::from("hello"))).unwrap_err();
+        assert_eq!(err.code, RpcCode::RpcObjectError);
+        assert_eq!(err.kinds, tor_error::ErrorKind::RpcObjectNotFound);
+        let serialized = serde_json::to_string(&err).unwrap();
+        let expected = r#"
+        {
+            "message": "error: No visible object with ID ObjectId(\"hello\")",
+            "code": 1,
+            "kinds": ["arti:RpcObjectNotFound"]
+        }
+        "#;
+        assert_json_eq!(&serialized, expected);
+
+        let err = lookup(LookupError::WrongType(ObjectId::from("hello"))).unwrap_err();
+        assert_eq!(err.code, RpcCode::RpcInvalidRequest);
+        assert_eq!(err.kinds, tor_error::ErrorKind::RpcInvalidRequest);
+        let serialized = serde_json::to_string(&err).unwrap();
+        let expected = r#"
+        {
+            "message": "error: Unexpected type on object with ID ObjectId(\"hello\")",
+            "code": -32600,
+            "kinds": ["arti:RpcInvalidRequest"]
+        }
+        "#;
+        assert_json_eq!(&serialized, expected);
+    }
 }
@@ -44,16 +44,19 @@ pub mod dispatch;
 mod err;
 mod method;
 mod obj;
+mod principal;
 
 use std::{convert::Infallible, sync::Arc};
 
 pub use dispatch::{DispatchTable, InvokeError, UpdateSink};
 pub use err::RpcError;
 pub use method::{
-    check_method_names, is_method_name, iter_method_names, DeserMethod, DynMethod,
-    InvalidMethodName, Method, NoUpdates, RpcMethod,
+    check_method_names, deserialize_method, detect_duplicate_method_names, is_method_name,
+    iter_conditional_methods, iter_method_names, DeserMethod, DynMethod, InvalidMethodName, Method,
+    NoUpdates, RpcMethod,
 };
 pub use obj::{Object, ObjectArcExt, ObjectId};
+pub use principal::Principal;
 
 #[doc(hidden)]
 pub use obj::cast::CastTable;
@@ -70,6 +73,12 @@ pub mod templates {
 }
 
 /// An error returned from [`ContextExt::lookup`].
+///
+/// Like any other error that implements [`HasKind`](tor_error::HasKind),
+/// this converts into an [`RpcError`], so a method can apply `?` directly
+/// to the result of a lookup.  The resulting error codes are stable:
+/// `NoObject` becomes an `RpcObjectNotFound` error (with code 1),
+/// and `WrongType` becomes an `RpcInvalidRequest` error (with code -32600).
 #[derive(Debug, Clone, thiserror::Error)]
 #[non_exhaustive]
 pub enum LookupError {
@@ -130,6 +139,15 @@ pub trait Context: Send + Sync {
 
     /// Return a dispatch table that can be used to invoke other RPC methods.
     fn dispatch_table(&self) -> &Arc<std::sync::RwLock<DispatchTable>>;
+
+    /// Return the identity with which this context's session was
+    /// authenticated, if we know it.
+    ///
+    /// Methods can use this to enforce per-caller policy.
+    /// The default implementation returns `None`.
+    fn principal(&self) -> Option<&Principal> {
+        None
+    }
 }
 
 /// An error caused while trying to send an update to a method.
@@ -143,14 +161,46 @@ pub trait Context: Send + Sync {
 ///
 /// Still, programming errors are real, so we are handling this rather than
 /// declaring it a panic or something.
+///
+/// ## Contract for method implementations
+///
+/// If the client did not ask for updates, sending an update is _not_ an
+/// error: the update is silently discarded. Methods therefore do not need to
+/// check whether updates were requested before sending them.
+///
+/// If sending an update fails, the method should check
+/// [`is_fatal`](SendUpdateError::is_fatal): if it returns true, nobody will
+/// ever see the method's output, so the method should stop doing work and
+/// return as soon as it can.
+///
+/// ## Cancellation
+///
+/// A method does not need to check whether its request has been cancelled
+/// before sending an update.  When a request is cancelled, the session stops
+/// polling the future that runs its method, and drops it: so the method never
+/// resumes from the `.await` at which it was suspended, and no update that it
+/// had not already handed to its sink is serialized or sent.
+/// Cancellation is therefore never reported as a `SendUpdateError`.
 #[derive(Debug, Clone, thiserror::Error)]
 #[non_exhaustive]
 pub enum SendUpdateError {
-    /// The request was cancelled, or the connection was closed.
+    /// The connection was closed.
     #[error("Unable to send on MPSC connection")]
     ConnectionClosed,
 }
 
+impl SendUpdateError {
+    /// Return true if this error means that the request can make no further
+    /// progress, and the method sending the update should stop.
+    ///
+    /// Currently, this is true for every kind of `SendUpdateError`.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            SendUpdateError::ConnectionClosed => true,
+        }
+    }
+}
+
 impl tor_error::HasKind for SendUpdateError {
     fn kind(&self) -> tor_error::ErrorKind {
         tor_error::ErrorKind::Internal
@@ -180,6 +230,22 @@ pub trait ContextExt: Context {
             .downcast_arc()
             .map_err(|_| LookupError::WrongType(id.clone()))
     }
+
+    /// Look up an object, and return it as a `C`,
+    /// where `C` is `dyn Tr` for some capability trait `Tr`.
+    ///
+    /// This lets a method work with any object that implements `Tr`,
+    /// whatever its concrete type.
+    /// An object type registers the traits it supports with
+    /// `#[deftly(rpc(downcastable_to = "..."))]`;
+    /// see [`ObjectArcExt`].
+    ///
+    /// Return an error if the object can't be found, or doesn't support `C`.
+    fn lookup_cap<C: ?Sized + 'static>(&self, id: &ObjectId) -> Result<Arc<C>, LookupError> {
+        self.lookup_object(id)?
+            .cast_to_arc_trait()
+            .map_err(|_| LookupError::WrongType(id.clone()))
+    }
 }
 
 impl<T: Context> ContextExt for T {}
@@ -232,6 +298,61 @@ pub async fn invoke_special_method<M: Method>(
         .map_err(|_| InvokeError::Bug(tor_error::internal!("Downcast to wrong type")))
 }
 
+/// Send every item in `items` as an update on `updates`, and return the number
+/// of items sent.
+///
+/// This is meant for methods that return a large collection:
+/// instead of building the whole collection in their output,
+/// such methods can stream its items as updates,
+/// and return a summary (such as the count) as their output.
+///
+/// If sending an update fails, we stop consuming `items`,
+/// and return the error (see the contract on [`SendUpdateError`]).
+/// If the request is cancelled, the future returned by this function
+/// is dropped, and so no further items are consumed.
+pub async fn stream_collection<T, S>(
+    updates: &mut UpdateSink<T>,
+    items: S,
+) -> Result<usize, SendUpdateError>
+where
+    S: futures::Stream<Item = T>,
+{
+    use futures::{SinkExt as _, StreamExt as _};
+
+    let mut items = std::pin::pin!(items);
+    let mut n_sent = 0;
+    while let Some(item) = items.next().await {
+        updates.send(item).await?;
+        n_sent += 1;
+    }
+    Ok(n_sent)
+}
+
+/// Send `update` as the last update for a request, and close `updates`.
+///
+/// Unlike an ordinary update, this one is marked as final on the wire,
+/// so that the client knows no further updates will follow.
+/// The method's `Output` is still sent afterwards, as the request's
+/// final response, once the method returns.
+///
+/// (This is a function on the update sink, rather than on [`Context`],
+/// since a `Context` is shared by every request on a connection.)
+///
+/// After calling this function, the method must not send any more updates
+/// on `updates`: doing so will fail.
+pub async fn send_final_update<T>(
+    updates: &mut UpdateSink<T>,
+    update: T,
+) -> Result<(), SendUpdateError> {
+    use futures::SinkExt as _;
+
+    // We use `feed` rather than `send` here: the update must still be
+    // pending when we close the sink, so that the sink can tell that it was
+    // the last one.  (See [`dispatch::BoxedUpdateSink`].)
+    updates.feed(update).await?;
+    updates.close().await
+}
+
 /// A serializable empty object.
 ///
 /// Used when we need to declare that a method returns nothing.
@@ -265,6 +386,10 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
 
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    use derive_deftly::Deftly;
     use futures::SinkExt as _;
     use futures_await_test::async_test;
 
@@ -287,4 +412,143 @@ mod test {
             .unwrap();
         assert_eq!(r.v, "cygnets");
     }
+
+    trait HasWheels {
+        fn num_wheels(&self) -> usize;
+    }
+
+    #[derive(Deftly)]
+    #[derive_deftly(Object)]
+    #[deftly(rpc(downcastable_to = "HasWheels"))]
+    struct Bicycle;
+    impl HasWheels for Bicycle {
+        fn num_wheels(&self) -> usize {
+            2
+        }
+    }
+
+    #[derive(Deftly)]
+    #[derive_deftly(Object)]
+    #[deftly(rpc(downcastable_to = "HasWheels"))]
+    struct Tricycle;
+    impl HasWheels for Tricycle {
+        fn num_wheels(&self) -> usize {
+            3
+        }
+    }
+
+    /// A context that can look up a fixed set of objects.
+    struct ObjCtx {
+        objects: HashMap<ObjectId, Arc<dyn Object>>,
+        table: Arc<RwLock<DispatchTable>>,
+    }
+
+    impl Context for ObjCtx {
+        fn lookup_object(&self, id: &ObjectId) -> Result<Arc<dyn Object>, LookupError> {
+            self.objects
+                .get(id)
+                .cloned()
+                .ok_or_else(|| LookupError::NoObject(id.clone()))
+        }
+        fn register_owned(&self, _object: Arc<dyn Object>) -> ObjectId {
+            todo!()
+        }
+        fn register_weak(&self, _object: Arc<dyn Object>) -> ObjectId {
+            todo!()
+        }
+        fn release_owned(&self, _object: &ObjectId) -> Result<(), LookupError> {
+            todo!()
+        }
+        fn dispatch_table(&self) -> &Arc<RwLock<DispatchTable>> {
+            &self.table
+        }
+    }
+
+    #[test]
+    fn lookup_cap() {
+        let objects: [(&str, Arc<dyn Object>); 3] = [
+            ("bicycle", Arc::new(Bicycle)),
+            ("tricycle", Arc::new(Tricycle)),
+            ("swan", Arc::new(Swan)),
+        ];
+        let ctx = ObjCtx {
+            objects: objects
+                .into_iter()
+                .map(|(id, obj)| (ObjectId::from(id), obj))
+                .collect(),
+            table: Arc::new(RwLock::new(DispatchTable::from_inventory())),
+        };
+
+        let wheels = |id: &str| {
+            ctx.lookup_cap::<dyn HasWheels>(&id.into())
+                .map(|obj| obj.num_wheels())
+        };
+        assert_eq!(wheels("bicycle").unwrap(), 2);
+        assert_eq!(wheels("tricycle").unwrap(), 3);
+        assert!(matches!(wheels("swan"), Err(LookupError::WrongType(_))));
+        assert!(matches!(wheels("unicycle"), Err(LookupError::NoObject(_))));
+    }
+
+    #[async_test]
+    async fn send_update_no_updates_wanted() {
+        // When the client didn't ask for updates, we give the method a sink
+        // that discards them: sending on it must not fail.
+        let mut sink: dispatch::BoxedUpdateSink = Box::pin(futures::sink::drain().sink_err_into());
+        let update: dispatch::RpcValue = Box::new("hello");
+        sink.send(update).await.unwrap();
+    }
+
+    #[async_test]
+    async fn stream_collection_sends_updates() {
+        use futures::StreamExt as _;
+
+        const N: usize = 17;
+        let (tx, rx) = futures::channel::mpsc::unbounded::<usize>();
+        let mut sink: UpdateSink<usize> = Box::pin(tx.sink_err_into());
+        let n = stream_collection(&mut sink, futures::stream::iter(0..N))
+            .await
+            .unwrap();
+        assert_eq!(n, N);
+
+        drop(sink);
+        let updates: Vec<usize> = rx.collect().await;
+        assert_eq!(updates, (0..N).collect::<Vec<_>>());
+    }
+
+    #[async_test]
+    async fn stream_collection_connection_closed() {
+        use futures::StreamExt as _;
+
+        let (tx, mut rx) = futures::channel::mpsc::channel::<usize>(0);
+        let mut sink: UpdateSink<usize> = Box::pin(tx.sink_err_into());
+        // Stop listening for updates before the first one is sent.
+        let items = futures::stream::iter(0..100).inspect(move |_| rx.close());
+        let err = stream_collection(&mut sink, items).await.unwrap_err();
+        assert!(err.is_fatal());
+    }
+
+    #[async_test]
+    async fn send_final_update_closes_sink() {
+        use futures::StreamExt as _;
+
+        let (tx, rx) = futures::channel::mpsc::unbounded::<usize>();
+        let mut sink: UpdateSink<usize> = Box::pin(tx.sink_err_into());
+        sink.send(1).await.unwrap();
+        send_final_update(&mut sink, 2).await.unwrap();
+        assert!(sink.send(3).await.is_err());
+
+        drop(sink);
+        let updates: Vec<usize> = rx.collect().await;
+        assert_eq!(updates, vec![1, 2]);
+    }
+
+    #[async_test]
+    async fn send_update_connection_closed() {
+        let (tx, rx) = futures::channel::mpsc::channel::<String>(1);
+        drop(rx);
+        let mut sink: UpdateSink<String> = Box::pin(tx.sink_err_into());
+        let err = sink.send("hello".to_string()).await.unwrap_err();
+        assert!(matches!(err, SendUpdateError::ConnectionClosed));
+        assert!(err.is_fatal());
+    }
 }
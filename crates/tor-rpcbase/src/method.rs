@@ -22,7 +22,19 @@ use once_cell::sync::Lazy;
 /// * the crate should only have access to the public Rust methods of the object,
 ///   which is presumably safe to call.
 /// * if you are linking a crate, you are already trusting that crate.
-pub trait DynMethod: std::fmt::Debug + Send + Downcast {}
+pub trait DynMethod: std::fmt::Debug + Send + Downcast {
+    /// Return the capability that must be enabled on a [`DispatchTable`](crate::DispatchTable)
+    /// for this method to be invoked, if any.
+    ///
+    /// Methods that need a capability are only conditionally available:
+    /// when the capability is not enabled,
+    /// invoking them fails with [`InvokeError::MethodUnavailable`](crate::InvokeError::MethodUnavailable).
+    ///
+    /// Set this with `#[deftly(rpc(requires_capability = "..."))]`.
+    fn required_capability(&self) -> Option<&'static str> {
+        None
+    }
+}
 downcast_rs::impl_downcast!(DynMethod);
 
 /// A DynMethod that can be deserialized.
@@ -37,6 +49,43 @@ pub trait DeserMethod: DynMethod {
     fn upcast_box(self: Box<Self>) -> Box<dyn DynMethod>;
 }
 
+/// Deserialize a method name and its parameters from `deserializer`.
+///
+/// This expects a map with a `method` field and a `params` field, as in an RPC request.
+/// (It can be used with `#[serde(flatten, deserialize_with = "deserialize_method")]`.)
+///
+/// Unlike the `Deserialize` implementation for `Box<dyn DeserMethod>`,
+/// this rejects requests whose `params` are not a JSON object,
+/// as our specification requires.
+/// (Otherwise, a method whose parameter type also has a sequence representation,
+/// as derived `Deserialize` implementations for structs do,
+/// would accept its parameters as an array.)
+pub fn deserialize_method<'de, D>(deserializer: D) -> Result<Box<dyn DeserMethod>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error as _;
+    use serde::Deserialize as _;
+
+    /// A method and its parameters, before we check and decode the parameters.
+    #[derive(serde::Deserialize)]
+    struct RawMethod {
+        /// The name of the method.
+        method: String,
+        /// The parameters for the method.
+        params: serde_json::Value,
+    }
+
+    let RawMethod { method, params } = RawMethod::deserialize(deserializer)?;
+    if !params.is_object() {
+        return Err(D::Error::custom(format!(
+            "parameters for {method:?} must be a JSON object"
+        )));
+    }
+    serde_json::from_value(serde_json::json!({ "method": method, "params": params }))
+        .map_err(D::Error::custom)
+}
+
 /// A typed method, used to ensure that all implementations of a method have the
 /// same success and updates types.
 ///
@@ -98,6 +147,8 @@ pub enum NoUpdates {}
 pub struct MethodInfo_ {
     /// The name of the method.
     pub method_name: &'static str,
+    /// The capability that the method requires, if any.
+    pub required_capability: Option<&'static str>,
 }
 
 inventory::collect!(MethodInfo_);
@@ -126,9 +177,24 @@ define_derive_deftly! {
 ///     type Update = rpc::NoUpdates;
 /// }
 /// ```
+///
+/// # Conditionally available methods
+///
+/// A method can declare that it requires some capability
+/// (such as a cargo feature, or a runtime configuration option),
+/// with `#[deftly(rpc(requires_capability = "..."))]`.
+/// Such a method can only be invoked through a [`DispatchTable`](crate::DispatchTable)
+/// on which that capability has been
+/// [enabled](crate::DispatchTable::enable_capability).
     export DynMethod:
     const _: () = {
-        impl $crate::DynMethod for $ttype {}
+        impl $crate::DynMethod for $ttype {
+            ${if tmeta(rpc(requires_capability)) {
+                fn required_capability(&self) -> Option<&'static str> {
+                    Some(${tmeta(rpc(requires_capability)) as str})
+                }
+            }}
+        }
 
         ${select1 tmeta(rpc(method_name)) {
             // Alas, `typetag does not work correctly when not in scope as `typetag`.
@@ -142,7 +208,14 @@ define_derive_deftly! {
                 }
             }
             $crate::inventory::submit! {
-                $crate::MethodInfo_ { method_name : ${tmeta(rpc(method_name)) as str} }
+                $crate::MethodInfo_ {
+                    method_name : ${tmeta(rpc(method_name)) as str},
+                    required_capability : ${if tmeta(rpc(requires_capability)) {
+                        Some(${tmeta(rpc(requires_capability)) as str})
+                    } else {
+                        None
+                    }},
+                }
             }
         } else if tmeta(rpc(no_method_name)) {
             // don't derive DeserMethod.
@@ -165,6 +238,35 @@ pub fn iter_method_names() -> impl Iterator<Item = &'static str> {
     inventory::iter::<MethodInfo_>().map(|mi| mi.method_name)
 }
 
+/// Return an iterator that yields the name and required capability
+/// of every registered method that is only conditionally available.
+///
+/// See [`DynMethod::required_capability`].
+pub fn iter_conditional_methods() -> impl Iterator<Item = (&'static str, &'static str)> {
+    inventory::iter::<MethodInfo_>()
+        .filter_map(|mi| Some((mi.method_name, mi.required_capability?)))
+}
+
+/// Return every method name that has been registered by more than one type.
+///
+/// If two method types are registered with the same name
+/// (perhaps by different crates), dispatch is ambiguous:
+/// we can't tell which type a request is meant for,
+/// so requests using that name can't be deserialized at all.
+///
+/// Returns each such name once, in sorted order.
+/// [`DispatchTable::from_inventory`](crate::DispatchTable::from_inventory)
+/// asserts (in debug builds) that this list is empty.
+pub fn detect_duplicate_method_names() -> Vec<&'static str> {
+    let mut seen = HashSet::new();
+    let mut dups: Vec<_> = iter_method_names()
+        .filter(|name| !seen.insert(*name))
+        .collect();
+    dups.sort_unstable();
+    dups.dedup();
+    dups
+}
+
 /// Error representing an "invalid" method name.
 #[derive(Clone, Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -239,7 +341,95 @@ pub fn check_method_names<'a>(
 
 #[cfg(test)]
 mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
     use super::*;
+    use derive_deftly::Deftly;
+
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(method_name = "x-test:paramcheck"))]
+    struct ParamCheck {
+        color: String,
+        count: u32,
+    }
+
+    impl RpcMethod for ParamCheck {
+        type Output = ();
+        type Update = NoUpdates;
+    }
+
+    #[test]
+    fn params_must_be_object() {
+        let m = deserialize_method(serde_json::json!({
+            "method": "x-test:paramcheck",
+            "params": { "color": "green", "count": 7 },
+        }))
+        .unwrap();
+        let m = m.upcast_box().downcast::<ParamCheck>().unwrap();
+        assert_eq!(m.color, "green");
+        assert_eq!(m.count, 7);
+
+        // The same parameters, given as an array, would be accepted by the plain
+        // `Deserialize` implementation...
+        let array = serde_json::json!({
+            "method": "x-test:paramcheck",
+            "params": ["green", 7],
+        });
+        assert!(serde_json::from_value::<Box<dyn DeserMethod>>(array.clone()).is_ok());
+
+        // ...but not here.
+        let err = deserialize_method(array).unwrap_err();
+        assert!(err.to_string().contains("must be a JSON object"), "{err}");
+
+        for params in [serde_json::json!(7), serde_json::json!(null)] {
+            let req = serde_json::json!({ "method": "x-test:paramcheck", "params": params });
+            assert!(deserialize_method(req).is_err());
+        }
+    }
+
+    // Two different types that (wrongly) claim the same method name.
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(method_name = "x-test:collide"))]
+    struct Collide1 {}
+
+    impl RpcMethod for Collide1 {
+        type Output = ();
+        type Update = NoUpdates;
+    }
+
+    #[derive(Debug, serde::Deserialize, Deftly)]
+    #[derive_deftly(DynMethod)]
+    #[deftly(rpc(method_name = "x-test:collide"))]
+    struct Collide2 {}
+
+    impl RpcMethod for Collide2 {
+        type Output = ();
+        type Update = NoUpdates;
+    }
+
+    #[test]
+    fn duplicate_method_names() {
+        assert_eq!(detect_duplicate_method_names(), vec!["x-test:collide"]);
+
+        // A request for this method can't be deserialized, since we can't tell
+        // which type it is for.
+        let req = serde_json::json!({ "method": "x-test:collide", "params": {} });
+        assert!(deserialize_method(req).is_err());
+    }
 
     #[test]
     fn valid_method_names() {
@@ -0,0 +1,50 @@
+//! Information about who is making an RPC call.
+
+/// The identity with which an RPC session was authenticated.
+///
+/// Methods can look this up with [`Context::principal`](crate::Context::principal),
+/// and use it to decide whether the caller may do what it is asking to do.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Principal {
+    /// An identifier for the authenticated session.
+    session_id: String,
+    /// The scopes that this session was granted when it authenticated.
+    scopes: Vec<String>,
+}
+
+impl Principal {
+    /// Create a new `Principal` for the session called `session_id`, with no
+    /// scopes.
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Grant `scope` to this principal.
+    #[must_use]
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        let scope = scope.into();
+        if !self.has_scope(&scope) {
+            self.scopes.push(scope);
+        }
+        self
+    }
+
+    /// Return the identifier of the authenticated session.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Return the scopes that this principal has been granted, in the order
+    /// in which they were granted.
+    pub fn scopes(&self) -> impl Iterator<Item = &str> + '_ {
+        self.scopes.iter().map(String::as_str)
+    }
+
+    /// Return true if this principal has been granted `scope`.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}